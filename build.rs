@@ -1,5 +1,20 @@
-#[cfg(target_os = "windows")]
 fn main() {
+    if let Err(err) = tonic_build::compile_protos("proto/gameperf.proto") {
+        eprint!("{}", err);
+        std::process::exit(1);
+    }
+    resources();
+    // Baked into `integrity::PUBLIC_KEY`, release manifests can't be verified without it
+    println!("cargo:rerun-if-env-changed=GAMEPERF_MANIFEST_KEY");
+    if std::env::var("PROFILE").as_deref() == Ok("release")
+        && std::env::var("GAMEPERF_MANIFEST_KEY").is_err()
+    {
+        println!("cargo:warning=GAMEPERF_MANIFEST_KEY not set, integrity.json will be unsigned");
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn resources() {
     if std::env::var("PROFILE").unwrap() == "release" {
         let mut res = winres::WindowsResource::new();
 
@@ -15,4 +30,4 @@ fn main() {
 }
 
 #[cfg(not(target_os = "windows"))]
-fn main() {}
+fn resources() {}
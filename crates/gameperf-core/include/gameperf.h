@@ -0,0 +1,75 @@
+/*
+ * C ABI of the GamePerf capture engine (gameperf_core.dll / libgameperf_core.so).
+ *
+ * Functions returning int give 0 (or 1, see gp_capture_poll) on success and -1 on error,
+ * functions returning a pointer give NULL on error. gp_last_error() then tells why.
+ * Strings are UTF-8. Strings returned by the engine are freed with gp_string_free.
+ * Handles are not thread safe, use one handle from one thread at a time.
+ */
+#ifndef GAMEPERF_H
+#define GAMEPERF_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Checked against GP_ABI_VERSION before anything else is called */
+#define GP_ABI_VERSION 1
+
+typedef struct GpCapture GpCapture;
+typedef struct GpRecording GpRecording;
+
+typedef struct GpStats {
+    double duration_secs;
+    uint64_t frames;
+    double avg_fps;
+    double min_fps;
+    double max_fps;
+    double p1_low;
+    double p01_low;
+    double avg_frametime;
+    double p99_frametime;
+} GpStats;
+
+uint32_t gp_abi_version(void);
+
+/* Error of the last failed call on this thread, NULL if none. Valid until the next failure. */
+const char *gp_last_error(void);
+
+/* Scripted captures without a device: "steady", "stutter" or "throttle" */
+int gp_mock(const char *pattern);
+
+/*
+ * Starts capturing the game `package` (or pid) on the device adb is connected to. NULL for names
+ * with anything but letters, digits, '.' and '_'.
+ */
+GpCapture *gp_capture_start(const char *package);
+
+/*
+ * Takes a sample, about once a second. Returns 1 and a JSON object in *sample, or 0 when there
+ * is none (the device sleeps or the game went away, see the recording).
+ */
+int gp_capture_poll(GpCapture *capture, char **sample);
+
+/* Ends the capture, `capture` is freed */
+GpRecording *gp_capture_stop(GpCapture *capture);
+
+/* Abandons the capture */
+void gp_capture_free(GpCapture *capture);
+
+int gp_recording_stats(GpRecording *recording, GpStats *stats);
+
+/* The recording as a .gpcap file, which GamePerf opens */
+char *gp_recording_write(GpRecording *recording);
+
+void gp_recording_free(GpRecording *recording);
+
+void gp_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
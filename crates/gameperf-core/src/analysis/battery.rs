@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Stats;
+use crate::capture::battery::CAPACITY;
+use crate::capture::{Recording, Sample};
+
+const MS_PER_HOUR: f64 = 3_600_000.0;
+
+// Handheld view of a run: what each watt buys and how long the battery lasts at this load
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatteryReport {
+    pub avg_watts: f64,
+    pub fps_per_watt: f64,
+    // Level lost per hour, None when it didn't move
+    pub drain_pct_per_hour: Option<f64>,
+    // From a full charge, None while charging or without a capacity or a level drop
+    pub hours_to_empty: Option<f64>,
+    // Plugged in for part of the run, the charging samples are left out
+    pub charging: bool,
+    // `tdp.*` settings the run was recorded with
+    pub tdp: BTreeMap<String, String>,
+}
+
+fn metric(sample: &Sample, name: &str) -> Option<f64> {
+    sample.metrics.get(name).copied()
+}
+
+// None without battery samples, see `Recorder::battery`
+pub fn analyze(recording: &Recording, stats: &Stats) -> Option<BatteryReport> {
+    let measured: Vec<&Sample> =
+        recording.samples.iter().filter(|s| metric(s, "battery.watts").is_some()).collect();
+    let discharging: Vec<&Sample> =
+        measured.iter().copied().filter(|s| metric(s, "battery.charging") != Some(1.0)).collect();
+    let charging = discharging.len() < measured.len();
+    let samples = if discharging.is_empty() { &measured } else { &discharging };
+    if samples.is_empty() {
+        return None;
+    }
+    let watts: Vec<f64> = samples.iter().filter_map(|s| metric(s, "battery.watts")).collect();
+    let avg_watts = watts.iter().sum::<f64>() / watts.len() as f64;
+    let fps_per_watt = if avg_watts > 0.0 { stats.avg_fps / avg_watts } else { 0.0 };
+
+    let levels: Vec<(u64, f64)> = discharging
+        .iter()
+        .filter_map(|s| Some((s.elapsed_ms, metric(s, "battery.level")?)))
+        .collect();
+    let drain_pct_per_hour = match (levels.first(), levels.last()) {
+        (Some(first), Some(last)) if last.1 < first.1 && last.0 > first.0 => {
+            Some((first.1 - last.1) / ((last.0 - first.0) as f64 / MS_PER_HOUR))
+        }
+        _ => None,
+    };
+    let system = recording.system.as_ref();
+    let capacity_wh = system.and_then(|s| s.device.get(CAPACITY)?.parse::<f64>().ok());
+    let hours_to_empty = match (capacity_wh, drain_pct_per_hour) {
+        _ if discharging.is_empty() => None,
+        (Some(capacity_wh), _) if avg_watts > 0.0 => Some(capacity_wh / avg_watts),
+        (_, Some(drain)) => Some(100.0 / drain),
+        _ => None,
+    };
+    let tdp = system
+        .map(|s| s.device.iter().filter(|(key, _)| key.starts_with("tdp.")))
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Some(BatteryReport {
+        avg_watts,
+        fps_per_watt,
+        drain_pct_per_hour,
+        hours_to_empty,
+        charging,
+        tdp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::system::SystemSnapshot;
+
+    fn sample(second: u64, watts: f64, level: f64, charging: bool) -> Sample {
+        let metrics = vec![
+            ("battery.watts".to_string(), watts),
+            ("battery.level".to_string(), level),
+            ("battery.charging".to_string(), if charging { 1.0 } else { 0.0 }),
+        ];
+        Sample { elapsed_ms: second * 1000, metrics: metrics.into_iter().collect() }
+    }
+
+    #[test]
+    fn test_analyze() {
+        // 10 W for half an hour, 5% gone, then plugged in
+        let mut samples: Vec<Sample> = (0..=1800)
+            .map(|second| sample(second, 10.0, 90.0 - second as f64 / 360.0, false))
+            .collect();
+        samples.push(sample(1801, 25.0, 85.0, true));
+        let mut recording = Recording { samples, ..Default::default() };
+        let stats = Stats { avg_fps: 40.0, ..Default::default() };
+
+        let report = analyze(&recording, &stats).unwrap();
+        assert_eq!(report.avg_watts, 10.0);
+        assert_eq!(report.fps_per_watt, 4.0);
+        assert!((report.drain_pct_per_hour.unwrap() - 10.0).abs() < 1e-9);
+        assert!((report.hours_to_empty.unwrap() - 10.0).abs() < 1e-9);
+        assert!(report.charging);
+
+        let mut system = SystemSnapshot::default();
+        system.device.insert(CAPACITY.into(), "40.0".into());
+        system.device.insert("tdp./sys/class/hwmon/hwmon5/power1_cap".into(), "15000000".into());
+        recording.system = Some(system);
+        let report = analyze(&recording, &stats).unwrap();
+        assert_eq!(report.hours_to_empty, Some(4.0));
+        assert_eq!(report.tdp.len(), 1);
+
+        assert_eq!(analyze(&Recording::default(), &stats), None);
+    }
+}
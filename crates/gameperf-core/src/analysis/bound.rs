@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::capture::engine::EngineTimings;
+
+// A frame whose GPU busy time fills most of its frame time was waiting on the GPU, anything
+// shorter means the CPU (game thread, driver) couldn't feed it fast enough
+const GPU_BOUND_RATIO: f64 = 0.9;
+// Presents further apart than this can't be queued behind each other
+const MAX_QUEUE_MS: f64 = 1000.0;
+
+pub fn is_gpu_bound(frametime: f64, gpu_busy: f64) -> bool {
+    frametime > 0.0 && gpu_busy >= frametime * GPU_BOUND_RATIO
+}
+
+// Percentage of GPU-bound frames, None without GPU timings
+pub fn gpu_bound_pct(frametimes: &[f64], gpu_busy: &[f64]) -> Option<f64> {
+    if gpu_busy.is_empty() || gpu_busy.len() != frametimes.len() {
+        return None;
+    }
+    let bound =
+        frametimes.iter().zip(gpu_busy).filter(|&(&ft, &gpu)| is_gpu_bound(ft, gpu)).count();
+    Some(bound as f64 * 100.0 / frametimes.len() as f64)
+}
+
+// Per sample `gpu_busy` (ms) and `gpu_bound` (% of frames) metrics
+pub fn gpu_metrics(frametimes: &[f64], gpu_busy: &[f64]) -> Vec<(String, f64)> {
+    match gpu_bound_pct(frametimes, gpu_busy) {
+        Some(bound) => {
+            let avg = gpu_busy.iter().sum::<f64>() / gpu_busy.len() as f64;
+            vec![("gpu_busy".into(), avg), ("gpu_bound".into(), bound)]
+        }
+        None => vec![],
+    }
+}
+
+// Share of engine frames limited by each side, from the engine's own timings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineBound {
+    pub game_thread_pct: f64,
+    // Render or RHI thread, whichever took longer
+    pub render_thread_pct: f64,
+    pub gpu_pct: f64,
+}
+
+// The longest of the three held the frame back, None without all of them
+pub fn engine_bound(timings: &EngineTimings) -> Option<EngineBound> {
+    let frames = timings.frame.len();
+    let columns = [&timings.game_thread, &timings.render_thread, &timings.gpu];
+    if frames == 0 || columns.iter().any(|values| values.len() != frames) {
+        return None;
+    }
+    let mut bound = EngineBound::default();
+    for i in 0..frames {
+        let game = timings.game_thread[i];
+        let render =
+            timings.render_thread[i].max(timings.rhi_thread.get(i).copied().unwrap_or(0.0));
+        let gpu = timings.gpu[i];
+        if gpu >= game && gpu >= render {
+            bound.gpu_pct += 1.0;
+        } else if game >= render {
+            bound.game_thread_pct += 1.0;
+        } else {
+            bound.render_thread_pct += 1.0;
+        }
+    }
+    let scale = 100.0 / frames as f64;
+    bound.game_thread_pct *= scale;
+    bound.render_thread_pct *= scale;
+    bound.gpu_pct *= scale;
+    Some(bound)
+}
+
+// Frames already presented but not displayed yet when each frame is presented, from the
+// present-to-display latencies. Frames never displayed (latency 0) don't hold a queue slot.
+pub fn queue_depths(frametimes: &[f64], until_displayed: &[f64]) -> Vec<u32> {
+    let mut presents = Vec::with_capacity(frametimes.len());
+    let mut now = 0.0;
+    for frametime in frametimes {
+        now += frametime;
+        presents.push(now);
+    }
+
+    let mut depths = Vec::with_capacity(presents.len());
+    for (i, &present) in presents.iter().enumerate() {
+        let depth = presents[..i]
+            .iter()
+            .zip(until_displayed)
+            .rev()
+            .take_while(|&(&earlier, _)| present - earlier <= MAX_QUEUE_MS)
+            .filter(|&(&earlier, &latency)| latency > 0.0 && earlier + latency > present)
+            .count();
+        depths.push(depth as u32);
+    }
+    depths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bound() {
+        assert!(is_gpu_bound(16.0, 15.0));
+        assert!(!is_gpu_bound(16.0, 8.0));
+        assert_eq!(gpu_bound_pct(&[16.0, 16.0], &[15.0, 8.0]), Some(50.0));
+        assert_eq!(gpu_bound_pct(&[16.0, 16.0], &[]), None);
+
+        // The second frame is displayed 40ms after its present, two more get queued behind it
+        let depths = queue_depths(&[10.0, 10.0, 10.0, 10.0], &[5.0, 40.0, 0.0, 5.0]);
+        assert_eq!(depths, vec![0, 0, 1, 1]);
+
+        let timings = EngineTimings {
+            frame: vec![16.0, 33.0, 20.0, 16.0],
+            game_thread: vec![10.0, 30.0, 8.0, 9.0],
+            render_thread: vec![12.0, 12.0, 18.0, 9.0],
+            gpu: vec![15.0, 14.0, 10.0, 15.0],
+            ..Default::default()
+        };
+        let bound = engine_bound(&timings).unwrap();
+        assert_eq!(
+            (bound.game_thread_pct, bound.render_thread_pct, bound.gpu_pct),
+            (25.0, 25.0, 50.0)
+        );
+        assert_eq!(engine_bound(&EngineTimings { gpu: vec![], ..timings }), None);
+    }
+}
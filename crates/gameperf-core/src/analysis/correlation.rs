@@ -0,0 +1,196 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use super::stutter;
+use crate::capture::{Recording, Sample};
+
+// Not a sampled metric, the average frame time since the previous sample
+pub const FRAMETIME: &str = "frametime";
+// Stutter hints shown, strongest first
+const MAX_CORRELATES: usize = 5;
+// Fewer pairs than this say nothing
+const MIN_PAIRS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RollingPoint {
+    // The sample ending the window
+    pub elapsed_ms: u64,
+    // None when either metric is flat or missing in the window
+    pub r: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Correlate {
+    pub metric: String,
+    // With the number of stutters per sample, positive when the metric is high around stutters
+    pub r: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Correlation {
+    pub metric_a: String,
+    pub metric_b: String,
+    // In samples
+    pub window: usize,
+    // Pearson over the whole run
+    pub overall: Option<f64>,
+    pub rolling: Vec<RollingPoint>,
+    pub stutter_correlates: Vec<Correlate>,
+}
+
+// Frames ending after the previous sample up to each sample
+fn frames_per_sample(samples: &[Sample], frametimes: &[f64]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::with_capacity(samples.len());
+    let (mut frame, mut elapsed_ms) = (0, 0.0);
+    for sample in samples {
+        let start = frame;
+        while frame < frametimes.len() && elapsed_ms + frametimes[frame] <= sample.elapsed_ms as f64
+        {
+            elapsed_ms += frametimes[frame];
+            frame += 1;
+        }
+        ranges.push(start..frame);
+    }
+    ranges
+}
+
+fn values(recording: &Recording, frames: &[Range<usize>], metric: &str) -> Vec<Option<f64>> {
+    if metric == FRAMETIME {
+        return frames
+            .iter()
+            .map(|range| {
+                let frametimes = &recording.frametimes[range.clone()];
+                if frametimes.is_empty() {
+                    return None;
+                }
+                Some(frametimes.iter().sum::<f64>() / frametimes.len() as f64)
+            })
+            .collect();
+    }
+    recording.samples.iter().map(|sample| sample.metrics.get(metric).copied()).collect()
+}
+
+fn pearson(a: &[Option<f64>], b: &[Option<f64>]) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> =
+        a.iter().zip(b).filter_map(|pair| Some(((*pair.0)?, (*pair.1)?))).collect();
+    if pairs.len() < MIN_PAIRS {
+        return None;
+    }
+    let count = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|p| p.0).sum::<f64>() / count;
+    let mean_b = pairs.iter().map(|p| p.1).sum::<f64>() / count;
+    let (mut covariance, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (a, b) in pairs {
+        covariance += (a - mean_a) * (b - mean_b);
+        var_a += (a - mean_a) * (a - mean_a);
+        var_b += (b - mean_b) * (b - mean_b);
+    }
+    if var_a <= 0.0 || var_b <= 0.0 {
+        return None;
+    }
+    Some(covariance / (var_a * var_b).sqrt())
+}
+
+// Rolling Pearson correlation between two metrics over `window` samples, and the metrics that go
+// along with stutters the most as hints at their cause
+pub fn correlate(
+    recording: &Recording,
+    metric_a: &str,
+    metric_b: &str,
+    window: usize,
+) -> Result<Correlation> {
+    if window < MIN_PAIRS {
+        bail!("Window must be at least {} samples", MIN_PAIRS);
+    }
+    let names: BTreeSet<&str> = recording
+        .samples
+        .iter()
+        .flat_map(|sample| sample.metrics.keys().map(String::as_str))
+        .chain(Some(FRAMETIME))
+        .collect();
+    for metric in [metric_a, metric_b] {
+        if !names.contains(metric) {
+            bail!("No {} samples in this session", metric);
+        }
+    }
+
+    let frames = frames_per_sample(&recording.samples, &recording.frametimes);
+    let a = values(recording, &frames, metric_a);
+    let b = values(recording, &frames, metric_b);
+    let rolling = (window.min(a.len())..=a.len())
+        .filter(|&end| end > 0)
+        .map(|end| RollingPoint {
+            elapsed_ms: recording.samples[end - 1].elapsed_ms,
+            r: pearson(&a[end - window.min(end)..end], &b[end - window.min(end)..end]),
+        })
+        .collect();
+
+    let mut stutters = vec![0; frames.len()];
+    for stutter in stutter::detect(&recording.frametimes) {
+        if let Some(sample) = frames.iter().position(|range| range.contains(&stutter.frame)) {
+            stutters[sample] += 1;
+        }
+    }
+    let stutters: Vec<Option<f64>> = stutters.into_iter().map(|count| Some(count as f64)).collect();
+    // FPS metrics are the stutters themselves
+    let mut stutter_correlates: Vec<Correlate> = names
+        .iter()
+        .filter(|name| **name != FRAMETIME && !name.starts_with("fps"))
+        .filter_map(|name| {
+            let r = pearson(&values(recording, &frames, name), &stutters)?;
+            Some(Correlate { metric: name.to_string(), r })
+        })
+        .collect();
+    stutter_correlates.sort_by(|x, y| y.r.abs().total_cmp(&x.r.abs()));
+    stutter_correlates.truncate(MAX_CORRELATES);
+
+    Ok(Correlation {
+        metric_a: metric_a.into(),
+        metric_b: metric_b.into(),
+        window,
+        overall: pearson(&a, &b),
+        rolling,
+        stutter_correlates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(elapsed_ms: u64, metrics: &[(&str, f64)]) -> Sample {
+        let metrics = metrics.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+        Sample { elapsed_ms, metrics }
+    }
+
+    #[test]
+    fn test_correlate() -> Result<()> {
+        // Frames slow down as the GPU heats up, disk reads spike with the stutter in second 30
+        let mut frametimes = vec![];
+        let mut samples = vec![];
+        for second in 0..40 {
+            let frametime = 10.0 + second as f64 / 10.0;
+            let frames = (1000.0 / frametime) as usize;
+            frametimes.extend(vec![frametime; frames]);
+            if second == 30 {
+                frametimes.push(200.0);
+            }
+            let elapsed_ms = frametimes.iter().sum::<f64>().ceil() as u64;
+            let disk = if second == 30 { 90.0 } else { 5.0 + (second % 3) as f64 };
+            samples.push(sample(elapsed_ms, &[("temp.gpu", 50.0 + second as f64), ("disk", disk)]));
+        }
+        let recording = Recording { frametimes, samples, ..Default::default() };
+
+        let correlation = correlate(&recording, FRAMETIME, "temp.gpu", 10)?;
+        assert!(correlation.overall.unwrap() > 0.9);
+        assert_eq!(correlation.rolling.len(), 31);
+        assert_eq!(correlation.stutter_correlates[0].metric, "disk");
+        assert!(correlation.stutter_correlates[0].r > 0.9);
+        assert!(correlate(&recording, FRAMETIME, "temp.cpu", 10).is_err());
+        assert!(correlate(&recording, FRAMETIME, "disk", 1).is_err());
+        Ok(())
+    }
+}
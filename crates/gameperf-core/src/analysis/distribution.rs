@@ -0,0 +1,238 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use super::percentile;
+
+// More would be noise on any chart, and a lot of JSON
+const MAX_BINS: usize = 2000;
+const MAX_POINTS: usize = 1000;
+const MAX_CELLS: usize = 200_000;
+// Heatmap rows and histogram bins stop here, a loading hitch would otherwise stretch them over
+// empty space
+const RANGE_PERCENTILE: f64 = 99.9;
+// Always in the percentile curve, the tail is where stutters show
+const TAIL_PERCENTILES: &[f64] = &[99.0, 99.5, 99.9, 99.99];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bin {
+    // Frame times from `start_ms` up to `start_ms + bin_width_ms`
+    pub start_ms: f64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub bin_width_ms: f64,
+    pub frames: usize,
+    // From the shortest frame to the 99.9th percentile, empty bins included
+    pub bins: Vec<Bin>,
+    // Frames longer than the last bin
+    pub overflow: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CdfPoint {
+    pub frametime_ms: f64,
+    // Of the frames, at most `frametime_ms` long
+    pub fraction: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PercentilePoint {
+    pub percentile: f64,
+    pub frametime_ms: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Heatmap {
+    // Columns are stretches of the run this long, rows frame times this wide
+    pub time_bin_ms: f64,
+    pub frametime_bin_ms: f64,
+    // Lower edge of the first row, the last row also holds every longer frame
+    pub frametime_start_ms: f64,
+    pub frames: usize,
+    // Frames per cell, `counts[column][row]`
+    pub counts: Vec<Vec<u32>>,
+}
+
+fn sorted(frametimes: &[f64]) -> Vec<f64> {
+    let mut sorted: Vec<f64> = frametimes.iter().copied().filter(|f| f.is_finite()).collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+}
+
+fn check_points(points: usize) -> Result<()> {
+    if !(2..=MAX_POINTS).contains(&points) {
+        bail!("Points must be between 2 and {}", MAX_POINTS);
+    }
+    Ok(())
+}
+
+pub fn histogram(frametimes: &[f64], bin_width_ms: f64) -> Result<Histogram> {
+    if !bin_width_ms.is_finite() || bin_width_ms <= 0.0 {
+        bail!("Bin width must be a positive number of ms");
+    }
+    let sorted = sorted(frametimes);
+    let mut histogram = Histogram { bin_width_ms, frames: sorted.len(), bins: vec![], overflow: 0 };
+    let first = match sorted.first() {
+        Some(first) => *first,
+        None => return Ok(histogram),
+    };
+    let last = percentile(&sorted, RANGE_PERCENTILE);
+    let offset = (first / bin_width_ms).floor();
+    let count = (last / bin_width_ms).floor() - offset + 1.0;
+    if count > MAX_BINS as f64 {
+        bail!(
+            "{} ms bins would make {} bins, at most {} are allowed",
+            bin_width_ms,
+            count,
+            MAX_BINS
+        );
+    }
+    histogram.bins = (0..count as usize)
+        .map(|bin| Bin { start_ms: (offset + bin as f64) * bin_width_ms, count: 0 })
+        .collect();
+    for frametime in sorted {
+        let bin = ((frametime / bin_width_ms).floor() - offset) as usize;
+        match histogram.bins.get_mut(bin) {
+            Some(bin) => bin.count += 1,
+            None => histogram.overflow += 1,
+        }
+    }
+    Ok(histogram)
+}
+
+// `points` evenly spaced frame times from the shortest frame to the longest
+pub fn cdf(frametimes: &[f64], points: usize) -> Result<Vec<CdfPoint>> {
+    check_points(points)?;
+    let sorted = sorted(frametimes);
+    let (first, last) = match (sorted.first(), sorted.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Ok(vec![]),
+    };
+    let step = (last - first) / (points - 1) as f64;
+    Ok((0..points)
+        .map(|point| {
+            let frametime_ms = if point == points - 1 { last } else { first + step * point as f64 };
+            let below = sorted.partition_point(|&frametime| frametime <= frametime_ms);
+            CdfPoint { frametime_ms, fraction: below as f64 / sorted.len() as f64 }
+        })
+        .collect())
+}
+
+// `points` evenly spaced percentiles from 0 to 100, and the tail
+pub fn percentile_curve(frametimes: &[f64], points: usize) -> Result<Vec<PercentilePoint>> {
+    check_points(points)?;
+    let sorted = sorted(frametimes);
+    if sorted.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut percentiles: Vec<f64> =
+        (0..points).map(|point| 100.0 * point as f64 / (points - 1) as f64).collect();
+    percentiles.extend(TAIL_PERCENTILES);
+    percentiles.sort_by(|a, b| a.total_cmp(b));
+    percentiles.dedup();
+    Ok(percentiles
+        .into_iter()
+        .map(|p| PercentilePoint { percentile: p, frametime_ms: percentile(&sorted, p) })
+        .collect())
+}
+
+// Frame time density over the run, where a line chart averages two clusters into one
+pub fn heatmap(frametimes: &[f64], columns: usize, frametime_bin_ms: f64) -> Result<Heatmap> {
+    if !frametime_bin_ms.is_finite() || frametime_bin_ms <= 0.0 {
+        bail!("Bin width must be a positive number of ms");
+    }
+    if !(1..=MAX_BINS).contains(&columns) {
+        bail!("Columns must be between 1 and {}", MAX_BINS);
+    }
+    let frametimes: Vec<f64> = frametimes.iter().copied().filter(|f| f.is_finite()).collect();
+    let sorted = sorted(&frametimes);
+    let duration_ms: f64 = frametimes.iter().sum();
+    let mut heatmap = Heatmap {
+        time_bin_ms: duration_ms / columns as f64,
+        frametime_bin_ms,
+        frametime_start_ms: 0.0,
+        frames: frametimes.len(),
+        counts: vec![],
+    };
+    let first = match sorted.first() {
+        Some(first) if duration_ms > 0.0 => *first,
+        _ => return Ok(heatmap),
+    };
+    let offset = (first / frametime_bin_ms).floor();
+    let last = percentile(&sorted, RANGE_PERCENTILE);
+    let rows = (last / frametime_bin_ms).floor() - offset + 1.0;
+    if rows * columns as f64 > MAX_CELLS as f64 {
+        bail!("{} columns of {} ms rows is over {} cells", columns, frametime_bin_ms, MAX_CELLS);
+    }
+    let rows = rows as usize;
+    heatmap.frametime_start_ms = offset * frametime_bin_ms;
+    heatmap.counts = vec![vec![0; rows]; columns];
+    let mut elapsed_ms = 0.0;
+    for frametime in frametimes {
+        let column = ((elapsed_ms / heatmap.time_bin_ms) as usize).min(columns - 1);
+        let row = ((frametime / frametime_bin_ms).floor() - offset) as usize;
+        heatmap.counts[column][row.min(rows - 1)] += 1;
+        elapsed_ms += frametime;
+    }
+    Ok(heatmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() -> Result<()> {
+        let frametimes = [16.2, 16.9, 17.4, 33.1, f64::NAN];
+        let histogram = histogram(&frametimes, 5.0)?;
+        assert_eq!(histogram.frames, 4);
+        let counts: Vec<_> = histogram.bins.iter().map(|bin| (bin.start_ms, bin.count)).collect();
+        assert_eq!(counts, vec![(15.0, 3), (20.0, 0), (25.0, 0), (30.0, 1)]);
+        assert!(super::histogram(&frametimes, 0.0).is_err());
+        assert!(super::histogram(&frametimes, 0.001).is_err());
+        assert!(super::histogram(&[], 1.0)?.bins.is_empty());
+
+        // A loading hitch past the 99.9th percentile doesn't need thousands of empty bins
+        let mut frametimes = vec![16.0; 2000];
+        frametimes.push(5000.0);
+        let histogram = super::histogram(&frametimes, 1.0)?;
+        assert_eq!(histogram.bins, vec![Bin { start_ms: 16.0, count: 2000 }]);
+        assert_eq!(histogram.overflow, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cdf_and_percentiles() -> Result<()> {
+        let frametimes: Vec<f64> = (1..=100).map(f64::from).collect();
+        let cdf = cdf(&frametimes, 3)?;
+        let points: Vec<_> = cdf.iter().map(|p| (p.frametime_ms, p.fraction)).collect();
+        assert_eq!(points, vec![(1.0, 0.01), (50.5, 0.5), (100.0, 1.0)]);
+
+        let curve = percentile_curve(&frametimes, 5)?;
+        let percentiles: Vec<_> = curve.iter().map(|p| p.percentile).collect();
+        assert_eq!(percentiles, vec![0.0, 25.0, 50.0, 75.0, 99.0, 99.5, 99.9, 99.99, 100.0]);
+        assert_eq!(curve[2].frametime_ms, 50.0);
+        assert_eq!(curve.last().map(|p| p.frametime_ms), Some(100.0));
+        assert!(percentile_curve(&frametimes, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_heatmap() -> Result<()> {
+        // Alternating 10 and 20 ms frames, averaging 15 ms on a line chart
+        let mut frametimes: Vec<f64> =
+            (0..2000).map(|i| if i % 2 == 0 { 10.0 } else { 20.0 }).collect();
+        frametimes.push(500.0);
+        let heatmap = heatmap(&frametimes, 2, 5.0)?;
+        assert_eq!(heatmap.frames, 2001);
+        assert_eq!(heatmap.time_bin_ms, 15250.0);
+        assert_eq!(heatmap.frametime_start_ms, 10.0);
+        // The hitch is past the 99.9th percentile, so it lands in the last row
+        assert_eq!(heatmap.counts, vec![vec![509, 0, 508], vec![491, 0, 493]]);
+        assert!(super::heatmap(&frametimes, 0, 5.0).is_err());
+        assert!(super::heatmap(&[], 10, 1.0)?.counts.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::capture::focus::{self, Focus};
+use crate::capture::{Gap, Sample};
+
+// Stretches the game wasn't in front, from the last sample it was to the first one it is again.
+// Recorded as gaps, the FPS stats leave them out like any other.
+pub fn losses(samples: &[Sample]) -> Vec<Gap> {
+    let mut losses = vec![];
+    let mut start_ms: Option<u64> = None;
+    let mut last_focused_ms = samples.first().map(|sample| sample.elapsed_ms);
+    for sample in samples {
+        let focus = match sample.metrics.get(focus::METRIC) {
+            Some(&value) => Focus::from_value(value),
+            None => continue,
+        };
+        match (focus, start_ms) {
+            (Focus::Foreground, Some(start)) => {
+                let gap = Gap { start_ms: start, end_ms: sample.elapsed_ms, reason: reason() };
+                losses.push(gap);
+                start_ms = None;
+            }
+            (Focus::Foreground, None) => (),
+            (_, None) => start_ms = last_focused_ms,
+            (_, Some(_)) => (),
+        }
+        if focus == Focus::Foreground {
+            last_focused_ms = Some(sample.elapsed_ms);
+        }
+    }
+    if let (Some(start_ms), Some(last)) = (start_ms, samples.last()) {
+        losses.push(Gap { start_ms, end_ms: last.elapsed_ms, reason: reason() });
+    }
+    losses.retain(|gap| gap.duration_ms() > 0);
+    losses
+}
+
+pub fn lost_ms(gaps: &[Gap]) -> u64 {
+    gaps.iter().filter(|gap| gap.reason == focus::GAP_REASON).map(Gap::duration_ms).sum()
+}
+
+fn reason() -> String {
+    focus::GAP_REASON.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_losses() {
+        let sample = |second: u64, focus: Focus| Sample {
+            elapsed_ms: second * 1000,
+            metrics: vec![(focus::METRIC.to_string(), focus.value())].into_iter().collect(),
+        };
+        let samples = vec![
+            sample(1, Focus::Foreground),
+            sample(2, Focus::Background),
+            sample(3, Focus::Occluded),
+            sample(4, Focus::Foreground),
+            sample(5, Focus::Foreground),
+            sample(6, Focus::Background),
+        ];
+        let losses = losses(&samples);
+        assert_eq!(losses.len(), 2);
+        assert_eq!((losses[0].start_ms, losses[0].end_ms), (1000, 4000));
+        assert_eq!((losses[1].start_ms, losses[1].end_ms), (5000, 6000));
+        assert_eq!(losses[0].reason, "focus");
+        assert_eq!(lost_ms(&losses), 4000);
+    }
+}
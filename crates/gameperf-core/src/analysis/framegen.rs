@@ -0,0 +1,61 @@
+// Frame generation (DLSS 3, FSR 3, AFMF) inserts a generated frame after each rendered one.
+// PresentMon 2.x tags them in `FrameType`, otherwise the cadence gives them away: the
+// generated frame is presented right after the rendered one, frame times alternate short/long.
+
+const MIN_FRAMES: usize = 60;
+// The short frame of a pair takes less than this share of the pair
+const SHORT_RATIO: f64 = 0.35;
+// Share of pairs that need the short/long pattern
+const MIN_ALTERNATING: f64 = 0.8;
+
+// `Application` frames are rendered, `Repeated` ones are the same frame presented again
+pub fn is_generated_type(frame_type: &str) -> bool {
+    !matches!(frame_type, "" | "NotSet" | "Unspecified" | "Application" | "Repeated")
+}
+
+// Per frame generated flags, None when the cadence doesn't look like frame generation
+pub fn detect(frametimes: &[f64]) -> Option<Vec<bool>> {
+    if frametimes.len() < MIN_FRAMES {
+        return None;
+    }
+    // The generated frame is either the odd or the even one, for the whole capture
+    for offset in 0..2 {
+        let pairs = frametimes[offset..].chunks_exact(2);
+        let total = pairs.len();
+        let alternating = pairs.filter(|pair| pair[0] < (pair[0] + pair[1]) * SHORT_RATIO).count();
+        if alternating as f64 >= total as f64 * MIN_ALTERNATING {
+            let generated = (0..frametimes.len()).map(|i| i >= offset && (i - offset) % 2 == 0);
+            return Some(generated.collect());
+        }
+    }
+    None
+}
+
+// Frames the game actually rendered per second, None without frame generation
+pub fn rendered_fps(frametimes: &[f64], generated: &[bool]) -> Option<f64> {
+    if generated.len() != frametimes.len() || !generated.contains(&true) {
+        return None;
+    }
+    let rendered = generated.iter().filter(|&&generated| !generated).count();
+    let total_ms = frametimes.iter().sum::<f64>();
+    Some(rendered as f64 * 1000.0 / total_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let native = vec![16.0; 120];
+        assert_eq!(detect(&native), None);
+
+        let generated: Vec<f64> = (0..120).map(|i| if i % 2 == 1 { 2.0 } else { 14.0 }).collect();
+        let flags = detect(&generated).unwrap();
+        assert!(!flags[0] && flags[1] && !flags[2]);
+        assert_eq!(rendered_fps(&generated, &flags), Some(62.5));
+
+        assert!(is_generated_type("AMD_AFMF"));
+        assert!(!is_generated_type("Application"));
+    }
+}
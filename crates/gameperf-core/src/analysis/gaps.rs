@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+use crate::capture::focus;
+use crate::capture::{Gap, Sample};
+
+// What stats make of the time without samples
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapPolicy {
+    // Left out of the duration, and the samples ending a gap out of the metrics
+    Exclude,
+    // Counted, with samples filled in on a straight line between both ends. Focus losses are
+    // excluded all the same, the game wasn't played.
+    Interpolate,
+}
+
+impl Default for GapPolicy {
+    fn default() -> Self {
+        GapPolicy::Exclude
+    }
+}
+
+// Gaps overlap, e.g. a focus loss over a stall, the time is counted once
+pub fn total_ms(gaps: &[Gap]) -> u64 {
+    merged_ms(gaps.iter())
+}
+
+// Left out of the duration under `policy`
+pub fn excluded_ms(gaps: &[Gap], policy: GapPolicy) -> u64 {
+    match policy {
+        GapPolicy::Exclude => total_ms(gaps),
+        GapPolicy::Interpolate => merged_ms(gaps.iter().filter(|gap| is_focus(gap))),
+    }
+}
+
+fn merged_ms<'a>(gaps: impl Iterator<Item = &'a Gap>) -> u64 {
+    let mut spans: Vec<(u64, u64)> = gaps.map(|gap| (gap.start_ms, gap.end_ms)).collect();
+    spans.sort_unstable();
+    let mut total = 0;
+    let mut covered_to = 0;
+    for (start_ms, end_ms) in spans {
+        let start_ms = start_ms.max(covered_to);
+        if end_ms > start_ms {
+            total += end_ms - start_ms;
+            covered_to = end_ms;
+        }
+    }
+    total
+}
+
+fn is_focus(gap: &Gap) -> bool {
+    gap.reason == focus::GAP_REASON
+}
+
+// A sample at the end of a gap stands for all of it
+fn ends_gap(gaps: &[Gap], elapsed_ms: u64) -> bool {
+    gaps.iter().any(|gap| elapsed_ms > gap.start_ms && elapsed_ms <= gap.end_ms)
+}
+
+// The samples metrics are summarized over
+pub fn apply(samples: &[Sample], gaps: &[Gap], policy: GapPolicy) -> Vec<Sample> {
+    if gaps.is_empty() {
+        return samples.to_vec();
+    }
+    match policy {
+        GapPolicy::Exclude => {
+            samples.iter().filter(|sample| !ends_gap(gaps, sample.elapsed_ms)).cloned().collect()
+        }
+        GapPolicy::Interpolate => {
+            let (focus, others): (Vec<Gap>, Vec<Gap>) = gaps.iter().cloned().partition(is_focus);
+            let kept: Vec<Sample> = samples
+                .iter()
+                .filter(|sample| !ends_gap(&focus, sample.elapsed_ms))
+                .cloned()
+                .collect();
+            interpolate(&kept, &others)
+        }
+    }
+}
+
+fn interpolate(samples: &[Sample], gaps: &[Gap]) -> Vec<Sample> {
+    // Filled in at the usual sampling interval
+    let mut spacings: Vec<u64> = samples
+        .windows(2)
+        .filter(|pair| !ends_gap(gaps, pair[1].elapsed_ms))
+        .map(|pair| pair[1].elapsed_ms - pair[0].elapsed_ms)
+        .filter(|&spacing| spacing > 0)
+        .collect();
+    spacings.sort_unstable();
+    let step = match spacings.get(spacings.len() / 2) {
+        Some(&step) => step,
+        None => return samples.to_vec(),
+    };
+
+    let mut filled = vec![];
+    for pair in samples.windows(2) {
+        let (from, to) = (&pair[0], &pair[1]);
+        filled.push(from.clone());
+        if !ends_gap(gaps, to.elapsed_ms) {
+            continue;
+        }
+        let span = (to.elapsed_ms - from.elapsed_ms) as f64;
+        let mut elapsed_ms = from.elapsed_ms + step;
+        while elapsed_ms < to.elapsed_ms {
+            let t = (elapsed_ms - from.elapsed_ms) as f64 / span;
+            let metrics = from
+                .metrics
+                .iter()
+                .filter_map(|(name, &a)| {
+                    to.metrics.get(name).map(|&b| (name.clone(), a + (b - a) * t))
+                })
+                .collect();
+            filled.push(Sample { elapsed_ms, metrics });
+            elapsed_ms += step;
+        }
+    }
+    filled.extend(samples.last().cloned());
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gaps() {
+        let sample = |elapsed_ms, fps| {
+            let metrics = [("fps".to_string(), fps)].into_iter().collect();
+            Sample { elapsed_ms, metrics }
+        };
+        let samples =
+            vec![sample(0, 60.0), sample(1000, 60.0), sample(4000, 30.0), sample(5000, 30.0)];
+        let gaps = vec![Gap { start_ms: 1000, end_ms: 4000, reason: "stall".into() }];
+        assert_eq!(total_ms(&gaps), 3000);
+
+        let excluded = apply(&samples, &gaps, GapPolicy::Exclude);
+        let elapsed: Vec<u64> = excluded.iter().map(|sample| sample.elapsed_ms).collect();
+        assert_eq!(elapsed, vec![0, 1000, 5000]);
+
+        let filled = apply(&samples, &gaps, GapPolicy::Interpolate);
+        let fps: Vec<f64> = filled.iter().map(|sample| sample.metrics["fps"]).collect();
+        assert_eq!(fps, vec![60.0, 60.0, 50.0, 40.0, 30.0, 30.0]);
+        assert_eq!(filled[2].elapsed_ms, 2000);
+
+        // Focus losses aren't filled in
+        let gaps = vec![Gap { start_ms: 1000, end_ms: 4000, reason: focus::GAP_REASON.into() }];
+        let filled = apply(&samples, &gaps, GapPolicy::Interpolate);
+        let elapsed: Vec<u64> = filled.iter().map(|sample| sample.elapsed_ms).collect();
+        assert_eq!(elapsed, vec![0, 1000, 5000]);
+        assert_eq!(excluded_ms(&gaps, GapPolicy::Interpolate), 3000);
+    }
+
+    #[test]
+    fn test_overlapping_gaps() {
+        let gap = |start_ms, end_ms, reason: &str| Gap { start_ms, end_ms, reason: reason.into() };
+        // Sleep recorded twice and a focus loss over both, then a separate stall
+        let gaps = vec![
+            gap(1000, 4000, "sleep"),
+            gap(1500, 4000, "sleep"),
+            gap(500, 3000, focus::GAP_REASON),
+            gap(6000, 7000, "stall"),
+            gap(6500, 6500, "stall"),
+        ];
+        assert_eq!(total_ms(&gaps), 3500 + 1000);
+        assert_eq!(excluded_ms(&gaps, GapPolicy::Exclude), 4500);
+        assert_eq!(excluded_ms(&gaps, GapPolicy::Interpolate), 2500);
+        assert_eq!(total_ms(&[]), 0);
+    }
+}
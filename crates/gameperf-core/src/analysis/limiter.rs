@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use super::percentile;
+
+const MIN_FRAMES: usize = 120;
+// Share of frames that have to sit on the cap
+const MIN_CAPPED: f64 = 0.7;
+// Display rates tried when the capture doesn't know its own
+const COMMON_RATES: &[f64] = &[60.0, 75.0, 90.0, 120.0, 144.0, 165.0, 240.0];
+// Frame pacing tighter than this (ms std dev) points at an external limiter such as RTSS
+const EXTERNAL_JITTER: f64 = 0.2;
+// Share of frames that have to land on whole refresh intervals to call a run V-Synced
+const MIN_QUANTIZED: f64 = 0.9;
+// Refresh intervals a frame can wait for, at 60 Hz down to 15 FPS
+const MAX_INTERVALS: f64 = 4.0;
+// Share of frames a refresh multiple needs to count, a steady cap sits on a single one
+const MIN_MULTIPLE: f64 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimiterKind {
+    // The display rate or an integer fraction of it
+    VSync,
+    External,
+    InGame,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Limiter {
+    pub kind: LimiterKind,
+    pub fps: f64,
+    // Share of frames at the cap
+    pub capped: f64,
+}
+
+// A capped run piles its frame times up at the cap with next to nothing faster
+pub fn detect(frametimes: &[f64], refresh_rate: Option<f64>) -> Option<Limiter> {
+    if frametimes.len() < MIN_FRAMES {
+        return None;
+    }
+    let mut sorted = frametimes.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = percentile(&sorted, 50.0);
+    if median <= 0.0 || percentile(&sorted, 5.0) < median * 0.9 {
+        return None;
+    }
+
+    let tolerance = (median * 0.03).max(0.5);
+    let at_cap: Vec<f64> =
+        frametimes.iter().copied().filter(|ft| (ft - median).abs() <= tolerance).collect();
+    let capped = at_cap.len() as f64 / frametimes.len() as f64;
+    if capped < MIN_CAPPED {
+        return None;
+    }
+
+    let mean = at_cap.iter().sum::<f64>() / at_cap.len() as f64;
+    let jitter =
+        (at_cap.iter().map(|ft| (ft - mean).powi(2)).sum::<f64>() / at_cap.len() as f64).sqrt();
+    let fps = 1000.0 / mean;
+
+    let rates = refresh_rate.map_or_else(|| COMMON_RATES.to_vec(), |rate| vec![rate]);
+    let vsync = rates.iter().any(|rate| (1..=4).any(|div| (fps - rate / div as f64).abs() < 1.0));
+    let kind = if vsync {
+        LimiterKind::VSync
+    } else if jitter < EXTERNAL_JITTER {
+        LimiterKind::External
+    } else {
+        LimiterKind::InGame
+    };
+    Some(Limiter { kind, fps, capped })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quantization {
+    pub refresh_rate: f64,
+    // Share of frames on whole refresh intervals
+    pub quantized: f64,
+}
+
+// V-Sync holds every frame to a whole number of refresh intervals, a game missing 60 FPS drops
+// straight to 30 instead of landing in between. Unlike `detect`, this also catches runs that
+// don't sit on one cap, and it needs frames on at least two multiples: a steady cap is left to
+// `limiter`, which is what `detect` found for the run. External and in-game limiters win.
+pub fn quantization(
+    frametimes: &[f64],
+    refresh_rate: Option<f64>,
+    limiter: Option<&Limiter>,
+) -> Option<Quantization> {
+    if frametimes.len() < MIN_FRAMES {
+        return None;
+    }
+    if limiter.map_or(false, |limiter| limiter.kind != LimiterKind::VSync) {
+        return None;
+    }
+    let rates = refresh_rate.map_or_else(|| COMMON_RATES.to_vec(), |rate| vec![rate]);
+    let min_multiple = (frametimes.len() as f64 * MIN_MULTIPLE).ceil() as usize;
+    rates
+        .into_iter()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+        .filter_map(|refresh_rate| {
+            let interval = 1000.0 / refresh_rate;
+            let tolerance = (interval * 0.05).max(0.5);
+            // Frames per multiple of the interval, 1 to `MAX_INTERVALS`
+            let mut multiples = [0; MAX_INTERVALS as usize];
+            for &ft in frametimes {
+                let intervals = (ft / interval).round();
+                if (1.0..=MAX_INTERVALS).contains(&intervals)
+                    && (ft - intervals * interval).abs() <= tolerance
+                {
+                    multiples[intervals as usize - 1] += 1;
+                }
+            }
+            if multiples.iter().filter(|&&count| count >= min_multiple).count() < 2 {
+                return None;
+            }
+            let on_interval = multiples.iter().sum::<usize>();
+            Some(Quantization {
+                refresh_rate,
+                quantized: on_interval as f64 / frametimes.len() as f64,
+            })
+        })
+        .filter(|quantization| quantization.quantized >= MIN_QUANTIZED)
+        // A 60 FPS run fits 120 Hz as well, the lowest rate explains it
+        .fold(None, |best: Option<Quantization>, quantization| match best {
+            Some(best) if best.quantized >= quantization.quantized => Some(best),
+            _ => Some(quantization),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let uncapped: Vec<f64> = (0..200).map(|i| 8.0 + (i % 10) as f64).collect();
+        assert_eq!(detect(&uncapped, None), None);
+
+        let vsync: Vec<f64> = (0..200).map(|i| if i % 20 == 0 { 33.3 } else { 16.67 }).collect();
+        let limiter = detect(&vsync, Some(60.0)).unwrap();
+        assert_eq!(limiter.kind, LimiterKind::VSync);
+        assert_eq!(limiter.capped, 0.95);
+
+        let external: Vec<f64> = (0..200).map(|i| 20.0 + (i % 2) as f64 * 0.1).collect();
+        assert_eq!(detect(&external, Some(60.0)).unwrap().kind, LimiterKind::External);
+
+        let in_game: Vec<f64> = (0..200).map(|i| 19.5 + (i % 3) as f64 * 0.5).collect();
+        assert_eq!(detect(&in_game, Some(60.0)).unwrap().kind, LimiterKind::InGame);
+
+        // Missing 60 Hz now and then, every late frame waits for the next refresh
+        let quantized: Vec<f64> =
+            (0..200).map(|i| if i % 3 == 0 { 33.4 } else { 16.6 + (i % 2) as f64 * 0.2 }).collect();
+        assert_eq!(detect(&quantized, Some(60.0)), None);
+        let vsync = quantization(&quantized, Some(60.0), None).unwrap();
+        assert_eq!(vsync.refresh_rate, 60.0);
+        assert_eq!(vsync.quantized, 1.0);
+        assert_eq!(quantization(&quantized, None, None).unwrap().refresh_rate, 60.0);
+        assert_eq!(quantization(&uncapped, None, None), None);
+        assert_eq!(quantization(&in_game, Some(144.0), None), None);
+
+        // A steady cap sits on one multiple, and a limiter that isn't V-Sync explains the run
+        let steady = vec![16.67; 200];
+        assert_eq!(quantization(&steady, Some(60.0), None), None);
+        let external = Limiter { kind: LimiterKind::External, fps: 60.0, capped: 0.9 };
+        assert_eq!(quantization(&quantized, Some(60.0), Some(&external)), None);
+    }
+}
@@ -0,0 +1,172 @@
+pub mod battery;
+pub mod bound;
+pub mod correlation;
+pub mod distribution;
+pub mod focus;
+pub mod framegen;
+pub mod gaps;
+pub mod limiter;
+pub mod normalize;
+pub mod segment;
+pub mod smoothing;
+pub mod stutter;
+pub mod summary;
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::capture::Recording;
+use crate::settings;
+
+use bound::EngineBound;
+use gaps::GapPolicy;
+use limiter::Limiter;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricSummary {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub duration_secs: f64,
+    pub frames: usize,
+    // Displayed frames, generated ones included
+    pub avg_fps: f64,
+    // Frames the game rendered, None without frame generation
+    pub rendered_fps: Option<f64>,
+    pub min_fps: f64,
+    pub max_fps: f64,
+    pub p1_low: f64,
+    pub p01_low: f64,
+    pub avg_frametime: f64,
+    pub p99_frametime: f64,
+    // Percentage of frames waiting on the GPU, None without GPU timings
+    pub gpu_bound_pct: Option<f64>,
+    // Game thread, render thread or GPU, None without engine timings
+    #[serde(default)]
+    pub engine_bound: Option<EngineBound>,
+    // Frame rate cap the run was held at, runs with different caps don't compare
+    pub limiter: Option<Limiter>,
+    // Stretches without samples, in the duration or not depending on the `GapPolicy`
+    #[serde(default)]
+    pub gaps: usize,
+    #[serde(default)]
+    pub gap_secs: f64,
+    // Part of the gaps the game wasn't in front for, see `focus`
+    #[serde(default)]
+    pub focus_loss_secs: f64,
+    pub metrics: BTreeMap<String, MetricSummary>,
+}
+
+impl Stats {
+    // With the configured `GapPolicy`
+    pub fn compute(recording: &Recording) -> Stats {
+        let policy = settings::get().gap_policy;
+        Self::compute_with(recording, policy)
+    }
+
+    pub fn compute_with(recording: &Recording, policy: GapPolicy) -> Stats {
+        // Compacted sessions have no frames left
+        if let Some(summary) = &recording.summary {
+            return summary.clone();
+        }
+        let gap_ms = gaps::total_ms(&recording.gaps);
+        let duration_ms =
+            recording.duration_ms.saturating_sub(gaps::excluded_ms(&recording.gaps, policy));
+        let mut stats = Stats {
+            duration_secs: duration_ms as f64 / 1000.0,
+            gaps: recording.gaps.len(),
+            gap_secs: gap_ms as f64 / 1000.0,
+            focus_loss_secs: focus::lost_ms(&recording.gaps) as f64 / 1000.0,
+            ..Default::default()
+        };
+
+        let frametimes = &recording.frametimes;
+        if !frametimes.is_empty() {
+            let mut sorted = frametimes.clone();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+
+            stats.frames = frametimes.len();
+            stats.avg_frametime = frametimes.iter().sum::<f64>() / frametimes.len() as f64;
+            stats.avg_fps = 1000.0 / stats.avg_frametime;
+            stats.min_fps = 1000.0 / sorted[sorted.len() - 1];
+            stats.max_fps = 1000.0 / sorted[0];
+            stats.p99_frametime = percentile(&sorted, 99.0);
+            stats.p1_low = 1000.0 / stats.p99_frametime;
+            stats.p01_low = 1000.0 / percentile(&sorted, 99.9);
+            stats.rendered_fps = framegen::rendered_fps(frametimes, &recording.generated);
+            stats.limiter = limiter::detect(frametimes, recording.refresh_rate);
+            stats.gpu_bound_pct = bound::gpu_bound_pct(frametimes, &recording.gpu_busy);
+            stats.engine_bound = recording.engine.as_ref().and_then(bound::engine_bound);
+        }
+
+        let samples = gaps::apply(&recording.samples, &recording.gaps, policy);
+        for sample in &samples {
+            for (name, &value) in &sample.metrics {
+                let summary = stats.metrics.entry(name.clone()).or_insert(MetricSummary {
+                    min: value,
+                    max: value,
+                    avg: 0.0,
+                });
+                summary.min = summary.min.min(value);
+                summary.max = summary.max.max(value);
+                // Accumulate the sum, averaged below
+                summary.avg += value;
+            }
+        }
+        for (name, summary) in stats.metrics.iter_mut() {
+            let count = samples.iter().filter(|s| s.metrics.contains_key(name)).count();
+            summary.avg /= count.max(1) as f64;
+        }
+
+        stats
+    }
+
+    // Looks up a stat by name: `p1_low`, `avg_fps` or `<metric>.<min|max|avg>`
+    pub fn get(&self, key: &str) -> Option<f64> {
+        let value = match key {
+            "duration_secs" => self.duration_secs,
+            "frames" => self.frames as f64,
+            "avg_fps" => self.avg_fps,
+            "rendered_fps" => self.rendered_fps?,
+            "min_fps" => self.min_fps,
+            "max_fps" => self.max_fps,
+            "p1_low" => self.p1_low,
+            "p01_low" => self.p01_low,
+            "avg_frametime" => self.avg_frametime,
+            "p99_frametime" => self.p99_frametime,
+            "gpu_bound_pct" => self.gpu_bound_pct?,
+            "limiter_fps" => self.limiter.as_ref()?.fps,
+            "game_thread_bound_pct" => self.engine_bound.as_ref()?.game_thread_pct,
+            "render_thread_bound_pct" => self.engine_bound.as_ref()?.render_thread_pct,
+            "engine_gpu_bound_pct" => self.engine_bound.as_ref()?.gpu_pct,
+            "gaps" => self.gaps as f64,
+            "gap_secs" => self.gap_secs,
+            "focus_loss_secs" => self.focus_loss_secs,
+            _ => {
+                let (metric, stat) = key.rsplit_once('.')?;
+                let summary = self.metrics.get(metric)?;
+                match stat {
+                    "min" => summary.min,
+                    "max" => summary.max,
+                    "avg" => summary.avg,
+                    _ => return None,
+                }
+            }
+        };
+        Some(value)
+    }
+}
+
+// Nearest-rank percentile over an ascending slice
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Stats;
+use crate::capture::system::{self, DISPLAY_SIZE};
+use crate::capture::{resolution, Recording};
+
+// Divided by the basis, frame time stats don't scale that way and are left out
+const FPS_STATS: &[&str] = &["avg_fps", "p1_low", "p01_low", "min_fps", "max_fps"];
+
+// Sessions from a desktop, a handheld and a phone only compare once FPS is put against what the
+// device spent on it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    None,
+    PerWatt,
+    // Per megapixel rendered, of the screen when the run's render size is unknown or changed
+    PerMegapixel,
+    // Per GHz of the GPU clock
+    PerClock,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization::None
+    }
+}
+
+// What a session's FPS is divided by, measured from its samples and system snapshot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Basis {
+    pub watts: Option<f64>,
+    pub megapixels: Option<f64>,
+    pub clock_mhz: Option<f64>,
+}
+
+impl Basis {
+    // Battery drain on handhelds, CPU and GPU package power on desktops (MangoHud). The clock is
+    // the GPU's from the sysfs counters or MangoHud.
+    pub fn measure(recording: &Recording, stats: &Stats) -> Basis {
+        let power: Vec<f64> =
+            ["power.cpu.avg", "power.gpu.avg"].iter().filter_map(|key| stats.get(key)).collect();
+        let watts = stats
+            .get("battery.watts.avg")
+            .or_else(|| Some(power.iter().sum()).filter(|_| !power.is_empty()));
+        let rendered = match resolution::sizes(recording).as_slice() {
+            [size] => system::megapixels(size),
+            _ => None,
+        };
+        let megapixels = rendered.or_else(|| {
+            let snapshot = recording.system.as_ref()?;
+            system::megapixels(snapshot.device.get(DISPLAY_SIZE)?)
+        });
+        let clock_mhz = stats.get("gpu.freq_mhz.avg").or_else(|| stats.get("gpu.clock.avg"));
+        Basis { watts, megapixels, clock_mhz }
+    }
+
+    // Values given by the user win, e.g. the render resolution or a wall meter's reading
+    pub fn or(self, measured: Basis) -> Basis {
+        Basis {
+            watts: self.watts.or(measured.watts),
+            megapixels: self.megapixels.or(measured.megapixels),
+            clock_mhz: self.clock_mhz.or(measured.clock_mhz),
+        }
+    }
+
+    fn divisor(&self, normalization: Normalization) -> Option<f64> {
+        let divisor = match normalization {
+            Normalization::None => 1.0,
+            Normalization::PerWatt => self.watts?,
+            Normalization::PerMegapixel => self.megapixels?,
+            Normalization::PerClock => self.clock_mhz? / 1000.0,
+        };
+        Some(divisor).filter(|divisor| divisor.is_finite() && *divisor > 0.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Normalized {
+    pub basis: Basis,
+    // None without the basis the normalization needs
+    pub stats: BTreeMap<String, Option<f64>>,
+    // Against the first session, None for the first one
+    pub vs_first_pct: BTreeMap<String, Option<f64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub sessions: Vec<Normalized>,
+    // Sizes each session rendered at, see `resolution::sizes`
+    pub resolutions: Vec<Vec<String>>,
+    // Set when the sessions didn't all render at one and the same size
+    pub resolution_mismatch: bool,
+}
+
+impl Comparison {
+    pub fn new(sessions: Vec<Normalized>, resolutions: Vec<Vec<String>>) -> Self {
+        let resolution_mismatch = resolution::mismatch(&resolutions);
+        Comparison { sessions, resolutions, resolution_mismatch }
+    }
+}
+
+// One per session, in the given order
+pub fn normalize(sessions: &[(Stats, Basis)], normalization: Normalization) -> Vec<Normalized> {
+    let values: Vec<BTreeMap<String, Option<f64>>> = sessions
+        .iter()
+        .map(|(stats, basis)| {
+            let divisor = basis.divisor(normalization);
+            FPS_STATS
+                .iter()
+                .map(|key| (key.to_string(), stats.get(key).zip(divisor).map(|(v, d)| v / d)))
+                .collect()
+        })
+        .collect();
+    sessions
+        .iter()
+        .zip(&values)
+        .enumerate()
+        .map(|(index, ((_, basis), stats))| {
+            let vs_first_pct = stats
+                .iter()
+                .map(|(key, value)| {
+                    let first = values[0][key].filter(|first| *first != 0.0 && index > 0);
+                    let pct = value.zip(first).map(|(value, first)| (value / first - 1.0) * 100.0);
+                    (key.clone(), pct)
+                })
+                .collect();
+            Normalized { basis: *basis, stats: stats.clone(), vs_first_pct }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let desktop = Stats { avg_fps: 120.0, p1_low: 90.0, ..Default::default() };
+        let handheld = Stats { avg_fps: 45.0, p1_low: 30.0, ..Default::default() };
+        let sessions = [
+            (desktop, Basis { watts: Some(300.0), megapixels: Some(8.29), clock_mhz: None }),
+            (handheld, Basis { watts: Some(15.0), megapixels: Some(1.024), clock_mhz: None }),
+        ];
+
+        let per_watt = normalize(&sessions, Normalization::PerWatt);
+        assert_eq!(per_watt[0].stats["avg_fps"], Some(0.4));
+        assert_eq!(per_watt[1].stats["avg_fps"], Some(3.0));
+        assert_eq!(per_watt[0].vs_first_pct["avg_fps"], None);
+        assert!((per_watt[1].vs_first_pct["avg_fps"].unwrap() - 650.0).abs() < 1e-9);
+
+        let per_clock = normalize(&sessions, Normalization::PerClock);
+        assert_eq!(per_clock[1].stats["avg_fps"], None);
+        let raw = normalize(&sessions, Normalization::None);
+        assert_eq!(raw[1].stats["p1_low"], Some(30.0));
+        let sizes = vec![vec!["2560x1440".to_string()], vec!["1280x800".to_string()]];
+        assert!(Comparison::new(raw, sizes).resolution_mismatch);
+
+        let given = Basis { megapixels: Some(0.9216), ..Default::default() };
+        assert_eq!(given.or(sessions[1].1).watts, Some(15.0));
+        assert_eq!(given.or(sessions[1].1).megapixels, Some(0.9216));
+    }
+}
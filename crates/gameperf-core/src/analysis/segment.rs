@@ -0,0 +1,98 @@
+use serde::Serialize;
+
+use crate::capture::Recording;
+use crate::edit;
+
+use super::Stats;
+
+// From one marker to the next (or the end). Whatever comes before the first marker, usually
+// loading, isn't a segment.
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub label: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub stats: Stats,
+}
+
+// Stats of the segments sharing a label, one entry per compared session
+#[derive(Debug, Clone, Serialize)]
+pub struct SegmentComparison {
+    pub label: String,
+    pub stats: Vec<Option<Stats>>,
+}
+
+pub fn segments(recording: &Recording) -> Vec<Segment> {
+    // Compacted sessions only have whole-session stats left
+    if recording.summary.is_some() {
+        return vec![];
+    }
+    let mut markers = recording.markers.clone();
+    markers.sort_by_key(|marker| marker.elapsed_ms);
+    let ends = markers.iter().skip(1).map(|marker| marker.elapsed_ms);
+    markers
+        .iter()
+        .zip(ends.chain(std::iter::once(recording.duration_ms)))
+        .filter(|(marker, end)| marker.elapsed_ms < *end)
+        .map(|(marker, end)| Segment {
+            label: marker.label.clone(),
+            start_ms: marker.elapsed_ms,
+            end_ms: end,
+            stats: Stats::compute(&edit::slice(recording, marker.elapsed_ms, end)),
+        })
+        .collect()
+}
+
+// Labels in the order they first appear. A label repeated within a session (e.g. two laps
+// through "city") compares its first occurrence.
+pub fn compare(recordings: &[Recording]) -> Vec<SegmentComparison> {
+    let segments: Vec<Vec<Segment>> = recordings.iter().map(segments).collect();
+    let mut comparisons: Vec<SegmentComparison> = vec![];
+    for segment in segments.iter().flatten() {
+        if comparisons.iter().any(|c| c.label == segment.label) {
+            continue;
+        }
+        let stats = segments
+            .iter()
+            .map(|s| s.iter().find(|other| other.label == segment.label).map(|s| s.stats.clone()))
+            .collect();
+        comparisons.push(SegmentComparison { label: segment.label.clone(), stats });
+    }
+    comparisons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Marker;
+
+    #[test]
+    fn test_segments() {
+        let marker = |elapsed_ms, label: &str| Marker { elapsed_ms, label: label.into() };
+        let mut frametimes = vec![10.0; 100];
+        frametimes.extend(vec![20.0; 100]);
+        let recording = Recording {
+            duration_ms: 3000,
+            frametimes,
+            markers: vec![marker(1000, "forest"), marker(500, "city")],
+            ..Default::default()
+        };
+
+        let segments = segments(&recording);
+        let labels: Vec<_> = segments.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(labels, vec!["city", "forest"]);
+        assert_eq!((segments[1].start_ms, segments[1].end_ms), (1000, 3000));
+        assert_eq!(segments[0].stats.avg_fps, 100.0);
+        assert_eq!(segments[1].stats.avg_fps, 50.0);
+
+        let other = Recording {
+            duration_ms: 1000,
+            markers: vec![marker(0, "forest")],
+            ..recording.clone()
+        };
+        let comparisons = compare(&[recording, other]);
+        assert_eq!(comparisons.len(), 2);
+        assert!(comparisons[0].stats[1].is_none());
+        assert!(comparisons[1].stats[1].is_some());
+    }
+}
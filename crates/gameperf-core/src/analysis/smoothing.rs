@@ -0,0 +1,170 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+// A chart is never wider than this, and long runs have millions of frames
+pub const MAX_POINTS: usize = 2000;
+// Frames a moving average or median looks back at most
+const MAX_WINDOW: usize = 1000;
+
+// Applied per frame before the series is thinned, the raw series is always served alongside
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Smoothing {
+    None,
+    // Mean of the last `window` frames
+    MovingAverage { window: usize },
+    // Each frame weighs `alpha`, everything before it the rest
+    Ewma { alpha: f64 },
+    // Median of the last `window` frames, single spikes disappear entirely
+    Median { window: usize },
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing::None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Point {
+    // End of the frames the point stands for
+    pub elapsed_ms: f64,
+    pub fps: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FpsSeries {
+    pub smoothing: Smoothing,
+    // Frames per point, 1 unless thinned
+    pub frames_per_point: usize,
+    pub raw: Vec<Point>,
+    pub smoothed: Vec<Point>,
+}
+
+impl Smoothing {
+    fn check(&self) -> Result<()> {
+        match *self {
+            Smoothing::MovingAverage { window } | Smoothing::Median { window }
+                if !(1..=MAX_WINDOW).contains(&window) =>
+            {
+                bail!("Smoothing window must be between 1 and {} frames", MAX_WINDOW)
+            }
+            Smoothing::Ewma { alpha } if !alpha.is_finite() || alpha <= 0.0 || alpha > 1.0 => {
+                bail!("Smoothing alpha must be above 0 and at most 1")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, values: &[f64]) -> Result<Vec<f64>> {
+        self.check()?;
+        Ok(match *self {
+            Smoothing::None => values.to_vec(),
+            Smoothing::MovingAverage { window } => {
+                let mut sum = 0.0;
+                let mut smoothed = Vec::with_capacity(values.len());
+                for (i, value) in values.iter().enumerate() {
+                    sum += value;
+                    if i >= window {
+                        sum -= values[i - window];
+                    }
+                    smoothed.push(sum / (i + 1).min(window) as f64);
+                }
+                smoothed
+            }
+            Smoothing::Ewma { alpha } => {
+                let mut last = values.first().copied().unwrap_or_default();
+                values
+                    .iter()
+                    .map(|value| {
+                        last = alpha * value + (1.0 - alpha) * last;
+                        last
+                    })
+                    .collect()
+            }
+            Smoothing::Median { window } => {
+                // Kept sorted as frames come in and drop out
+                let mut sorted: Vec<f64> = Vec::with_capacity(window);
+                let mut smoothed = Vec::with_capacity(values.len());
+                for (i, &value) in values.iter().enumerate() {
+                    if i >= window {
+                        let old = values[i - window];
+                        let index = sorted.partition_point(|&v| v < old);
+                        sorted.remove(index);
+                    }
+                    let index = sorted.partition_point(|&v| v < value);
+                    sorted.insert(index, value);
+                    let middle = sorted.len() / 2;
+                    smoothed.push(if sorted.len() % 2 == 0 {
+                        (sorted[middle - 1] + sorted[middle]) / 2.0
+                    } else {
+                        sorted[middle]
+                    });
+                }
+                smoothed
+            }
+        })
+    }
+}
+
+// FPS of every frame, smoothed, then both series thinned to at most `points` by averaging runs of
+// frames so they line up
+pub fn fps_series(frametimes: &[f64], smoothing: Smoothing, points: usize) -> Result<FpsSeries> {
+    if !(2..=MAX_POINTS).contains(&points) {
+        bail!("Points must be between 2 and {}", MAX_POINTS);
+    }
+    let frametimes: Vec<f64> =
+        frametimes.iter().copied().filter(|f| f.is_finite() && *f > 0.0).collect();
+    let fps: Vec<f64> = frametimes.iter().map(|frametime| 1000.0 / frametime).collect();
+    let smoothed = smoothing.apply(&fps)?;
+
+    let frames_per_point = ((frametimes.len() + points - 1) / points).max(1);
+    let mut series = FpsSeries { smoothing, frames_per_point, raw: vec![], smoothed: vec![] };
+    let mut elapsed_ms = 0.0;
+    for (chunk, values) in
+        frametimes.chunks(frames_per_point).zip(smoothed.chunks(frames_per_point))
+    {
+        let duration: f64 = chunk.iter().sum();
+        elapsed_ms += duration;
+        // Frames over their time, an average of per frame FPS would overweight short frames
+        let fps = chunk.len() as f64 * 1000.0 / duration;
+        let smoothed = values.iter().sum::<f64>() / values.len() as f64;
+        series.raw.push(Point { elapsed_ms, fps });
+        series.smoothed.push(Point { elapsed_ms, fps: smoothed });
+    }
+    Ok(series)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smoothing() -> Result<()> {
+        let values = [60.0, 60.0, 10.0, 60.0, 30.0];
+        assert_eq!(Smoothing::None.apply(&values)?, values.to_vec());
+        let average = Smoothing::MovingAverage { window: 2 }.apply(&values)?;
+        assert_eq!(average, vec![60.0, 60.0, 35.0, 35.0, 45.0]);
+        let ewma = Smoothing::Ewma { alpha: 0.5 }.apply(&values)?;
+        assert_eq!(ewma, vec![60.0, 60.0, 35.0, 47.5, 38.75]);
+        let median = Smoothing::Median { window: 3 }.apply(&values)?;
+        assert_eq!(median, vec![60.0, 60.0, 60.0, 60.0, 30.0]);
+        assert!(Smoothing::MovingAverage { window: 0 }.apply(&values).is_err());
+        assert!(Smoothing::Ewma { alpha: 1.5 }.apply(&values).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fps_series() -> Result<()> {
+        let mut frametimes = vec![10.0; 8];
+        frametimes[3] = 30.0;
+        let series = fps_series(&frametimes, Smoothing::Median { window: 3 }, 4)?;
+        assert_eq!(series.frames_per_point, 2);
+        let raw: Vec<_> = series.raw.iter().map(|p| (p.elapsed_ms, p.fps)).collect();
+        assert_eq!(raw, vec![(20.0, 100.0), (60.0, 50.0), (80.0, 100.0), (100.0, 100.0)]);
+        assert!(series.smoothed.iter().all(|p| p.fps == 100.0));
+        assert!(fps_series(&frametimes, Smoothing::None, 1).is_err());
+        assert!(fps_series(&[], Smoothing::None, 10)?.raw.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use super::percentile;
+
+// A frame this many times longer than the median of the frames before it
+pub const RATIO: f64 = 2.5;
+// Frames the median is taken over, and needed before anything counts (loading is choppy)
+const WINDOW: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stutter {
+    // Index into the frame times, and when the frame started
+    pub frame: usize,
+    pub elapsed_ms: u64,
+    pub frametime: f64,
+    // Over the median of the preceding frames
+    pub ratio: f64,
+}
+
+pub fn detect(frametimes: &[f64]) -> Vec<Stutter> {
+    let mut stutters = vec![];
+    let mut elapsed_ms = 0.0;
+    for (frame, &frametime) in frametimes.iter().enumerate() {
+        if frame >= WINDOW {
+            let mut window = frametimes[frame - WINDOW..frame].to_vec();
+            window.sort_by(|a, b| a.total_cmp(b));
+            let median = percentile(&window, 50.0);
+            if median > 0.0 && frametime >= RATIO * median {
+                let ratio = frametime / median;
+                stutters.push(Stutter { frame, elapsed_ms: elapsed_ms as u64, frametime, ratio });
+            }
+        }
+        elapsed_ms += frametime;
+    }
+    stutters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let mut frametimes = vec![16.7; 100];
+        frametimes[10] = 100.0;
+        frametimes[50] = 50.0;
+        frametimes[70] = 30.0;
+        let stutters = detect(&frametimes);
+        assert_eq!(stutters.len(), 1);
+        assert_eq!(stutters[0].frame, 50);
+        assert_eq!(stutters[0].elapsed_ms, 918);
+        assert!((stutters[0].ratio - 50.0 / 16.7).abs() < 1e-9);
+        assert!(detect(&[16.7; 10]).is_empty());
+    }
+}
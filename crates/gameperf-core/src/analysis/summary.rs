@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+
+use super::limiter;
+use super::stutter::{self, Stutter};
+use super::Stats;
+use crate::capture::{Recording, Sample};
+
+// Share of the run one side has to hold back to be called the bottleneck
+const BOUND_MIN_PCT: f64 = 50.0;
+// FPS of the first minute is what the device manages cold
+const BASELINE_MS: u64 = 60_000;
+// Throttled once FPS stays this far under the baseline while the device heats up this much
+const THROTTLE_DROP: f64 = 0.15;
+const HEAT_RISE: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Gpu,
+    Cpu,
+    GameThread,
+    RenderThread,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Finding {
+    Bound { side: Side, pct: f64 },
+    // A frame rate cap, nothing else held the run back while it's met
+    Capped { fps: f64 },
+    Throttling { after_ms: u64, fps_drop_pct: f64 },
+    // Single-frame hitches with normal frames around them, like shaders compiled on first use
+    ShaderStutters { count: usize },
+    // Frame times quantized to the refresh rate, the run doesn't show what the device can do
+    VSync { refresh_rate: f64, quantized_pct: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    // The findings in one line, e.g. "GPU-bound 82% of the run; thermal throttling after 14 min"
+    pub verdict: String,
+    pub findings: Vec<Finding>,
+}
+
+impl Finding {
+    pub fn text(&self) -> String {
+        match self {
+            Finding::Bound { side, pct } => {
+                let side = match side {
+                    Side::Gpu => "GPU",
+                    Side::Cpu => "CPU",
+                    Side::GameThread => "Game thread",
+                    Side::RenderThread => "Render thread",
+                };
+                format!("{}-bound {:.0}% of the run", side, pct)
+            }
+            Finding::Capped { fps } => format!("capped at {:.0} FPS", fps),
+            Finding::Throttling { after_ms, fps_drop_pct } => format!(
+                "thermal throttling after {} ({:.0}% fewer FPS)",
+                duration(*after_ms),
+                fps_drop_pct
+            ),
+            Finding::ShaderStutters { count } => {
+                format!(
+                    "{} shader-compile-like stutter{}",
+                    count,
+                    if *count == 1 { "" } else { "s" }
+                )
+            }
+            Finding::VSync { refresh_rate, quantized_pct } => format!(
+                "V-Sync on at {:.0} Hz ({:.0}% of frames on refresh intervals), not an uncapped \
+                 result",
+                refresh_rate, quantized_pct
+            ),
+        }
+    }
+}
+
+fn duration(ms: u64) -> String {
+    if ms >= 60_000 {
+        format!("{} min", ms / 60_000)
+    } else {
+        format!("{} s", ms / 1000)
+    }
+}
+
+fn bound(stats: &Stats) -> Option<Finding> {
+    let (side, pct) = match (&stats.engine_bound, stats.gpu_bound_pct) {
+        (Some(engine), _) => [
+            (Side::GameThread, engine.game_thread_pct),
+            (Side::RenderThread, engine.render_thread_pct),
+            (Side::Gpu, engine.gpu_pct),
+        ]
+        .into_iter()
+        .fold((Side::Gpu, f64::MIN), |max, side| if side.1 > max.1 { side } else { max }),
+        (None, Some(gpu)) if gpu >= BOUND_MIN_PCT => (Side::Gpu, gpu),
+        (None, Some(gpu)) => (Side::Cpu, 100.0 - gpu),
+        (None, None) => return None,
+    };
+    Some(Finding::Bound { side, pct }).filter(|_| pct >= BOUND_MIN_PCT)
+}
+
+fn hottest(sample: &Sample) -> Option<f64> {
+    sample
+        .metrics
+        .iter()
+        .filter(|(name, _)| name.starts_with("temp."))
+        .map(|(_, &value)| value)
+        .fold(None, |max: Option<f64>, value| Some(max.map_or(value, |max| max.max(value))))
+}
+
+// The first sample from which FPS stays under the baseline, on a device hotter than it started.
+// The baseline is the first minute with FPS, loading screens before it have none.
+fn throttling(samples: &[Sample]) -> Option<Finding> {
+    let fps: Vec<(&Sample, f64)> =
+        samples.iter().filter_map(|sample| Some((sample, *sample.metrics.get("fps")?))).collect();
+    let first_ms = fps.first()?.0.elapsed_ms;
+    if fps.last()?.0.elapsed_ms < first_ms + 2 * BASELINE_MS {
+        return None;
+    }
+    let cold = fps.iter().take_while(|s| s.0.elapsed_ms <= first_ms + BASELINE_MS).count();
+    let baseline = fps[..cold].iter().map(|s| s.1).sum::<f64>() / cold as f64;
+    let baseline_temp = fps[..cold].iter().filter_map(|s| hottest(s.0)).fold(f64::MIN, f64::max);
+    let threshold = baseline * (1.0 - THROTTLE_DROP);
+    // FPS sums and hottest temperatures from each sample to the end, in one pass
+    let mut rest_sum = vec![0.0; fps.len() + 1];
+    let mut rest_hot = vec![f64::MIN; fps.len() + 1];
+    for (i, &(sample, value)) in fps.iter().enumerate().rev() {
+        rest_sum[i] = rest_sum[i + 1] + value;
+        rest_hot[i] = hottest(sample).map_or(rest_hot[i + 1], |temp| temp.max(rest_hot[i + 1]));
+    }
+    for start in cold..fps.len() {
+        let rest = rest_sum[start] / (fps.len() - start) as f64;
+        if fps[start].1 >= threshold || rest >= threshold {
+            continue;
+        }
+        if rest_hot[start] < baseline_temp + HEAT_RISE {
+            return None;
+        }
+        let after_ms = fps[start].0.elapsed_ms;
+        return Some(Finding::Throttling {
+            after_ms,
+            fps_drop_pct: (1.0 - rest / baseline) * 100.0,
+        });
+    }
+    None
+}
+
+// The frames on either side are back under the stutter threshold
+fn is_isolated(frametimes: &[f64], stutter: &Stutter) -> bool {
+    let threshold = stutter.frametime / stutter.ratio * stutter::RATIO;
+    let before = frametimes.get(stutter.frame.wrapping_sub(1));
+    let after = frametimes.get(stutter.frame + 1);
+    [before, after].into_iter().all(|frametime| frametime.map_or(true, |&f| f < threshold))
+}
+
+// What held the run back, in the order it reads best
+pub fn summarize(recording: &Recording, stats: &Stats) -> Summary {
+    let mut findings = vec![];
+    // First, the other findings are about a run held back by the display
+    let vsync = limiter::quantization(
+        &recording.frametimes,
+        recording.refresh_rate,
+        stats.limiter.as_ref(),
+    );
+    findings.extend(vsync.map(|vsync| Finding::VSync {
+        refresh_rate: vsync.refresh_rate,
+        quantized_pct: vsync.quantized * 100.0,
+    }));
+    findings.extend(bound(stats));
+    // V-Sync already says what holds the frame rate
+    let capped = stats.limiter.as_ref().filter(|_| vsync.is_none());
+    findings.extend(capped.map(|limiter| Finding::Capped { fps: limiter.fps }));
+    findings.extend(throttling(&recording.samples));
+    let shader = stutter::detect(&recording.frametimes)
+        .iter()
+        .filter(|stutter| is_isolated(&recording.frametimes, stutter))
+        .count();
+    if shader > 0 {
+        findings.push(Finding::ShaderStutters { count: shader });
+    }
+
+    let verdict = if findings.is_empty() {
+        "No bottleneck found".to_string()
+    } else {
+        let texts: Vec<String> = findings.iter().map(Finding::text).collect();
+        let verdict = texts.join("; ");
+        verdict[..1].to_uppercase() + &verdict[1..]
+    };
+    Summary { verdict, findings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        // 60 FPS for two minutes, then 45 FPS while the GPU heats up
+        let mut samples = vec![];
+        let mut frametimes = vec![];
+        for second in 1..=300 {
+            let (fps, temp) = if second <= 120 { (60.0, 50.0) } else { (45.0, 70.0) };
+            let metrics = vec![("fps".to_string(), fps), ("temp.gpu".to_string(), temp)]
+                .into_iter()
+                .collect();
+            samples.push(Sample { elapsed_ms: second * 1000, metrics });
+            frametimes.extend(vec![1000.0 / fps; fps as usize]);
+        }
+        frametimes[100] = 80.0;
+        frametimes[200] = 90.0;
+        frametimes[201] = 90.0;
+        let gpu_busy = vec![0.0; frametimes.len()];
+        let recording = Recording { samples, frametimes, gpu_busy, ..Default::default() };
+        let stats = Stats::compute_with(&recording, Default::default());
+
+        let summary = summarize(&recording, &stats);
+        assert_eq!(
+            summary.findings,
+            vec![
+                Finding::Bound { side: Side::Cpu, pct: 100.0 },
+                Finding::Throttling { after_ms: 121_000, fps_drop_pct: 25.0 },
+                Finding::ShaderStutters { count: 1 },
+            ]
+        );
+        assert_eq!(
+            summary.verdict,
+            "CPU-bound 100% of the run; thermal throttling after 2 min (25% fewer FPS); \
+             1 shader-compile-like stutter"
+        );
+
+        // Dropping from 60 to 30 FPS with V-Sync, never in between
+        let frametimes: Vec<f64> = (0..600).map(|i| if i % 4 == 0 { 33.3 } else { 16.7 }).collect();
+        let recording = Recording { frametimes, refresh_rate: Some(60.0), ..Default::default() };
+        let summary = summarize(&recording, &Stats::compute_with(&recording, Default::default()));
+        assert_eq!(
+            summary.findings,
+            vec![Finding::VSync { refresh_rate: 60.0, quantized_pct: 100.0 }]
+        );
+        assert_eq!(
+            summary.verdict,
+            "V-Sync on at 60 Hz (100% of frames on refresh intervals), not an uncapped result"
+        );
+
+        let empty = Recording::default();
+        assert_eq!(summarize(&empty, &Stats::default()).verdict, "No bottleneck found");
+    }
+
+    #[test]
+    fn test_throttling() {
+        // Loading screens without FPS for the first 70 s
+        let samples: Vec<Sample> = (1..=400)
+            .map(|second| {
+                let mut metrics = std::collections::BTreeMap::new();
+                let (fps, temp) = if second <= 200 { (60.0, 50.0) } else { (45.0, 70.0) };
+                if second > 70 {
+                    metrics.insert("fps".into(), fps);
+                }
+                metrics.insert("temp.gpu".into(), temp);
+                Sample { elapsed_ms: second * 1000, metrics }
+            })
+            .collect();
+        assert_eq!(
+            throttling(&samples),
+            Some(Finding::Throttling { after_ms: 201_000, fps_drop_pct: 25.0 })
+        );
+        assert_eq!(throttling(&samples[..150]), None);
+        assert_eq!(throttling(&[]), None);
+    }
+}
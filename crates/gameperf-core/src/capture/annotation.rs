@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+// Per call, and what a running capture holds between two samples
+const MAX_EVENTS: usize = 10_000;
+const MAX_CHANNEL_LEN: usize = 64;
+const MAX_LABEL_LEN: usize = 256;
+
+lazy_static! {
+    // Events for the running capture, None when there is none
+    static ref INBOX: Mutex<Option<Vec<ExternalEvent>>> = Mutex::new(None);
+}
+
+// A point on one annotation channel, e.g. "level loaded" on `game`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub elapsed_ms: u64,
+    pub label: String,
+    #[serde(default)]
+    pub value: Option<f64>,
+}
+
+// Channel name to its annotations, sorted by time
+pub type Channels = BTreeMap<String, Vec<Annotation>>;
+
+// What external tools (game telemetry, mod hooks) send, timed either from the start of the
+// capture or on the wall clock
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ExternalEvent {
+    pub channel: String,
+    pub label: String,
+    #[serde(default)]
+    pub value: Option<f64>,
+    #[serde(default)]
+    pub elapsed_ms: Option<u64>,
+    // Unix time in ms
+    #[serde(default)]
+    pub at_ms: Option<u64>,
+}
+
+impl ExternalEvent {
+    fn validate(&self) -> Result<()> {
+        let valid_channel = !self.channel.is_empty()
+            && self.channel.len() <= MAX_CHANNEL_LEN
+            && self.channel.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c));
+        if !valid_channel {
+            bail!("Invalid channel: {}", self.channel);
+        }
+        if self.label.len() > MAX_LABEL_LEN || self.label.chars().any(char::is_control) {
+            bail!("Invalid label on {}", self.channel);
+        }
+        if matches!(self.value, Some(value) if !value.is_finite()) {
+            bail!("Invalid value on {}", self.channel);
+        }
+        if self.elapsed_ms.is_none() && self.at_ms.is_none() {
+            bail!("No time on {}", self.channel);
+        }
+        Ok(())
+    }
+
+    // None when the event came before the capture started
+    fn annotation(&self, started_at_ms: u64) -> Option<Annotation> {
+        let elapsed_ms = match self.elapsed_ms {
+            Some(elapsed_ms) => elapsed_ms,
+            None => self.at_ms?.checked_sub(started_at_ms)?,
+        };
+        Some(Annotation { elapsed_ms, label: self.label.clone(), value: self.value })
+    }
+}
+
+pub fn validate(events: &[ExternalEvent]) -> Result<()> {
+    if events.len() > MAX_EVENTS {
+        bail!("Too many events: {} (max {})", events.len(), MAX_EVENTS);
+    }
+    events.iter().try_for_each(ExternalEvent::validate)
+}
+
+// Adds the events to `channels`, returns how many were kept
+pub fn merge(channels: &mut Channels, events: &[ExternalEvent], started_at_ms: u64) -> usize {
+    let mut kept = 0;
+    for event in events {
+        if let Some(annotation) = event.annotation(started_at_ms) {
+            channels.entry(event.channel.clone()).or_default().push(annotation);
+            kept += 1;
+        }
+    }
+    for annotations in channels.values_mut() {
+        annotations.sort_by_key(|annotation| annotation.elapsed_ms);
+    }
+    kept
+}
+
+// For the running capture, picked up with its next sample
+pub fn send(events: Vec<ExternalEvent>) -> Result<usize> {
+    validate(&events)?;
+    let mut inbox = INBOX.lock();
+    let pending = inbox.as_mut().context("No capture running")?;
+    if pending.len() + events.len() > MAX_EVENTS {
+        bail!("Too many pending events");
+    }
+    let count = events.len();
+    pending.extend(events);
+    Ok(count)
+}
+
+// Open while the capture runs, events sent meanwhile end up in `take`
+pub struct Inbox;
+
+impl Inbox {
+    pub fn open() -> Self {
+        *INBOX.lock() = Some(vec![]);
+        Inbox
+    }
+
+    pub fn take(&self) -> Vec<ExternalEvent> {
+        INBOX.lock().as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        *INBOX.lock() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let event = |channel: &str, elapsed_ms, at_ms| ExternalEvent {
+            channel: channel.into(),
+            label: "level loaded".into(),
+            elapsed_ms,
+            at_ms,
+            ..Default::default()
+        };
+        let events = vec![
+            event("game", None, Some(1_000_500)),
+            event("game", Some(200), None),
+            event("gc", None, Some(999_000)),
+        ];
+        assert!(validate(&events).is_ok());
+        assert!(validate(&[event("game", None, None)]).is_err());
+        assert!(validate(&[event("game/../x", Some(0), None)]).is_err());
+
+        let mut channels = Channels::new();
+        assert_eq!(merge(&mut channels, &events, 1_000_000), 2);
+        let times: Vec<u64> = channels["game"].iter().map(|a| a.elapsed_ms).collect();
+        assert_eq!(times, vec![200, 500]);
+        assert!(!channels.contains_key("gc"));
+    }
+}
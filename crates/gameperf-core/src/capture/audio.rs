@@ -0,0 +1,83 @@
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::util;
+
+// Audio crackle: playback tracks of the game that ran out of data (underruns), as counted by
+// AudioFlinger. Usually comes with heavy CPU contention.
+pub struct AudioMonitor {
+    pid: String,
+    last: Option<u64>,
+}
+
+impl AudioMonitor {
+    pub fn new(package: &str) -> Result<Self> {
+        Ok(AudioMonitor { pid: util::pid_of(package)?, last: None })
+    }
+
+    // Underruns since the previous poll
+    pub fn poll(&mut self) -> Result<u64> {
+        let (_, stdout, _) = util::adb("shell dumpsys media.audio_flinger".into())?;
+        let total = underruns(&stdout, &self.pid);
+        // Tracks come and go (e.g. a sound effect ends), the total can shrink
+        let new = total.saturating_sub(self.last.unwrap_or(total));
+        self.last = Some(total);
+        Ok(new)
+    }
+}
+
+// Sums the `Underruns` column of the track tables for one client pid. Cells can be empty, so
+// columns are matched by position under the header rather than by counting fields.
+fn underruns(dump: &str, pid: &str) -> u64 {
+    let mut total = 0;
+    let mut columns = None;
+    for line in dump.lines() {
+        if let (Some(client), Some(underruns)) = (span(line, "Client"), span(line, "Underruns")) {
+            columns = Some((client, underruns));
+            continue;
+        }
+        if line.trim().is_empty() {
+            columns = None;
+            continue;
+        }
+        if let Some((client, underruns)) = &columns {
+            if cell(line, client) == Some(pid) {
+                total += cell(line, underruns).and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+            }
+        }
+    }
+    total
+}
+
+fn span(line: &str, name: &str) -> Option<Range<usize>> {
+    line.find(name).map(|start| start..start + name.len())
+}
+
+// The field overlapping a header column
+fn cell<'a>(line: &'a str, column: &Range<usize>) -> Option<&'a str> {
+    line.split_whitespace().find(|field| {
+        let start = field.as_ptr() as usize - line.as_ptr() as usize;
+        start < column.end && start + field.len() > column.start
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underruns() {
+        let dump = "\
+  Tracks of which 2 are active
+    Type     Id Active Client Session S   Flags  SRate Underruns Flushed
+             15    yes   4242      97 A   0x000  48000         3       0
+             16    yes   1000     105 A   0x000  48000         9       0
+    static   17          4242     113 A   0x000  48000         2       0
+
+  Effect chains
+";
+        assert_eq!(underruns(dump, "4242"), 5);
+        assert_eq!(underruns(dump, "1"), 0);
+    }
+}
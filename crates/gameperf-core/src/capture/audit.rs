@@ -0,0 +1,80 @@
+use std::cmp::Ordering;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+// Other processes above this share of one core are listed
+const MIN_CPU: f64 = 5.0;
+// Warn when the rest of the device uses more than this (100 per busy core)
+pub const HEAVY_CPU: f64 = 50.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackgroundProcess {
+    pub pid: u32,
+    pub name: String,
+    pub cpu: f64,
+}
+
+// What else was running when a capture started, to explain odd runs later. GPU use can't be
+// attributed per process through adb, CPU load is the proxy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackgroundAudit {
+    pub total_cpu: f64,
+    pub heavy: bool,
+    // Busiest first
+    pub processes: Vec<BackgroundProcess>,
+}
+
+pub fn run(package: &str) -> Result<BackgroundAudit> {
+    let (_, stdout, _) = util::adb("shell top -b -n 1 -q -o PID,%CPU,NAME".into())?;
+    Ok(parse_top(&stdout, package))
+}
+
+fn parse_top(output: &str, package: &str) -> BackgroundAudit {
+    let mut audit = BackgroundAudit::default();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        let (pid, cpu, name) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(pid), Some(cpu), Some(name)) => (pid, cpu, name),
+            _ => continue,
+        };
+        let (pid, cpu) = match (pid.parse::<u32>(), cpu.parse::<f64>()) {
+            (Ok(pid), Ok(cpu)) => (pid, cpu),
+            _ => continue,
+        };
+        // The game itself and top sampling itself
+        if name.starts_with(package) || name == "top" {
+            continue;
+        }
+        audit.total_cpu += cpu;
+        if cpu >= MIN_CPU {
+            audit.processes.push(BackgroundProcess { pid, name: name.into(), cpu });
+        }
+    }
+    audit.processes.sort_by(|a, b| b.cpu.partial_cmp(&a.cpu).unwrap_or(Ordering::Equal));
+    audit.heavy = audit.total_cpu > HEAVY_CPU;
+    audit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_top() {
+        let output = "\
+  4242 180.0 com.example.game
+  1337  35.5 com.android.vending
+   900  20.0 system_server
+  5000   1.0 logd
+  6000   3.0 top
+";
+        let audit = parse_top(output, "com.example.game");
+        assert_eq!(audit.total_cpu, 56.5);
+        assert!(audit.heavy);
+        let names: Vec<_> = audit.processes.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["com.android.vending", "system_server"]);
+    }
+}
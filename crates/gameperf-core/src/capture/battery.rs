@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+
+use super::sysfs::{self, Host};
+
+// Microamps, microvolts and microamp hours. The sign of the current depends on the vendor.
+// Android names its supply `battery`, PC handhelds `BAT0` or `BAT1` (the Steam Deck).
+const SUPPLIES: &[&str] = &[
+    "/sys/class/power_supply/battery",
+    "/sys/class/power_supply/BAT0",
+    "/sys/class/power_supply/BAT1",
+];
+const FILES: &[&str] = &["current_now", "voltage_now", "capacity", "status", "charge_full"];
+// System snapshot key of the full charge, in Wh
+pub const CAPACITY: &str = "battery.capacity_wh";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub watts: f64,
+    // Percent left
+    pub level: f64,
+    pub charging: bool,
+    // None when the driver doesn't report the full charge
+    pub capacity_wh: Option<f64>,
+}
+
+impl Reading {
+    // `battery.*` sample metrics
+    pub fn metrics(&self) -> Vec<(String, f64)> {
+        vec![
+            ("battery.watts".into(), self.watts),
+            ("battery.level".into(), self.level),
+            ("battery.charging".into(), if self.charging { 1.0 } else { 0.0 }),
+        ]
+    }
+}
+
+pub fn read(host: Host) -> Result<Reading> {
+    let paths: Vec<String> = SUPPLIES
+        .iter()
+        .flat_map(|supply| FILES.iter().map(move |file| format!("{}/{}", supply, file)))
+        .collect();
+    parse_reading(&sysfs::read(host, &paths)?)
+}
+
+// The first supply reporting current, voltage and level
+fn parse_reading(values: &BTreeMap<String, String>) -> Result<Reading> {
+    for supply in SUPPLIES {
+        let value = |file: &str| -> Option<f64> {
+            values.get(&format!("{}/{}", supply, file))?.parse().ok()
+        };
+        let (current, voltage, level) =
+            match (value("current_now"), value("voltage_now"), value("capacity")) {
+                (Some(current), Some(voltage), Some(level)) => (current, voltage, level),
+                _ => continue,
+            };
+        let status = values.get(&format!("{}/status", supply)).map(String::as_str);
+        return Ok(Reading {
+            watts: (current * voltage / 1e12).abs(),
+            level,
+            charging: matches!(status, Some("Charging") | Some("Full")),
+            capacity_wh: value("charge_full").map(|charge| charge * voltage / 1e12),
+        });
+    }
+    bail!("No battery current, voltage or level reported")
+}
+
+// Power limit and performance mode files of handhelds, e.g. `/sys/class/hwmon/hwmon*/power1_cap`,
+// as `tdp.<path>` system settings. Globs are expanded on the host.
+pub fn tdp_settings(host: Host, paths: &[String]) -> Result<BTreeMap<String, String>> {
+    if paths.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let values = sysfs::read(host, paths)?;
+    Ok(values.into_iter().map(|(path, value)| (format!("tdp.{}", path), value)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reading() -> Result<()> {
+        let output = "/sys/class/power_supply/battery/current_now:-1500000\n\
+                      /sys/class/power_supply/battery/voltage_now:4000000\n\
+                      /sys/class/power_supply/battery/capacity:87\n\
+                      /sys/class/power_supply/battery/status:Discharging\n\
+                      /sys/class/power_supply/battery/charge_full:5000000\n";
+        let reading = parse_reading(&sysfs::parse(output))?;
+        assert_eq!(
+            reading,
+            Reading { watts: 6.0, level: 87.0, charging: false, capacity_wh: Some(20.0) }
+        );
+        assert!(
+            parse_reading(&sysfs::parse("/sys/class/power_supply/battery/capacity:87")).is_err()
+        );
+
+        let output = "/sys/class/power_supply/BAT1/current_now:2000000\n\
+                      /sys/class/power_supply/BAT1/voltage_now:8000000\n\
+                      /sys/class/power_supply/BAT1/capacity:50\n\
+                      /sys/class/power_supply/BAT1/status:Charging\n";
+        let reading = parse_reading(&sysfs::parse(output))?;
+        assert_eq!(
+            reading,
+            Reading { watts: 16.0, level: 50.0, charging: true, capacity_wh: None }
+        );
+        Ok(())
+    }
+}
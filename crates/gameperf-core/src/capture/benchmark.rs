@@ -0,0 +1,248 @@
+use std::io::{BufRead, BufReader};
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+use super::foreground;
+
+// Focus and `top` go through adb, no need to ask more often
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// What marks the measured part of a game's built-in benchmark, set per profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Rule {
+    // Measured while the focused window contains `title`, e.g. the benchmark activity
+    Window { title: String },
+    // Measured once the game stays above `min_cpu` (100 per busy core) for `settle_secs`, until
+    // it stays below as long
+    CpuSignature { min_cpu: f64, settle_secs: u64 },
+    // Measured for `duration_secs` after `key` (e.g. `KEY_VOLUMEUP`) is pressed on the device
+    Hotkey { key: String, duration_secs: u64 },
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Rule::Window { title } if title.trim().is_empty() => bail!("Empty window title"),
+            Rule::CpuSignature { min_cpu, .. } if !min_cpu.is_finite() || *min_cpu <= 0.0 => {
+                bail!("Invalid CPU threshold: {}", min_cpu)
+            }
+            Rule::Hotkey { key, .. } if !key.starts_with("KEY_") => bail!("Invalid key: {}", key),
+            Rule::Hotkey { duration_secs: 0, .. } => bail!("Empty benchmark duration"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Start,
+    Stop,
+}
+
+// Turns what the rule watches into start/stop edges, times in ms since arming
+struct Detector {
+    rule: Rule,
+    started: Option<u64>,
+    // When the watched condition last flipped, until it held for the settle time
+    pending: Option<u64>,
+}
+
+impl Detector {
+    fn new(rule: Rule) -> Self {
+        Detector { rule, started: None, pending: None }
+    }
+
+    fn step(&mut self, active: bool, now: u64) -> Option<Edge> {
+        if let Rule::Hotkey { duration_secs, .. } = self.rule {
+            return match self.started {
+                None if active => {
+                    self.started = Some(now);
+                    Some(Edge::Start)
+                }
+                Some(started) if now - started >= duration_secs * 1000 => {
+                    self.started = None;
+                    Some(Edge::Stop)
+                }
+                _ => None,
+            };
+        }
+
+        if active == self.started.is_some() {
+            self.pending = None;
+            return None;
+        }
+        let settle = match self.rule {
+            Rule::CpuSignature { settle_secs, .. } => settle_secs * 1000,
+            _ => 0,
+        };
+        let since = *self.pending.get_or_insert(now);
+        if now - since < settle {
+            return None;
+        }
+        self.pending = None;
+        if active {
+            self.started = Some(now);
+            Some(Edge::Start)
+        } else {
+            self.started = None;
+            Some(Edge::Stop)
+        }
+    }
+}
+
+// Key presses seen by the kernel since the last poll
+struct KeyWatch {
+    child: Child,
+    pressed: Arc<AtomicBool>,
+}
+
+impl KeyWatch {
+    fn start(key: &str) -> Result<Self> {
+        let mut child = util::adb_spawn("shell getevent -l")?;
+        let stdout = child.stdout.take().context("No getevent output")?;
+        let pressed = Arc::new(AtomicBool::new(false));
+        let sink = pressed.clone();
+        let key = key.to_string();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().flatten() {
+                if is_key_down(&line, &key) {
+                    sink.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        Ok(KeyWatch { child, pressed })
+    }
+
+    fn take(&self) -> bool {
+        self.pressed.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for KeyWatch {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+pub struct Timer {
+    package: String,
+    armed: Instant,
+    last_poll: Option<Instant>,
+    detector: Detector,
+    keys: Option<KeyWatch>,
+}
+
+impl Timer {
+    pub fn arm(package: &str, rule: Rule) -> Result<Self> {
+        rule.validate()?;
+        let keys = match &rule {
+            Rule::Hotkey { key, .. } => Some(KeyWatch::start(key)?),
+            _ => None,
+        };
+        Ok(Timer {
+            package: package.into(),
+            armed: Instant::now(),
+            last_poll: None,
+            detector: Detector::new(rule),
+            keys,
+        })
+    }
+
+    pub fn package(&self) -> &str {
+        &self.package
+    }
+
+    pub fn poll(&mut self) -> Result<Option<Edge>> {
+        if matches!(self.last_poll, Some(last) if last.elapsed() < POLL_INTERVAL) {
+            return Ok(None);
+        }
+        self.last_poll = Some(Instant::now());
+
+        let active = match &self.detector.rule {
+            Rule::Window { title } => {
+                let focused = focused_window()?;
+                // An overlay or a screen reader taking the focus for a moment doesn't end the run
+                if foreground::is_excluded(&focused) {
+                    self.detector.started.is_some()
+                } else {
+                    focused.contains(title.as_str())
+                }
+            }
+            Rule::CpuSignature { min_cpu, .. } => package_cpu(&self.package)? >= *min_cpu,
+            Rule::Hotkey { .. } => self.keys.as_ref().map_or(false, KeyWatch::take),
+        };
+        let now = self.armed.elapsed().as_millis() as u64;
+        Ok(self.detector.step(active, now))
+    }
+}
+
+// `mCurrentFocus=Window{1a2b3c u0 com.example.game/com.example.game.BenchmarkActivity}`
+fn focused_window() -> Result<String> {
+    let (_, stdout, _) = util::adb("shell dumpsys window | grep mCurrentFocus".into())?;
+    Ok(stdout.trim().to_string())
+}
+
+fn package_cpu(package: &str) -> Result<f64> {
+    let (_, stdout, _) = util::adb("shell top -b -n 1 -q -o %CPU,NAME".into())?;
+    Ok(parse_cpu(&stdout, package))
+}
+
+// All processes of the game, e.g. `com.example.game:unity`
+fn parse_cpu(output: &str, package: &str) -> f64 {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let cpu = fields.next()?.parse::<f64>().ok()?;
+            fields.next().filter(|name| name.starts_with(package)).map(|_| cpu)
+        })
+        .sum()
+}
+
+// `/dev/input/event3: EV_KEY       KEY_VOLUMEUP         DOWN`
+fn is_key_down(line: &str, key: &str) -> bool {
+    let mut fields = line.split_whitespace().skip(1);
+    (fields.next(), fields.next(), fields.next()) == (Some("EV_KEY"), Some(key), Some("DOWN"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detector() {
+        let mut window = Detector::new(Rule::Window { title: "Benchmark".into() });
+        assert_eq!(window.step(false, 0), None);
+        assert_eq!(window.step(true, 1000), Some(Edge::Start));
+        assert_eq!(window.step(true, 2000), None);
+        assert_eq!(window.step(false, 3000), Some(Edge::Stop));
+
+        let mut cpu = Detector::new(Rule::CpuSignature { min_cpu: 150.0, settle_secs: 2 });
+        assert_eq!(cpu.step(true, 0), None);
+        assert_eq!(cpu.step(false, 1000), None);
+        assert_eq!(cpu.step(true, 2000), None);
+        assert_eq!(cpu.step(true, 4000), Some(Edge::Start));
+        assert_eq!(cpu.step(false, 5000), None);
+        assert_eq!(cpu.step(false, 7000), Some(Edge::Stop));
+
+        let mut hotkey =
+            Detector::new(Rule::Hotkey { key: "KEY_VOLUMEUP".into(), duration_secs: 60 });
+        assert_eq!(hotkey.step(true, 1000), Some(Edge::Start));
+        assert_eq!(hotkey.step(true, 2000), None);
+        assert_eq!(hotkey.step(false, 61_000), Some(Edge::Stop));
+
+        assert!(is_key_down("/dev/input/event3: EV_KEY KEY_VOLUMEUP DOWN", "KEY_VOLUMEUP"));
+        assert!(!is_key_down("/dev/input/event3: EV_KEY KEY_VOLUMEUP UP", "KEY_VOLUMEUP"));
+        let top = "180 com.example.game\n20 com.example.game:unity\n90 system_server";
+        assert_eq!(parse_cpu(top, "com.example.game"), 200.0);
+    }
+}
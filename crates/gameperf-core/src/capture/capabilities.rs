@@ -0,0 +1,149 @@
+use std::panic;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+
+use super::{clocks, thermal};
+use crate::settings;
+use crate::util;
+
+// GPU debug layers came with Android 10
+const GPU_LAYER_MIN_SDK: u32 = 29;
+
+// Name and what depends on it
+type Entry = (&'static str, &'static [&'static str]);
+
+// Probed once a device is connected
+const DEVICE_CAPABILITIES: [Entry; 8] = [
+    ("frame_timing", &["FPS", "frame times", "input latency"]),
+    ("root", &["clock limits"]),
+    ("sensors", &["temperatures", "thermal soak"]),
+    ("trace", &["trace markers"]),
+    ("input", &["input latency", "controller events"]),
+    ("audio", &["audio underruns"]),
+    ("gpu", &["GPU clock limits"]),
+    ("gpu_layer", &["GPU pipeline statistics"]),
+];
+
+// One thing a capture may rely on, for the first-run checklist
+#[derive(Debug, Clone, Serialize)]
+pub struct Capability {
+    pub name: &'static str,
+    pub available: bool,
+    // What was found, or why it's missing
+    pub detail: String,
+    // Metrics and features that depend on it
+    pub enables: &'static [&'static str],
+}
+
+fn capability(
+    name: &'static str,
+    enables: &'static [&'static str],
+    probe: impl FnOnce() -> Result<String>,
+) -> Capability {
+    match probe() {
+        Ok(detail) => Capability { name, available: true, detail, enables },
+        Err(err) => Capability { name, available: false, detail: err.to_string(), enables },
+    }
+}
+
+fn shell(command: &str) -> Result<String> {
+    let (_, stdout, _) = util::adb(format!("shell {}", command))?;
+    Ok(stdout.trim().to_string())
+}
+
+fn prop(key: &str) -> Result<String> {
+    shell(&format!("getprop {}", key))
+}
+
+// Everything is probed in turn, a few seconds with a device connected. Without adb or a device
+// the rest is reported missing without trying.
+pub fn probe() -> Vec<Capability> {
+    let adb = capability("adb", &["everything"], || {
+        // `util::cmd` panics when the program isn't there at all
+        let version = panic::catch_unwind(|| util::adb("version".into()))
+            .map_err(|_| anyhow!("adb not found, install the platform tools"))??;
+        Ok(version.1.lines().next().unwrap_or_default().to_string())
+    });
+    let device = capability("device", &["memory", "CPU usage"], || {
+        if !adb.available {
+            bail!("Needs adb");
+        }
+        let (_, state, _) = util::adb("get-state".into()).context("No device connected")?;
+        if state.trim() != "device" {
+            bail!("Device {}, authorize this PC on the device", state.trim());
+        }
+        Ok(format!("{}, Android {}", prop("ro.product.model")?, prop("ro.build.version.release")?))
+    });
+    let mut capabilities = vec![adb];
+    if !device.available {
+        let detail = device.detail.clone();
+        capabilities.push(device);
+        for (name, enables) in DEVICE_CAPABILITIES {
+            capabilities.push(Capability {
+                name,
+                available: false,
+                detail: format!("Needs a device: {}", detail),
+                enables,
+            });
+        }
+        return capabilities;
+    }
+    capabilities.push(device);
+
+    let [frames, root, sensors, trace, input, audio, gpu, gpu_layer] = DEVICE_CAPABILITIES;
+    capabilities.extend([
+        capability(frames.0, frames.1, || {
+            shell("dumpsys SurfaceFlinger --list")?;
+            Ok("SurfaceFlinger frame stats".into())
+        }),
+        capability(root.0, root.1, || {
+            if shell("id -u")? != "0" {
+                bail!("adbd isn't running as root, see `adb root`");
+            }
+            Ok("adbd runs as root".into())
+        }),
+        capability(sensors.0, sensors.1, || {
+            let temperatures = thermal::read()?;
+            let found: Vec<&str> = [("CPU", temperatures.cpu), ("GPU", temperatures.gpu)]
+                .iter()
+                .filter(|(_, value)| value.is_some())
+                .map(|&(name, _)| name)
+                .collect();
+            if found.is_empty() {
+                bail!("No CPU or GPU temperature sensor found");
+            }
+            Ok(format!("{} temperature", found.join(" and ")))
+        }),
+        capability(trace.0, trace.1, || {
+            let categories = shell("atrace --list_categories")?;
+            Ok(format!("atrace, {} categories", categories.lines().count()))
+        }),
+        capability(input.0, input.1, || {
+            shell("getevent -p").context("getevent isn't available")?;
+            Ok("getevent".into())
+        }),
+        capability(audio.0, audio.1, || {
+            shell("dumpsys media.audio_flinger")?;
+            Ok("AudioFlinger".into())
+        }),
+        capability(gpu.0, gpu.1, || {
+            let model = shell("cat /sys/class/kgsl/kgsl-3d0/gpu_model")
+                .or_else(|_| prop("ro.hardware.egl"))
+                .unwrap_or_default();
+            let control = clocks::gpu_max_freq()
+                .with_context(|| format!("No known frequency control for the {} GPU", model))?;
+            Ok(format!("{}, {}", model, control))
+        }),
+        capability(gpu_layer.0, gpu_layer.1, || {
+            let sdk: u32 =
+                prop("ro.build.version.sdk")?.parse().context("Unknown Android version")?;
+            if sdk < GPU_LAYER_MIN_SDK {
+                bail!("Needs Android 10");
+            }
+            let library = settings::get().gpu_layer.context("`gpu_layer` isn't set")?;
+            Ok(format!("{}, debuggable games only", library.display()))
+        }),
+    ]);
+    capabilities
+}
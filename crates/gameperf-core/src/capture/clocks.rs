@@ -0,0 +1,129 @@
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+// Every cpufreq policy (cluster) is capped to the same frequency, or its max when lower
+const CPU_POLICIES: &str = "/sys/devices/system/cpu/cpufreq/policy*";
+// Adreno, then generic devfreq GPUs
+const GPU_MAX_FREQ: &[&str] =
+    &["/sys/class/kgsl/kgsl-3d0/max_gpuclk", "/sys/class/devfreq/gpufreq/max_freq"];
+
+lazy_static! {
+    static ref APPLIED: Mutex<Option<Applied>> = Mutex::new(None);
+}
+
+// Clock caps for sustained-performance comparisons, they need a rooted device (`adb root`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockLimits {
+    pub cpu_max_khz: Option<u64>,
+    pub gpu_max_hz: Option<u64>,
+}
+
+struct Applied {
+    limits: ClockLimits,
+    // (sysfs file, value before the cap)
+    previous: Vec<(String, String)>,
+}
+
+// Put back by `restore`
+pub fn apply(limits: &ClockLimits) -> Result<ClockLimits> {
+    restore()?;
+    let mut previous = vec![];
+    let result = write_all(limits, &mut previous);
+    *APPLIED.lock() = Some(Applied { limits: limits.clone(), previous });
+    result?;
+    log::info!("clock limits: {:?}", limits);
+    Ok(limits.clone())
+}
+
+pub fn applied() -> Option<ClockLimits> {
+    APPLIED.lock().as_ref().map(|applied| applied.limits.clone())
+}
+
+// Every file is put back even when some fail, the error lists those
+pub fn restore() -> Result<()> {
+    match APPLIED.lock().take() {
+        Some(applied) => restore_each(&applied.previous, write),
+        None => Ok(()),
+    }
+}
+
+// Last written first, a GPU max can't go back above a min raised in between
+fn restore_each(
+    previous: &[(String, String)],
+    mut write: impl FnMut(&str, &str) -> Result<()>,
+) -> Result<()> {
+    let failed: Vec<String> = previous
+        .iter()
+        .rev()
+        .filter_map(|(path, value)| write(path, value).err().map(|err| format!("{:#}", err)))
+        .collect();
+    if !failed.is_empty() {
+        bail!("Failed to restore {} clock limit(s): {}", failed.len(), failed.join("; "));
+    }
+    Ok(())
+}
+
+fn write_all(limits: &ClockLimits, previous: &mut Vec<(String, String)>) -> Result<()> {
+    if let Some(khz) = limits.cpu_max_khz {
+        let (_, policies, _) = util::adb(format!("shell ls -d {}", CPU_POLICIES))?;
+        for policy in policies.split_whitespace() {
+            let max = read(&format!("{}/cpuinfo_max_freq", policy))?;
+            let max: u64 = max.parse().with_context(|| format!("Invalid frequency {}", max))?;
+            let path = format!("{}/scaling_max_freq", policy);
+            previous.push((path.clone(), read(&path)?));
+            write(&path, &khz.min(max).to_string())?;
+        }
+    }
+    if let Some(hz) = limits.gpu_max_hz {
+        let path = gpu_max_freq().context("No supported GPU frequency control")?;
+        previous.push((path.to_string(), read(path)?));
+        write(path, &hz.to_string())?;
+    }
+    Ok(())
+}
+
+// The GPU's frequency cap, None when the driver has no known one
+pub fn gpu_max_freq() -> Option<&'static str> {
+    GPU_MAX_FREQ.iter().copied().find(|path| read(path).is_ok())
+}
+
+fn read(path: &str) -> Result<String> {
+    let (_, stdout, _) = util::adb(format!("shell cat {}", path))?;
+    Ok(stdout.trim().to_string())
+}
+
+// Frequencies snap to the nearest supported step, so the value isn't read back
+fn write(path: &str, value: &str) -> Result<()> {
+    util::adb(format!("shell echo {} > {}", value, path))
+        .with_context(|| format!("Could not write {}, the device needs root", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_each() {
+        let previous: Vec<(String, String)> =
+            ["policy0", "policy4", "gpu"].iter().map(|p| (p.to_string(), "1".into())).collect();
+        let mut written = vec![];
+        let result = restore_each(&previous, |path, _| {
+            written.push(path.to_string());
+            if path == "policy4" {
+                bail!("Could not write {}", path);
+            }
+            Ok(())
+        });
+        assert_eq!(written, vec!["gpu", "policy4", "policy0"]);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("1 clock limit(s)") && err.contains("policy4"), "{}", err);
+
+        assert!(restore_each(&previous, |_, _| Ok(())).is_ok());
+    }
+}
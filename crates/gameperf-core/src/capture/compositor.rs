@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+// Composes the desktop, its presents are in every PresentMon capture of all processes
+pub const PROCESS: &str = "dwm.exe";
+
+// What DWM cost while a windowed game ran, kept apart from the game's own frames. From the same
+// PresentMon capture: DWM's presents and how the game's frames reached the display.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Compositor {
+    // DWM presents, 0 when the capture was filtered to the game
+    pub frames: usize,
+    // Share of the run DWM kept the CPU and the GPU busy, None before PresentMon 2.0
+    pub cpu_pct: Option<f64>,
+    pub gpu_pct: Option<f64>,
+    // Game frames DWM composed instead of flipping them to the display, 0 in exclusive fullscreen
+    pub composed_pct: f64,
+    // Present to display of the composed frames, in ms
+    pub composition_latency_ms: Option<f64>,
+}
+
+pub fn is_compositor(application: &str) -> bool {
+    application.eq_ignore_ascii_case(PROCESS)
+}
+
+#[derive(Debug, Default)]
+pub struct Tally {
+    frames: usize,
+    cpu_busy_ms: Option<f64>,
+    gpu_busy_ms: Option<f64>,
+    // Game frames with a present mode
+    presented: usize,
+    composed: usize,
+    latencies: Vec<f64>,
+}
+
+impl Tally {
+    pub fn compositor_frame(&mut self, cpu_busy_ms: Option<f64>, gpu_busy_ms: Option<f64>) {
+        self.frames += 1;
+        if let Some(ms) = cpu_busy_ms {
+            *self.cpu_busy_ms.get_or_insert(0.0) += ms;
+        }
+        if let Some(ms) = gpu_busy_ms {
+            *self.gpu_busy_ms.get_or_insert(0.0) += ms;
+        }
+    }
+
+    // `Composed: Flip`, `Hardware: Independent Flip`...
+    pub fn game_frame(&mut self, present_mode: &str, until_displayed_ms: Option<f64>) {
+        if present_mode.is_empty() {
+            return;
+        }
+        self.presented += 1;
+        if present_mode.starts_with("Composed") {
+            self.composed += 1;
+            self.latencies.extend(until_displayed_ms.filter(|ms| *ms > 0.0));
+        }
+    }
+
+    // None when the capture had neither DWM presents nor present modes
+    pub fn finish(self, duration_ms: f64) -> Option<Compositor> {
+        if self.frames == 0 && self.presented == 0 {
+            return None;
+        }
+        let pct = |busy_ms: Option<f64>| {
+            busy_ms.filter(|_| duration_ms > 0.0).map(|ms| ms / duration_ms * 100.0)
+        };
+        let latencies = &self.latencies;
+        Some(Compositor {
+            frames: self.frames,
+            cpu_pct: pct(self.cpu_busy_ms),
+            gpu_pct: pct(self.gpu_busy_ms),
+            composed_pct: self.composed as f64 / self.presented.max(1) as f64 * 100.0,
+            composition_latency_ms: Some(latencies.iter().sum::<f64>() / latencies.len() as f64)
+                .filter(|_| !latencies.is_empty()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tally() {
+        let mut tally = Tally::default();
+        for _ in 0..100 {
+            tally.compositor_frame(Some(0.5), Some(1.0));
+        }
+        tally.game_frame("Composed: Flip", Some(20.0));
+        tally.game_frame("Composed: Flip", Some(30.0));
+        tally.game_frame("Hardware: Independent Flip", Some(10.0));
+        tally.game_frame("Hardware Composed: Independent Flip", Some(10.0));
+        let compositor = tally.finish(1000.0).unwrap();
+        assert_eq!(compositor.frames, 100);
+        assert_eq!(compositor.cpu_pct, Some(5.0));
+        assert_eq!(compositor.gpu_pct, Some(10.0));
+        assert_eq!(compositor.composed_pct, 50.0);
+        assert_eq!(compositor.composition_latency_ms, Some(25.0));
+
+        let mut older = Tally::default();
+        older.compositor_frame(None, None);
+        assert_eq!(older.finish(1000.0).unwrap().cpu_pct, None);
+        assert_eq!(Tally::default().finish(1000.0), None);
+        assert!(is_compositor("DWM.exe"));
+    }
+}
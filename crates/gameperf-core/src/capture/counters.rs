@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::process::Child;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+
+use crate::util;
+
+use super::metric_key;
+use super::sysfs::{self, Host};
+
+const EVENTS: &[&str] = &["cpu-cycles", "instructions", "cache-misses"];
+const INTERVAL_MS: u64 = 1000;
+// Busiest threads kept per sample, worker pools would add dozens of metrics otherwise
+const MAX_THREADS: usize = 8;
+// Adreno, then generic devfreq GPUs, both in Hz
+const GPU_FREQ: &[&str] =
+    &["/sys/class/kgsl/kgsl-3d0/gpuclk", "/sys/class/devfreq/gpufreq/cur_freq"];
+// `45 %` on Adreno, a plain percentage elsewhere
+const GPU_BUSY: &[&str] =
+    &["/sys/class/kgsl/kgsl-3d0/gpu_busy_percentage", "/sys/kernel/gpu/gpu_busy"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Counts {
+    cycles: f64,
+    instructions: f64,
+    cache_misses: f64,
+}
+
+impl Counts {
+    fn add(&mut self, event: &str, count: f64) {
+        match event {
+            "cpu-cycles" => self.cycles += count,
+            "instructions" => self.instructions += count,
+            "cache-misses" => self.cache_misses += count,
+            _ => (),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Intervals {
+    // Rows of the interval being printed, by thread name
+    current: BTreeMap<String, Counts>,
+    done: Vec<BTreeMap<String, Counts>>,
+}
+
+// `simpleperf stat --per-thread --csv` rows: `thread,pid,tid,count,event,...`. Worker threads
+// sharing a name are added up.
+fn parse_row(line: &str) -> Option<(String, &str, f64)> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let event = fields.iter().position(|field| EVENTS.contains(field))?;
+    if event < 4 {
+        return None;
+    }
+    let count = fields[event - 1].parse().ok()?;
+    Some((fields[0].to_string(), fields[event], count))
+}
+
+fn read_line(intervals: &Mutex<Intervals>, line: &str) {
+    let mut intervals = intervals.lock();
+    if let Some((thread, event, count)) = parse_row(line) {
+        intervals.current.entry(thread).or_default().add(event, count);
+    } else if line.starts_with("Total test time") {
+        let current = std::mem::take(&mut intervals.current);
+        intervals.done.push(current);
+    }
+}
+
+// `cpu.ipc` and the cache misses of the whole process, `thread.<name>.mhz` for the busiest
+// threads: the cycles each ran per second, how far it is from the big cores' clock tells
+// whether it's the bottleneck.
+fn metrics(intervals: &[BTreeMap<String, Counts>]) -> Vec<(String, f64)> {
+    if intervals.is_empty() {
+        return vec![];
+    }
+    let mut threads: BTreeMap<&str, Counts> = BTreeMap::new();
+    for interval in intervals {
+        for (thread, counts) in interval {
+            let total = threads.entry(thread).or_default();
+            total.cycles += counts.cycles;
+            total.instructions += counts.instructions;
+            total.cache_misses += counts.cache_misses;
+        }
+    }
+    let secs = intervals.len() as f64 * INTERVAL_MS as f64 / 1000.0;
+    let cycles: f64 = threads.values().map(|counts| counts.cycles).sum();
+    let instructions: f64 = threads.values().map(|counts| counts.instructions).sum();
+    let cache_misses: f64 = threads.values().map(|counts| counts.cache_misses).sum();
+    let mut metrics = vec![("cpu.cache_misses".to_string(), cache_misses / secs)];
+    if cycles > 0.0 {
+        metrics.push(("cpu.ipc".into(), instructions / cycles));
+    }
+    let mut busiest: Vec<(&str, f64)> =
+        threads.iter().map(|(thread, counts)| (*thread, counts.cycles)).collect();
+    busiest.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    for (thread, cycles) in busiest.into_iter().take(MAX_THREADS) {
+        metrics.push((format!("thread.{}.mhz", metric_key(thread)), cycles / secs / 1e6));
+    }
+    metrics
+}
+
+// Hardware counters of the game's threads. simpleperf ships with Android 9+, reading another
+// app's counters needs the game to be profileable or debuggable, or a rooted device.
+pub struct CpuCounters {
+    child: Child,
+    intervals: Arc<Mutex<Intervals>>,
+}
+
+impl CpuCounters {
+    pub fn start(pid: u32) -> Result<Self> {
+        let args = format!(
+            "shell simpleperf stat -p {} --per-thread --csv --interval {} -e {}",
+            pid,
+            INTERVAL_MS,
+            EVENTS.join(",")
+        );
+        let mut child = util::adb_spawn(&args)?;
+        let stdout = child.stdout.take().context("No simpleperf output")?;
+        let intervals = Arc::new(Mutex::new(Intervals::default()));
+        let sink = intervals.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().flatten() {
+                read_line(&sink, &line);
+            }
+        });
+        Ok(CpuCounters { child, intervals })
+    }
+
+    // Since the last call, nothing until simpleperf printed its first interval
+    pub fn metrics(&self) -> Vec<(String, f64)> {
+        let done = std::mem::take(&mut self.intervals.lock().done);
+        metrics(&done)
+    }
+}
+
+impl Drop for CpuCounters {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+// `gpu.freq_mhz` and `gpu.busy` (percent) from whichever node the driver has
+pub fn gpu_metrics() -> Result<Vec<(String, f64)>> {
+    let paths: Vec<String> = GPU_FREQ.iter().chain(GPU_BUSY).map(|path| path.to_string()).collect();
+    let values = sysfs::read(Host::Device, &paths)?;
+    let metrics = parse_gpu(&values);
+    if metrics.is_empty() {
+        bail!("No GPU frequency or utilization node");
+    }
+    Ok(metrics)
+}
+
+fn parse_gpu(values: &BTreeMap<String, String>) -> Vec<(String, f64)> {
+    let find = |nodes: &[&str]| {
+        nodes
+            .iter()
+            .find_map(|node| values.get(*node)?.trim_end_matches('%').trim().parse::<f64>().ok())
+    };
+    let mut metrics = vec![];
+    if let Some(hz) = find(GPU_FREQ) {
+        metrics.push(("gpu.freq_mhz".to_string(), hz / 1e6));
+    }
+    if let Some(busy) = find(GPU_BUSY) {
+        metrics.push(("gpu.busy".to_string(), busy));
+    }
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let intervals = Mutex::new(Intervals::default());
+        let output = "Performance counter statistics,\n\
+                      RenderThread,4321,4330,1500000000,cpu-cycles,# 1.5 GHz,\n\
+                      RenderThread,4321,4330,3000000000,instructions,# 2 ipc,\n\
+                      Worker,4321,4340,250000000,cpu-cycles,,\n\
+                      Worker,4321,4341,250000000,cpu-cycles,,\n\
+                      Worker,4321,4341,1000000,cache-misses,,\n\
+                      Total test time,1.000000,seconds,\n";
+        for line in output.lines() {
+            read_line(&intervals, line);
+        }
+        let done = std::mem::take(&mut intervals.lock().done);
+        assert_eq!(
+            metrics(&done),
+            vec![
+                ("cpu.cache_misses".to_string(), 1_000_000.0),
+                ("cpu.ipc".to_string(), 1.5),
+                ("thread.renderthread.mhz".to_string(), 1500.0),
+                ("thread.worker.mhz".to_string(), 500.0),
+            ]
+        );
+        assert!(metrics(&[]).is_empty());
+
+        let values = sysfs::parse(
+            "/sys/class/kgsl/kgsl-3d0/gpuclk:587000000\n\
+             /sys/class/kgsl/kgsl-3d0/gpu_busy_percentage:45 %\n",
+        );
+        assert_eq!(
+            parse_gpu(&values),
+            vec![("gpu.freq_mhz".to_string(), 587.0), ("gpu.busy".to_string(), 45.0)]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::Sample;
+
+// Engine side timings in ms, one per engine frame, from the engine's own profiler. Empty
+// columns weren't recorded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EngineTimings {
+    // `unreal` or `unity`, prefixes the per sample metrics
+    #[serde(default)]
+    pub engine: String,
+    // From the start of the capture to the first engine frame
+    #[serde(default)]
+    pub start_ms: f64,
+    pub frame: Vec<f64>,
+    // Game thread (Unreal) or main thread (Unity)
+    pub game_thread: Vec<f64>,
+    pub render_thread: Vec<f64>,
+    #[serde(default)]
+    pub rhi_thread: Vec<f64>,
+    pub gpu: Vec<f64>,
+}
+
+impl EngineTimings {
+    // Per sample averages (`unreal.game_thread`...), engine frames placed by their summed frame
+    // times from `start_ms`
+    pub fn add_metrics(&self, samples: &mut [Sample]) {
+        let columns = [
+            ("game_thread", &self.game_thread),
+            ("render_thread", &self.render_thread),
+            ("rhi_thread", &self.rhi_thread),
+            ("gpu", &self.gpu),
+        ];
+        let mut frame = 0;
+        let mut ends_at = self.start_ms;
+        for sample in samples {
+            let first = frame;
+            while frame < self.frame.len()
+                && ends_at + self.frame[frame] <= sample.elapsed_ms as f64
+            {
+                ends_at += self.frame[frame];
+                frame += 1;
+            }
+            if frame == first {
+                continue;
+            }
+            for (name, values) in columns.iter().filter(|(_, values)| values.len() >= frame) {
+                let avg = values[first..frame].iter().sum::<f64>() / (frame - first) as f64;
+                sample.metrics.insert(format!("{}.{}", self.engine, name), avg);
+            }
+        }
+    }
+}
+
+// The named columns of a profiler CSV, in order, empty when missing. Rows are read up to the
+// first one that isn't all numbers (e.g. Unreal's trailing metadata).
+pub fn read_csv(csv: &str, names: &[&str]) -> Result<Vec<Vec<f64>>> {
+    let mut lines = csv.lines();
+    let header: Vec<&str> = lines.next().context("Empty CSV")?.split(',').map(str::trim).collect();
+    let columns: Vec<Option<usize>> =
+        names.iter().map(|&name| header.iter().position(|&column| column == name)).collect();
+    if columns.iter().all(Option::is_none) {
+        bail!("None of {} in the CSV", names.join(", "));
+    }
+
+    let mut values = vec![vec![]; names.len()];
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let row: Option<Vec<Option<f64>>> = columns
+            .iter()
+            .map(|column| match column {
+                Some(i) => fields.get(*i)?.parse().ok().map(Some),
+                None => Some(None),
+            })
+            .collect();
+        let row = match row {
+            Some(row) => row,
+            None => break,
+        };
+        for (values, value) in values.iter_mut().zip(row) {
+            values.extend(value);
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_metrics() -> Result<()> {
+        let csv = "FrameTime,GameThreadTime,Memory/Total\n16.6,10.0,900\n33.4,30.0,900\n[meta],1\n";
+        let columns = read_csv(csv, &["FrameTime", "GameThreadTime", "GPUTime"])?;
+        assert_eq!(columns, vec![vec![16.6, 33.4], vec![10.0, 30.0], vec![]]);
+        assert!(read_csv(csv, &["GPUTime"]).is_err());
+
+        let timings = EngineTimings {
+            engine: "unreal".into(),
+            frame: vec![16.0, 34.0],
+            game_thread: vec![10.0, 30.0],
+            gpu: vec![15.0, 14.0],
+            ..Default::default()
+        };
+        let mut samples = vec![
+            Sample { elapsed_ms: 20, ..Default::default() },
+            Sample { elapsed_ms: 40, ..Default::default() },
+            Sample { elapsed_ms: 60, ..Default::default() },
+        ];
+        timings.add_metrics(&mut samples);
+        assert_eq!(samples[0].metrics.get("unreal.game_thread"), Some(&10.0));
+        assert_eq!(samples[1].metrics.get("unreal.gpu"), None);
+        assert_eq!(samples[2].metrics.get("unreal.game_thread"), Some(&30.0));
+        assert!(!samples[0].metrics.contains_key("unreal.rhi_thread"));
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use winreg::{
+    enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE},
+    RegKey,
+};
+
+use super::system::WindowsEnvironment;
+
+const GAME_BAR: &str = "Software\\Microsoft\\GameBar";
+const GRAPHICS_DRIVERS: &str = "SYSTEM\\CurrentControlSet\\Control\\GraphicsDrivers";
+const DEVICE_GUARD: &str = "SYSTEM\\CurrentControlSet\\Control\\DeviceGuard";
+const HVCI: &str =
+    "SYSTEM\\CurrentControlSet\\Control\\DeviceGuard\\Scenarios\\HypervisorEnforcedCodeIntegrity";
+const COMPAT_LAYERS: &str =
+    "Software\\Microsoft\\Windows NT\\CurrentVersion\\AppCompatFlags\\Layers";
+const FSO_DISABLED: &str = "DISABLEDXMAXIMIZEDWINDOWEDMODE";
+
+pub fn read(exe: &str) -> WindowsEnvironment {
+    let user = RegKey::predef(HKEY_CURRENT_USER);
+    let machine = RegKey::predef(HKEY_LOCAL_MACHINE);
+    WindowsEnvironment {
+        // On unless turned off in the settings
+        game_mode: Some(dword(&user, GAME_BAR, "AutoGameModeEnabled").map_or(true, |v| v != 0)),
+        hags: dword(&machine, GRAPHICS_DRIVERS, "HwSchMode").map(|v| v == 2),
+        vbs: dword(&machine, DEVICE_GUARD, "EnableVirtualizationBasedSecurity").map(|v| v != 0),
+        hvci: dword(&machine, HVCI, "Enabled").map(|v| v != 0),
+        fullscreen_optimizations: fullscreen_optimizations(&user, exe),
+    }
+}
+
+fn dword(root: &RegKey, path: &str, name: &str) -> Option<u32> {
+    root.open_subkey(path).ok()?.get_value(name).ok()
+}
+
+// Compatibility flags are keyed by the exe's full path
+fn fullscreen_optimizations(user: &RegKey, exe: &str) -> Option<bool> {
+    if exe.is_empty() {
+        return None;
+    }
+    let disabled = match user.open_subkey(COMPAT_LAYERS) {
+        Ok(layers) => layers.enum_values().flatten().filter(|(path, _)| same_exe(path, exe)).any(
+            |(path, _)| {
+                layers
+                    .get_value::<String, _>(&path)
+                    .map_or(false, |flags| flags.contains(FSO_DISABLED))
+            },
+        ),
+        Err(_) => false,
+    };
+    Some(!disabled)
+}
+
+// By file name, `game.exe` isn't `mygame.exe`. `exe` may be a name or a path.
+fn same_exe(path: &str, exe: &str) -> bool {
+    let file_name = |path: &str| path.rsplit(['\\', '/']).next().unwrap_or_default().to_lowercase();
+    !exe.is_empty() && file_name(path) == file_name(exe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_exe() {
+        assert!(same_exe("C:\\Games\\Game.exe", "game.exe"));
+        assert!(same_exe("C:\\Games\\Game.exe", "D:/Other/GAME.EXE"));
+        assert!(!same_exe("C:\\Games\\MyGame.exe", "game.exe"));
+        assert!(!same_exe("C:\\Games\\Game.exe", ""));
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+
+use crate::util;
+
+use super::foreground;
+
+// Per sample, see `Focus::value`
+pub const METRIC: &str = "focus";
+// Reason of the gaps focus losses leave, see `analysis::focus`
+pub const GAP_REASON: &str = "focus";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Focus {
+    Foreground,
+    // Still resumed with something else focused on top: the notification shade, a dialog or the
+    // other app in split screen
+    Occluded,
+    // Not resumed on any display, e.g. switched away from
+    Background,
+}
+
+impl Focus {
+    pub fn value(self) -> f64 {
+        match self {
+            Focus::Foreground => 0.0,
+            Focus::Occluded => 1.0,
+            Focus::Background => 2.0,
+        }
+    }
+
+    pub fn from_value(value: f64) -> Focus {
+        match value.round() as i64 {
+            0 => Focus::Foreground,
+            1 => Focus::Occluded,
+            _ => Focus::Background,
+        }
+    }
+}
+
+// One adb call for the resumed activities and the focused window
+pub fn read(package: &str) -> Result<Focus> {
+    let (_, stdout, _) = util::adb(
+        "shell dumpsys activity activities|grep ResumedActivity;dumpsys window|grep mCurrentFocus"
+            .into(),
+    )?;
+    let (focused, activities): (Vec<&str>, Vec<&str>) =
+        stdout.lines().partition(|line| line.contains("mCurrentFocus"));
+    let resumed = util::parse_resumed(&activities.join("\n"));
+    Ok(classify(&resumed, focused.first().copied().unwrap_or_default(), package))
+}
+
+// Screen readers and other excluded overlays taking the focus don't occlude the game
+fn classify(resumed: &[String], focused: &str, package: &str) -> Focus {
+    if !resumed.iter().any(|app| app == package) {
+        Focus::Background
+    } else if focused.is_empty() || focused.contains(package) || foreground::is_excluded(focused) {
+        Focus::Foreground
+    } else {
+        Focus::Occluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let resumed = vec!["com.example.game".to_string()];
+        let game = "mCurrentFocus=Window{1a2b u0 com.example.game/com.example.game.Main}";
+        let shade = "mCurrentFocus=Window{3c4d u0 NotificationShade}";
+        assert_eq!(classify(&resumed, game, "com.example.game"), Focus::Foreground);
+        assert_eq!(classify(&resumed, shade, "com.example.game"), Focus::Occluded);
+        assert_eq!(classify(&[], shade, "com.example.game"), Focus::Background);
+        assert_eq!(Focus::from_value(Focus::Occluded.value()), Focus::Occluded);
+    }
+}
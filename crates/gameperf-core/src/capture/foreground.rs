@@ -0,0 +1,159 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::settings;
+use crate::util;
+
+// Take the focus for a moment without anything changing for the game: screen readers and the
+// overlays of Samsung's and Xiaomi's game modes. More come from `Settings::foreground_exclusions`.
+const EXCLUDED: &[&str] = &[
+    "com.google.android.marvin.talkback",
+    "com.samsung.android.accessibility.talkback",
+    "com.samsung.android.game.gametools",
+    "com.miui.securitycenter",
+];
+
+lazy_static! {
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker::default());
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Foreground {
+    // Resumed on each display, see `util::resumed_apps`
+    pub resumed: Vec<String>,
+    // What a capture goes for: the pinned app, or the last game that was in front on any display
+    pub target: Option<String>,
+    pub pinned: bool,
+}
+
+#[derive(Default)]
+struct Tracker {
+    pinned: Option<String>,
+    last_game: Option<String>,
+}
+
+impl Tracker {
+    // A game stays the target while the other display is used, e.g. for the settings, until its
+    // process is gone. `games` are the resumed apps that render like one, see `is_game`.
+    fn observe(&mut self, resumed: &[String], games: &[String], last_alive: bool) -> Foreground {
+        if !last_alive {
+            self.last_game = None;
+        }
+        if let Some(game) = resumed.iter().find(|app| games.contains(app)) {
+            self.last_game = Some(game.clone());
+        }
+        Foreground {
+            resumed: resumed.to_vec(),
+            target: self.pinned.clone().or_else(|| self.last_game.clone()),
+            pinned: self.pinned.is_some(),
+        }
+    }
+}
+
+// Games draw into a SurfaceView, launchers, settings and keyboards into their windows.
+// `layers` is `dumpsys SurfaceFlinger --list`, `SurfaceView[com.example.game/...]#0` or
+// `SurfaceView - com.example.game/...` depending on the version.
+fn is_game(package: &str, layers: &str) -> bool {
+    let component = format!("{}/", package);
+    layers
+        .lines()
+        .map(str::trim)
+        .any(|layer| layer.starts_with("SurfaceView") && layer.contains(&component))
+}
+
+// A failing adb leaves the game be
+fn is_running(package: &str) -> bool {
+    match util::adb(format!("shell pidof {}; true", package)) {
+        Ok((_, stdout, _)) => !stdout.trim().is_empty(),
+        Err(_) => true,
+    }
+}
+
+// Also true for a focused window line naming an excluded package
+pub fn is_excluded(name: &str) -> bool {
+    excluded_by(name, &settings::get().foreground_exclusions)
+}
+
+fn excluded_by(name: &str, exclusions: &[String]) -> bool {
+    let mut excluded = EXCLUDED.iter().copied().chain(exclusions.iter().map(String::as_str));
+    excluded.any(|package| !package.is_empty() && name.contains(package))
+}
+
+// Excluded apps are left out as if they weren't there
+pub fn poll() -> Result<Foreground> {
+    let exclusions = settings::get().foreground_exclusions;
+    let mut resumed = util::resumed_apps()?;
+    resumed.retain(|app| !excluded_by(app, &exclusions));
+    let last_game = TRACKER.lock().last_game.clone();
+    let last_alive =
+        last_game.as_ref().map_or(true, |game| resumed.contains(game) || is_running(game));
+    let games: Vec<String> = if resumed.iter().any(|app| Some(app) != last_game.as_ref()) {
+        let (_, layers, _) = util::adb("shell dumpsys SurfaceFlinger --list".into())?;
+        resumed.iter().filter(|app| is_game(app, &layers)).cloned().collect()
+    } else {
+        resumed.clone()
+    };
+    Ok(TRACKER.lock().observe(&resumed, &games, last_alive))
+}
+
+// Explicit target, whatever is in front. None goes back to following the foreground.
+pub fn pin(package: Option<String>) -> Result<Foreground> {
+    TRACKER.lock().pinned = package;
+    poll()
+}
+
+// Pinned, resumed on any display or the last game in front
+pub fn is_target(package: &str) -> Result<bool> {
+    let foreground = poll()?;
+    let resumed = foreground.resumed.iter().any(|app| app == package);
+    Ok(resumed || foreground.target.as_deref() == Some(package))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_foreground() {
+        let dump = "    mResumedActivity: ActivityRecord{8a1c2 u0 com.example.game/.Main t47}\n\
+                    \x20   mLastResumedActivity: ActivityRecord{1b3 u0 com.other/.Main t12}\n\
+                    \x20 ResumedActivity:ActivityRecord{8a1c2 u0 com.example.game/.Main t47}\n\
+                    \x20 ResumedActivity:ActivityRecord{77f u0 \
+                    com.sec.android.app.launcher/.Home t2}\n";
+        let resumed = util::parse_resumed(dump);
+        assert_eq!(resumed, vec!["com.example.game", "com.sec.android.app.launcher"]);
+
+        let layers = "Display 0 HWC layers:\n\
+                      SurfaceView[com.example.game/com.example.game.Main]#0\n\
+                      com.sec.android.app.launcher/com.sec.android.app.launcher.Home#0\n";
+        let games: Vec<String> =
+            resumed.iter().filter(|app| is_game(app, layers)).cloned().collect();
+        assert_eq!(games, vec!["com.example.game"]);
+        assert!(is_game("com.example.game", "SurfaceView - com.example.game/.Main#0"));
+
+        let mut tracker = Tracker::default();
+        let foreground = tracker.observe(&resumed, &games, true);
+        assert_eq!(foreground.target.as_deref(), Some("com.example.game"));
+        // The game went to the background, the other display shows the launcher
+        let launcher = vec!["com.sec.android.app.launcher".to_string()];
+        assert_eq!(
+            tracker.observe(&launcher, &[], true).target.as_deref(),
+            Some("com.example.game")
+        );
+        // Until it's gone
+        assert_eq!(tracker.observe(&launcher, &[], false).target, None);
+        tracker.observe(&resumed, &games, true);
+
+        let exclusions = vec!["com.example.overlay".to_string()];
+        assert!(excluded_by("com.example.overlay", &exclusions));
+        assert!(excluded_by("com.google.android.marvin.talkback", &[]));
+        assert!(!excluded_by("com.example.game", &exclusions));
+
+        tracker.pinned = Some("com.example.other".into());
+        let foreground = tracker.observe(&resumed, &games, true);
+        assert_eq!(foreground.target.as_deref(), Some("com.example.other"));
+        assert!(foreground.pinned);
+    }
+}
@@ -0,0 +1,226 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::engine;
+use super::timesync::ClockSync;
+use super::Sample;
+use crate::util;
+
+// The layer isn't part of this tree, `settings::gpu_layer` points to a build of it. It must
+// register this name and write `OUTPUT` with `COLUMNS`, see `parse_csv`.
+pub const LAYER_NAME: &str = "VK_LAYER_GAMEPERF_frame_stats";
+const LAYER_LIBRARY: &str = "libVkLayer_gameperf_frame_stats.so";
+const STAGING: &str = "/data/local/tmp/libVkLayer_gameperf_frame_stats.so";
+// Written by the layer in the game's own files, one row per present
+const OUTPUT: &str = "files/gameperf_frame_stats.csv";
+const COLUMNS: [&str; 9] = [
+    "timestamp_ns",
+    "gpu_ms",
+    "ia_vertices",
+    "ia_primitives",
+    "vs_invocations",
+    "fs_invocations",
+    "cs_invocations",
+    "width",
+    "height",
+];
+
+// Per frame numbers from inside the game: GPU time between the first and last command buffer
+// timestamps and the pipeline statistics queries of the frame, summed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineStats {
+    // Device CLOCK_MONOTONIC at each present
+    pub timestamps: Vec<u64>,
+    pub gpu_ms: Vec<f64>,
+    pub ia_vertices: Vec<f64>,
+    pub ia_primitives: Vec<f64>,
+    pub vs_invocations: Vec<f64>,
+    pub fs_invocations: Vec<f64>,
+    pub cs_invocations: Vec<f64>,
+    // Swapchain extent of each present, empty from layers built before they were written
+    #[serde(default)]
+    pub width: Vec<f64>,
+    #[serde(default)]
+    pub height: Vec<f64>,
+}
+
+impl PipelineStats {
+    // Per sample averages (`gpu.pipeline_ms`, `gpu.fs_invocations`...)
+    pub fn add_metrics(&self, samples: &mut [Sample], clock: &ClockSync) {
+        let columns = [
+            ("gpu.pipeline_ms", &self.gpu_ms),
+            ("gpu.ia_vertices", &self.ia_vertices),
+            ("gpu.ia_primitives", &self.ia_primitives),
+            ("gpu.vs_invocations", &self.vs_invocations),
+            ("gpu.fs_invocations", &self.fs_invocations),
+            ("gpu.cs_invocations", &self.cs_invocations),
+        ];
+        let placed: Vec<Option<u64>> =
+            self.timestamps.iter().map(|&ns| clock.device_elapsed_ms(ns)).collect();
+        let mut frame = 0;
+        for sample in samples {
+            let first = frame;
+            while frame < placed.len() && placed[frame].map_or(true, |at| at <= sample.elapsed_ms) {
+                frame += 1;
+            }
+            if frame == first {
+                continue;
+            }
+            for (name, values) in columns.iter().filter(|(_, values)| values.len() >= frame) {
+                let avg = values[first..frame].iter().sum::<f64>() / (frame - first) as f64;
+                sample.metrics.insert(name.to_string(), avg);
+            }
+        }
+    }
+
+    // Presents whose extent differs from the previous one's, swapchain resizes included
+    pub fn extent_changes(&self, clock: &ClockSync) -> Vec<(u64, (u32, u32))> {
+        let mut changes = vec![];
+        let mut last = None;
+        let extents = self.width.iter().zip(&self.height);
+        for (&ns, (&width, &height)) in self.timestamps.iter().zip(extents) {
+            let extent = (width as u32, height as u32);
+            if extent.0 == 0 || extent.1 == 0 || last == Some(extent) {
+                continue;
+            }
+            last = Some(extent);
+            if let Some(elapsed_ms) = clock.device_elapsed_ms(ns) {
+                changes.push((elapsed_ms, extent));
+            }
+        }
+        changes
+    }
+}
+
+// Loaded into the game through Android's GPU debug layers, which only debuggable builds allow.
+// Settings are put back when dropped.
+pub struct GpuLayer {
+    package: String,
+    running: bool,
+}
+
+impl GpuLayer {
+    // Takes effect from the game's next `vkCreateInstance`, see `restart_game`
+    pub fn enable(package: &str, library: &Path) -> Result<Self> {
+        if !library.is_file() {
+            bail!("Layer library not found: {}", library.display());
+        }
+        util::adb_push(library, STAGING)?;
+        let run_as = |command: String| -> Result<()> {
+            let (success, _, stderr) = util::adb(format!("shell run-as {} {}", package, command))?;
+            if !success {
+                bail!("{} isn't debuggable: {}", package, stderr.trim());
+            }
+            Ok(())
+        };
+        run_as(format!("cp {} {}", STAGING, LAYER_LIBRARY))?;
+        run_as(format!("rm -f {}", OUTPUT))?;
+
+        let layer = GpuLayer { package: package.into(), running: true };
+        for (key, value) in [
+            ("enable_gpu_debug_layers", "1"),
+            ("gpu_debug_app", package),
+            ("gpu_debug_layers", LAYER_NAME),
+        ] {
+            util::adb(format!("shell settings put global {} {}", key, value))?;
+        }
+        Ok(layer)
+    }
+
+    // The layer loads with the game, a running one is started again
+    pub fn restart_game(&self) -> Result<()> {
+        util::adb(format!("shell am force-stop {}", self.package))?;
+        let launch =
+            format!("shell monkey -p {} -c android.intent.category.LAUNCHER 1", self.package);
+        let (success, _, stderr) = util::adb(launch)?;
+        if !success {
+            bail!("Failed to start {}: {}", self.package, stderr.trim());
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<PipelineStats> {
+        self.disable();
+        let (success, stdout, stderr) =
+            util::adb(format!("shell run-as {} cat {}", self.package, OUTPUT))?;
+        if !success {
+            bail!("No frame stats from the layer, was the game restarted? {}", stderr.trim());
+        }
+        parse_csv(&stdout)
+    }
+
+    fn disable(&mut self) {
+        if !self.running {
+            return;
+        }
+        self.running = false;
+        for key in ["enable_gpu_debug_layers", "gpu_debug_app", "gpu_debug_layers"] {
+            let _ = util::adb(format!("shell settings delete global {}", key));
+        }
+    }
+}
+
+impl Drop for GpuLayer {
+    fn drop(&mut self) {
+        self.disable();
+    }
+}
+
+fn parse_csv(csv: &str) -> Result<PipelineStats> {
+    let mut columns = engine::read_csv(csv, &COLUMNS)?.into_iter();
+    let mut next = || columns.next().unwrap_or_default();
+    let timestamps = next().into_iter().map(|ns| ns as u64).collect::<Vec<_>>();
+    let stats = PipelineStats {
+        timestamps,
+        gpu_ms: next(),
+        ia_vertices: next(),
+        ia_primitives: next(),
+        vs_invocations: next(),
+        fs_invocations: next(),
+        cs_invocations: next(),
+        width: next(),
+        height: next(),
+    };
+    if stats.timestamps.is_empty() {
+        bail!("No frames in the layer's output");
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_metrics() -> Result<()> {
+        let csv = "timestamp_ns,gpu_ms,ia_vertices,fs_invocations\n\
+                   1010000000,8.0,1000,50000\n\
+                   1030000000,12.0,3000,70000\n\
+                   1070000000,10.0,2000,60000\n";
+        let stats = parse_csv(csv)?;
+        assert_eq!(stats.timestamps.len(), 3);
+        assert!(stats.cs_invocations.is_empty());
+
+        let clock = ClockSync { device_epoch_ns: Some(1_000_000_000), ..Default::default() };
+        let mut samples = vec![
+            Sample { elapsed_ms: 50, ..Default::default() },
+            Sample { elapsed_ms: 100, ..Default::default() },
+        ];
+        stats.add_metrics(&mut samples, &clock);
+        assert_eq!(samples[0].metrics.get("gpu.pipeline_ms"), Some(&10.0));
+        assert_eq!(samples[0].metrics.get("gpu.ia_vertices"), Some(&2000.0));
+        assert_eq!(samples[1].metrics.get("gpu.fs_invocations"), Some(&60000.0));
+        assert!(!samples[1].metrics.contains_key("gpu.cs_invocations"));
+        assert!(stats.extent_changes(&clock).is_empty());
+
+        let csv = "timestamp_ns,gpu_ms,width,height\n\
+                   1010000000,8.0,1920,1080\n\
+                   1030000000,8.0,1920,1080\n\
+                   1070000000,6.0,1280,720\n";
+        let stats = parse_csv(csv)?;
+        assert_eq!(stats.extent_changes(&clock), vec![(10, (1920, 1080)), (70, (1280, 720))]);
+        Ok(())
+    }
+}
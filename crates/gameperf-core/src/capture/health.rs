@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+pub const FRAMES: &str = "frames";
+pub const SAMPLES: &str = "samples";
+
+lazy_static! {
+    // Live capture and delivery to the page, reset when a capture starts
+    static ref LIVE: Mutex<CaptureHealth> = Mutex::new(CaptureHealth::default());
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelHealth {
+    pub delivered: u64,
+    pub dropped: u64,
+}
+
+// Per channel counts of what was kept and what was lost because a consumer fell behind
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaptureHealth {
+    pub channels: BTreeMap<String, ChannelHealth>,
+}
+
+impl CaptureHealth {
+    pub fn delivered(&mut self, channel: &str, count: u64) {
+        self.channel(channel).delivered += count;
+    }
+
+    pub fn dropped(&mut self, channel: &str, count: u64) {
+        if count > 0 {
+            self.channel(channel).dropped += count;
+        }
+    }
+
+    pub fn total_dropped(&self) -> u64 {
+        self.channels.values().map(|channel| channel.dropped).sum()
+    }
+
+    fn channel(&mut self, channel: &str) -> &mut ChannelHealth {
+        self.channels.entry(channel.into()).or_default()
+    }
+}
+
+pub fn live() -> CaptureHealth {
+    LIVE.lock().clone()
+}
+
+pub fn reset_live() {
+    *LIVE.lock() = CaptureHealth::default();
+}
+
+pub fn live_delivered(channel: &str, count: u64) {
+    LIVE.lock().delivered(channel, count);
+}
+
+pub fn live_dropped(channel: &str, count: u64) {
+    LIVE.lock().dropped(channel, count);
+}
@@ -0,0 +1,135 @@
+use std::io::{BufRead, BufReader};
+use std::process::Child;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+
+use crate::util;
+
+// Labels `getevent -l` prints for gamepad buttons, depending on the kernel headers it was built with
+const GAMEPAD_BUTTONS: &[&str] = &[
+    "BTN_A",
+    "BTN_B",
+    "BTN_C",
+    "BTN_X",
+    "BTN_Y",
+    "BTN_Z",
+    "BTN_SOUTH",
+    "BTN_EAST",
+    "BTN_NORTH",
+    "BTN_WEST",
+    "BTN_GAMEPAD",
+    "BTN_TL",
+    "BTN_TR",
+    "BTN_TL2",
+    "BTN_TR2",
+    "BTN_SELECT",
+    "BTN_START",
+    "BTN_MODE",
+    "BTN_THUMBL",
+    "BTN_THUMBR",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Input {
+    // In ns
+    pub timestamp: u64,
+    // Controller button, e.g. `a` or `dpad_up`, None for touches
+    pub button: Option<String>,
+}
+
+// Touches and controller presses seen by the kernel, timestamps on the same monotonic clock as
+// SurfaceFlinger presents. Only physical input shows up here, `input tap` is injected above evdev.
+pub struct InputMonitor {
+    child: Child,
+    inputs: Arc<Mutex<Vec<Input>>>,
+}
+
+impl InputMonitor {
+    pub fn start() -> Result<Self> {
+        let mut child = util::adb_spawn("shell getevent -lt")?;
+        let stdout = child.stdout.take().context("No getevent output")?;
+        let inputs = Arc::new(Mutex::new(vec![]));
+        let sink = inputs.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().flatten() {
+                if let Some(input) = parse_input(&line) {
+                    sink.lock().push(input);
+                }
+            }
+        });
+        Ok(InputMonitor { child, inputs })
+    }
+
+    pub fn drain(&self) -> Vec<Input> {
+        std::mem::take(&mut *self.inputs.lock())
+    }
+}
+
+impl Drop for InputMonitor {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+// `[   1234.567890] /dev/input/event2: EV_KEY       BTN_TOUCH            DOWN`, presses only
+fn parse_input(line: &str) -> Option<Input> {
+    let (timestamp, event) = line.trim_start_matches('[').split_once(']')?;
+    let mut fields = event.split_whitespace().skip(1);
+    let button = match (fields.next()?, fields.next()?, fields.next()?) {
+        ("EV_KEY", "BTN_TOUCH", "DOWN") => None,
+        ("EV_KEY", button, "DOWN") if GAMEPAD_BUTTONS.contains(&button) => {
+            Some(button.trim_start_matches("BTN_").to_lowercase())
+        }
+        // D-pads are reported as a hat, -1 for left/up and 1 for right/down, 0 on release
+        ("EV_ABS", "ABS_HAT0X", "ffffffff") => Some("dpad_left".into()),
+        ("EV_ABS", "ABS_HAT0X", "00000001") => Some("dpad_right".into()),
+        ("EV_ABS", "ABS_HAT0Y", "ffffffff") => Some("dpad_up".into()),
+        ("EV_ABS", "ABS_HAT0Y", "00000001") => Some("dpad_down".into()),
+        _ => return None,
+    };
+    let secs: f64 = timestamp.trim().parse().ok()?;
+    Some(Input { timestamp: (secs * 1_000_000_000.0) as u64, button })
+}
+
+// Click-to-photon proxy: the first frame started after the input (the previous present) is
+// the earliest that can react to it, its present is when the result reaches the screen.
+// Inputs without such a frame yet are returned to retry once more presents are known.
+pub fn latencies(inputs: &[u64], presents: &[u64]) -> (Vec<f64>, Vec<u64>) {
+    let mut latencies = vec![];
+    let mut pending = vec![];
+    for &input in inputs {
+        let frame = presents.windows(2).find(|frame| frame[0] >= input);
+        match frame {
+            Some(frame) => latencies.push((frame[1] - input) as f64 / 1_000_000.0),
+            None => pending.push(input),
+        }
+    }
+    (latencies, pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latencies() {
+        let line = "[   1234.500000] /dev/input/event2: EV_KEY       BTN_TOUCH            DOWN";
+        assert_eq!(parse_input(line), Some(Input { timestamp: 1_234_500_000_000, button: None }));
+        let line = "[   1234.500000] /dev/input/event2: EV_ABS       ABS_MT_POSITION_X    000001f4";
+        assert_eq!(parse_input(line), None);
+        let line = "[   1234.500000] /dev/input/event5: EV_KEY       BTN_SOUTH            DOWN";
+        assert_eq!(parse_input(line).and_then(|input| input.button), Some("south".into()));
+        let line = "[   1234.500000] /dev/input/event5: EV_ABS       ABS_HAT0Y            ffffffff";
+        assert_eq!(parse_input(line).and_then(|input| input.button), Some("dpad_up".into()));
+        let line = "[   1234.500000] /dev/input/event5: EV_KEY       BTN_SOUTH            UP";
+        assert_eq!(parse_input(line), None);
+
+        let presents = [10_000_000, 26_000_000, 42_000_000];
+        let (latencies, pending) = latencies(&[5_000_000, 20_000_000, 30_000_000], &presents);
+        assert_eq!(latencies, vec![21.0, 22.0]);
+        assert_eq!(pending, vec![30_000_000]);
+    }
+}
@@ -0,0 +1,77 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::syslog::Entry;
+
+lazy_static! {
+    // Kernel GPU faults name the process by pid, e.g. `pid = 1234` or `pid:1234`
+    static ref PID: Regex = Regex::new(r"(?i)\bpid\s*[=:]?\s*(\d+)").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    // The game's Vulkan device or GL context was lost, usually after a GPU reset
+    DeviceLost,
+    // The driver faulted or hung on the game's work
+    GpuFault,
+    Crash,
+    // By the low memory killer
+    Killed,
+    // Gone without anything in the logs
+    Exited,
+}
+
+// Why a capture ended early, instead of a flatline to make sense of
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interruption {
+    pub elapsed_ms: u64,
+    pub reason: Reason,
+    // The log line, or how the game went away
+    pub detail: String,
+}
+
+fn mentions(entry: &Entry, package: &str, pid: u32) -> bool {
+    entry.message.contains(package)
+        || PID.captures_iter(&entry.message).any(|cap| cap[1].parse() == Ok(pid))
+}
+
+// Whether a device log entry means the game with `pid` was hit
+pub fn classify(entry: &Entry, package: &str, pid: u32) -> Option<Reason> {
+    match entry.kind {
+        "device_lost" if entry.pid == pid => Some(Reason::DeviceLost),
+        "gpu_fault" if mentions(entry, package, pid) => Some(Reason::GpuFault),
+        "crash" if entry.pid == pid || mentions(entry, package, pid) => Some(Reason::Crash),
+        "low_memory_kill" if mentions(entry, package, pid) => Some(Reason::Killed),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let entry =
+            |pid, kind, message: &str| Entry { timestamp: 0, pid, kind, message: message.into() };
+        let classify = |entry: Entry| classify(&entry, "com.example.game", 4321);
+
+        let lost = "vulkan: vkQueueSubmit returned VK_ERROR_DEVICE_LOST";
+        assert_eq!(classify(entry(4321, "device_lost", lost)), Some(Reason::DeviceLost));
+        assert_eq!(classify(entry(1000, "device_lost", lost)), None);
+
+        let fault = "kgsl-3d0: GPU PAGE FAULT: pid = 4321 (com.example.g)";
+        assert_eq!(classify(entry(0, "gpu_fault", fault)), Some(Reason::GpuFault));
+        let fault = "kgsl-3d0: |adreno_hang_int| GPU hang detected";
+        assert_eq!(classify(entry(0, "gpu_fault", fault)), None);
+
+        let crash = "DEBUG: Fatal signal 11 (SIGSEGV), pid 4321 (com.example.game)";
+        assert_eq!(classify(entry(4321, "crash", crash)), Some(Reason::Crash));
+        let kill = "lmkd: Kill 'com.example.game' (4321), uid 10123, oom_score_adj 0";
+        assert_eq!(classify(entry(500, "low_memory_kill", kill)), Some(Reason::Killed));
+        let kill = "lmkd: Kill 'com.other.app' (5555), uid 10124, oom_score_adj 900";
+        assert_eq!(classify(entry(500, "low_memory_kill", kill)), None);
+    }
+}
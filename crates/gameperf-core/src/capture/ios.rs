@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::util;
+
+use super::system::SystemSnapshot;
+
+// `ideviceinfo` keys worth comparing between devices
+const DEVICE_KEYS: &[&str] = &["ProductType", "ProductVersion", "BuildVersion"];
+// Frame rate of the Instruments graphics samples, printed as a dict by the bridge
+const FPS_KEY: &str = "CoreAnimationFramesPerSecond";
+// `idevicediagnostics` takes a while to answer, it's polled off the capture loop
+const TEMPERATURE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub udid: String,
+    // `ProductType` (e.g. iPhone15,2), `ProductVersion` and `BuildVersion`
+    pub device: BTreeMap<String, String>,
+}
+
+// Tethered iPhones and iPads by UDID, through libimobiledevice. Fails when it isn't installed.
+pub fn devices() -> Result<Vec<String>> {
+    let (_, stdout, _) = util::cmd("idevice_id", "-l".into())?;
+    Ok(stdout.lines().map(str::trim).filter(|udid| !udid.is_empty()).map(String::from).collect())
+}
+
+// The only device connected when no UDID is given
+pub fn resolve(udid: Option<&str>) -> Result<String> {
+    let devices = devices()?;
+    match (udid, devices.as_slice()) {
+        (Some(udid), _) if devices.iter().any(|device| device == udid) => Ok(udid.into()),
+        (Some(udid), _) => bail!("iOS device {} isn't connected", udid),
+        (None, [udid]) => Ok(udid.clone()),
+        (None, []) => bail!("No iOS device connected"),
+        (None, _) => bail!("{} iOS devices connected, pick one by UDID", devices.len()),
+    }
+}
+
+pub fn list() -> Result<Vec<DeviceInfo>> {
+    let devices = devices()?.into_iter().map(|udid| {
+        let device = device_snapshot(&udid).device;
+        DeviceInfo { udid, device }
+    });
+    Ok(devices.collect())
+}
+
+pub fn device_snapshot(udid: &str) -> SystemSnapshot {
+    let device = DEVICE_KEYS
+        .iter()
+        .filter_map(|key| {
+            let (_, value, _) = util::cmd("ideviceinfo", format!("-u {} -k {}", udid, key)).ok()?;
+            Some((key.to_string(), value.trim().to_string())).filter(|(_, value)| !value.is_empty())
+        })
+        .collect();
+    SystemSnapshot { device, windows: None }
+}
+
+// iOS doesn't expose SoC temperatures, the battery's is the closest to how hot the device runs
+fn battery_temperature(udid: &str) -> Result<f64> {
+    let args = format!("-u {} ioregentry AppleSmartBattery", udid);
+    let (_, plist, _) = util::cmd("idevicediagnostics", args)?;
+    parse_temperature(&plist).context("No battery temperature reported")
+}
+
+// `<key>Temperature</key><integer>3120</integer>`, in hundredths of °C
+fn parse_temperature(plist: &str) -> Option<f64> {
+    let (_, rest) = plist.split_once("<key>Temperature</key>")?;
+    let value = rest.trim_start().strip_prefix("<integer>")?.split('<').next()?;
+    Some(value.trim().parse::<f64>().ok()? / 100.0)
+}
+
+// `{'CoreAnimationFramesPerSecond': 59, ...}` or the same as JSON
+fn parse_fps(line: &str) -> Option<f64> {
+    let (_, rest) = line.split_once(FPS_KEY)?;
+    let rest = rest.trim_start_matches(|c: char| c == '\'' || c == '"' || c == ':' || c == ' ');
+    let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+// Frame rate once a second from the Instruments graphics service. libimobiledevice doesn't
+// speak its DTX protocol, `bridge` does: pymobiledevice3 or a tool with the same command line.
+// The count is device-wide, the game has to stay in front. Instruments doesn't time single
+// frames, so iOS captures have a frame rate but no frametimes.
+pub struct GraphicsStream {
+    child: Child,
+    fps: Arc<Mutex<Vec<f64>>>,
+    // The battery's, see `battery_temperature`
+    temperature: Arc<Mutex<Option<f64>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl GraphicsStream {
+    pub fn start(bridge: &Path, udid: &str) -> Result<Self> {
+        let bridge = bridge.to_string_lossy();
+        let args = ["developer", "dvt", "graphics", "--udid", udid];
+        let mut child = util::spawn(&bridge, &args)?;
+        let fps = Arc::new(Mutex::new(vec![]));
+        // The samples are logged, to stdout or stderr depending on the version
+        let stdout = child.stdout.take().context("No graphics output")?;
+        let stderr = child.stderr.take().context("No graphics output")?;
+        for output in [Box::new(stdout) as Box<dyn Read + Send>, Box::new(stderr)] {
+            let sink = fps.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(output).lines().flatten() {
+                    if let Some(fps) = parse_fps(&line) {
+                        sink.lock().push(fps);
+                    }
+                }
+            });
+        }
+
+        let temperature = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sink, stopped, udid) = (temperature.clone(), stop.clone(), udid.to_string());
+        thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                let reading = battery_temperature(&udid);
+                if let Err(err) = &reading {
+                    log::debug!("ios: {}", err);
+                }
+                *sink.lock() = reading.ok();
+                thread::sleep(TEMPERATURE_INTERVAL);
+            }
+        });
+        Ok(GraphicsStream { child, fps, temperature, stop })
+    }
+
+    pub fn drain(&self) -> Vec<f64> {
+        std::mem::take(&mut *self.fps.lock())
+    }
+
+    // The latest reading, None until the first one or after a failed one
+    pub fn temperature(&self) -> Option<f64> {
+        *self.temperature.lock()
+    }
+}
+
+impl Drop for GraphicsStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.child.kill();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let line = "2024-05-01 12:00:00 host pymobiledevice3.cli.developer[42] INFO \
+                    {'Alloc system memory': 50003968, 'CoreAnimationFramesPerSecond': 59, \
+                    'Device Utilization %': 31}";
+        assert_eq!(parse_fps(line), Some(59.0));
+        assert_eq!(parse_fps(r#"{"CoreAnimationFramesPerSecond": 119.5}"#), Some(119.5));
+        assert_eq!(parse_fps("{'Device Utilization %': 31}"), None);
+
+        let plist = "<dict>\n\t<key>Temperature</key>\n\t<integer>3120</integer>\n</dict>";
+        assert_eq!(parse_temperature(plist), Some(31.2));
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::util::PssInfo;
+
+use super::thermal;
+
+const REFRESH_RATE: f64 = 60.0;
+// A 100 ms hitch every two seconds
+const STUTTER_EVERY: u64 = 120;
+const STUTTER_MS: f64 = 100.0;
+// Throttling starts after `THROTTLE_AFTER_MS` and halves the frame rate over `THROTTLE_RAMP_MS`
+const THROTTLE_AFTER_MS: f64 = 10_000.0;
+const THROTTLE_RAMP_MS: f64 = 60_000.0;
+
+lazy_static! {
+    // Set by `--mock-capture`, captures then never touch adb
+    static ref SELECTED: RwLock<Option<Pattern>> = RwLock::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Steady,
+    Stutter,
+    Throttle,
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "steady" => Pattern::Steady,
+            "stutter" => Pattern::Stutter,
+            "throttle" => Pattern::Throttle,
+            _ => bail!("Unknown mock pattern {}, expected steady, stutter or throttle", name),
+        })
+    }
+}
+
+pub fn select(pattern: Pattern) {
+    log::info!("mock capture: {:?}", pattern);
+    *SELECTED.write() = Some(pattern);
+}
+
+pub fn selected() -> Option<Pattern> {
+    *SELECTED.read()
+}
+
+// 0 before throttling, 1 once fully throttled
+fn throttle(elapsed_ms: f64) -> f64 {
+    ((elapsed_ms - THROTTLE_AFTER_MS) / THROTTLE_RAMP_MS).clamp(0.0, 1.0)
+}
+
+// Scripted frame times and sensor readings, the same for every run of a pattern
+pub struct MockProvider {
+    pattern: Pattern,
+    frames: u64,
+    // Sum of the frame times handed out
+    presented_ms: f64,
+}
+
+impl MockProvider {
+    pub fn new(pattern: Pattern) -> Self {
+        MockProvider { pattern, frames: 0, presented_ms: 0.0 }
+    }
+
+    pub fn refresh_rate(&self) -> f64 {
+        REFRESH_RATE
+    }
+
+    fn frametime(&self) -> f64 {
+        let vsync = 1000.0 / REFRESH_RATE;
+        match self.pattern {
+            Pattern::Steady => vsync,
+            Pattern::Stutter if self.frames % STUTTER_EVERY == STUTTER_EVERY - 1 => STUTTER_MS,
+            Pattern::Stutter => vsync,
+            Pattern::Throttle => vsync * (1.0 + throttle(self.presented_ms)),
+        }
+    }
+
+    // The frames presented up to `elapsed_ms` that weren't handed out yet
+    pub fn frametimes(&mut self, elapsed_ms: u64) -> Vec<f64> {
+        let mut frametimes = vec![];
+        loop {
+            let frametime = self.frametime();
+            if self.presented_ms + frametime > elapsed_ms as f64 {
+                return frametimes;
+            }
+            self.presented_ms += frametime;
+            self.frames += 1;
+            frametimes.push(frametime);
+        }
+    }
+
+    // Temperatures climb with throttling, memory grows slowly like a level streaming in
+    pub fn sensors(&self, elapsed_ms: u64) -> BTreeMap<String, f64> {
+        let heat = match self.pattern {
+            Pattern::Throttle => throttle(elapsed_ms as f64),
+            _ => 0.0,
+        };
+        let mut metrics = BTreeMap::new();
+        metrics.insert("temp.cpu".to_string(), 45.0 + 40.0 * heat);
+        metrics.insert("temp.gpu".to_string(), 42.0 + 38.0 * heat);
+        // Light throttling as soon as it heats up, severe once fully hot
+        let status = if heat > 0.0 { 1.0 + (heat * 2.0).round() } else { 0.0 };
+        metrics.insert(thermal::STATUS_METRIC.to_string(), status);
+        metrics
+    }
+
+    // In MB, like `dump_pss`
+    pub fn pss(&self, package: &str, elapsed_ms: u64) -> PssInfo {
+        let native = 300 + elapsed_ms / 10_000;
+        let graphics = 450;
+        let metrics =
+            [("Native Heap", native), ("Graphics", graphics), ("TOTAL", native + graphics)];
+        PssInfo::from_metrics(package, &metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let mut steady = MockProvider::new(Pattern::Steady);
+        assert_eq!(steady.frametimes(1010).len(), 60);
+        assert!(steady.frametimes(1010).is_empty());
+
+        let mut stutter = MockProvider::new(Pattern::Stutter);
+        let frametimes = stutter.frametimes(4000);
+        let hitches = frametimes.iter().filter(|&&frametime| frametime == STUTTER_MS).count();
+        assert_eq!(hitches, 1);
+
+        let mut throttle = MockProvider::new(Pattern::Throttle);
+        let frametimes = throttle.frametimes(90_000);
+        let last = frametimes.last().copied().unwrap_or_default();
+        assert!((last - 2000.0 / REFRESH_RATE).abs() < 1e-9);
+        assert!(throttle.sensors(90_000)["temp.cpu"] > steady.sensors(90_000)["temp.cpu"]);
+        assert_eq!(throttle.sensors(90_000)[thermal::STATUS_METRIC], 3.0);
+        assert_eq!(steady.sensors(90_000)[thermal::STATUS_METRIC], 0.0);
+
+        assert_eq!("stutter".parse::<Pattern>().ok(), Some(Pattern::Stutter));
+        assert!("jitter".parse::<Pattern>().is_err());
+    }
+}
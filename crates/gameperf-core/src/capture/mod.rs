@@ -0,0 +1,894 @@
+pub mod annotation;
+pub mod audio;
+pub mod audit;
+pub mod battery;
+pub mod benchmark;
+pub mod capabilities;
+pub mod clocks;
+pub mod compositor;
+pub mod counters;
+pub mod engine;
+#[cfg(target_os = "windows")]
+pub mod environment;
+pub mod focus;
+pub mod foreground;
+pub mod gpu_layer;
+pub mod health;
+pub mod input;
+pub mod interrupt;
+pub mod ios;
+pub mod mock;
+pub mod overhead;
+pub mod power;
+pub mod replay;
+pub mod resolution;
+pub mod sysfs;
+pub mod syslog;
+pub mod system;
+pub mod thermal;
+pub mod timesync;
+pub mod trace;
+pub mod tuning;
+pub mod unity;
+pub mod unreal;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::{self, framegen, Stats};
+use crate::settings;
+use crate::util::{self, PssInfo};
+
+use annotation::{Annotation, Channels, Inbox};
+use audio::AudioMonitor;
+use audit::BackgroundAudit;
+use clocks::ClockLimits;
+use compositor::Compositor;
+use counters::CpuCounters;
+use engine::EngineTimings;
+use focus::Focus;
+use gpu_layer::{GpuLayer, PipelineStats};
+use health::CaptureHealth;
+use input::InputMonitor;
+use interrupt::{Interruption, Reason};
+use ios::GraphicsStream;
+use mock::{MockProvider, Pattern};
+use overhead::{Overhead, OverheadMeter};
+use power::{Change, DeviceWatcher};
+use sysfs::Host;
+use syslog::SystemLog;
+use system::SystemSnapshot;
+use thermal::SoakReport;
+use timesync::ClockSync;
+use trace::{TraceSession, TraceSettings};
+use tuning::Tuning;
+use unreal::CsvProfiler;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Sample {
+    pub elapsed_ms: u64,
+    pub metrics: BTreeMap<String, f64>,
+}
+
+// Something that happened at one point of the capture, e.g. an audio glitch
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    pub elapsed_ms: u64,
+    pub kind: String,
+    pub count: u64,
+}
+
+// Start of a scene or test step, e.g. "city" or "boss"
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub elapsed_ms: u64,
+    pub label: String,
+}
+
+// Stretch of the capture without samples, e.g. adb stalled or merged sessions apart
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Gap {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub reason: String,
+}
+
+impl Gap {
+    pub fn duration_ms(&self) -> u64 {
+        self.end_ms.saturating_sub(self.start_ms)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub package: String,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub samples: Vec<Sample>,
+    // Frame times in ms, in present order
+    pub frametimes: Vec<f64>,
+    // Display refresh rate in Hz, when the source reports it
+    #[serde(default)]
+    pub refresh_rate: Option<f64>,
+    // Per frame GPU busy time in ms and present queue depth, empty when the source has none
+    #[serde(default)]
+    pub gpu_busy: Vec<f64>,
+    #[serde(default)]
+    pub queue_depth: Vec<u32>,
+    // Per frame, set when frame generation inserted the frame, empty without frame generation
+    #[serde(default)]
+    pub generated: Vec<bool>,
+    // Estimated input to present latency in ms, one per touch
+    #[serde(default)]
+    pub input_latency: Vec<f64>,
+    #[serde(default)]
+    pub events: Vec<TimelineEvent>,
+    #[serde(default)]
+    pub markers: Vec<Marker>,
+    // Left out or filled in by analysis, see `GapPolicy`
+    #[serde(default)]
+    pub gaps: Vec<Gap>,
+    // The engine's own frame timings, from Unreal's CSV profiler or a Unity import
+    #[serde(default)]
+    pub engine: Option<EngineTimings>,
+    // Per frame GPU statistics from the injected layer, see `gpu_layer`
+    #[serde(default)]
+    pub pipeline: Option<PipelineStats>,
+    // DWM's cost during windowed PC runs, from PresentMon captures of all processes
+    #[serde(default)]
+    pub compositor: Option<Compositor>,
+    // Sent by external tools through `ingest_events`
+    #[serde(default)]
+    pub annotations: Channels,
+    // Stats of the full data, set once frames were dropped by retention
+    #[serde(default)]
+    pub summary: Option<Stats>,
+    // Priority and affinity the game was run with, None when left alone
+    #[serde(default)]
+    pub tuning: Option<Tuning>,
+    #[serde(default)]
+    pub clock_limits: Option<ClockLimits>,
+    // Other processes at the start of the capture
+    #[serde(default)]
+    pub background: Option<BackgroundAudit>,
+    #[serde(default)]
+    pub system: Option<SystemSnapshot>,
+    // Temperatures while waiting for them to settle before the capture
+    #[serde(default)]
+    pub soak: Option<SoakReport>,
+    #[serde(default)]
+    pub health: CaptureHealth,
+    #[serde(default)]
+    pub overhead: Overhead,
+    // To place device timestamps on `elapsed_ms`, None for imports
+    #[serde(default)]
+    pub clock: Option<ClockSync>,
+    // Set when the game crashed or lost its GPU device, the capture ends there
+    #[serde(default)]
+    pub interrupted: Option<Interruption>,
+}
+
+const MAX_PRESENTS: usize = 512;
+// Samples further apart than this many intervals leave a gap
+const GAP_INTERVALS: u64 = 2;
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+// Between telling the user the game restarts for the GPU layer and stopping it
+const RESTART_NOTICE: Duration = Duration::from_secs(3);
+
+#[derive(Default)]
+pub struct FrameTracker {
+    layer: Option<String>,
+    last_present: u64,
+    refresh_period: u64,
+    // Latest presents, to line inputs up with
+    presents: Vec<u64>,
+}
+
+impl FrameTracker {
+    pub fn poll(&mut self, package: &str) -> Result<(Vec<f64>, u64)> {
+        let layer = match &self.layer {
+            Some(layer) => layer.clone(),
+            None => {
+                let layer = util::surface_layer(package)?;
+                self.layer = Some(layer.clone());
+                layer
+            }
+        };
+
+        let (refresh_period, presents) = util::dump_latency(&layer)?;
+        self.refresh_period = refresh_period;
+        Ok(self.track(refresh_period, &presents))
+    }
+
+    // Frame times since the last dump and an estimate of the frames lost in between
+    fn track(&mut self, refresh_period: u64, presents: &[u64]) -> (Vec<f64>, u64) {
+        let mut dropped = 0;
+        // SurfaceFlinger only keeps the last 128 frames, when even the oldest one is new the
+        // buffer wrapped since the last dump and the gap would show up as one huge frame
+        let overrun = match presents.first() {
+            Some(&first) => self.last_present != 0 && first > self.last_present,
+            None => false,
+        };
+        if overrun {
+            let gap = presents[0] - self.last_present;
+            dropped = (gap / refresh_period.max(1)).saturating_sub(1).max(1);
+            self.last_present = presents[0];
+        }
+
+        let mut frametimes = vec![];
+        for &present in presents {
+            if present <= self.last_present {
+                continue;
+            }
+            if self.last_present != 0 {
+                frametimes.push((present - self.last_present) as f64 / 1_000_000.0);
+            }
+            self.last_present = present;
+            self.presents.push(present);
+        }
+        let excess = self.presents.len().saturating_sub(MAX_PRESENTS);
+        self.presents.drain(..excess);
+        (frametimes, dropped)
+    }
+}
+
+pub struct Recorder {
+    started: Instant,
+    // Unix time in ms, for events timed on the wall clock
+    started_at_ms: u64,
+    inbox: Inbox,
+    frames: FrameTracker,
+    overhead: OverheadMeter,
+    // None when `getevent` isn't available
+    input: Option<InputMonitor>,
+    audio: Option<AudioMonitor>,
+    // None when logcat can't be read
+    syslog: Option<SystemLog>,
+    trace: Option<TraceSession>,
+    unreal: Option<CsvProfiler>,
+    gpu_layer: Option<GpuLayer>,
+    // Battery drain per sample, for handhelds, read where the battery is
+    battery: Option<Host>,
+    // simpleperf on the game's threads, None when not asked for or not allowed
+    cpu_counters: Option<CpuCounters>,
+    gpu_counters: bool,
+    // Last `thermal.status`, throttling onset and release go on the timeline
+    thermal_status: u32,
+    // The game wasn't in front at the last sample, see `focus`
+    unfocused: bool,
+    pending_inputs: Vec<u64>,
+    // How often `poll` is meant to be called
+    interval_ms: u64,
+    // None when the device's screen state can't be read
+    device: Option<DeviceWatcher>,
+    // Since when the screen is off, nothing is sampled until it's back on
+    asleep_since: Option<u64>,
+    // Seen by `watch_device`, for the app to follow, see `device_changes`
+    changes: Vec<Change>,
+    // The game's, once it answered, to tell its log lines and its exit apart
+    pid: Option<u32>,
+    // Set with `--mock-capture`, nothing else is polled then
+    mock: Option<MockProvider>,
+    // iPhones and iPads, see `Recorder::ios`
+    ios: Option<GraphicsStream>,
+    // Memory at the last sample, as the live charts show it
+    pss: Option<PssInfo>,
+    // `top` takes a while to sample, `recording.background` is set once it's done
+    audit: Option<JoinHandle<Option<BackgroundAudit>>>,
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(package: &str) -> Self {
+        if let Some(pattern) = mock::selected() {
+            return Self::mock(package, pattern);
+        }
+        let started_at_ms =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+        let started = Instant::now();
+        let time_server = settings::get().time_server;
+        Recorder {
+            started,
+            started_at_ms: started_at_ms as u64,
+            inbox: Inbox::open(),
+            frames: FrameTracker::default(),
+            overhead: OverheadMeter::new(),
+            input: InputMonitor::start().map_err(|err| log::warn!("input: {}", err)).ok(),
+            audio: AudioMonitor::new(package).map_err(|err| log::warn!("audio: {}", err)).ok(),
+            syslog: SystemLog::start().map_err(|err| log::warn!("syslog: {}", err)).ok(),
+            trace: None,
+            unreal: None,
+            gpu_layer: None,
+            battery: None,
+            cpu_counters: None,
+            gpu_counters: false,
+            thermal_status: thermal::STATUS_NONE,
+            unfocused: false,
+            pending_inputs: vec![],
+            interval_ms: DEFAULT_INTERVAL_MS,
+            device: DeviceWatcher::new().map_err(|err| log::warn!("power: {}", err)).ok(),
+            asleep_since: None,
+            changes: vec![],
+            pid: None,
+            mock: None,
+            ios: None,
+            pss: None,
+            audit: Some(thread::spawn({
+                let package = package.to_string();
+                move || audit::run(&package).map_err(|err| log::warn!("audit: {}", err)).ok()
+            })),
+            recording: Recording {
+                package: package.into(),
+                started_at: (started_at_ms / 1000) as u64,
+                system: Some(system::device_snapshot()),
+                clock: Some(ClockSync::measure(started, time_server.as_deref())),
+                ..Default::default()
+            },
+        }
+    }
+
+    // Without a device, see `mock`
+    fn mock(package: &str, pattern: Pattern) -> Self {
+        let started_at_ms =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+        let mock = MockProvider::new(pattern);
+        let refresh_rate = Some(mock.refresh_rate());
+        Recorder {
+            started: Instant::now(),
+            started_at_ms: started_at_ms as u64,
+            inbox: Inbox::open(),
+            frames: FrameTracker::default(),
+            overhead: OverheadMeter::new(),
+            input: None,
+            audio: None,
+            syslog: None,
+            trace: None,
+            unreal: None,
+            gpu_layer: None,
+            battery: None,
+            cpu_counters: None,
+            gpu_counters: false,
+            thermal_status: thermal::STATUS_NONE,
+            unfocused: false,
+            pending_inputs: vec![],
+            interval_ms: DEFAULT_INTERVAL_MS,
+            device: None,
+            asleep_since: None,
+            changes: vec![],
+            pid: None,
+            mock: Some(mock),
+            ios: None,
+            pss: None,
+            audit: None,
+            recording: Recording {
+                package: package.into(),
+                started_at: (started_at_ms / 1000) as u64,
+                refresh_rate,
+                ..Default::default()
+            },
+        }
+    }
+
+    // An app on a tethered iPhone or iPad by bundle id, any device when there's only one. iOS
+    // only tells the frame rate (through `bridge`, see `GraphicsStream`) and temperature.
+    pub fn ios(bundle_id: &str, udid: Option<&str>, bridge: &Path) -> Result<Self> {
+        let udid = ios::resolve(udid)?;
+        let graphics = GraphicsStream::start(bridge, &udid)?;
+        let started_at_ms =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+        Ok(Recorder {
+            started: Instant::now(),
+            started_at_ms: started_at_ms as u64,
+            inbox: Inbox::open(),
+            frames: FrameTracker::default(),
+            overhead: OverheadMeter::new(),
+            input: None,
+            audio: None,
+            syslog: None,
+            trace: None,
+            unreal: None,
+            gpu_layer: None,
+            battery: None,
+            cpu_counters: None,
+            gpu_counters: false,
+            thermal_status: thermal::STATUS_NONE,
+            unfocused: false,
+            pending_inputs: vec![],
+            interval_ms: DEFAULT_INTERVAL_MS,
+            device: None,
+            asleep_since: None,
+            changes: vec![],
+            pid: None,
+            mock: None,
+            ios: Some(graphics),
+            pss: None,
+            audit: None,
+            recording: Recording {
+                package: bundle_id.into(),
+                started_at: (started_at_ms / 1000) as u64,
+                system: Some(ios::device_snapshot(&udid)),
+                ..Default::default()
+            },
+        })
+    }
+
+    // Polls later than expected are recorded as gaps
+    pub fn interval(&mut self, interval: Duration) {
+        self.interval_ms = (interval.as_millis() as u64).max(1);
+    }
+
+    // The game's trace markers, as annotation channels once finished
+    pub fn trace(&mut self, settings: &TraceSettings) -> Result<()> {
+        self.trace = Some(TraceSession::start(&self.recording.package, settings)?);
+        Ok(())
+    }
+
+    // Engine timings for Unreal games (development builds), merged in once finished
+    pub fn unreal(&mut self) -> Result<()> {
+        self.unreal = Some(CsvProfiler::start(&self.recording.package)?);
+        Ok(())
+    }
+
+    // Opt-in, the game has to be debuggable. It is restarted with the layer, `RESTART_NOTICE`
+    // after `warn` is called with the package.
+    pub fn gpu_layer(&mut self, library: &Path, warn: impl FnOnce(&str)) -> Result<()> {
+        let layer = GpuLayer::enable(&self.recording.package, library)?;
+        log::warn!("restarting {} with the GPU layer", self.recording.package);
+        warn(&self.recording.package);
+        thread::sleep(RESTART_NOTICE);
+        layer.restart_game()?;
+        self.gpu_layer = Some(layer);
+        Ok(())
+    }
+
+    // Also records the power limits read from `tdp` files and the battery's capacity
+    pub fn battery(&mut self, host: Host, tdp: &[String]) -> Result<()> {
+        let reading = battery::read(host)?;
+        let system = self.recording.system.get_or_insert_with(SystemSnapshot::default);
+        system.device.extend(battery::tdp_settings(host, tdp)?);
+        if let Some(capacity_wh) = reading.capacity_wh {
+            system.device.insert(battery::CAPACITY.into(), format!("{:.1}", capacity_wh));
+        }
+        self.battery = Some(host);
+        Ok(())
+    }
+
+    // CPU counters per thread and the GPU's clock and utilization, each as far as the device allows
+    pub fn counters(&mut self) -> Result<()> {
+        let pid = util::pid_of(&self.recording.package)?.parse()?;
+        self.cpu_counters =
+            CpuCounters::start(pid).map_err(|err| log::warn!("simpleperf: {}", err)).ok();
+        self.gpu_counters = match counters::gpu_metrics() {
+            Ok(_) => true,
+            Err(err) if self.cpu_counters.is_some() => {
+                log::warn!("gpu counters: {}", err);
+                false
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(())
+    }
+
+    pub fn interrupted(&self) -> Option<&Interruption> {
+        self.recording.interrupted.as_ref()
+    }
+
+    // None until the audit is done
+    pub fn background(&self) -> Option<&BackgroundAudit> {
+        self.recording.background.as_ref()
+    }
+
+    fn collect_audit(&mut self, wait: bool) {
+        if !self.audit.as_ref().map_or(false, |audit| wait || audit.is_finished()) {
+            return;
+        }
+        if let Some(audit) = self.audit.take() {
+            self.recording.background = audit.join().ok().flatten();
+        }
+    }
+
+    pub fn pss(&self) -> Option<&PssInfo> {
+        self.pss.as_ref()
+    }
+
+    // False while there is no surface to read yet (loading screens) or the game isn't in front,
+    // samples come without FPS then
+    pub fn expects_frames(&self) -> bool {
+        self.frames.layer.is_some() && !self.unfocused
+    }
+
+    // Frame tracking starts over, e.g. when the surface was recreated
+    pub fn restart_frames(&mut self) {
+        self.frames = FrameTracker::default();
+    }
+
+    // Time not captured on purpose (paused, the device asleep) is a gap of its own rather than a
+    // stall, the frames after it don't span it
+    pub fn skip(&mut self, reason: &str) {
+        let start_ms = self.recording.samples.last().map_or(0, |sample| sample.elapsed_ms);
+        let end_ms = self.started.elapsed().as_millis() as u64;
+        self.recording.gaps.push(Gap { start_ms, end_ms, reason: reason.into() });
+        self.restart_frames();
+    }
+
+    // None while the device sleeps and once the capture was interrupted
+    pub fn poll(&mut self) -> Result<Option<&Sample>> {
+        let package = self.recording.package.clone();
+        let mut sample =
+            Sample { elapsed_ms: self.started.elapsed().as_millis() as u64, ..Default::default() };
+        if self.recording.interrupted.is_some() {
+            return Ok(None);
+        }
+        // Overrides can be set at any time during the run, the last ones applied are kept even
+        // when they are restored before `finish`
+        if let Some(tuning) = tuning::applied() {
+            self.recording.tuning = Some(tuning);
+        }
+        if let Some(limits) = clocks::applied() {
+            self.recording.clock_limits = Some(limits);
+        }
+        self.collect_audit(false);
+        if let Some(mock) = &mut self.mock {
+            let pss = mock.pss(&package, sample.elapsed_ms);
+            for (name, value) in pss.metrics() {
+                sample.metrics.insert(format!("mem.{}", metric_key(name)), value as f64);
+            }
+            let sensors = mock.sensors(sample.elapsed_ms);
+            let frametimes = mock.frametimes(sample.elapsed_ms);
+            self.mark_throttling(sample.elapsed_ms, sensors.get(thermal::STATUS_METRIC).copied());
+            sample.metrics.extend(sensors);
+            if !frametimes.is_empty() {
+                let avg = frametimes.iter().sum::<f64>() / frametimes.len() as f64;
+                sample.metrics.insert("fps".into(), 1000.0 / avg);
+            }
+            self.recording.health.delivered(health::FRAMES, frametimes.len() as u64);
+            self.recording.frametimes.extend(frametimes);
+            let events = self.inbox.take();
+            annotation::merge(&mut self.recording.annotations, &events, self.started_at_ms);
+            self.pss = Some(pss);
+            self.recording.health.delivered(health::SAMPLES, 1);
+            self.recording.samples.push(sample);
+            return Ok(self.recording.samples.last());
+        }
+        if let Some(graphics) = &self.ios {
+            let probe = Instant::now();
+            let fps = graphics.drain();
+            if !fps.is_empty() {
+                sample.metrics.insert("fps".into(), fps.iter().sum::<f64>() / fps.len() as f64);
+            }
+            if let Some(temp) = graphics.temperature() {
+                sample.metrics.insert("temp.battery".into(), temp);
+            }
+            let events = self.inbox.take();
+            annotation::merge(&mut self.recording.annotations, &events, self.started_at_ms);
+            self.overhead.sample(probe.elapsed());
+            self.recording.health.delivered(health::SAMPLES, 1);
+            self.recording.samples.push(sample);
+            return Ok(self.recording.samples.last());
+        }
+        self.watch_device(sample.elapsed_ms);
+        if self.asleep_since.is_some() {
+            return Ok(None);
+        }
+        // Sleeping already left a gap
+        let last_ms = self.recording.samples.last().map(|last| last.elapsed_ms);
+        if let Some(last_ms) = last_ms.max(self.recording.gaps.last().map(|gap| gap.end_ms)) {
+            if sample.elapsed_ms - last_ms > GAP_INTERVALS * self.interval_ms {
+                let gap =
+                    Gap { start_ms: last_ms, end_ms: sample.elapsed_ms, reason: "stall".into() };
+                self.recording.gaps.push(gap);
+            }
+        }
+
+        let probe = Instant::now();
+        let pss = match util::dump_pss(&package) {
+            Ok(pss) => pss,
+            Err(err) => {
+                // The logs usually tell why the game went away
+                self.read_syslog(sample.elapsed_ms);
+                if !self.exited(sample.elapsed_ms) {
+                    return Err(err);
+                }
+                return Ok(None);
+            }
+        };
+        if self.pid.is_none() {
+            self.pid = util::pid_of(&package).ok().and_then(|pid| pid.parse().ok());
+        }
+        for (name, value) in pss.metrics() {
+            sample.metrics.insert(format!("mem.{}", metric_key(name)), value as f64);
+        }
+        self.pss = Some(pss);
+
+        // Frames while the game is switched away from or covered aren't its performance, nor
+        // the first ones back that span the time away
+        let focus = focus::read(&package).unwrap_or_else(|err| {
+            log::debug!("focus: {}", err);
+            Focus::Foreground
+        });
+        sample.metrics.insert(focus::METRIC.into(), focus.value());
+        let unfocused = self.unfocused || focus != Focus::Foreground;
+        self.unfocused = focus != Focus::Foreground;
+
+        // The surface may not exist yet (loading screen), memory is still worth recording
+        match self.frames.poll(&package) {
+            Ok((frametimes, dropped)) => {
+                self.recording.health.delivered(health::FRAMES, frametimes.len() as u64);
+                self.recording.health.dropped(health::FRAMES, dropped);
+                let frametimes = if unfocused { vec![] } else { frametimes };
+                if !frametimes.is_empty() {
+                    let avg = frametimes.iter().sum::<f64>() / frametimes.len() as f64;
+                    sample.metrics.insert("fps".into(), 1000.0 / avg);
+                }
+                self.recording.frametimes.extend(frametimes);
+            }
+            Err(err) => log::debug!("{}", err),
+        }
+        // Dynamic resolution and in-game settings resize the game's buffers
+        let due = self.recording.samples.len() % resolution::POLL_EVERY == 0;
+        if let (Some(layer), true) = (&self.frames.layer, due) {
+            match resolution::read(layer) {
+                Ok(Some(size)) => {
+                    resolution::annotate(&mut self.recording.annotations, sample.elapsed_ms, size)
+                }
+                Ok(None) => (),
+                Err(err) => log::debug!("resolution: {}", err),
+            }
+        }
+        if let Some(input) = &self.input {
+            let mut inputs = std::mem::take(&mut self.pending_inputs);
+            // Controller presses on the timeline, at this sample when the device clock is unknown
+            let clock = self.recording.clock.as_ref();
+            let mut buttons: BTreeMap<(u64, String), u64> = BTreeMap::new();
+            for pressed in input.drain() {
+                if let Some(button) = pressed.button {
+                    let elapsed_ms = clock
+                        .and_then(|clock| clock.device_elapsed_ms(pressed.timestamp))
+                        .unwrap_or(sample.elapsed_ms);
+                    let kind = format!("controller.{}", button);
+                    *buttons.entry((elapsed_ms, kind)).or_default() += 1;
+                }
+                inputs.push(pressed.timestamp);
+            }
+            for ((elapsed_ms, kind), count) in buttons {
+                self.recording.events.push(TimelineEvent { elapsed_ms, kind, count });
+            }
+            let (latencies, pending) = input::latencies(&inputs, &self.frames.presents);
+            if !latencies.is_empty() {
+                let avg = latencies.iter().sum::<f64>() / latencies.len() as f64;
+                sample.metrics.insert("input_latency".into(), avg);
+            }
+            self.recording.input_latency.extend(latencies);
+            self.pending_inputs = pending;
+        }
+        if let Some(audio) = &mut self.audio {
+            match audio.poll() {
+                Ok(underruns) => {
+                    sample.metrics.insert("audio_underruns".into(), underruns as f64);
+                    if underruns > 0 {
+                        self.recording.events.push(TimelineEvent {
+                            elapsed_ms: sample.elapsed_ms,
+                            kind: "audio_glitch".into(),
+                            count: underruns,
+                        });
+                    }
+                }
+                Err(err) => log::debug!("audio: {}", err),
+            }
+        }
+        if let Some(counters) = &self.cpu_counters {
+            sample.metrics.extend(counters.metrics());
+        }
+        if self.gpu_counters {
+            match counters::gpu_metrics() {
+                Ok(metrics) => sample.metrics.extend(metrics),
+                Err(err) => log::debug!("gpu counters: {}", err),
+            }
+        }
+        match thermal::sample() {
+            Ok(metrics) => {
+                let status = metrics.iter().find(|m| m.0 == thermal::STATUS_METRIC).map(|m| m.1);
+                self.mark_throttling(sample.elapsed_ms, status);
+                sample.metrics.extend(metrics);
+            }
+            Err(err) => log::debug!("thermal: {}", err),
+        }
+        if let Some(host) = self.battery {
+            match battery::read(host) {
+                Ok(reading) => sample.metrics.extend(reading.metrics()),
+                Err(err) => log::debug!("battery: {}", err),
+            }
+        }
+        self.read_syslog(sample.elapsed_ms);
+        let events = self.inbox.take();
+        annotation::merge(&mut self.recording.annotations, &events, self.started_at_ms);
+        self.overhead.sample(probe.elapsed());
+
+        self.recording.health.delivered(health::SAMPLES, 1);
+        self.recording.samples.push(sample);
+        Ok(self.recording.samples.last())
+    }
+
+    // Device log entries as annotations, the first one hitting the game interrupts the capture
+    fn read_syslog(&mut self, now_ms: u64) {
+        let syslog = match &self.syslog {
+            Some(syslog) => syslog,
+            None => return,
+        };
+        let clock = self.recording.clock.as_ref();
+        let annotations = self.recording.annotations.entry(syslog::CHANNEL.into()).or_default();
+        for entry in syslog.drain() {
+            let elapsed_ms =
+                clock.and_then(|clock| clock.device_elapsed_ms(entry.timestamp)).unwrap_or(now_ms);
+            let reason =
+                self.pid.and_then(|pid| interrupt::classify(&entry, &self.recording.package, pid));
+            if let Some(reason) = reason.filter(|_| self.recording.interrupted.is_none()) {
+                log::warn!("capture interrupted, {}", entry.label());
+                let detail = entry.message.clone();
+                self.recording.interrupted = Some(Interruption { elapsed_ms, reason, detail });
+            }
+            annotations.push(Annotation { elapsed_ms, label: entry.label(), value: None });
+        }
+        if annotations.is_empty() {
+            self.recording.annotations.remove(syslog::CHANNEL);
+        }
+    }
+
+    // The game's process is gone or was started over, whether or not the logs said why
+    fn exited(&mut self, now_ms: u64) -> bool {
+        let pid = match self.pid {
+            Some(pid) => pid,
+            None => return false,
+        };
+        let running = util::pid_of(&self.recording.package).ok().and_then(|pid| pid.parse().ok());
+        if running == Some(pid) {
+            return false;
+        }
+        if self.recording.interrupted.is_none() {
+            let detail = match running {
+                Some(restarted) => format!("Process {} replaced by {}", pid, restarted),
+                None => format!("Process {} exited", pid),
+            };
+            log::warn!("capture interrupted, {}", detail);
+            let interruption = Interruption { elapsed_ms: now_ms, reason: Reason::Exited, detail };
+            self.recording.interrupted = Some(interruption);
+        }
+        true
+    }
+
+    // Sleeping leaves a gap and starts frame tracking over, display changes are annotated
+    fn watch_device(&mut self, elapsed_ms: u64) {
+        let changes = match &mut self.device {
+            Some(device) => device.poll().unwrap_or_else(|err| {
+                log::debug!("power: {}", err);
+                vec![]
+            }),
+            None => return,
+        };
+        for change in changes {
+            let kind = match &change {
+                Change::Sleep => {
+                    self.asleep_since = Some(elapsed_ms);
+                    "sleep"
+                }
+                Change::Wake => {
+                    if let Some(start_ms) = self.asleep_since.take() {
+                        let gap = Gap { start_ms, end_ms: elapsed_ms, reason: "sleep".into() };
+                        self.recording.gaps.push(gap);
+                    }
+                    self.frames = FrameTracker::default();
+                    "wake"
+                }
+                Change::Display(display) => {
+                    let annotations = self.recording.annotations.entry("display".into());
+                    annotations.or_default().push(Annotation {
+                        elapsed_ms,
+                        label: display.clone(),
+                        value: None,
+                    });
+                    self.frames = FrameTracker::default();
+                    "display_changed"
+                }
+            };
+            self.recording.events.push(TimelineEvent { elapsed_ms, kind: kind.into(), count: 1 });
+            self.changes.push(change);
+        }
+    }
+
+    // Device changes since the last call, the recording already has their gaps and annotations
+    pub fn device_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    // From `thermal.status`, None when the device doesn't report it
+    fn mark_throttling(&mut self, elapsed_ms: u64, status: Option<f64>) {
+        let status = match status {
+            Some(status) => status as u32,
+            None => return,
+        };
+        if let Some(kind) = thermal::transition(self.thermal_status, status) {
+            self.recording.events.push(TimelineEvent { elapsed_ms, kind: kind.into(), count: 1 });
+        }
+        self.thermal_status = status;
+    }
+
+    pub fn finish(mut self) -> Recording {
+        self.recording.duration_ms = self.started.elapsed().as_millis() as u64;
+        self.collect_audit(true);
+        self.recording.tuning = tuning::applied().or(self.recording.tuning.take());
+        self.recording.clock_limits = clocks::applied().or(self.recording.clock_limits.take());
+        self.recording.gaps.extend(analysis::focus::losses(&self.recording.samples));
+        self.recording.gaps.sort_by_key(|gap| gap.start_ms);
+        self.recording.overhead = self.overhead.overhead();
+        if self.frames.refresh_period > 0 {
+            self.recording.refresh_rate = Some(1e9 / self.frames.refresh_period as f64);
+        }
+        self.recording.generated = framegen::detect(&self.recording.frametimes).unwrap_or_default();
+        if let Some(trace) = self.trace.take() {
+            match trace.finish(self.recording.clock.as_ref()) {
+                Ok(channels) => self.recording.annotations.extend(channels),
+                Err(err) => log::warn!("trace: {}", err),
+            }
+        }
+        if let Some(profiler) = self.unreal.take() {
+            match profiler.finish() {
+                Ok(engine) => {
+                    engine.add_metrics(&mut self.recording.samples);
+                    self.recording.engine = Some(engine);
+                }
+                Err(err) => log::warn!("unreal: {}", err),
+            }
+        }
+        if let Some(layer) = self.gpu_layer.take() {
+            match (layer.finish(), &self.recording.clock) {
+                (Ok(pipeline), Some(clock)) => {
+                    pipeline.add_metrics(&mut self.recording.samples, clock);
+                    // Every present's extent beats the polled buffer size
+                    let changes = pipeline.extent_changes(clock);
+                    if !changes.is_empty() {
+                        self.recording.annotations.remove(resolution::CHANNEL);
+                    }
+                    for (elapsed_ms, extent) in changes {
+                        resolution::annotate(&mut self.recording.annotations, elapsed_ms, extent);
+                    }
+                    self.recording.pipeline = Some(pipeline);
+                }
+                (Ok(pipeline), None) => self.recording.pipeline = Some(pipeline),
+                (Err(err), _) => log::warn!("gpu layer: {}", err),
+            }
+        }
+        self.recording
+    }
+}
+
+pub fn metric_key(name: &str) -> String {
+    name.trim().to_lowercase().split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_overrun() {
+        let mut frames = FrameTracker::default();
+        let (frametimes, dropped) = frames.track(10, &[100, 110, 125]);
+        assert_eq!(frametimes, vec![10.0 / 1_000_000.0, 15.0 / 1_000_000.0]);
+        assert_eq!(dropped, 0);
+
+        // Overlaps the previous dump
+        let (frametimes, dropped) = frames.track(10, &[110, 125, 135]);
+        assert_eq!(frametimes.len(), 1);
+        assert_eq!(dropped, 0);
+
+        // Nothing in common with the previous dump
+        let (frametimes, dropped) = frames.track(10, &[235, 245]);
+        assert_eq!(frametimes, vec![10.0 / 1_000_000.0]);
+        assert_eq!(dropped, 9);
+    }
+}
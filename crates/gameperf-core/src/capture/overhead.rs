@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, ProcessExt, System, SystemExt};
+
+// GamePerf's own footprint while capturing. The webview's GPU time can't be attributed per
+// process here, low-impact mode keeps it out of the way instead.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Overhead {
+    // Percent of one core
+    pub cpu_avg: f64,
+    pub cpu_max: f64,
+    // Bytes
+    pub memory_peak: u64,
+    // Time spent querying the device per sample, adb and dumpsys run on the device too
+    pub probe_ms_avg: f64,
+    pub probe_ms_max: f64,
+}
+
+pub struct OverheadMeter {
+    system: System,
+    pid: Option<Pid>,
+    samples: u32,
+    cpu_total: f64,
+    probe_ms_total: f64,
+    overhead: Overhead,
+}
+
+impl OverheadMeter {
+    pub fn new() -> Self {
+        OverheadMeter {
+            system: System::new(),
+            pid: sysinfo::get_current_pid().ok(),
+            samples: 0,
+            cpu_total: 0.0,
+            probe_ms_total: 0.0,
+            overhead: Overhead::default(),
+        }
+    }
+
+    pub fn sample(&mut self, probe: Duration) {
+        self.samples += 1;
+        let probe_ms = probe.as_secs_f64() * 1000.0;
+        self.probe_ms_total += probe_ms;
+        self.overhead.probe_ms_max = self.overhead.probe_ms_max.max(probe_ms);
+        self.overhead.probe_ms_avg = self.probe_ms_total / self.samples as f64;
+
+        let pid = match self.pid {
+            Some(pid) if self.system.refresh_process(pid) => pid,
+            _ => return,
+        };
+        if let Some(process) = self.system.process(pid) {
+            let cpu = process.cpu_usage() as f64;
+            self.cpu_total += cpu;
+            self.overhead.cpu_max = self.overhead.cpu_max.max(cpu);
+            self.overhead.cpu_avg = self.cpu_total / self.samples as f64;
+            self.overhead.memory_peak = self.overhead.memory_peak.max(process.memory());
+        }
+    }
+
+    pub fn overhead(&self) -> Overhead {
+        self.overhead.clone()
+    }
+}
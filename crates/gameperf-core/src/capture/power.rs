@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::util;
+
+// One round trip for the screen state and the display layout
+const STATUS: &str =
+    "shell dumpsys power | grep mWakefulness= ; dumpsys SurfaceFlinger --display-id ; wm size";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Sleep,
+    Wake,
+    // The new layout, e.g. a display plugged in or a resolution switch
+    Display(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Status {
+    awake: bool,
+    // Connected displays and their sizes, compared as a whole
+    displays: Vec<String>,
+}
+
+fn status() -> Result<Status> {
+    let (_, stdout, _) = util::adb(STATUS.into())?;
+    Ok(parse_status(&stdout))
+}
+
+// Dozing and dreaming count as asleep, the game isn't on screen
+fn parse_status(output: &str) -> Status {
+    let lines = output.lines().map(str::trim);
+    let awake = lines
+        .clone()
+        .find_map(|line| line.strip_prefix("mWakefulness="))
+        .map_or(true, |wakefulness| wakefulness == "Awake");
+    let displays = lines
+        .filter(|line| line.starts_with("Display ") || line.contains(" size: "))
+        .map(str::to_string)
+        .collect();
+    Status { awake, displays }
+}
+
+// Screen and display changes of the device, frame times across them don't mean anything
+pub struct DeviceWatcher {
+    status: Status,
+    polled: Instant,
+}
+
+impl DeviceWatcher {
+    pub fn new() -> Result<Self> {
+        Ok(DeviceWatcher { status: status()?, polled: Instant::now() })
+    }
+
+    pub fn is_awake(&self) -> bool {
+        self.status.awake
+    }
+
+    // At most once per `POLL_INTERVAL`, nothing in between
+    pub fn poll(&mut self) -> Result<Vec<Change>> {
+        if self.polled.elapsed() < POLL_INTERVAL {
+            return Ok(vec![]);
+        }
+        self.polled = Instant::now();
+        let status = status()?;
+        let mut changes = vec![];
+        if status.awake != self.status.awake {
+            changes.push(if status.awake { Change::Wake } else { Change::Sleep });
+        }
+        self.status.awake = status.awake;
+        // The display list is empty while the screen is off on some devices
+        if !status.displays.is_empty() {
+            if !self.status.displays.is_empty() && status.displays != self.status.displays {
+                changes.push(Change::Display(status.displays.join(", ")));
+            }
+            self.status.displays = status.displays;
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let output = "  mWakefulness=Asleep\n\
+                      Display 4619827259835644672 (HWC display 0): port=0 displayName=\"Built-in\"\n\
+                      Physical size: 1080x2400\n\
+                      Override size: 720x1600\n";
+        let status = parse_status(output);
+        assert!(!status.awake);
+        assert_eq!(status.displays.len(), 3);
+        assert_eq!(status.displays[1], "Physical size: 1080x2400");
+
+        assert!(parse_status("mWakefulness=Awake\n").awake);
+        assert!(!parse_status("mWakefulness=Dozing\n").awake);
+    }
+}
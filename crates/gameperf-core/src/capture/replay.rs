@@ -0,0 +1,274 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::Child;
+use std::sync::Arc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::settings;
+use crate::util;
+
+const REMOTE_SCRIPT: &str = "/data/local/tmp/gameperf_replay.sh";
+// Shorter gaps are sent back to back, `sendevent` itself takes about that long
+const MIN_SLEEP_US: u64 = 1000;
+
+lazy_static! {
+    static ref RECORDER: Mutex<Option<InputRecorder>> = Mutex::new(None);
+    static ref REPLAY: Mutex<Option<Replay>> = Mutex::new(None);
+}
+
+// Raw evdev event, replayed as is with `sendevent`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputEvent {
+    // Since the first recorded event
+    pub at_us: u64,
+    pub device: String,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputScript {
+    pub name: String,
+    pub recorded_at: u64,
+    pub duration_ms: u64,
+    pub events: Vec<InputEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputScriptInfo {
+    pub name: String,
+    pub recorded_at: u64,
+    pub duration_ms: u64,
+    pub events: usize,
+}
+
+impl InputScript {
+    fn info(&self) -> InputScriptInfo {
+        InputScriptInfo {
+            name: self.name.clone(),
+            recorded_at: self.recorded_at,
+            duration_ms: self.duration_ms,
+            events: self.events.len(),
+        }
+    }
+
+    // One `sendevent` per event, sleeping through the recorded gaps. An edited script may be out
+    // of order, an event before the last one goes out right away.
+    fn to_shell(&self) -> String {
+        let mut script = String::new();
+        let mut last_us = 0;
+        for event in &self.events {
+            let gap = event.at_us.saturating_sub(last_us);
+            if gap >= MIN_SLEEP_US {
+                script.push_str(&format!("sleep {:.6}\n", gap as f64 / 1_000_000.0));
+                last_us = event.at_us;
+            }
+            script.push_str(&format!(
+                "sendevent {} {} {} {}\n",
+                event.device, event.kind, event.code, event.value
+            ));
+        }
+        script
+    }
+}
+
+// Every input device through `getevent -t`, until stopped
+struct InputRecorder {
+    child: Child,
+    events: Arc<Mutex<Vec<(u64, InputEvent)>>>,
+    started_at: u64,
+}
+
+impl Drop for InputRecorder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+struct Replay {
+    child: Child,
+}
+
+impl Drop for Replay {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+fn scripts_dir() -> Result<PathBuf> {
+    Ok(settings::get().data_dir.context("No data directory")?.join("inputs"))
+}
+
+fn script_path(name: &str) -> Result<PathBuf> {
+    let valid =
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!("Invalid input script name: {}", name);
+    }
+    Ok(scripts_dir()?.join(format!("{}.json", name)))
+}
+
+pub fn start_recording() -> Result<()> {
+    let mut recorder = RECORDER.lock();
+    if recorder.is_some() {
+        bail!("Already recording inputs");
+    }
+    let mut child = util::adb_spawn("shell getevent -t")?;
+    let stdout = child.stdout.take().context("No getevent output")?;
+    let events = Arc::new(Mutex::new(vec![]));
+    let sink = events.clone();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().flatten() {
+            if let Some(event) = parse_event(&line) {
+                sink.lock().push(event);
+            }
+        }
+    });
+    let started_at =
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    *recorder = Some(InputRecorder { child, events, started_at });
+    Ok(())
+}
+
+pub fn stop_recording(name: &str) -> Result<InputScriptInfo> {
+    let path = script_path(name)?;
+    let recorder = RECORDER.lock().take().context("Not recording inputs")?;
+    let recorded = std::mem::take(&mut *recorder.events.lock());
+    let first = recorded.first().map_or(0, |(timestamp, _)| *timestamp);
+    let events: Vec<InputEvent> = recorded
+        .into_iter()
+        .map(|(timestamp, event)| InputEvent { at_us: timestamp.saturating_sub(first), ..event })
+        .collect();
+    let script = InputScript {
+        name: name.into(),
+        recorded_at: recorder.started_at,
+        duration_ms: events.last().map_or(0, |event| event.at_us / 1000),
+        events,
+    };
+    fs::create_dir_all(scripts_dir()?)?;
+    fs::write(path, serde_json::to_vec(&script)?)?;
+    Ok(script.info())
+}
+
+pub fn list() -> Result<Vec<InputScriptInfo>> {
+    let dir = match fs::read_dir(scripts_dir()?) {
+        Ok(dir) => dir,
+        Err(_) => return Ok(vec![]),
+    };
+    let mut scripts = vec![];
+    for entry in dir.flatten() {
+        let script = fs::read(entry.path())
+            .ok()
+            .and_then(|json| serde_json::from_slice::<InputScript>(&json).ok());
+        if let Some(script) = script {
+            scripts.push(script.info());
+        }
+    }
+    scripts.sort_by(|a, b| b.recorded_at.cmp(&a.recorded_at));
+    Ok(scripts)
+}
+
+pub fn load(name: &str) -> Result<InputScript> {
+    let json =
+        fs::read(script_path(name)?).with_context(|| format!("Unknown input script: {}", name))?;
+    let script: InputScript = serde_json::from_slice(&json)?;
+    // Devices end up in a shell script
+    let valid = |device: &str| {
+        device.starts_with("/dev/input/")
+            && device.chars().all(|c| c.is_ascii_alphanumeric() || c == '/' || c == '_')
+    };
+    if let Some(event) = script.events.iter().find(|event| !valid(&event.device)) {
+        bail!("Invalid input device in {}: {}", name, event.device);
+    }
+    Ok(script)
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    fs::remove_file(script_path(name)?)?;
+    Ok(())
+}
+
+// Replaces a replay still running, the device plays the whole script on its own
+pub fn start_replay(name: &str) -> Result<InputScriptInfo> {
+    let script = load(name)?;
+    let local = std::env::temp_dir().join("gameperf_replay.sh");
+    fs::write(&local, script.to_shell())?;
+    util::adb_push(&local, REMOTE_SCRIPT)?;
+
+    let mut replay = REPLAY.lock();
+    *replay = None;
+    let child = util::adb_spawn(&format!("shell sh {}", REMOTE_SCRIPT))?;
+    *replay = Some(Replay { child });
+    Ok(script.info())
+}
+
+pub fn stop_replay() {
+    *REPLAY.lock() = None;
+}
+
+// `[   1234.567890] /dev/input/event2: 0003 0035 000001f4`, timestamp in µs
+fn parse_event(line: &str) -> Option<(u64, InputEvent)> {
+    let (timestamp, event) = line.trim_start_matches('[').split_once(']')?;
+    let (device, fields) = event.trim().split_once(": ")?;
+    let mut fields = fields.split_whitespace();
+    let kind = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let code = u16::from_str_radix(fields.next()?, 16).ok()?;
+    // Negative values (e.g. relative mouse moves) are printed as 32 bits two's complement
+    let value = u32::from_str_radix(fields.next()?, 16).ok()? as i32;
+    let secs: f64 = timestamp.trim().parse().ok()?;
+    let event = InputEvent { at_us: 0, device: device.into(), kind, code, value };
+    Some(((secs * 1_000_000.0).round() as u64, event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_script() {
+        let (timestamp, event) =
+            parse_event("[   1234.500000] /dev/input/event2: 0003 0035 000001f4").unwrap();
+        assert_eq!(timestamp, 1_234_500_000);
+        assert_eq!((event.kind, event.code, event.value), (3, 0x35, 500));
+        let (_, event) = parse_event("[   1234.5] /dev/input/event5: 0002 0000 ffffffff").unwrap();
+        assert_eq!(event.value, -1);
+        assert!(parse_event("add device 1: /dev/input/event2").is_none());
+
+        let event = |at_us, value| InputEvent {
+            at_us,
+            device: "/dev/input/event2".into(),
+            kind: 3,
+            code: 0x35,
+            value,
+        };
+        let script = InputScript {
+            name: "path".into(),
+            recorded_at: 0,
+            duration_ms: 20,
+            events: vec![event(0, 1), event(500, 2), event(20_000, 3)],
+        };
+        assert_eq!(
+            script.to_shell(),
+            "sendevent /dev/input/event2 3 53 1\n\
+             sendevent /dev/input/event2 3 53 2\n\
+             sleep 0.020000\n\
+             sendevent /dev/input/event2 3 53 3\n"
+        );
+
+        let script = InputScript { events: vec![event(20_000, 1), event(500, 2)], ..script };
+        assert_eq!(
+            script.to_shell(),
+            "sleep 0.020000\n\
+             sendevent /dev/input/event2 3 53 1\n\
+             sendevent /dev/input/event2 3 53 2\n"
+        );
+    }
+}
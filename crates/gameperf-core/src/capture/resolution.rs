@@ -0,0 +1,89 @@
+use anyhow::Result;
+
+use crate::util;
+
+use super::annotation::{Annotation, Channels};
+use super::Recording;
+
+// Annotation channel of the size the game renders at, labelled `<width>x<height>` from when it
+// changed, with the megapixels as value
+pub const CHANNEL: &str = "resolution";
+// SurfaceFlinger's full dump is large, the size is only read every few samples
+pub const POLL_EVERY: usize = 5;
+
+// Buffer size of the game's layer: dynamic resolution and in-game settings show up there while
+// the display stays the same
+pub fn read(layer: &str) -> Result<Option<(u32, u32)>> {
+    let (_, dump, _) = util::adb("shell dumpsys SurfaceFlinger".into())?;
+    Ok(parse_surfaceflinger(&dump, layer))
+}
+
+// `+ BufferStateLayer (SurfaceView[com.example.game/...]#0) uid=10123` starts the layer's
+// section, `activeBuffer=[1920x1080:1920,RGBA_8888]` is in it
+fn parse_surfaceflinger(dump: &str, layer: &str) -> Option<(u32, u32)> {
+    let header = format!("({})", layer);
+    let section = dump.split("\n+ ").find(|section| section.lines().next()?.contains(&header))?;
+    let (_, buffer) = section.split_once("activeBuffer=[")?;
+    let (width, rest) = buffer.split_once('x')?;
+    let height = rest.split(|c: char| !c.is_ascii_digit()).next()?;
+    let size = (width.trim().parse().ok()?, height.parse().ok()?);
+    Some(size).filter(|&(width, height)| width > 0 && height > 0)
+}
+
+// Adds an annotation when the size differs from the last one on the channel
+pub fn annotate(channels: &mut Channels, elapsed_ms: u64, (width, height): (u32, u32)) {
+    let label = format!("{}x{}", width, height);
+    let annotations = channels.entry(CHANNEL.into()).or_default();
+    if annotations.last().map_or(true, |last| last.label != label) {
+        let value = Some(width as f64 * height as f64 / 1e6);
+        annotations.push(Annotation { elapsed_ms, label, value });
+    }
+}
+
+// Sizes a run rendered at, in the order they first appeared
+pub fn sizes(recording: &Recording) -> Vec<String> {
+    let mut sizes: Vec<String> = vec![];
+    for annotation in recording.annotations.get(CHANNEL).into_iter().flatten() {
+        if !sizes.contains(&annotation.label) {
+            sizes.push(annotation.label.clone());
+        }
+    }
+    sizes
+}
+
+// Runs that didn't render at one and the same size don't compare, runs without any recorded
+// size are left out of the check
+pub fn mismatch(sizes: &[Vec<String>]) -> bool {
+    let known: Vec<&Vec<String>> = sizes.iter().filter(|sizes| !sizes.is_empty()).collect();
+    known.iter().any(|sizes| sizes.len() > 1 || sizes != &known[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution() {
+        let dump = "Display 0 HWC layers:\n\
+                    + BufferStateLayer (com.example.game/com.example.Main#0) uid=10123\n\
+                    \x20 activeBuffer=[1080x2400:1088,RGBA_8888]\n\
+                    + BufferStateLayer (SurfaceView[com.example.game/com.example.Main]#0) uid=10123\n\
+                    \x20 activeBuffer=[1280x720:1280,RGBA_8888]\n";
+        let layer = "SurfaceView[com.example.game/com.example.Main]#0";
+        assert_eq!(parse_surfaceflinger(dump, layer), Some((1280, 720)));
+        assert_eq!(parse_surfaceflinger(dump, "SurfaceView[other]#0"), None);
+
+        let mut recording = Recording::default();
+        annotate(&mut recording.annotations, 0, (1280, 720));
+        annotate(&mut recording.annotations, 5000, (1280, 720));
+        annotate(&mut recording.annotations, 9000, (960, 540));
+        assert_eq!(recording.annotations[CHANNEL].len(), 2);
+        assert_eq!(recording.annotations[CHANNEL][1].elapsed_ms, 9000);
+        assert_eq!(sizes(&recording), vec!["1280x720".to_string(), "960x540".to_string()]);
+
+        let fixed = vec!["1280x720".to_string()];
+        assert!(!mismatch(&[fixed.clone(), vec![], fixed.clone()]));
+        assert!(mismatch(&[fixed, vec!["1920x1080".into()]]));
+        assert!(mismatch(&[sizes(&recording)]));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::util;
+
+// Where kernel files are read: the adb device, or this machine for handhelds running GamePerf
+// themselves (a Steam Deck)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Host {
+    Device,
+    Local,
+}
+
+// Kernel files in one round trip, by path. Globs are expanded on the host and files that
+// don't exist (or can't be read) are left out.
+pub fn read(host: Host, paths: &[String]) -> Result<BTreeMap<String, String>> {
+    match host {
+        // grep fails when any file is missing, the others are still printed
+        Host::Device => {
+            let (_, stdout, _) = util::adb(format!("shell grep -H . {}; true", paths.join(" ")))?;
+            Ok(parse(&stdout))
+        }
+        Host::Local => Ok(read_local(paths)),
+    }
+}
+
+// The last non-empty line of each file, like `parse` keeps from `grep -H`
+fn read_local(paths: &[String]) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    for path in paths.iter().flat_map(|path| expand(path)) {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        if let Some(value) = text.lines().map(str::trim).filter(|line| !line.is_empty()).last() {
+            values.insert(path.to_string_lossy().into_owned(), value.to_string());
+        }
+    }
+    values
+}
+
+// Absolute paths with `*` in any component, sorted like the shell does
+fn expand(pattern: &str) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/")];
+    for component in pattern.split('/').filter(|component| !component.is_empty()) {
+        if !component.contains('*') {
+            paths.iter_mut().for_each(|path| path.push(component));
+            continue;
+        }
+        let mut expanded = vec![];
+        for path in &paths {
+            let mut names: Vec<String> = match fs::read_dir(path) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .filter(|name| matches(component, name))
+                    .collect(),
+                Err(_) => continue,
+            };
+            names.sort();
+            expanded.extend(names.into_iter().map(|name| path.join(name)));
+        }
+        paths = expanded;
+    }
+    paths
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i + 1 == parts.len() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+// `grep -H` prints `path:value`
+pub fn parse(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .map(|(path, value)| (path.to_string(), value.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("hwmon*", "hwmon3"));
+        assert!(matches("*", "BAT1"));
+        assert!(matches("power*_cap", "power1_cap"));
+        assert!(matches("power1_cap", "power1_cap"));
+        assert!(!matches("power1_cap", "power1_cap_max"));
+        assert!(!matches("power*_cap", "power1_cap_max"));
+        assert!(!matches("hwmon*", "thermal0"));
+    }
+}
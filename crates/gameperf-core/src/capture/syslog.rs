@@ -0,0 +1,125 @@
+use std::io::{BufRead, BufReader};
+use std::process::Child;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use regex::Regex;
+
+use crate::util;
+
+// The kernel buffer needs root on most devices, it runs on its own so the rest works without
+const BUFFERS: [&str; 2] = ["main,crash,events", "kernel"];
+pub const CHANNEL: &str = "system";
+const MAX_MESSAGE_LEN: usize = 200;
+
+lazy_static! {
+    // Kind, then what it's recognized by in `<tag>: <message>`
+    static ref RULES: Vec<(&'static str, Regex)> = vec![
+        (
+            "gpu_fault",
+            Regex::new(r"(?i)(kgsl|adreno|mali|gpu).*\b(fault|hang|reset|recover(y|ed)?)\b").unwrap(),
+        ),
+        ("crash", Regex::new(r"FATAL EXCEPTION|Fatal signal \d+").unwrap()),
+        (
+            "device_lost",
+            Regex::new(r"VK_ERROR_DEVICE_LOST|GL_(GUILTY|INNOCENT|UNKNOWN)_CONTEXT_RESET").unwrap(),
+        ),
+        ("anr", Regex::new(r"^am_anr:").unwrap()),
+        ("low_memory_kill", Regex::new(r"^(lowmemorykiller|lmkd)\b.*(?i)kill").unwrap()),
+        ("hardware_error", Regex::new(r"(?i)hardware error|\bEDAC\b").unwrap()),
+    ];
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    // Device CLOCK_MONOTONIC, in ns
+    pub timestamp: u64,
+    // Of the process that logged it, 0 for the kernel
+    pub pid: u32,
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl Entry {
+    pub fn label(&self) -> String {
+        format!("{}: {}", self.kind, self.message)
+    }
+}
+
+// Driver resets, crashes and low memory kills from the device logs, to explain frame time cliffs
+pub struct SystemLog {
+    children: Vec<Child>,
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+impl SystemLog {
+    pub fn start() -> Result<Self> {
+        let entries = Arc::new(Mutex::new(vec![]));
+        let mut children = vec![];
+        for buffer in BUFFERS {
+            // Only what comes after the start
+            let mut child = util::adb_spawn(&format!("logcat -b {} -v monotonic -T 1", buffer))?;
+            let stdout = child.stdout.take().context("No logcat output")?;
+            let sink = entries.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().flatten() {
+                    if let Some(entry) = parse_line(&line) {
+                        sink.lock().push(entry);
+                    }
+                }
+            });
+            children.push(child);
+        }
+        Ok(SystemLog { children, entries })
+    }
+
+    pub fn drain(&self) -> Vec<Entry> {
+        std::mem::take(&mut *self.entries.lock())
+    }
+}
+
+impl Drop for SystemLog {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+        }
+    }
+}
+
+// `   1234.567  1000  1234 E AndroidRuntime: FATAL EXCEPTION: main`, matching lines only
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.split_whitespace();
+    let secs: f64 = fields.next()?.parse().ok()?;
+    let pid = fields.next()?.parse().ok()?;
+    // tid and level
+    fields.nth(1)?;
+    let text = fields.collect::<Vec<_>>().join(" ");
+    let (kind, _) = RULES.iter().find(|(_, rule)| rule.is_match(&text))?;
+    let message: String = text.chars().take(MAX_MESSAGE_LEN).collect();
+    Some(Entry { timestamp: (secs * 1_000_000_000.0) as u64, pid, kind, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let entry = parse_line("  1234.500  1000  1234 E AndroidRuntime: FATAL EXCEPTION: main");
+        assert_eq!(
+            entry.map(|entry| (entry.timestamp, entry.kind)),
+            Some((1_234_500_000_000, "crash"))
+        );
+
+        let line = "  1300.000     0     0 E kgsl-3d0: |adreno_hang_int| GPU hang detected";
+        assert_eq!(parse_line(line).map(|entry| entry.kind), Some("gpu_fault"));
+        let line = "  1301.000  1000  1500 I am_anr: [0,4321,com.example.game,1,Input dispatching]";
+        assert_eq!(parse_line(line).map(|entry| entry.kind), Some("anr"));
+
+        assert!(parse_line("  1302.000  1000  1500 I ActivityManager: Start proc").is_none());
+        assert!(parse_line("--------- beginning of crash").is_none());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+// Build props worth comparing between devices
+const DEVICE_PROPS: &[&str] =
+    &["ro.product.model", "ro.build.version.release", "ro.build.fingerprint"];
+// `<width>x<height>` of the screen, the override when one is set
+pub const DISPLAY_SIZE: &str = "display.size";
+
+// Settings of the machine and device a session was recorded on that change results
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SystemSnapshot {
+    pub device: BTreeMap<String, String>,
+    pub windows: Option<WindowsEnvironment>,
+}
+
+// None when the setting couldn't be read
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowsEnvironment {
+    pub game_mode: Option<bool>,
+    // Hardware-accelerated GPU scheduling
+    pub hags: Option<bool>,
+    // Virtualization-based security and memory integrity (HVCI)
+    pub vbs: Option<bool>,
+    pub hvci: Option<bool>,
+    // Fullscreen optimizations of the game's exe
+    pub fullscreen_optimizations: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Difference {
+    pub key: String,
+    // One per compared session, None when it wasn't recorded
+    pub values: Vec<Option<String>>,
+}
+
+impl SystemSnapshot {
+    // Flat `device.<prop>` and `windows.<setting>` values
+    pub fn entries(&self) -> BTreeMap<String, String> {
+        let mut entries: BTreeMap<String, String> = self
+            .device
+            .iter()
+            .map(|(key, value)| (format!("device.{}", key), value.clone()))
+            .collect();
+        if let Some(windows) = &self.windows {
+            let settings = [
+                ("game_mode", windows.game_mode),
+                ("hags", windows.hags),
+                ("vbs", windows.vbs),
+                ("hvci", windows.hvci),
+                ("fullscreen_optimizations", windows.fullscreen_optimizations),
+            ];
+            for (key, value) in settings {
+                if let Some(value) = value {
+                    let value = if value { "on" } else { "off" };
+                    entries.insert(format!("windows.{}", key), value.into());
+                }
+            }
+        }
+        entries
+    }
+}
+
+// The connected device, the game runs there
+pub fn device_snapshot() -> SystemSnapshot {
+    let mut device: BTreeMap<String, String> = DEVICE_PROPS
+        .iter()
+        .filter_map(|prop| Some((prop.to_string(), util::get_android_prop(prop).ok()?)))
+        .collect();
+    let size = util::adb("shell wm size".into()).ok().and_then(|(_, wm, _)| parse_wm_size(&wm));
+    device.extend(size.map(|size| (DISPLAY_SIZE.to_string(), size)));
+    SystemSnapshot { device, windows: None }
+}
+
+// `Physical size: 1080x2400`, then `Override size: 720x1600` when the resolution was lowered
+fn parse_wm_size(output: &str) -> Option<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("size:").map(|(_, size)| size.trim().to_string()))
+        .last()
+}
+
+// Width and height of a `display.size`
+pub fn megapixels(size: &str) -> Option<f64> {
+    let (width, height) = size.split_once('x')?;
+    Some(width.trim().parse::<f64>().ok()? * height.trim().parse::<f64>().ok()? / 1e6)
+}
+
+// This PC, for logs of PC games (`exe` is the game's file name)
+pub fn host_snapshot(exe: &str) -> SystemSnapshot {
+    #[cfg(target_os = "windows")]
+    let windows = Some(super::environment::read(exe));
+    #[cfg(not(target_os = "windows"))]
+    let windows = {
+        let _ = exe;
+        None
+    };
+    SystemSnapshot { device: BTreeMap::new(), windows }
+}
+
+// Settings that aren't the same in every snapshot
+pub fn differences(snapshots: &[Option<&SystemSnapshot>]) -> Vec<Difference> {
+    let entries: Vec<_> = snapshots.iter().map(|s| s.map(SystemSnapshot::entries)).collect();
+    let keys: BTreeSet<&String> = entries.iter().flatten().flat_map(|e| e.keys()).collect();
+    keys.into_iter()
+        .map(|key| Difference {
+            key: key.clone(),
+            values: entries.iter().map(|e| e.as_ref().and_then(|e| e.get(key)).cloned()).collect(),
+        })
+        .filter(|difference| difference.values.windows(2).any(|pair| pair[0] != pair[1]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_differences() {
+        let windows =
+            WindowsEnvironment { game_mode: Some(true), hags: Some(true), ..Default::default() };
+        let a = SystemSnapshot { device: BTreeMap::new(), windows: Some(windows.clone()) };
+        let b = SystemSnapshot {
+            device: BTreeMap::new(),
+            windows: Some(WindowsEnvironment { hags: Some(false), ..windows }),
+        };
+
+        let differences = differences(&[Some(&a), Some(&b), None]);
+        assert_eq!(differences.len(), 2);
+        assert_eq!(differences[0].key, "windows.game_mode");
+        assert_eq!(differences[0].values, vec![Some("on".into()), Some("on".into()), None]);
+        assert_eq!(differences[1].values, vec![Some("on".into()), Some("off".into()), None]);
+        assert!(super::differences(&[Some(&a), Some(&a)]).is_empty());
+    }
+
+    #[test]
+    fn test_display_size() {
+        let wm = "Physical size: 1080x2400\nOverride size: 720x1600\n";
+        assert_eq!(parse_wm_size(wm), Some("720x1600".into()));
+        assert_eq!(parse_wm_size(""), None);
+        assert_eq!(megapixels("1920x1080"), Some(2.0736));
+        assert_eq!(megapixels("1080p"), None);
+    }
+}
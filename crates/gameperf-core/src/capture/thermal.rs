@@ -0,0 +1,194 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+// Thermal HAL types in `dumpsys thermalservice`
+const TYPE_CPU: u32 = 0;
+const TYPE_GPU: u32 = 1;
+// `PowerManager.THERMAL_STATUS_*`, from none through light, moderate, severe... to shutdown
+pub const STATUS_NONE: u32 = 0;
+pub const STATUS_METRIC: &str = "thermal.status";
+// Timeline event kinds
+pub const THROTTLE_ONSET: &str = "throttle_onset";
+pub const THROTTLE_RELEASE: &str = "throttle_release";
+
+// Wait before measuring until temperatures settle, a cold device boosts higher than it can
+// sustain and makes the first run look better than the next ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Soak {
+    // Stable when every reading of the window stays within this many °C
+    pub band: f64,
+    pub window_secs: u64,
+    pub interval_secs: u64,
+    // Measure anyway after this long
+    pub timeout_secs: u64,
+}
+
+impl Default for Soak {
+    fn default() -> Self {
+        Soak { band: 1.0, window_secs: 60, interval_secs: 5, timeout_secs: 600 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Temperatures {
+    pub elapsed_ms: u64,
+    pub cpu: Option<f64>,
+    pub gpu: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SoakReport {
+    pub stable: bool,
+    pub duration_ms: u64,
+    pub readings: Vec<Temperatures>,
+}
+
+pub fn soak(soak: &Soak) -> Result<SoakReport> {
+    let started = Instant::now();
+    let interval = Duration::from_secs(soak.interval_secs.max(1));
+    let window = (soak.window_secs / interval.as_secs()).max(2) as usize;
+    let mut report = SoakReport::default();
+    loop {
+        let mut temperatures = read()?;
+        temperatures.elapsed_ms = started.elapsed().as_millis() as u64;
+        report.readings.push(temperatures);
+        report.duration_ms = temperatures.elapsed_ms;
+
+        report.stable = is_stable(&report.readings, window, soak.band);
+        if report.stable {
+            log::info!("temperatures stable after {}s", started.elapsed().as_secs());
+            return Ok(report);
+        }
+        if started.elapsed() >= Duration::from_secs(soak.timeout_secs) {
+            log::warn!("temperatures still moving after {}s, measuring anyway", soak.timeout_secs);
+            return Ok(report);
+        }
+        thread::sleep(interval);
+    }
+}
+
+// Hottest sensor of each kind
+pub fn read() -> Result<Temperatures> {
+    let (_, stdout, _) = util::adb("shell dumpsys thermalservice".into())?;
+    let temperatures = parse_thermalservice(&stdout);
+    if temperatures.cpu.is_none() && temperatures.gpu.is_none() {
+        bail!("No CPU or GPU temperature reported");
+    }
+    Ok(temperatures)
+}
+
+// Per sample: the hottest CPU and GPU sensors of the thermal HAL and the platform's throttling
+// status, the same that `PowerManager` reports to games
+pub fn sample() -> Result<Vec<(String, f64)>> {
+    let (_, stdout, _) = util::adb("shell dumpsys thermalservice".into())?;
+    Ok(sample_metrics(&stdout))
+}
+
+fn sample_metrics(dump: &str) -> Vec<(String, f64)> {
+    let temperatures = parse_thermalservice(dump);
+    let mut metrics = vec![];
+    metrics.extend(temperatures.cpu.map(|cpu| ("temp.cpu".to_string(), cpu)));
+    metrics.extend(temperatures.gpu.map(|gpu| ("temp.gpu".to_string(), gpu)));
+    metrics.extend(parse_status(dump).map(|status| (STATUS_METRIC.to_string(), status as f64)));
+    metrics
+}
+
+// `Thermal Status: 2`, missing before Android 10
+fn parse_status(dump: &str) -> Option<u32> {
+    let status = dump.lines().find_map(|line| line.trim().strip_prefix("Thermal Status:"))?;
+    status.trim().parse().ok()
+}
+
+// Throttling starting or ending, changes of severity in between are only in `thermal.status`
+pub fn transition(previous: u32, current: u32) -> Option<&'static str> {
+    match (previous > STATUS_NONE, current > STATUS_NONE) {
+        (false, true) => Some(THROTTLE_ONSET),
+        (true, false) => Some(THROTTLE_RELEASE),
+        _ => None,
+    }
+}
+
+// `Temperature{mValue=45.2, mType=0, mName=cpu0, mStatus=0}` lines, cached ones come first and
+// are superseded by the HAL ones
+fn parse_thermalservice(dump: &str) -> Temperatures {
+    let section = dump.split("Current temperatures from HAL:").nth(1).unwrap_or(dump);
+    let mut temperatures = Temperatures::default();
+    for line in section.lines() {
+        let field = |name: &str| {
+            let start = line.find(name)? + name.len();
+            line[start..].split(|c| c == ',' || c == '}').next()
+        };
+        let value = field("mValue=").and_then(|v| v.trim().parse::<f64>().ok());
+        let kind = field("mType=").and_then(|v| v.trim().parse::<u32>().ok());
+        let slot = match kind {
+            Some(TYPE_CPU) => &mut temperatures.cpu,
+            Some(TYPE_GPU) => &mut temperatures.gpu,
+            _ => continue,
+        };
+        if let Some(value) = value {
+            *slot = Some(slot.map_or(value, |max: f64| max.max(value)));
+        }
+    }
+    temperatures
+}
+
+// The last `window` readings of each sensor are within `band`
+fn is_stable(readings: &[Temperatures], window: usize, band: f64) -> bool {
+    if readings.len() < window {
+        return false;
+    }
+    let recent = &readings[readings.len() - window..];
+    let within = |values: Vec<f64>| {
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        values.is_empty() || max - min <= band
+    };
+    within(recent.iter().filter_map(|t| t.cpu).collect())
+        && within(recent.iter().filter_map(|t| t.gpu).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soak() {
+        let dump = "\
+IsStatusOverride: false
+Thermal Status: 2
+Cached temperatures:
+\tTemperature{mValue=30.0, mType=0, mName=cpu0, mStatus=0}
+Current temperatures from HAL:
+\tTemperature{mValue=45.5, mType=0, mName=cpu0, mStatus=0}
+\tTemperature{mValue=47.0, mType=0, mName=cpu4, mStatus=0}
+\tTemperature{mValue=40.0, mType=1, mName=gpu, mStatus=0}
+\tTemperature{mValue=35.0, mType=2, mName=battery, mStatus=0}
+";
+        let temperatures = parse_thermalservice(dump);
+        assert_eq!(temperatures.cpu, Some(47.0));
+        assert_eq!(temperatures.gpu, Some(40.0));
+        assert_eq!(
+            sample_metrics(dump),
+            vec![
+                ("temp.cpu".to_string(), 47.0),
+                ("temp.gpu".to_string(), 40.0),
+                (STATUS_METRIC.to_string(), 2.0)
+            ]
+        );
+        assert_eq!(transition(0, 2), Some(THROTTLE_ONSET));
+        assert_eq!(transition(2, 3), None);
+        assert_eq!(transition(3, 0), Some(THROTTLE_RELEASE));
+
+        let reading = |cpu| Temperatures { cpu: Some(cpu), gpu: Some(40.0), ..Default::default() };
+        let readings = [reading(40.0), reading(44.0), reading(44.5), reading(45.0)];
+        assert!(!is_stable(&readings, 4, 1.0));
+        assert!(is_stable(&readings, 3, 1.0));
+        assert!(!is_stable(&readings[..2], 3, 1.0));
+    }
+}
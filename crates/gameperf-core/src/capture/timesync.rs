@@ -0,0 +1,152 @@
+use std::net::UdpSocket;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+// Round trips per estimate, the quickest one is kept
+const PROBES: usize = 3;
+const NTP_TIMEOUT: Duration = Duration::from_secs(1);
+// Seconds between the NTP era (1900) and the Unix epoch
+const NTP_EPOCH: u64 = 2_208_988_800;
+
+// One round trip: our clock when asking and when the answer came back, the remote clock in
+// between, all in the same unit
+#[derive(Debug, Clone, Copy)]
+pub struct Probe {
+    pub sent: u64,
+    pub remote: u64,
+    pub received: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Offset {
+    // Remote clock minus ours
+    pub offset: i64,
+    // Half the round trip, the remote reading can be anywhere in it
+    pub uncertainty: u64,
+}
+
+// Assumes the remote clock was read halfway through the quickest round trip
+pub fn estimate(probes: &[Probe]) -> Option<Offset> {
+    let probe = probes.iter().min_by_key(|probe| probe.received.saturating_sub(probe.sent))?;
+    let rtt = probe.received.saturating_sub(probe.sent);
+    let midpoint = probe.sent + rtt / 2;
+    Some(Offset { offset: probe.remote as i64 - midpoint as i64, uncertainty: rtt / 2 })
+}
+
+pub fn probe_all(mut probe: impl FnMut() -> Result<Probe>) -> Result<Offset> {
+    let probes = (0..PROBES).map(|_| probe()).collect::<Result<Vec<_>>>()?;
+    estimate(&probes).context("No clock probe")
+}
+
+// How the clocks of a capture relate, so timestamps from the device land on the capture's
+// `elapsed_ms` timeline
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClockSync {
+    // Device CLOCK_MONOTONIC (input events, presents) at elapsed 0, in ns
+    pub device_epoch_ns: Option<u64>,
+    pub device_uncertainty_us: Option<u64>,
+    // Host wall clock minus NTP time in ms, `started_at` is off by that much
+    pub ntp_offset_ms: Option<i64>,
+}
+
+impl ClockSync {
+    // Measures both clocks against `started`, what can't be measured is left to None
+    pub fn measure(started: Instant, ntp_server: Option<&str>) -> Self {
+        let mut sync = ClockSync::default();
+        match device_offset(started) {
+            Ok(offset) => {
+                sync.device_epoch_ns = Some(offset.offset.max(0) as u64);
+                sync.device_uncertainty_us = Some(offset.uncertainty / 1000);
+            }
+            Err(err) => log::debug!("device clock: {}", err),
+        }
+        if let Some(server) = ntp_server {
+            match ntp_offset(server) {
+                Ok(offset) => sync.ntp_offset_ms = Some(offset),
+                Err(err) => log::debug!("ntp: {}", err),
+            }
+        }
+        sync
+    }
+
+    pub fn device_elapsed_ms(&self, timestamp_ns: u64) -> Option<u64> {
+        let epoch = self.device_epoch_ns?;
+        Some(timestamp_ns.checked_sub(epoch)? / 1_000_000)
+    }
+}
+
+// Device monotonic clock minus `started.elapsed()`, both in ns
+fn device_offset(started: Instant) -> Result<Offset> {
+    probe_all(|| {
+        let sent = started.elapsed().as_nanos() as u64;
+        let (_, stdout, _) = util::adb("shell head -n 3 /proc/timer_list".into())?;
+        let received = started.elapsed().as_nanos() as u64;
+        let remote = parse_timer_list(&stdout).context("No time in /proc/timer_list")?;
+        Ok(Probe { sent, remote, received })
+    })
+}
+
+// `now at 1234567890123 nsecs`
+fn parse_timer_list(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("now at ")?.split(' ').next()?.parse().ok())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+// SNTP, our wall clock minus the server's
+pub fn ntp_offset(server: &str) -> Result<i64> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(NTP_TIMEOUT))?;
+    socket.connect((server, 123))?;
+    let offset = probe_all(|| {
+        // Version 3, client mode
+        let mut packet = [0u8; 48];
+        packet[0] = 0x1b;
+        let sent = now_ms();
+        socket.send(&packet)?;
+        if socket.recv(&mut packet)? < 48 {
+            bail!("Short NTP reply");
+        }
+        let received = now_ms();
+        Ok(Probe { sent, remote: ntp_transmit_ms(&packet), received })
+    })?;
+    Ok(-offset.offset)
+}
+
+// Transmit timestamp, seconds and fraction since 1900
+fn ntp_transmit_ms(packet: &[u8; 48]) -> u64 {
+    let secs = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as u64;
+    secs.saturating_sub(NTP_EPOCH) * 1000 + fraction * 1000 / (1 << 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let probes = [
+            Probe { sent: 0, remote: 1_100, received: 60 },
+            Probe { sent: 100, remote: 1_110, received: 120 },
+        ];
+        assert_eq!(estimate(&probes), Some(Offset { offset: 1_000, uncertainty: 10 }));
+        assert_eq!(estimate(&[]), None);
+
+        let output =
+            "Timer List Version: v0.8\nHRTIMER_MAX_CLOCK_BASES: 8\nnow at 5000000000 nsecs\n";
+        assert_eq!(parse_timer_list(output), Some(5_000_000_000));
+
+        let sync = ClockSync { device_epoch_ns: Some(4_000_000_000), ..Default::default() };
+        assert_eq!(sync.device_elapsed_ms(5_500_000_000), Some(1_500));
+        assert_eq!(sync.device_elapsed_ms(3_000_000_000), None);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::annotation::{Annotation, Channels};
+use super::timesync::ClockSync;
+use crate::util;
+
+// In KB, per CPU. Markers are read once at the end, the buffer has to hold the whole capture.
+const BUFFER_KB: u32 = 16 * 1024;
+pub const SLICES: &str = "trace";
+pub const COUNTERS: &str = "trace.counter";
+
+// Trace markers the game emits itself (`ATrace_beginSection`, `Trace.beginSection`, Unreal and
+// Unity profiler markers), captured with atrace and placed on the capture's timeline
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TraceSettings {
+    // Extra atrace categories, e.g. `gfx` or `view`. The game's own markers are always on.
+    pub categories: Vec<String>,
+    // Section name prefixes to keep, all of them when empty
+    pub sections: Vec<String>,
+}
+
+impl TraceSettings {
+    pub fn validate(&self) -> Result<()> {
+        // Passed to the device shell
+        let invalid = self
+            .categories
+            .iter()
+            .find(|c| c.is_empty() || !c.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+        if let Some(category) = invalid {
+            bail!("Invalid trace category: {}", category);
+        }
+        Ok(())
+    }
+
+    fn keeps(&self, name: &str) -> bool {
+        self.sections.is_empty() || self.sections.iter().any(|prefix| name.starts_with(prefix))
+    }
+}
+
+pub struct TraceSession {
+    settings: TraceSettings,
+    // CLOCK_BOOTTIME minus CLOCK_MONOTONIC, atrace uses the boot clock when the kernel has it
+    boot_offset_ns: u64,
+    running: bool,
+}
+
+impl TraceSession {
+    pub fn start(package: &str, settings: &TraceSettings) -> Result<Self> {
+        settings.validate()?;
+        let mut args = format!("shell atrace --async_start -c -b {} -a {}", BUFFER_KB, package);
+        for category in &settings.categories {
+            args.push(' ');
+            args.push_str(category);
+        }
+        let (success, _, stderr) = util::adb(args)?;
+        if !success {
+            bail!("atrace failed: {}", stderr.trim());
+        }
+        Ok(TraceSession {
+            settings: settings.clone(),
+            boot_offset_ns: boot_offset_ns().unwrap_or_default(),
+            running: true,
+        })
+    }
+
+    // Markers without a known device clock can't be placed
+    pub fn finish(mut self, clock: Option<&ClockSync>) -> Result<Channels> {
+        self.running = false;
+        let (success, stdout, stderr) = util::adb("shell atrace --async_stop".into())?;
+        if !success {
+            bail!("atrace failed: {}", stderr.trim());
+        }
+        let clock = match clock {
+            Some(clock) if clock.device_epoch_ns.is_some() => clock,
+            _ => bail!("Device clock unknown, trace markers dropped"),
+        };
+        let place = |timestamp_ns: u64| {
+            clock.device_elapsed_ms(timestamp_ns.saturating_sub(self.boot_offset_ns))
+        };
+        Ok(parse_trace(&stdout, &self.settings, place))
+    }
+}
+
+impl Drop for TraceSession {
+    fn drop(&mut self) {
+        if self.running {
+            let _ = util::adb("shell atrace --async_stop > /dev/null".into());
+        }
+    }
+}
+
+// `.get_time:   ktime_get_boottime` is followed by `.offset:     1234 nsecs`
+fn boot_offset_ns() -> Option<u64> {
+    let (_, stdout, _) =
+        util::adb("shell grep -m 1 -A 1 ktime_get_boottime /proc/timer_list".into()).ok()?;
+    let line = stdout.lines().find(|line| line.trim().starts_with(".offset:"))?;
+    line.trim().trim_start_matches(".offset:").split_whitespace().next()?.parse().ok()
+}
+
+struct Marker<'a> {
+    tid: &'a str,
+    timestamp_ns: u64,
+    // `B|pid|name`, `E|pid`, `C|pid|name|value`...
+    payload: &'a str,
+}
+
+// `RenderThread-1234 ( 1200) [002] ...1 12345.678901: tracing_mark_write: B|1200|DrawFrame`
+fn parse_marker(line: &str) -> Option<Marker> {
+    let (prefix, payload) = line.split_once(": tracing_mark_write: ")?;
+    let (task, rest) = prefix.split_once(" [")?;
+    let task = task.split(" (").next()?.trim();
+    let (_, tid) = task.rsplit_once('-')?;
+    let secs: f64 = rest.split_whitespace().last()?.trim_end_matches(':').parse().ok()?;
+    Some(Marker { tid, timestamp_ns: (secs * 1e9).round() as u64, payload: payload.trim() })
+}
+
+// Slices (sync and async) with their duration, counters with their value, instants alone
+fn parse_trace(
+    output: &str,
+    settings: &TraceSettings,
+    place: impl Fn(u64) -> Option<u64>,
+) -> Channels {
+    let mut channels = Channels::new();
+    let mut stacks: HashMap<&str, Vec<(u64, &str)>> = HashMap::new();
+    let mut pending: HashMap<(&str, &str), u64> = HashMap::new();
+    let mut push = |channel: &str, begin: u64, name: &str, value: Option<f64>| {
+        if !settings.keeps(name) {
+            return;
+        }
+        if let Some(elapsed_ms) = place(begin) {
+            let annotation = Annotation { elapsed_ms, label: name.into(), value };
+            channels.entry(channel.to_string()).or_default().push(annotation);
+        }
+    };
+    let duration_ms = |begin: u64, end: u64| Some(end.saturating_sub(begin) as f64 / 1e6);
+
+    for marker in output.lines().filter_map(parse_marker) {
+        let mut fields = marker.payload.splitn(4, '|');
+        let (kind, name) = (fields.next(), fields.nth(1));
+        let now = marker.timestamp_ns;
+        match (kind, name) {
+            (Some("B"), Some(name)) => stacks.entry(marker.tid).or_default().push((now, name)),
+            (Some("E"), _) => {
+                if let Some((begin, name)) = stacks.get_mut(marker.tid).and_then(Vec::pop) {
+                    push(SLICES, begin, name, duration_ms(begin, now));
+                }
+            }
+            (Some("S"), Some(name)) => {
+                pending.insert((name, fields.next().unwrap_or_default()), now);
+            }
+            (Some("F"), Some(name)) => {
+                if let Some(begin) = pending.remove(&(name, fields.next().unwrap_or_default())) {
+                    push(SLICES, begin, name, duration_ms(begin, now));
+                }
+            }
+            (Some("C"), Some(name)) => {
+                let value = fields.next().and_then(|value| value.trim().parse().ok());
+                push(COUNTERS, now, name, value);
+            }
+            (Some("I"), Some(name)) => push(SLICES, now, name, None),
+            _ => {}
+        }
+    }
+    for annotations in channels.values_mut() {
+        annotations.sort_by_key(|annotation| annotation.elapsed_ms);
+    }
+    channels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trace() {
+        let trace = "\
+# tracer: nop
+     GameThread-1201 ( 1200) [001] ...1 100.000000: tracing_mark_write: B|1200|LoadLevel
+     GameThread-1201 ( 1200) [001] ...1 100.250000: tracing_mark_write: B|1200|Stream
+   RenderThread-1202 ( 1200) [002] ...1 100.300000: tracing_mark_write: B|1200|DrawFrame
+     GameThread-1201 ( 1200) [001] ...1 100.500000: tracing_mark_write: E|1200
+     GameThread-1201 ( 1200) [001] ...1 101.000000: tracing_mark_write: E|1200
+     GameThread-1201 ( 1200) [001] ...1 101.000000: tracing_mark_write: C|1200|Actors|350
+     GameThread-1201 ( 1200) [001] ...1 101.100000: tracing_mark_write: S|1200|GC|7
+     GameThread-1201 ( 1200) [003] ...1 101.150000: tracing_mark_write: F|1200|GC|7
+";
+        let place =
+            |timestamp_ns: u64| timestamp_ns.checked_sub(99_000_000_000).map(|ns| ns / 1_000_000);
+        let channels = parse_trace(trace, &TraceSettings::default(), place);
+        let slices: Vec<_> =
+            channels[SLICES].iter().map(|a| (a.elapsed_ms, a.label.as_str(), a.value)).collect();
+        assert_eq!(
+            slices,
+            vec![
+                (1000, "LoadLevel", Some(1000.0)),
+                (1250, "Stream", Some(250.0)),
+                (2100, "GC", Some(50.0))
+            ]
+        );
+        assert_eq!(channels[COUNTERS][0].value, Some(350.0));
+
+        let settings = TraceSettings { sections: vec!["Load".into()], ..Default::default() };
+        let channels = parse_trace(trace, &settings, place);
+        assert_eq!(channels[SLICES].len(), 1);
+        assert!(!channels.contains_key(COUNTERS));
+    }
+}
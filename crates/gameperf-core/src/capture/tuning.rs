@@ -0,0 +1,155 @@
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::util;
+
+lazy_static! {
+    static ref APPLIED: Mutex<Option<Applied>> = Mutex::new(None);
+}
+
+// Scheduling overrides for the captured game, e.g. pinning it to the big cores. Changing
+// another app's process usually needs a rooted device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    // -20 (highest priority) to 19
+    pub nice: Option<i32>,
+    // CPUs the game may run on
+    pub cpus: Option<Vec<u32>>,
+}
+
+struct Applied {
+    pid: String,
+    previous: Tuning,
+    tuning: Tuning,
+}
+
+// Applies to every thread of the game, the previous values are put back by `restore`. Kept
+// before writing, a priority changed before the affinity failed is put back all the same.
+pub fn apply(package: &str, tuning: &Tuning) -> Result<Tuning> {
+    restore()?;
+    let pid = util::pid_of(package)?;
+    let previous = read(&pid)?;
+    let applied = Applied { pid: pid.clone(), previous: previous.clone(), tuning: tuning.clone() };
+    *APPLIED.lock() = Some(applied);
+    if let Err(err) = write(&pid, &previous, tuning) {
+        if let Err(err) = restore() {
+            log::warn!("tuning: {:#}", err);
+        }
+        return Err(err);
+    }
+    log::info!("tuning {}: {:?}, was {:?}", package, tuning, previous);
+    Ok(tuning.clone())
+}
+
+pub fn applied() -> Option<Tuning> {
+    APPLIED.lock().as_ref().map(|applied| applied.tuning.clone())
+}
+
+// The values are read again, a write may have failed halfway. A game that exited meanwhile has
+// nothing left to restore.
+pub fn restore() -> Result<()> {
+    restore_with(&mut APPLIED.lock(), |applied| {
+        if !is_running(&applied.pid)? {
+            return Ok(());
+        }
+        let current = read(&applied.pid)?;
+        write(&applied.pid, &current, &applied.previous)
+    })
+}
+
+// Cleared once `undo` worked, a failed restore is tried again with the next one
+fn restore_with(
+    applied: &mut Option<Applied>,
+    undo: impl FnOnce(&Applied) -> Result<()>,
+) -> Result<()> {
+    if let Some(current) = applied.as_ref() {
+        undo(current)?;
+    }
+    *applied = None;
+    Ok(())
+}
+
+fn is_running(pid: &str) -> Result<bool> {
+    let (_, out, _) = util::adb(format!("shell test -d /proc/{} && echo running; true", pid))?;
+    Ok(out.trim() == "running")
+}
+
+fn read(pid: &str) -> Result<Tuning> {
+    let (_, stat, _) = util::adb(format!("shell cat /proc/{}/stat", pid))?;
+    // Fields after the `(comm)`, nice is the 19th field of the line
+    let nice = stat
+        .rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(16))
+        .and_then(|nice| nice.parse().ok())
+        .context("Can't read process priority")?;
+
+    // `pid 1234's current affinity mask: f0`
+    let (_, taskset, _) = util::adb(format!("shell taskset -p {}", pid))?;
+    let mask = taskset
+        .rsplit(':')
+        .next()
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .context("Can't read CPU affinity")?;
+    Ok(Tuning { nice: Some(nice), cpus: Some(cpus_of(mask)) })
+}
+
+fn write(pid: &str, current: &Tuning, tuning: &Tuning) -> Result<()> {
+    if let Some(nice) = tuning.nice {
+        if !(-20..=19).contains(&nice) {
+            bail!("Invalid priority {}, expected -20 to 19", nice);
+        }
+        // Toybox `renice -n` is relative
+        let delta = nice - current.nice.unwrap_or_default();
+        let script =
+            format!("for t in /proc/{}/task/*; do renice -n {} -p ${{t##*/}}; done", pid, delta);
+        util::adb(format!("shell {}", script))?;
+    }
+    if let Some(cpus) = &tuning.cpus {
+        let mask = mask_of(cpus)?;
+        util::adb(format!("shell taskset -a -p {:x} {}", mask, pid))?;
+    }
+    Ok(())
+}
+
+fn cpus_of(mask: u64) -> Vec<u32> {
+    (0..64).filter(|cpu| mask & (1 << cpu) != 0).collect()
+}
+
+fn mask_of(cpus: &[u32]) -> Result<u64> {
+    if cpus.is_empty() || cpus.iter().any(|&cpu| cpu >= 64) {
+        bail!("Invalid CPU list {:?}", cpus);
+    }
+    Ok(cpus.iter().fold(0, |mask, cpu| mask | 1 << cpu))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() -> Result<()> {
+        assert_eq!(mask_of(&[4, 5, 6, 7])?, 0xf0);
+        assert_eq!(cpus_of(0xf0), vec![4, 5, 6, 7]);
+        assert!(mask_of(&[]).is_err());
+        assert!(mask_of(&[64]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_with() {
+        let tuning = Tuning { nice: Some(-10), cpus: None };
+        let mut applied = Some(Applied { pid: "1234".into(), previous: Tuning::default(), tuning });
+        assert!(restore_with(&mut applied, |_| bail!("device offline")).is_err());
+        assert!(applied.is_some());
+        assert!(restore_with(&mut applied, |applied| {
+            assert_eq!(applied.pid, "1234");
+            Ok(())
+        })
+        .is_ok());
+        assert!(applied.is_none());
+        assert!(restore_with(&mut applied, |_| bail!("Nothing to restore")).is_ok());
+    }
+}
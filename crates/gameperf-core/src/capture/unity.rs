@@ -0,0 +1,70 @@
+use anyhow::{bail, Result};
+
+use super::engine::{self, EngineTimings};
+
+// Unity's FrameTimingManager, one row per frame as a script in the game writes it, with the
+// `FrameTiming` field names as columns (ms) and `timestamp` (Unix time in ms) when the frame
+// started. Profiler `.data` captures are a private format, the Profile Analyzer can't export
+// per frame timings either.
+const COLUMNS: [&str; 5] = [
+    "cpuFrameTime",
+    "cpuMainThreadFrameTime",
+    "cpuRenderThreadFrameTime",
+    "gpuFrameTime",
+    "timestamp",
+];
+
+// Frames from before `started_at_ms` (the session's start, Unix time in ms) are dropped. Without
+// timestamps, the first frame is taken as the start of the session.
+pub fn parse_csv(csv: &str, started_at_ms: u64) -> Result<EngineTimings> {
+    let mut columns = engine::read_csv(csv, &COLUMNS)?.into_iter();
+    let mut next = || columns.next().unwrap_or_default();
+    let (mut frame, mut main_thread, mut render_thread, mut gpu, timestamps) =
+        (next(), next(), next(), next(), next());
+
+    let mut start_ms = 0.0;
+    if !timestamps.is_empty() {
+        let skip = timestamps.iter().take_while(|&&at| at < started_at_ms as f64).count();
+        for column in [&mut frame, &mut main_thread, &mut render_thread, &mut gpu] {
+            column.drain(..skip.min(column.len()));
+        }
+        if let Some(first) = timestamps.get(skip) {
+            start_ms = first - started_at_ms as f64;
+        }
+    }
+    if frame.is_empty() {
+        bail!("No frames during the session");
+    }
+    Ok(EngineTimings {
+        engine: "unity".into(),
+        start_ms,
+        frame,
+        game_thread: main_thread,
+        render_thread,
+        rhi_thread: vec![],
+        gpu,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() -> Result<()> {
+        let csv =
+            "timestamp,cpuFrameTime,cpuMainThreadFrameTime,cpuRenderThreadFrameTime,gpuFrameTime\n\
+             999990,16.6,9.0,11.0,15.0\n\
+             1000500,16.6,10.0,12.0,15.0\n\
+             1000517,16.6,10.0,12.0,14.0\n";
+        let timings = parse_csv(csv, 1_000_000)?;
+        assert_eq!(timings.start_ms, 500.0);
+        assert_eq!(timings.game_thread, vec![10.0, 10.0]);
+        assert_eq!(timings.gpu, vec![15.0, 14.0]);
+        assert!(parse_csv(csv, 2_000_000).is_err());
+
+        let timings = parse_csv("cpuFrameTime\n16.6\n", 1_000_000)?;
+        assert_eq!((timings.start_ms, timings.frame.len()), (0.0, 1));
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::engine::{self, EngineTimings};
+use crate::util;
+
+// The CSV is written after `csvprofile stop` returns, bigger captures take a while
+const CSV_TIMEOUT: Duration = Duration::from_secs(10);
+const CSV_POLL: Duration = Duration::from_millis(500);
+
+// Works with development builds, shipping builds drop console commands
+fn console(package: &str, command: &str) -> Result<()> {
+    let args = format!(
+        "shell am broadcast -a android.intent.action.RUN -p {} -e cmd '{}'",
+        package, command
+    );
+    let (success, _, stderr) = util::adb(args)?;
+    if !success {
+        bail!("Unreal console command failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+// Newest first, `<project>/<project>/Saved/Profiling/CSV` under the game's files
+fn list_csvs(package: &str) -> Result<Vec<String>> {
+    let (_, stdout, _) = util::adb(format!(
+        "shell ls -t /sdcard/Android/data/{}/files/*/*/*/Saved/Profiling/CSV/*.csv",
+        package
+    ))?;
+    Ok(stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.ends_with(".csv"))
+        .map(Into::into)
+        .collect())
+}
+
+// Unreal's CSV profiler, running along with the capture
+pub struct CsvProfiler {
+    package: String,
+    // Left from earlier runs, the new one is the first CSV not in there
+    previous: Vec<String>,
+}
+
+impl CsvProfiler {
+    pub fn start(package: &str) -> Result<Self> {
+        let previous = list_csvs(package).unwrap_or_default();
+        console(package, "csvprofile start")?;
+        Ok(CsvProfiler { package: package.into(), previous })
+    }
+
+    pub fn finish(self) -> Result<EngineTimings> {
+        console(&self.package, "csvprofile stop")?;
+        let started = Instant::now();
+        let path = loop {
+            let csvs = list_csvs(&self.package)?;
+            if let Some(path) = csvs.into_iter().find(|path| !self.previous.contains(path)) {
+                break path;
+            }
+            if started.elapsed() > CSV_TIMEOUT {
+                bail!("No CSV from Unreal's profiler, is it a development build?");
+            }
+            thread::sleep(CSV_POLL);
+        };
+        // Still being written when listed
+        thread::sleep(CSV_POLL);
+        let (_, stdout, _) = util::adb(format!("shell cat '{}'", path))?;
+        parse_csv(&stdout).with_context(|| format!("Invalid profiler CSV: {}", path))
+    }
+}
+
+// A header row, one row per frame, then metadata rows (`[HasHeaderRowAtEnd],1,...`)
+fn parse_csv(csv: &str) -> Result<EngineTimings> {
+    let names = ["FrameTime", "GameThreadTime", "RenderThreadTime", "RHIThreadTime", "GPUTime"];
+    let mut columns = engine::read_csv(csv, &names)?.into_iter();
+    let mut next = || columns.next().unwrap_or_default();
+    let timings = EngineTimings {
+        engine: "unreal".into(),
+        start_ms: 0.0,
+        frame: next(),
+        game_thread: next(),
+        render_thread: next(),
+        rhi_thread: next(),
+        gpu: next(),
+    };
+    if timings.frame.is_empty() {
+        bail!("No frames");
+    }
+    Ok(timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() -> Result<()> {
+        let csv = "FrameTime,GameThreadTime,RenderThreadTime,GPUTime,Memory/Total\n\
+                   16.6,10.0,12.0,15.0,900\n\
+                   33.4,30.0,12.0,14.0,900\n\
+                   [HasHeaderRowAtEnd],1,[platform],Android\n\
+                   FrameTime,GameThreadTime,RenderThreadTime,GPUTime,Memory/Total\n";
+        let timings = parse_csv(csv)?;
+        assert_eq!(timings.frame, vec![16.6, 33.4]);
+        assert_eq!(timings.game_thread, vec![10.0, 30.0]);
+        assert!(timings.rhi_thread.is_empty());
+        assert!(parse_csv("Memory/Total\n900\n").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{bail, Result};
+
+use crate::capture::{Gap, Marker, Recording};
+
+// A split point this close to a marker moves onto the marker
+const MARKER_SNAP_MS: u64 = 2000;
+
+// Where `split` actually cuts
+pub fn split_point(recording: &Recording, at_ms: u64) -> u64 {
+    let distance = |elapsed: u64| elapsed.max(at_ms) - elapsed.min(at_ms);
+    recording
+        .markers
+        .iter()
+        .map(|marker| marker.elapsed_ms)
+        .min_by_key(|&elapsed| distance(elapsed))
+        .filter(|&elapsed| distance(elapsed) <= MARKER_SNAP_MS)
+        .unwrap_or(at_ms)
+}
+
+// Both parts keep the capture metadata. A marker at the cut starts the second part.
+pub fn split(recording: &Recording, at_ms: u64) -> Result<(Recording, Recording)> {
+    if recording.summary.is_some() {
+        bail!("Compacted sessions can't be split");
+    }
+    let at_ms = split_point(recording, at_ms);
+    if at_ms == 0 || at_ms >= recording.duration_ms {
+        bail!("Split point {}ms is outside of the session", at_ms);
+    }
+    Ok(cut(recording, at_ms))
+}
+
+// The part of the recording from `from_ms` up to `to_ms`
+pub fn slice(recording: &Recording, from_ms: u64, to_ms: u64) -> Recording {
+    let (_, rest) = cut(recording, from_ms);
+    let (slice, _) = cut(&rest, to_ms.saturating_sub(from_ms));
+    slice
+}
+
+fn cut(recording: &Recording, at_ms: u64) -> (Recording, Recording) {
+    let at_ms = at_ms.min(recording.duration_ms);
+    // Frames are placed by their accumulated frame times
+    let mut elapsed = 0.0;
+    let cut = recording
+        .frametimes
+        .iter()
+        .position(|frametime| {
+            elapsed += frametime;
+            elapsed > at_ms as f64
+        })
+        .unwrap_or(recording.frametimes.len());
+
+    let mut first = recording.clone();
+    let mut second = recording.clone();
+    first.duration_ms = at_ms;
+    second.duration_ms = recording.duration_ms - at_ms;
+    second.started_at = recording.started_at + at_ms / 1000;
+
+    first.frametimes.truncate(cut);
+    second.frametimes.drain(..cut);
+    let frames = recording.frametimes.len();
+    split_per_frame(&mut first.gpu_busy, &mut second.gpu_busy, cut, frames);
+    split_per_frame(&mut first.queue_depth, &mut second.queue_depth, cut, frames);
+    split_per_frame(&mut first.generated, &mut second.generated, cut, frames);
+    // Latencies aren't timestamped, there is no telling which part they belong to
+    first.input_latency.clear();
+    second.input_latency.clear();
+
+    first.samples.retain(|sample| sample.elapsed_ms < at_ms);
+    second.samples.retain(|sample| sample.elapsed_ms >= at_ms);
+    second.samples.iter_mut().for_each(|sample| sample.elapsed_ms -= at_ms);
+    first.events.retain(|event| event.elapsed_ms < at_ms);
+    second.events.retain(|event| event.elapsed_ms >= at_ms);
+    second.events.iter_mut().for_each(|event| event.elapsed_ms -= at_ms);
+    first.markers.retain(|marker| marker.elapsed_ms < at_ms);
+    second.markers.retain(|marker| marker.elapsed_ms >= at_ms);
+    second.markers.iter_mut().for_each(|marker| marker.elapsed_ms -= at_ms);
+    first.gaps.retain(|gap| gap.start_ms < at_ms);
+    first.gaps.iter_mut().for_each(|gap| gap.end_ms = gap.end_ms.min(at_ms));
+    second.gaps.retain(|gap| gap.end_ms > at_ms);
+    second.gaps.iter_mut().for_each(|gap| {
+        gap.start_ms = gap.start_ms.max(at_ms) - at_ms;
+        gap.end_ms -= at_ms;
+    });
+    // Stays with the part it happened in
+    match &mut second.interrupted {
+        Some(interruption) if interruption.elapsed_ms >= at_ms => {
+            interruption.elapsed_ms -= at_ms;
+            first.interrupted = None;
+        }
+        _ => second.interrupted = None,
+    }
+    for annotations in first.annotations.values_mut() {
+        annotations.retain(|annotation| annotation.elapsed_ms < at_ms);
+    }
+    for annotations in second.annotations.values_mut() {
+        annotations.retain(|annotation| annotation.elapsed_ms >= at_ms);
+        annotations.iter_mut().for_each(|annotation| annotation.elapsed_ms -= at_ms);
+    }
+    first.annotations.retain(|_, annotations| !annotations.is_empty());
+    second.annotations.retain(|_, annotations| !annotations.is_empty());
+    (first, second)
+}
+
+// Per frame data that doesn't line up with the frame times can't be placed, neither part keeps it
+fn split_per_frame<T>(first: &mut Vec<T>, second: &mut Vec<T>, cut: usize, frames: usize) {
+    if first.len() != frames {
+        first.clear();
+        second.clear();
+        return;
+    }
+    first.truncate(cut);
+    second.drain(..cut);
+}
+
+// In start order, later captures are placed at their real start (or right after the previous
+// one when they overlap) and get a marker where they begin. Metadata comes from the first.
+pub fn merge(mut recordings: Vec<Recording>) -> Result<Recording> {
+    if recordings.len() < 2 {
+        bail!("Merging needs at least two sessions");
+    }
+    if recordings.iter().any(|recording| recording.summary.is_some()) {
+        bail!("Compacted sessions can't be merged");
+    }
+    recordings.sort_by_key(|recording| recording.started_at);
+    if recordings.iter().any(|recording| recording.package != recordings[0].package) {
+        bail!("Only sessions of the same game can be merged");
+    }
+
+    let frames: usize = recordings.iter().map(|recording| recording.frametimes.len()).sum();
+    let mut rest = recordings.split_off(1);
+    let mut merged = recordings.remove(0);
+    merged.input_latency.clear();
+    for recording in rest.iter_mut() {
+        let since_start = (recording.started_at - merged.started_at) * 1000;
+        let offset = since_start.max(merged.duration_ms);
+
+        merged.frametimes.append(&mut recording.frametimes);
+        merged.gpu_busy.append(&mut recording.gpu_busy);
+        merged.queue_depth.append(&mut recording.queue_depth);
+        merged.generated.append(&mut recording.generated);
+        merged.samples.extend(recording.samples.drain(..).map(|mut sample| {
+            sample.elapsed_ms += offset;
+            sample
+        }));
+        merged.events.extend(recording.events.drain(..).map(|mut event| {
+            event.elapsed_ms += offset;
+            event
+        }));
+        if offset > merged.duration_ms {
+            let (start_ms, end_ms) = (merged.duration_ms, offset);
+            merged.gaps.push(Gap { start_ms, end_ms, reason: "merged".into() });
+        }
+        merged.gaps.extend(recording.gaps.drain(..).map(|mut gap| {
+            gap.start_ms += offset;
+            gap.end_ms += offset;
+            gap
+        }));
+        merged.markers.push(Marker { elapsed_ms: offset, label: "merged".into() });
+        merged.markers.extend(recording.markers.drain(..).map(|mut marker| {
+            marker.elapsed_ms += offset;
+            marker
+        }));
+        for (channel, annotations) in std::mem::take(&mut recording.annotations) {
+            merged.annotations.entry(channel).or_default().extend(annotations.into_iter().map(
+                |mut annotation| {
+                    annotation.elapsed_ms += offset;
+                    annotation
+                },
+            ));
+        }
+        merged.duration_ms = offset + recording.duration_ms;
+    }
+
+    // Per frame data only holds up when every part had it
+    if merged.gpu_busy.len() != frames {
+        merged.gpu_busy.clear();
+    }
+    if merged.queue_depth.len() != frames {
+        merged.queue_depth.clear();
+    }
+    if merged.generated.len() != frames {
+        merged.generated.clear();
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Sample;
+
+    fn recording(started_at: u64, frames: usize) -> Recording {
+        let samples = (0..frames as u64 / 10)
+            .map(|i| Sample { elapsed_ms: i * 1000, ..Default::default() })
+            .collect();
+        Recording {
+            package: "com.example.game".into(),
+            started_at,
+            duration_ms: frames as u64 * 100,
+            frametimes: vec![100.0; frames],
+            samples,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_split() -> Result<()> {
+        let mut long = recording(1000, 100);
+        long.markers.push(Marker { elapsed_ms: 4500, label: "forest".into() });
+
+        // Snaps to the marker
+        let (first, second) = split(&long, 4000)?;
+        assert_eq!(first.duration_ms, 4500);
+        assert_eq!(first.frametimes.len(), 45);
+        assert_eq!(second.frametimes.len(), 55);
+        assert_eq!(second.started_at, 1004);
+        assert_eq!(second.markers, vec![Marker { elapsed_ms: 0, label: "forest".into() }]);
+        assert_eq!(second.samples[0].elapsed_ms, 500);
+
+        assert!(split(&long, 0).is_err());
+        assert!(split(&long, 20_000).is_err());
+
+        long.gpu_busy = vec![5.0; 100];
+        long.queue_depth = vec![1; 30];
+        let (first, second) = split(&long, 4000)?;
+        assert_eq!((first.gpu_busy.len(), second.gpu_busy.len()), (45, 55));
+        assert!(first.queue_depth.is_empty() && second.queue_depth.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let merged = merge(vec![recording(1030, 20), recording(1000, 100)])?;
+        assert_eq!(merged.started_at, 1000);
+        assert_eq!(merged.frametimes.len(), 120);
+        assert_eq!(merged.duration_ms, 32_000);
+        assert_eq!(merged.markers, vec![Marker { elapsed_ms: 30_000, label: "merged".into() }]);
+        assert_eq!(merged.samples.last().unwrap().elapsed_ms, 31_000);
+        let gap = Gap { start_ms: 10_000, end_ms: 30_000, reason: "merged".into() };
+        assert_eq!(merged.gaps, vec![gap]);
+
+        let mut other = recording(2000, 10);
+        other.package = "com.other".into();
+        assert!(merge(vec![recording(1000, 10), other]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+// C ABI for launchers and test harnesses embedding the engine, declared in
+// `include/gameperf.h`. The contract of every function is documented there.
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use anyhow::{bail, Context, Result};
+
+use crate::analysis::Stats;
+use crate::capture::mock;
+use crate::capture::{Recorder, Recording};
+use crate::format;
+use crate::util;
+
+// Bumped whenever a signature or `GpStats` changes
+pub const ABI_VERSION: u32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+pub struct GpCapture(Recorder);
+
+pub struct GpRecording(Recording);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GpStats {
+    pub duration_secs: f64,
+    pub frames: u64,
+    pub avg_fps: f64,
+    pub min_fps: f64,
+    pub max_fps: f64,
+    pub p1_low: f64,
+    pub p01_low: f64,
+    pub avg_frametime: f64,
+    pub p99_frametime: f64,
+}
+
+impl From<&Stats> for GpStats {
+    fn from(stats: &Stats) -> Self {
+        GpStats {
+            duration_secs: stats.duration_secs,
+            frames: stats.frames as u64,
+            avg_fps: stats.avg_fps,
+            min_fps: stats.min_fps,
+            max_fps: stats.max_fps,
+            p1_low: stats.p1_low,
+            p01_low: stats.p01_low,
+            avg_frametime: stats.avg_frametime,
+            p99_frametime: stats.p99_frametime,
+        }
+    }
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// Errors and panics never cross the boundary, the caller gets `fallback` and `gp_last_error`
+fn guard<T>(fallback: T, call: impl FnOnce() -> Result<T>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(err)) => {
+            set_error(format!("{:#}", err));
+            fallback
+        }
+        Err(_) => {
+            set_error("Internal error".into());
+            fallback
+        }
+    }
+}
+
+unsafe fn read_str(s: *const c_char) -> Result<String> {
+    if s.is_null() {
+        bail!("Null string");
+    }
+    Ok(CStr::from_ptr(s).to_str().context("Not UTF-8")?.to_string())
+}
+
+unsafe fn deref<'a, T>(handle: *mut T) -> Result<&'a mut T> {
+    handle.as_mut().context("Null handle")
+}
+
+fn into_c(s: String) -> Result<*mut c_char> {
+    Ok(CString::new(s)?.into_raw())
+}
+
+#[no_mangle]
+pub extern "C" fn gp_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn gp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |err| err.as_ptr()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_mock(pattern: *const c_char) -> c_int {
+    guard(-1, || {
+        mock::select(read_str(pattern)?.parse()?);
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_capture_start(package: *const c_char) -> *mut GpCapture {
+    guard(ptr::null_mut(), || {
+        let package = read_str(package)?;
+        // Goes into adb command lines
+        if !util::valid_package(&package) {
+            bail!("Invalid package: {:?}", package);
+        }
+        Ok(Box::into_raw(Box::new(GpCapture(Recorder::new(&package)))))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_capture_poll(
+    capture: *mut GpCapture,
+    sample: *mut *mut c_char,
+) -> c_int {
+    guard(-1, || {
+        let capture = deref(capture)?;
+        let out = deref(sample)?;
+        *out = ptr::null_mut();
+        match capture.0.poll()? {
+            Some(polled) => {
+                *out = into_c(serde_json::to_string(polled)?)?;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_capture_stop(capture: *mut GpCapture) -> *mut GpRecording {
+    guard(ptr::null_mut(), || {
+        if capture.is_null() {
+            bail!("Null handle");
+        }
+        let GpCapture(recorder) = *Box::from_raw(capture);
+        Ok(Box::into_raw(Box::new(GpRecording(recorder.finish()))))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_capture_free(capture: *mut GpCapture) {
+    if !capture.is_null() {
+        drop(Box::from_raw(capture));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_recording_stats(
+    recording: *mut GpRecording,
+    stats: *mut GpStats,
+) -> c_int {
+    guard(-1, || {
+        let recording = deref(recording)?;
+        *deref(stats)? = GpStats::from(&Stats::compute(&recording.0));
+        Ok(0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_recording_write(recording: *mut GpRecording) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let recording = deref(recording)?;
+        let bytes = format::write_gpcap(serde_json::to_value(&recording.0)?)?;
+        into_c(String::from_utf8(bytes)?)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_recording_free(recording: *mut GpRecording) {
+    if !recording.is_null() {
+        drop(Box::from_raw(recording));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gp_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            assert_eq!(gp_mock(b"jitter\0".as_ptr() as *const c_char), -1);
+            let err = CStr::from_ptr(gp_last_error()).to_string_lossy();
+            assert!(err.contains("Unknown mock pattern"));
+            assert!(gp_capture_start(ptr::null()).is_null());
+            assert!(
+                gp_capture_start(b"com.example.game; reboot\0".as_ptr() as *const c_char).is_null()
+            );
+            let err = CStr::from_ptr(gp_last_error()).to_string_lossy();
+            assert!(err.contains("Invalid package"));
+            assert_eq!(gp_recording_stats(ptr::null_mut(), ptr::null_mut()), -1);
+
+            assert_eq!(gp_mock(b"steady\0".as_ptr() as *const c_char), 0);
+            let capture = gp_capture_start(b"com.example.game\0".as_ptr() as *const c_char);
+            assert!(!capture.is_null());
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let mut sample = ptr::null_mut();
+            assert_eq!(gp_capture_poll(capture, &mut sample), 1);
+            assert!(CStr::from_ptr(sample).to_string_lossy().contains("elapsed_ms"));
+            gp_string_free(sample);
+
+            let recording = gp_capture_stop(capture);
+            let mut stats = GpStats::default();
+            assert_eq!(gp_recording_stats(recording, &mut stats), 0);
+            assert!(stats.frames > 0);
+            let gpcap = gp_recording_write(recording);
+            let read = format::parse_gpcap(CStr::from_ptr(gpcap).to_bytes());
+            assert_eq!(
+                read.map(|recording| recording.package).ok().as_deref(),
+                Some("com.example.game")
+            );
+            gp_string_free(gpcap);
+            gp_recording_free(recording);
+        }
+    }
+}
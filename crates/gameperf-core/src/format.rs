@@ -0,0 +1,522 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::analysis::{bound, framegen};
+use crate::capture::compositor::{self, Tally};
+use crate::capture::system::SystemSnapshot;
+use crate::capture::{Recording, Sample};
+use crate::migrate;
+
+// Everything read from files and links goes through here: bytes in, a checked value or an error
+// out, never a panic. See `fuzz` for the entry points.
+pub use crate::morph::{convert as convert_head_morph, validate as parse_head_morph};
+
+// PresentMon 1.x logs `MsBetweenPresents`, 2.x `FrameTime`
+const PRESENTMON_FRAMETIME_COLUMNS: &[&str] = &["MsBetweenPresents", "FrameTime"];
+// GPU timings from the scheduler (ETW) events, only logged by recent PresentMon versions
+const PRESENTMON_GPU_BUSY_COLUMNS: &[&str] = &["GPUBusy", "MsGPUActive"];
+const PRESENTMON_DISPLAYED_COLUMNS: &[&str] = &["MsUntilDisplayed"];
+// PresentMon 2.x, ms of each frame the CPU spent on it and waited, as metrics
+const PRESENTMON_CPU_COLUMNS: &[(&str, &str)] = &[("CPUBusy", "cpu_busy"), ("CPUWait", "cpu_wait")];
+// MangoHud logs a sensor reading with every frame, averaged per sample like the frame rate
+const MANGOHUD_METRICS: &[(&str, &str)] = &[
+    ("cpu_load", "cpu.load"),
+    ("cpu_power", "power.cpu"),
+    ("cpu_temp", "temp.cpu"),
+    ("gpu_load", "gpu.load"),
+    ("gpu_power", "power.gpu"),
+    ("gpu_temp", "temp.gpu"),
+    ("gpu_core_clock", "gpu.clock"),
+    ("gpu_mem_clock", "gpu.mem_clock"),
+    ("gpu_vram_used", "gpu.vram_used"),
+    ("ram_used", "mem.ram_used"),
+    ("swap_used", "mem.swap_used"),
+    ("process_rss", "mem.process_rss"),
+];
+// Between the system info and the frames since MangoHud 0.6.9
+const MANGOHUD_SEPARATOR: &str = "FRAME METRICS";
+
+// A `.gpcap` file or the recording of a shared session
+pub fn parse_gpcap(bytes: &[u8]) -> Result<Recording> {
+    read_recording(serde_json::from_slice(bytes).context("Invalid JSON")?)
+}
+
+pub fn read_recording(mut value: Value) -> Result<Recording> {
+    migrate::upgrade_recording(&mut value)?;
+    let recording = serde_json::from_value(value)?;
+    check(&recording)?;
+    Ok(recording)
+}
+
+// `recording` as saved by `session::export`, possibly anonymized
+pub fn write_gpcap(mut recording: Value) -> Result<Vec<u8>> {
+    migrate::stamp_recording(&mut recording);
+    Ok(serde_json::to_vec(&recording)?)
+}
+
+// What serde can't tell: values analysis would choke on later
+pub fn check(recording: &Recording) -> Result<()> {
+    if recording.frametimes.iter().any(|&ms| !ms.is_finite() || ms < 0.0) {
+        bail!("Invalid frame time");
+    }
+    let frames = recording.frametimes.len();
+    let per_frame =
+        [recording.gpu_busy.len(), recording.queue_depth.len(), recording.generated.len()];
+    if per_frame.iter().any(|&len| len > frames) {
+        bail!("More per frame values than frames");
+    }
+    if recording.gpu_busy.iter().chain(&recording.input_latency).any(|value| !value.is_finite()) {
+        bail!("Invalid frame timing");
+    }
+    Ok(())
+}
+
+pub fn parse_presentmon(text: &str) -> Result<Recording> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = lines.next().context("Empty CSV")?.split(',').map(str::trim).collect();
+    let column = |name: &str| header.iter().position(|h| *h == name);
+    let frametime = PRESENTMON_FRAMETIME_COLUMNS
+        .iter()
+        .find_map(|name| column(name))
+        .context("Not a PresentMon capture, no frame time column")?;
+    let application = column("Application");
+    let gpu_busy = PRESENTMON_GPU_BUSY_COLUMNS.iter().find_map(|name| column(name));
+    let displayed = PRESENTMON_DISPLAYED_COLUMNS.iter().find_map(|name| column(name));
+    let frame_type = column("FrameType");
+    let present_mode = column("PresentMode");
+    let cpu_busy = column("CPUBusy");
+    let cpu: Vec<(&str, usize)> = PRESENTMON_CPU_COLUMNS
+        .iter()
+        .filter_map(|(name, metric)| Some((*metric, column(name)?)))
+        .collect();
+    let optional = |fields: &[&str], column: usize| {
+        fields.get(column).and_then(|v| v.parse::<f64>().ok()).unwrap_or_default()
+    };
+
+    let mut recording = Recording::default();
+    let mut until_displayed = vec![];
+    let mut per_frame: Vec<(&str, Vec<f64>)> = cpu.iter().map(|(m, _)| (*m, vec![])).collect();
+    let mut tally = Tally::default();
+    let busy = |fields: &[&str], column: Option<usize>| {
+        Some(reading(fields, column?)).filter(|ms| ms.is_finite())
+    };
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let app = application.and_then(|c| fields.get(c)).copied().unwrap_or_default();
+        // DWM's presents in captures of all processes, its cost is kept apart from the game's
+        if compositor::is_compositor(app) {
+            tally.compositor_frame(busy(&fields, cpu_busy), busy(&fields, gpu_busy));
+            continue;
+        }
+        if recording.package.is_empty() {
+            recording.package = app.to_string();
+        }
+        let value = fields
+            .get(frametime)
+            .and_then(|v| v.parse::<f64>().ok())
+            .with_context(|| format!("Invalid frame time on line {}", i + 2))?;
+        recording.frametimes.push(value);
+        if let Some(column) = gpu_busy {
+            recording.gpu_busy.push(optional(&fields, column));
+        }
+        if let Some(column) = displayed {
+            until_displayed.push(optional(&fields, column));
+        }
+        if let Some(column) = frame_type {
+            let frame_type = fields.get(column).copied().unwrap_or_default();
+            recording.generated.push(framegen::is_generated_type(frame_type));
+        }
+        for ((_, column), (_, values)) in cpu.iter().zip(&mut per_frame) {
+            values.push(reading(&fields, *column));
+        }
+        if let Some(column) = present_mode {
+            let mode = fields.get(column).copied().unwrap_or_default();
+            tally.game_frame(mode, busy(&fields, displayed));
+        }
+    }
+    if displayed.is_some() {
+        recording.queue_depth = bound::queue_depths(&recording.frametimes, &until_displayed);
+    }
+    recording.compositor = tally.finish(recording.frametimes.iter().sum());
+    with_frame_metrics(recording, per_frame)
+}
+
+pub fn parse_capframex(json: &Value) -> Result<Recording> {
+    let runs =
+        json["Runs"].as_array().context("Invalid CapFrameX capture, `Runs` is not an array")?;
+    let package = json.pointer("/Info/ProcessName").and_then(Value::as_str).unwrap_or_default();
+
+    let mut recording = Recording { package: package.into(), ..Default::default() };
+    for run in runs {
+        let frametimes = run
+            .pointer("/CaptureData/MsBetweenPresents")
+            .and_then(Value::as_array)
+            .context("Invalid CapFrameX capture, missing `MsBetweenPresents`")?;
+        recording.frametimes.extend(frametimes.iter().filter_map(Value::as_f64));
+    }
+    with_samples(recording)
+}
+
+// MangoHud logs are CSV too, told apart from PresentMon by their first lines
+pub fn is_mangohud(text: &str) -> bool {
+    text.lines().take(4).any(|line| {
+        line.contains(MANGOHUD_SEPARATOR) || line.trim_start().starts_with("fps,frametime")
+    })
+}
+
+// A system info header and its values, the separator on newer versions, then a row per frame
+pub fn parse_mangohud(text: &str) -> Result<Recording> {
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let header_at = lines
+        .iter()
+        .position(|line| line.split(',').any(|name| name.trim() == "frametime"))
+        .context("Not a MangoHud log, no frametime column")?;
+    let header: Vec<&str> = lines[header_at].split(',').map(str::trim).collect();
+    let column = |name: &str| header.iter().position(|h| *h == name);
+    let frametime = column("frametime").context("Not a MangoHud log, no frametime column")?;
+    let metrics: Vec<(&str, usize)> = MANGOHUD_METRICS
+        .iter()
+        .filter_map(|(name, metric)| Some((*metric, column(name)?)))
+        .collect();
+
+    let mut recording = Recording::default();
+    if header_at >= 2 {
+        let device: BTreeMap<String, String> = lines[0]
+            .split(',')
+            .zip(lines[1].split(','))
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        recording.system = Some(SystemSnapshot { device, windows: None });
+    }
+    let mut per_frame: Vec<(&str, Vec<f64>)> =
+        metrics.iter().map(|(metric, _)| (*metric, vec![])).collect();
+    for (i, line) in lines[header_at + 1..].iter().enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let value = fields
+            .get(frametime)
+            .and_then(|v| v.parse::<f64>().ok())
+            .with_context(|| format!("Invalid frame time on frame {}", i + 1))?;
+        recording.frametimes.push(value);
+        for ((_, column), (_, values)) in metrics.iter().zip(&mut per_frame) {
+            values.push(reading(&fields, *column));
+        }
+    }
+    with_frame_metrics(recording, per_frame)
+}
+
+// A per frame value, NaN when the log has none for that frame
+fn reading(fields: &[&str], column: usize) -> f64 {
+    fields.get(column).and_then(|v| v.parse().ok()).unwrap_or(f64::NAN)
+}
+
+// Like `with_samples`, with per frame values averaged into each sample
+fn with_frame_metrics(recording: Recording, per_frame: Vec<(&str, Vec<f64>)>) -> Result<Recording> {
+    let mut recording = with_samples(recording)?;
+    for (sample, (_, frames)) in recording.samples.iter_mut().zip(seconds(&recording.frametimes)) {
+        for (metric, values) in &per_frame {
+            let values: Vec<f64> =
+                values[frames.clone()].iter().copied().filter(|v| v.is_finite()).collect();
+            if !values.is_empty() {
+                let avg = values.iter().sum::<f64>() / values.len() as f64;
+                sample.metrics.insert(metric.to_string(), avg);
+            }
+        }
+    }
+    Ok(recording)
+}
+
+// The frames of each second, and when the last of them ends
+fn seconds(frametimes: &[f64]) -> Vec<(f64, Range<usize>)> {
+    let mut elapsed = 0.0;
+    let mut start = 0;
+    let mut seconds = vec![];
+    for (i, &frametime) in frametimes.iter().enumerate() {
+        elapsed += frametime;
+        if elapsed >= (seconds.len() + 1) as f64 * 1000.0 {
+            seconds.push((elapsed, start..i + 1));
+            start = i + 1;
+        }
+    }
+    if start < frametimes.len() {
+        seconds.push((elapsed, start..frametimes.len()));
+    }
+    seconds
+}
+
+// Imported logs only have frame times, samples are rebuilt as one fps value per second
+fn with_samples(mut recording: Recording) -> Result<Recording> {
+    if recording.frametimes.is_empty() {
+        bail!("Capture has no frames");
+    }
+    check(&recording)?;
+
+    // Older logs don't tag generated frames
+    if !recording.generated.contains(&true) {
+        recording.generated = framegen::detect(&recording.frametimes).unwrap_or_default();
+    }
+
+    let seconds = seconds(&recording.frametimes);
+    recording.samples = seconds
+        .iter()
+        .map(|(elapsed, frames)| frame_sample(&recording, *elapsed, frames.clone()))
+        .collect();
+    recording.duration_ms = recording.frametimes.iter().sum::<f64>() as u64;
+    Ok(recording)
+}
+
+fn frame_sample(recording: &Recording, elapsed: f64, frames: Range<usize>) -> Sample {
+    let frametimes = &recording.frametimes[frames.clone()];
+    let avg = frametimes.iter().sum::<f64>() / frametimes.len() as f64;
+    let mut sample = Sample { elapsed_ms: elapsed as u64, ..Default::default() };
+    sample.metrics.insert("fps".into(), 1000.0 / avg);
+    if let Some(generated) = recording.generated.get(frames.clone()) {
+        if let Some(fps) = framegen::rendered_fps(frametimes, generated) {
+            sample.metrics.insert("fps_rendered".into(), fps);
+        }
+    }
+    if let Some(gpu_busy) = recording.gpu_busy.get(frames.clone()) {
+        sample.metrics.extend(bound::gpu_metrics(frametimes, gpu_busy));
+    }
+    if let Some(depths) = recording.queue_depth.get(frames) {
+        let avg = depths.iter().map(|&d| d as f64).sum::<f64>() / depths.len() as f64;
+        sample.metrics.insert("queue_depth".into(), avg);
+    }
+    // Rates over frames of 0 ms, they wouldn't survive JSON
+    sample.metrics.retain(|_, value| value.is_finite());
+    sample
+}
+
+// For a fuzzer (e.g. cargo-fuzz targets calling these) and the tests below. Inputs that parse
+// also have to survive being written and read back.
+pub mod fuzz {
+    use std::path::Path;
+
+    use crate::morph::Format;
+
+    use super::*;
+
+    fn round_trip(recording: &Recording) {
+        let bytes = serde_json::to_value(recording)
+            .map_err(anyhow::Error::from)
+            .and_then(write_gpcap)
+            .expect("Parsed recording can't be written");
+        parse_gpcap(&bytes).expect("Written recording can't be read back");
+    }
+
+    pub fn gpcap(data: &[u8]) {
+        if let Ok(recording) = parse_gpcap(data) {
+            round_trip(&recording);
+        }
+    }
+
+    pub fn presentmon(data: &[u8]) {
+        let recording =
+            std::str::from_utf8(data).map_err(anyhow::Error::from).and_then(parse_presentmon);
+        if let Ok(recording) = recording {
+            round_trip(&recording);
+        }
+    }
+
+    pub fn mangohud(data: &[u8]) {
+        let recording =
+            std::str::from_utf8(data).map_err(anyhow::Error::from).and_then(parse_mangohud);
+        if let Ok(recording) = recording {
+            round_trip(&recording);
+        }
+    }
+
+    pub fn capframex(data: &[u8]) {
+        let json = serde_json::from_slice(data).map_err(anyhow::Error::from);
+        if let Ok(recording) = json.and_then(|json| parse_capframex(&json)) {
+            round_trip(&recording);
+        }
+    }
+
+    pub fn head_morph(data: &[u8]) {
+        for name in ["morph.ron", "morph.me2headmorph"] {
+            let path = Path::new(name);
+            if parse_head_morph(path, data).is_err() {
+                continue;
+            }
+            for target in [Format::Me2, Format::Me3] {
+                if let Ok(converted) = convert_head_morph(path, data, target) {
+                    parse_head_morph(path, &converted).expect("Converted head morph is invalid");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use serde_json::json;
+
+    // Valid inputs of every format, mutated by `test_fuzz`
+    fn seeds() -> Vec<(fn(&[u8]), Vec<u8>)> {
+        let recording = json!({ "package": "com.example.game", "frametimes": [16.6, 33.3] });
+        let capframex = json!({ "Runs": [{ "CaptureData": { "MsBetweenPresents": [16.6] } }] });
+        let mut morph = b"GIBBEDMASSEFFECT2HEADMORPH".to_vec();
+        morph.extend([0; 4]);
+        morph.extend(2i32.to_le_bytes());
+        morph.extend(b"a\0");
+        morph.extend([0; 4 * 10]);
+        vec![
+            (fuzz::gpcap, recording.to_string().into_bytes()),
+            (fuzz::presentmon, b"Application,MsBetweenPresents\ngame.exe,16.6\n".to_vec()),
+            (fuzz::mangohud, b"os,cpu\nLinux,Ryzen\nfps,frametime,gpu_temp\n60,16.6,50\n".to_vec()),
+            (fuzz::capframex, capframex.to_string().into_bytes()),
+            (fuzz::head_morph, morph),
+            (
+                fuzz::head_morph,
+                b"(hair_mesh: \"h\", morph_features: [], offset_bones: [], lod0_vertices: [])"
+                    .to_vec(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_fuzz() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for (target, seed) in seeds() {
+            target(&seed);
+            for _ in 0..2000 {
+                let mut data = seed.clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    let at = rng.gen_range(0..data.len().max(1));
+                    match rng.gen_range(0..3) {
+                        0 if !data.is_empty() => data[at] = rng.gen(),
+                        1 => data.truncate(at),
+                        _ => data.insert(at.min(data.len()), rng.gen()),
+                    }
+                }
+                target(&data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(11);
+        for _ in 0..200 {
+            let frames = rng.gen_range(1..500);
+            // Two decimals, like captures, so JSON reads them back exactly
+            let mut ms = || (rng.gen_range(0.0..200.0f64) * 100.0).round() / 100.0;
+            let frametimes: Vec<f64> = (0..frames).map(|_| ms()).collect();
+            let gpu_busy = frametimes.iter().map(|_| ms()).collect();
+            let recording = Recording {
+                package: "com.example.game".into(),
+                duration_ms: frametimes.iter().sum::<f64>() as u64,
+                gpu_busy,
+                frametimes,
+                ..Default::default()
+            };
+            let written = serde_json::to_value(&recording)?;
+            let read = parse_gpcap(&write_gpcap(written.clone())?)?;
+            assert_eq!(serde_json::to_value(&read)?, written);
+        }
+
+        let mut negative = Recording { frametimes: vec![16.6, -1.0], ..Default::default() };
+        assert!(parse_gpcap(&write_gpcap(serde_json::to_value(&negative)?)?).is_err());
+        negative.frametimes = vec![16.6];
+        negative.generated = vec![true, false];
+        assert!(check(&negative).is_err());
+        assert!(parse_presentmon("MsBetweenPresents\ninf\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_presentmon() -> Result<()> {
+        let csv = "Application,ProcessID,TimeInSeconds,MsBetweenPresents\n\
+                   game.exe,42,0.0,16.0\n\
+                   game.exe,42,0.016,20.0\n";
+        let recording = parse_presentmon(csv)?;
+        assert_eq!(recording.package, "game.exe");
+        assert_eq!(recording.frametimes, vec![16.0, 20.0]);
+        assert_eq!(recording.duration_ms, 36);
+        assert_eq!(recording.samples.len(), 1);
+        assert!(recording.gpu_busy.is_empty());
+
+        let csv = "Application,MsBetweenPresents,MsUntilDisplayed,MsGPUActive\n\
+                   game.exe,16.0,20.0,15.0\n\
+                   game.exe,16.0,20.0,4.0\n";
+        let recording = parse_presentmon(csv)?;
+        assert_eq!(recording.queue_depth, vec![0, 1]);
+        assert_eq!(recording.samples[0].metrics["gpu_bound"], 50.0);
+
+        let csv = "Application,FrameTime,CPUBusy,CPUWait\n\
+                   game.exe,16.0,10.0,6.0\n\
+                   game.exe,16.0,12.0,\n";
+        let recording = parse_presentmon(csv)?;
+        assert_eq!(recording.samples[0].metrics["cpu_busy"], 11.0);
+        assert_eq!(recording.samples[0].metrics["cpu_wait"], 6.0);
+
+        // Windowed, with DWM's presents in the same capture
+        let csv = "Application,PresentMode,FrameTime,MsUntilDisplayed,CPUBusy,GPUBusy\n\
+                   dwm.exe,Composed: Flip,16.6,0.0,1.0,1.5\n\
+                   game.exe,Composed: Flip,20.0,30.0,12.0,15.0\n\
+                   dwm.exe,Composed: Flip,16.6,0.0,1.0,1.5\n\
+                   game.exe,Hardware: Independent Flip,20.0,22.0,12.0,15.0\n";
+        let recording = parse_presentmon(csv)?;
+        assert_eq!(recording.package, "game.exe");
+        assert_eq!(recording.frametimes, vec![20.0, 20.0]);
+        let compositor = recording.compositor.unwrap();
+        assert_eq!(compositor.frames, 2);
+        assert_eq!(compositor.cpu_pct, Some(5.0));
+        assert_eq!(compositor.composed_pct, 50.0);
+        assert_eq!(compositor.composition_latency_ms, Some(30.0));
+
+        assert!(parse_presentmon("Application,ProcessID\ngame.exe,42\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mangohud() -> Result<()> {
+        let log = "os,cpu,gpu,ram,kernel,driver,cpuscheduler\n\
+                   Arch Linux,AMD Ryzen 7 5800X,AMD Radeon RX 6800,32,6.1.1,Mesa 22.3,\n\
+                   --------------------FRAME METRICS--------------------\n\
+                   fps,frametime,cpu_load,gpu_load,cpu_temp,gpu_temp,elapsed\n\
+                   2,500,20,90,60,70,500000000\n\
+                   2,500,30,95,62,,1000000000\n\
+                   4,250,40,99,64,72,1250000000\n";
+        assert!(is_mangohud(log));
+        assert!(!is_mangohud("Application,MsBetweenPresents\ngame.exe,16.6\n"));
+        let recording = parse_mangohud(log)?;
+        assert_eq!(recording.frametimes, vec![500.0, 500.0, 250.0]);
+        assert_eq!(recording.duration_ms, 1250);
+        assert_eq!(recording.samples.len(), 2);
+        assert_eq!(recording.samples[0].metrics["cpu.load"], 25.0);
+        // A missing reading is left out of the average
+        assert_eq!(recording.samples[0].metrics["temp.gpu"], 70.0);
+        assert_eq!(recording.samples[1].metrics["gpu.load"], 99.0);
+        let system = recording.system.context("No system info")?;
+        assert_eq!(system.device["gpu"], "AMD Radeon RX 6800");
+        assert!(!system.device.contains_key("cpuscheduler"));
+
+        assert!(parse_mangohud("fps,frametime\n60,abc\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capframex() -> Result<()> {
+        let json = json!({
+            "Info": { "ProcessName": "game.exe" },
+            "Runs": [
+                { "CaptureData": { "MsBetweenPresents": [500.0, 500.0] } },
+                { "CaptureData": { "MsBetweenPresents": [250.0] } },
+            ],
+        });
+        let recording = parse_capframex(&json)?;
+        assert_eq!(recording.package, "game.exe");
+        assert_eq!(recording.frametimes.len(), 3);
+        assert_eq!(recording.samples.len(), 2);
+        assert_eq!(recording.samples[0].metrics["fps"], 2.0);
+        Ok(())
+    }
+}
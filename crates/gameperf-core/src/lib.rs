@@ -0,0 +1,14 @@
+// The capture engine: providers, analysis and file formats, without any window or webview. The
+// GamePerf app is built on top of it, other tools and integration tests can use it the same way.
+#![warn(clippy::all)]
+
+pub mod analysis;
+pub mod capture;
+pub mod edit;
+pub mod ffi;
+pub mod format;
+pub mod migrate;
+pub mod morph;
+pub mod report;
+pub mod settings;
+pub mod util;
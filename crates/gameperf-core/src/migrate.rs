@@ -0,0 +1,67 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+// `steps[n]` upgrades version n to n + 1, the current version is the number of steps. Files from
+// before versioning are version 0.
+pub type Step = fn(&mut Value) -> Result<()>;
+
+// Session recordings, in the session store and in `.gpcap` files
+pub const RECORDING_STEPS: &[Step] = &[unversioned];
+// Stamped in `.gpcap` files, the app keeps the session store's version
+const FORMAT_KEY: &str = "format_version";
+
+// Version 1 is the layout files had before versioning, only the version changes
+pub fn unversioned(_: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+pub fn current(steps: &[Step]) -> u32 {
+    steps.len() as u32
+}
+
+pub fn upgrade(value: &mut Value, steps: &[Step], from: u32) -> Result<()> {
+    for (version, step) in steps.iter().enumerate().skip(from as usize) {
+        step(value).with_context(|| format!("Upgrading from version {}", version))?;
+    }
+    Ok(())
+}
+
+// For a `.gpcap` file, files without a version are from before versioning
+pub fn upgrade_recording(value: &mut Value) -> Result<()> {
+    let version = value.get(FORMAT_KEY).and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > current(RECORDING_STEPS) {
+        bail!("Capture format {} is newer than this GamePerf", version);
+    }
+    upgrade(value, RECORDING_STEPS, version)?;
+    if let Some(object) = value.as_object_mut() {
+        object.remove(FORMAT_KEY);
+    }
+    Ok(())
+}
+
+// Before writing a `.gpcap` file
+pub fn stamp_recording(value: &mut Value) {
+    if let Some(object) = value.as_object_mut() {
+        object.insert(FORMAT_KEY.into(), json!(current(RECORDING_STEPS)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_recording() -> Result<()> {
+        let mut value = json!({ "package": "com.example.game", "frametimes": [16.6] });
+        stamp_recording(&mut value);
+        assert_eq!(value[FORMAT_KEY], current(RECORDING_STEPS));
+        upgrade_recording(&mut value)?;
+        assert_eq!(value.get(FORMAT_KEY), None);
+
+        upgrade_recording(&mut value)?;
+        assert_eq!(value["frametimes"], json!([16.6]));
+        value[FORMAT_KEY] = json!(current(RECORDING_STEPS) + 1);
+        assert!(upgrade_recording(&mut value).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+const ME2_MAGIC: &[u8] = b"GIBBEDMASSEFFECT2HEADMORPH";
+const ME3_MAGIC: &[u8] = b"GIBBEDMASSEFFECT3HEADMORPH";
+// Fields every exported RON head morph has
+const RON_FIELDS: &[&str] = &["hair_mesh", "morph_features", "offset_bones", "lod0_vertices"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Me2,
+    Me3,
+    Ron,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Me2 => "me2headmorph",
+            Format::Me3 => "me3headmorph",
+            Format::Ron => "ron",
+        }
+    }
+
+    fn magic(self) -> Option<&'static [u8]> {
+        match self {
+            Format::Me2 => Some(ME2_MAGIC),
+            Format::Me3 => Some(ME3_MAGIC),
+            Format::Ron => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeadMorphInfo {
+    pub format: Format,
+    pub hair_mesh: String,
+    pub accessory_meshes: usize,
+    pub morph_features: usize,
+    pub offset_bones: usize,
+    pub lod0_vertices: usize,
+}
+
+pub fn detect(path: &Path, bytes: &[u8]) -> Result<Format> {
+    if bytes.starts_with(ME2_MAGIC) {
+        return Ok(Format::Me2);
+    }
+    if bytes.starts_with(ME3_MAGIC) {
+        return Ok(Format::Me3);
+    }
+    let is_ron = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("ron"));
+    let first = bytes.iter().find(|b| !b.is_ascii_whitespace());
+    if is_ron || first == Some(&b'(') {
+        return Ok(Format::Ron);
+    }
+    bail!("Unknown head morph format, expected a .ron, .me2headmorph or .me3headmorph file")
+}
+
+pub fn validate(path: &Path, bytes: &[u8]) -> Result<HeadMorphInfo> {
+    match detect(path, bytes)? {
+        Format::Ron => validate_ron(bytes),
+        format => validate_gibbed(format, bytes),
+    }
+}
+
+// ME2 and ME3 share the payload layout, only the container header differs
+pub fn convert(path: &Path, bytes: &[u8], target: Format) -> Result<Vec<u8>> {
+    let format = detect(path, bytes)?;
+    validate(path, bytes)?;
+    if format == target {
+        return Ok(bytes.to_vec());
+    }
+    match (format.magic(), target.magic()) {
+        (Some(from), Some(to)) => {
+            let mut converted = to.to_vec();
+            converted.extend_from_slice(&bytes[from.len()..]);
+            Ok(converted)
+        }
+        _ => bail!("Conversion from {:?} to {:?} is not supported", format, target),
+    }
+}
+
+fn validate_ron(bytes: &[u8]) -> Result<HeadMorphInfo> {
+    let text = std::str::from_utf8(bytes).context("RON head morph is not valid UTF-8")?;
+    let text = text.trim();
+    if !text.starts_with('(') || !text.ends_with(')') {
+        bail!("RON head morph must be a `( ... )` structure");
+    }
+    for field in RON_FIELDS {
+        if !text.contains(&format!("{}:", field)) {
+            bail!("RON head morph is missing `{}`", field);
+        }
+    }
+    let hair_mesh = text
+        .split_once("hair_mesh:")
+        .and_then(|(_, rest)| rest.split('"').nth(1))
+        .unwrap_or_default()
+        .to_string();
+    Ok(HeadMorphInfo {
+        format: Format::Ron,
+        hair_mesh,
+        accessory_meshes: 0,
+        morph_features: 0,
+        offset_bones: 0,
+        lod0_vertices: 0,
+    })
+}
+
+fn validate_gibbed(format: Format, bytes: &[u8]) -> Result<HeadMorphInfo> {
+    let magic = format.magic().unwrap_or_default();
+    let mut reader = Reader { bytes, offset: magic.len() };
+
+    let _version = reader.u32("version")?;
+    let hair_mesh = reader.string("hair_mesh")?;
+    let accessory_meshes =
+        reader.array("accessory_mesh", |r| r.string("accessory_mesh").map(drop))?;
+    let morph_features = reader.array("morph_features", |r| {
+        r.string("morph_feature name")?;
+        r.skip(4, "morph_feature value")
+    })?;
+    let offset_bones = reader.array("offset_bones", |r| {
+        r.string("offset_bone name")?;
+        r.skip(12, "offset_bone vector")
+    })?;
+    let lod0_vertices = reader.array("lod0_vertices", |r| r.skip(12, "lod0 vertex"))?;
+    for lod in ["lod1_vertices", "lod2_vertices", "lod3_vertices"] {
+        reader.array(lod, |r| r.skip(12, lod))?;
+    }
+    reader.array("scalar_parameters", |r| {
+        r.string("scalar_parameter name")?;
+        r.skip(4, "scalar_parameter value")
+    })?;
+    reader.array("vector_parameters", |r| {
+        r.string("vector_parameter name")?;
+        r.skip(16, "vector_parameter color")
+    })?;
+    reader.array("texture_parameters", |r| {
+        r.string("texture_parameter name")?;
+        r.string("texture_parameter value").map(drop)
+    })?;
+
+    if reader.offset != bytes.len() {
+        bail!(
+            "{} unexpected trailing bytes at offset {}",
+            bytes.len() - reader.offset,
+            reader.offset
+        );
+    }
+
+    Ok(HeadMorphInfo {
+        format,
+        hair_mesh,
+        accessory_meshes,
+        morph_features,
+        offset_bones,
+        lod0_vertices,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, field: &str) -> Result<&'a [u8]> {
+        let end = self.offset.checked_add(len).filter(|end| *end <= self.bytes.len());
+        match end {
+            Some(end) => {
+                let slice = &self.bytes[self.offset..end];
+                self.offset = end;
+                Ok(slice)
+            }
+            None => {
+                bail!("Truncated head morph while reading `{}` at offset {}", field, self.offset)
+            }
+        }
+    }
+
+    fn skip(&mut self, len: usize, field: &str) -> Result<()> {
+        self.take(len, field).map(drop)
+    }
+
+    fn u32(&mut self, field: &str) -> Result<u32> {
+        let bytes = self.take(4, field)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self, field: &str) -> Result<i32> {
+        self.u32(field).map(|n| n as i32)
+    }
+
+    // Unreal string: positive length is latin1, negative is UTF-16, both null terminated
+    fn string(&mut self, field: &str) -> Result<String> {
+        let len = self.i32(field)?;
+        let string = match len {
+            0 => String::new(),
+            len if len > 0 => {
+                let bytes = self.take(len as usize, field)?;
+                bytes[..bytes.len() - 1].iter().map(|&b| b as char).collect()
+            }
+            len => {
+                let bytes = self.take(len.unsigned_abs() as usize * 2, field)?;
+                let utf16: Vec<u16> =
+                    bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                String::from_utf16(&utf16[..utf16.len() - 1])
+                    .with_context(|| format!("Invalid string in `{}`", field))?
+            }
+        };
+        Ok(string)
+    }
+
+    fn array(
+        &mut self,
+        field: &str,
+        mut item: impl FnMut(&mut Self) -> Result<()>,
+    ) -> Result<usize> {
+        let len = self.i32(field)?;
+        if len < 0 || len as usize > self.bytes.len() {
+            bail!("Invalid `{}` length {} at offset {}", field, len, self.offset - 4);
+        }
+        for _ in 0..len {
+            item(self)?;
+        }
+        Ok(len as usize)
+    }
+}
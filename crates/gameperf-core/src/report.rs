@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::analysis::battery::{self, BatteryReport};
+use crate::analysis::segment;
+use crate::analysis::stutter::{self, Stutter};
+use crate::analysis::summary;
+use crate::analysis::{MetricSummary, Stats};
+use crate::capture::compositor::Compositor;
+use crate::capture::Recording;
+
+// Reports keep their own types instead of serializing `Stats` and friends, so changes there don't
+// leak into a layout tools rely on. Within a version fields are only ever added, anything else
+// (renames, removals, other meanings) bumps it along with `schema/report.schema.json`.
+pub const SCHEMA: &str = "gameperf-report";
+pub const VERSION: u32 = 1;
+const SCHEMA_DOCUMENT: &str = include_str!("../schema/report.schema.json");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub schema: String,
+    pub version: u32,
+    // Unix seconds
+    pub generated_at: u64,
+    pub session: SessionSummary,
+    pub stats: ReportStats,
+    pub segments: Vec<ReportSegment>,
+    pub stutters: Stutters,
+    // Plain language verdict, see `summary::summarize`. Added within version 1, older reports
+    // don't have it.
+    #[serde(default)]
+    pub summary: String,
+    // Handheld runs recorded with the battery, see `Recorder::battery`. Added within version 1.
+    #[serde(default)]
+    pub battery: Option<ReportBattery>,
+    // DWM's cost in windowed PC runs, see `Compositor`. Added within version 1.
+    #[serde(default)]
+    pub compositor: Option<ReportCompositor>,
+    // Flat `device.<prop>` and `windows.<setting>` values, None when not recorded
+    pub system: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    // None for files outside the session store
+    pub id: Option<String>,
+    pub name: String,
+    pub package: String,
+    pub started_at: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportStats {
+    pub duration_secs: f64,
+    pub frames: u64,
+    pub avg_fps: f64,
+    pub min_fps: f64,
+    pub max_fps: f64,
+    pub p1_low: f64,
+    pub p01_low: f64,
+    pub avg_frametime: f64,
+    pub p99_frametime: f64,
+    pub rendered_fps: Option<f64>,
+    pub gpu_bound_pct: Option<f64>,
+    pub limiter_fps: Option<f64>,
+    pub gaps: u64,
+    pub gap_secs: f64,
+    pub metrics: BTreeMap<String, ReportMetric>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportMetric {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportSegment {
+    pub label: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub stats: ReportStats,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stutters {
+    // See `stutter::RATIO`
+    pub threshold_ratio: f64,
+    pub count: u64,
+    pub per_minute: f64,
+    pub frames: Vec<ReportStutter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportStutter {
+    pub frame: u64,
+    pub elapsed_ms: u64,
+    pub frametime: f64,
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportBattery {
+    pub avg_watts: f64,
+    pub fps_per_watt: f64,
+    pub drain_pct_per_hour: Option<f64>,
+    pub hours_to_empty: Option<f64>,
+    pub charging: bool,
+    pub tdp: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportCompositor {
+    pub frames: u64,
+    pub cpu_pct: Option<f64>,
+    pub gpu_pct: Option<f64>,
+    pub composed_pct: f64,
+    pub composition_latency_ms: Option<f64>,
+}
+
+impl From<&Stats> for ReportStats {
+    fn from(stats: &Stats) -> Self {
+        let metric = |summary: &MetricSummary| ReportMetric {
+            min: summary.min,
+            max: summary.max,
+            avg: summary.avg,
+        };
+        ReportStats {
+            duration_secs: stats.duration_secs,
+            frames: stats.frames as u64,
+            avg_fps: stats.avg_fps,
+            min_fps: stats.min_fps,
+            max_fps: stats.max_fps,
+            p1_low: stats.p1_low,
+            p01_low: stats.p01_low,
+            avg_frametime: stats.avg_frametime,
+            p99_frametime: stats.p99_frametime,
+            rendered_fps: stats.rendered_fps,
+            gpu_bound_pct: stats.gpu_bound_pct,
+            limiter_fps: stats.limiter.as_ref().map(|limiter| limiter.fps),
+            gaps: stats.gaps as u64,
+            gap_secs: stats.gap_secs,
+            metrics: stats.metrics.iter().map(|(name, s)| (name.clone(), metric(s))).collect(),
+        }
+    }
+}
+
+impl From<&Compositor> for ReportCompositor {
+    fn from(compositor: &Compositor) -> Self {
+        ReportCompositor {
+            frames: compositor.frames as u64,
+            cpu_pct: compositor.cpu_pct,
+            gpu_pct: compositor.gpu_pct,
+            composed_pct: compositor.composed_pct,
+            composition_latency_ms: compositor.composition_latency_ms,
+        }
+    }
+}
+
+impl From<BatteryReport> for ReportBattery {
+    fn from(battery: BatteryReport) -> Self {
+        ReportBattery {
+            avg_watts: battery.avg_watts,
+            fps_per_watt: battery.fps_per_watt,
+            drain_pct_per_hour: battery.drain_pct_per_hour,
+            hours_to_empty: battery.hours_to_empty,
+            charging: battery.charging,
+            tdp: battery.tdp,
+        }
+    }
+}
+
+impl From<&Stutter> for ReportStutter {
+    fn from(stutter: &Stutter) -> Self {
+        ReportStutter {
+            frame: stutter.frame as u64,
+            elapsed_ms: stutter.elapsed_ms,
+            frametime: stutter.frametime,
+            ratio: stutter.ratio,
+        }
+    }
+}
+
+pub fn generate(recording: &Recording, id: Option<&str>, name: &str) -> Report {
+    let stats = Stats::compute(recording);
+    let stutters: Vec<ReportStutter> =
+        stutter::detect(&recording.frametimes).iter().map(ReportStutter::from).collect();
+    let minutes = stats.duration_secs / 60.0;
+    let per_minute = if minutes > 0.0 { stutters.len() as f64 / minutes } else { 0.0 };
+    let segments = segment::segments(recording)
+        .iter()
+        .map(|segment| ReportSegment {
+            label: segment.label.clone(),
+            start_ms: segment.start_ms,
+            end_ms: segment.end_ms,
+            stats: ReportStats::from(&segment.stats),
+        })
+        .collect();
+    Report {
+        schema: SCHEMA.into(),
+        version: VERSION,
+        generated_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        session: SessionSummary {
+            id: id.map(String::from),
+            name: name.into(),
+            package: recording.package.clone(),
+            started_at: recording.started_at,
+            duration_ms: recording.duration_ms,
+        },
+        stats: ReportStats::from(&stats),
+        segments,
+        stutters: Stutters {
+            threshold_ratio: stutter::RATIO,
+            count: stutters.len() as u64,
+            per_minute,
+            frames: stutters,
+        },
+        summary: summary::summarize(recording, &stats).verdict,
+        battery: battery::analyze(recording, &stats).map(ReportBattery::from),
+        compositor: recording.compositor.as_ref().map(ReportCompositor::from),
+        system: recording.system.as_ref().map(|system| system.entries()),
+    }
+}
+
+// JSON Schema of the current version, for `get_report_schema`
+pub fn schema() -> Result<Value> {
+    Ok(serde_json::from_str(SCHEMA_DOCUMENT)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Marker, Sample};
+    use serde_json::json;
+
+    // Required fields that are missing and fields the schema doesn't describe, through objects,
+    // arrays and `$ref`s
+    fn mismatches(schema: &Value, defs: &Value, value: &Value, path: &str) -> Vec<String> {
+        let schema = match schema["$ref"].as_str() {
+            Some(name) => &defs[name.trim_start_matches("#/$defs/")],
+            None => schema,
+        };
+        let mut mismatches = vec![];
+        if let Some(object) = value.as_object() {
+            for key in schema["required"].as_array().into_iter().flatten() {
+                let key = key.as_str().unwrap_or_default();
+                if !object.contains_key(key) {
+                    mismatches.push(format!("{}.{} is missing", path, key));
+                }
+            }
+            for (key, value) in object {
+                let path = format!("{}.{}", path, key);
+                let property = match &schema["properties"][key] {
+                    Value::Null => &schema["additionalProperties"],
+                    property => property,
+                };
+                if !property.is_object() {
+                    mismatches.push(format!("{} isn't in the schema", path));
+                    continue;
+                }
+                mismatches.extend(self::mismatches(property, defs, value, &path));
+            }
+        }
+        for (index, item) in value.as_array().into_iter().flatten().enumerate() {
+            let path = format!("{}[{}]", path, index);
+            mismatches.extend(self::mismatches(&schema["items"], defs, item, &path));
+        }
+        mismatches
+    }
+
+    #[test]
+    fn test_report() -> Result<()> {
+        let mut frametimes = vec![16.7; 120];
+        frametimes[90] = 60.0;
+        let recording = Recording {
+            package: "com.example.game".into(),
+            duration_ms: 2000,
+            frametimes,
+            markers: vec![Marker { elapsed_ms: 0, label: "city".into() }],
+            samples: vec![Sample {
+                elapsed_ms: 1000,
+                metrics: vec![("mem.total".to_string(), 700.0)].into_iter().collect(),
+            }],
+            ..Default::default()
+        };
+        let report = generate(&recording, Some("0123456789abcdef"), "city run");
+        assert_eq!(report.stutters.count, 1);
+        assert_eq!(report.segments.len(), 1);
+
+        let schema = schema()?;
+        assert_eq!(schema["properties"]["version"]["const"], json!(VERSION));
+        assert_eq!(schema["properties"]["schema"]["const"], json!(SCHEMA));
+        let value = serde_json::to_value(&report)?;
+        assert_eq!(mismatches(&schema, &schema["$defs"], &value, "report"), Vec::<String>::new());
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use crate::analysis::gaps::GapPolicy;
+
+lazy_static! {
+    static ref PROVIDER: RwLock<Option<fn() -> Settings>> = RwLock::new(None);
+}
+
+// What the engine reads from the configuration of the app embedding it
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    // Recorded input scripts are kept in `inputs` there
+    pub data_dir: Option<PathBuf>,
+    // Packages never taken for the foreground, besides the built-in ones, see `foreground`
+    pub foreground_exclusions: Vec<String>,
+    pub gap_policy: GapPolicy,
+    pub gpu_layer: Option<PathBuf>,
+    pub time_server: Option<String>,
+}
+
+// Asked on every use so config changes apply right away, the defaults until it's set
+pub fn provide(settings: fn() -> Settings) {
+    *PROVIDER.write() = Some(settings);
+}
+
+pub fn get() -> Settings {
+    PROVIDER.read().map(|settings| settings()).unwrap_or_default()
+}
@@ -0,0 +1,350 @@
+use anyhow;
+use log::info;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::json;
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+use std::{process::Command, vec};
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+// Without a console window flashing up on Windows
+fn command(program: &str) -> Command {
+    #[allow(unused_mut)]
+    let mut command = Command::new(program);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+    command
+}
+
+pub fn cmd(program: &str, args: String) -> anyhow::Result<(bool, String, String)> {
+    let args: Vec<&str> = args.split(" ").collect();
+    let output = command(program)
+        .args(&args)
+        .output()
+        .map_err(|err| anyhow::anyhow!("Failed to run {}: {}", program, err))?;
+    //command.creation_flags(CREATE_NO_WINDOW);
+    // let output = command.output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{} {:?} {} {} {}",
+            program,
+            args,
+            output.status,
+            stdout.trim(),
+            stderr.trim()
+        ));
+    }
+    Ok((output.status.success(), stdout, stderr))
+}
+
+pub fn adb(args: String) -> anyhow::Result<(bool, String, String)> {
+    cmd("adb", args)
+}
+
+// Long running adb commands (e.g. `getevent`), read from the child's stdout
+pub fn adb_spawn(args: &str) -> anyhow::Result<std::process::Child> {
+    let child = command("adb")
+        .args(args.split(' '))
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    Ok(child)
+}
+
+// Long running tools besides adb, arguments as given and both outputs to read
+pub fn spawn(program: &str, args: &[&str]) -> anyhow::Result<std::process::Child> {
+    let child = command(program)
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    Ok(child)
+}
+
+// Local paths may contain spaces, which `adb` splits on
+pub fn adb_push(local: &std::path::Path, remote: &str) -> anyhow::Result<()> {
+    let output = command("adb").arg("push").arg(local).arg(remote).output()?;
+    if !output.status.success() {
+        anyhow::bail!("adb push failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+pub fn pid_of(package: &str) -> anyhow::Result<String> {
+    let b = package.chars().all(char::is_numeric);
+
+    if b {
+        Ok(package.to_string())
+    } else {
+        let package = fuzzy_runing(package)?;
+        Ok(package.0)
+    }
+}
+
+// Android package names (and bundle ids), nothing that would split or escape a shell command
+pub fn valid_package(package: &str) -> bool {
+    !package.is_empty()
+        && package.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+}
+
+pub fn fuzzy_runing(s: &str) -> anyhow::Result<(String, String)> {
+    let args = format!("shell ps -e");
+    let (succeed, stdout, _e) = adb(args)?;
+    if succeed {
+        for l in stdout.lines() {
+            let items: Vec<&str> = l.trim().split_whitespace().collect();
+
+            if items.last().unwrap().contains(s) {
+                let package = *items.last().unwrap();
+                let pid = items[1].to_string();
+                return Ok((pid, package.to_string()));
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Not Found {}", s))
+}
+
+pub fn fuzzy_package(s: &str) -> anyhow::Result<String> {
+    let args = format!("shell pm list packages -f {}", s);
+    let (succeed, stdout, _e) = adb(args)?;
+    if succeed {
+        for line in stdout.lines() {
+            if line.contains(s) {
+                let p = line.split("=").last().unwrap();
+                log::info!("{}", line,);
+                return Ok(format!("{}", p));
+            }
+        }
+    }
+    Err(anyhow::anyhow!("Not Found {}", s))
+}
+
+pub fn check_inject_so_succeed(package: &str, pid: &str, library: &str) -> anyhow::Result<bool> {
+    let (_, stdout, _) = adb(format!("shell run-as {} cat /proc/{}/maps", package, pid))?;
+    let mut lines = stdout.lines();
+    while let Some(line) = lines.next() {
+        if line.ends_with(library) {
+            info!("[*] {}", line);
+            return Ok(true);
+        }
+    }
+    Err(anyhow::anyhow!("Error Inject {}", library))
+}
+
+pub fn get_android_prop(key: &str) -> anyhow::Result<String> {
+    let (_, stdout, _) = adb(format!("shell getprop"))?;
+    let mut lines = stdout.lines();
+    while let Some(line) = lines.next() {
+        if line.contains(key) {
+            let re = Regex::new(r"\[(.*)\]: \[(.*)\]").unwrap();
+            let cap = re.captures(line);
+            let val = cap.unwrap().get(2).map_or("", |m| m.as_str());
+            // info!("get_android_prop: {:?}", ret);
+            //let items = line.split(' ');
+            // let v = items.last().unwrap();
+            // info!("[*] {}", line);
+            return Ok(val.to_string());
+        }
+    }
+    Err(anyhow::anyhow!("prop error"))
+}
+
+enum ParserSection {
+    HEADER,
+    PSSINFO,
+    APPSUMMARY,
+    OBJECTS,
+    SQL,
+    DATABASES,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PssInfo {
+    package_name: String,
+    pid: String,
+    pss_header: Vec<String>,
+    pss_values: Vec<u64>,
+    cursor: u64,
+    app_header: Vec<String>,
+    app_values: Vec<u64>,
+}
+static mut index: u64 = 0;
+
+impl PssInfo {
+    pub fn metrics(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.pss_header.iter().map(String::as_str).zip(self.pss_values.iter().copied()).skip(1)
+    }
+
+    pub fn new() -> PssInfo {
+        PssInfo {
+            package_name: "".into(),
+            pid: "".into(),
+            pss_header: vec!["index".to_string()],
+            pss_values: vec![],
+            cursor: 0,
+            app_header: vec!["index".to_string()],
+            app_values: vec![],
+        }
+    }
+
+    // Laid out like `dump_pss` output, for mock captures
+    pub fn from_metrics(package_name: &str, metrics: &[(&str, u64)]) -> PssInfo {
+        let mut pss = PssInfo::new();
+        pss.package_name = package_name.into();
+        pss.inc_index();
+        for (name, value) in metrics {
+            pss.pss_header.push(name.to_string());
+            pss.pss_values.push(*value);
+        }
+        pss
+    }
+
+    pub fn inc_index(&mut self) {
+        unsafe {
+            self.cursor = index;
+            index += 1;
+        }
+        self.pss_values.push(self.cursor);
+        self.app_values.push(self.cursor);
+    }
+}
+
+pub fn dump_pss(package_name: &str) -> anyhow::Result<PssInfo> {
+    log::info!("dump pss {}", package_name);
+    let (_, stdout, _) = adb(format!("shell dumpsys meminfo {}", package_name))?;
+    let mut lines = stdout.lines();
+    let mut parser_status = ParserSection::HEADER;
+    let mut pss_data = PssInfo::new();
+    let re_app_info = Regex::new(r"\*\* MEMINFO in pid (\d+) \[(.*)\] \*\*").unwrap();
+    let re_pss_info = Regex::new(r"(\D+)(\d+) .*").unwrap();
+    let re_summary_info = Regex::new(r"(\D+):\s+(\d+)").unwrap();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        match parser_status {
+            ParserSection::HEADER => {
+                if line.contains("------") {
+                    parser_status = ParserSection::PSSINFO;
+                    continue;
+                }
+                let cap = re_app_info.captures(line);
+                if let Some(cap) = cap {
+                    let package_name = cap.get(1).unwrap().as_str();
+                    let pid = cap.get(2).unwrap().as_str();
+                    pss_data.package_name = package_name.into();
+                    pss_data.pid = pid.into();
+                    pss_data.inc_index();
+                    log::debug!("package_name={}, pid={}", package_name, pid);
+                }
+            }
+            ParserSection::PSSINFO => {
+                if line.contains("App Summary") {
+                    parser_status = ParserSection::APPSUMMARY;
+                    log::debug!("App Summary:");
+                    continue;
+                }
+                let cap = re_pss_info.captures(line);
+                if let Some(cap) = cap {
+                    let name = cap.get(1).unwrap().as_str().trim();
+                    let val = cap.get(2).unwrap().as_str();
+                    pss_data.pss_header.push(name.into());
+                    pss_data.pss_values.push(val.parse::<u64>().unwrap() / 1024);
+                    log::debug!("name:{}, val:{}", name, val);
+                }
+            }
+            ParserSection::APPSUMMARY => {
+                if line.contains("Objects") {
+                    parser_status = ParserSection::OBJECTS;
+                    log::debug!("Objects:");
+                    continue;
+                }
+                let cap = re_summary_info.captures(line);
+                if let Some(cap) = cap {
+                    let name = cap.get(1).unwrap().as_str().trim();
+                    let val = cap.get(2).unwrap().as_str();
+                    pss_data.app_header.push(name.into());
+                    pss_data.app_values.push(val.parse::<u64>().unwrap() / 1024);
+                    log::debug!("{}\t{}", name, val);
+                }
+            }
+            ParserSection::OBJECTS => {}
+            ParserSection::SQL => {}
+            ParserSection::DATABASES => {}
+        }
+    }
+    return Ok(pss_data);
+    // Err(anyhow::anyhow!("dump pss error"))
+}
+
+// The first display's app, see `resumed_apps` and `foreground` for the others
+pub fn current_app() -> anyhow::Result<String> {
+    resumed_apps()?.into_iter().next().ok_or_else(|| anyhow::anyhow!("No resumed activity"))
+}
+
+// Packages of the resumed activities in display order, one per display on foldables, DeX and
+// external screens
+pub fn resumed_apps() -> anyhow::Result<Vec<String>> {
+    let (_, stdout, _) = adb(format!("shell dumpsys activity activities|grep ResumedActivity"))?;
+    Ok(parse_resumed(&stdout))
+}
+
+// `mResumedActivity: ActivityRecord{8a1c2 u0 com.example.game/.MainActivity t47}`, per display
+// `ResumedActivity:ActivityRecord{...}` since Android 10
+pub fn parse_resumed(dump: &str) -> Vec<String> {
+    let mut apps: Vec<String> = vec![];
+    for line in dump.lines().filter(|line| !line.contains("LastResumedActivity")) {
+        let component =
+            line.split(|c: char| c.is_whitespace() || c == '{').find(|w| w.contains('/'));
+        if let Some(package) = component.and_then(|component| component.split('/').next()) {
+            if !package.is_empty() && !apps.iter().any(|app| app == package) {
+                apps.push(package.to_string());
+            }
+        }
+    }
+    apps
+}
+
+pub fn surface_layer(package: &str) -> anyhow::Result<String> {
+    let (_, stdout, _) = adb(format!("shell dumpsys SurfaceFlinger --list"))?;
+    let layers: Vec<&str> = stdout.lines().map(str::trim).filter(|l| l.contains(package)).collect();
+    // Games render into a SurfaceView, prefer it over the activity layer
+    layers
+        .iter()
+        .find(|l| l.starts_with("SurfaceView"))
+        .or_else(|| layers.first())
+        .map(|l| l.to_string())
+        .ok_or_else(|| anyhow::anyhow!("No surface layer for {}", package))
+}
+
+// Returns the refresh period and the actual present timestamps (ns) of the last frames
+pub fn dump_latency(layer: &str) -> anyhow::Result<(u64, Vec<u64>)> {
+    let (_, stdout, _) = adb(format!("shell dumpsys SurfaceFlinger --latency '{}'", layer))?;
+    parse_latency(&stdout)
+}
+
+pub fn parse_latency(output: &str) -> anyhow::Result<(u64, Vec<u64>)> {
+    let mut lines = output.lines();
+    let refresh_period = lines
+        .next()
+        .and_then(|l| l.trim().parse::<u64>().ok())
+        .ok_or_else(|| anyhow::anyhow!("latency error"))?;
+
+    let mut presents = vec![];
+    for line in lines {
+        let items: Vec<&str> = line.split_whitespace().collect();
+        if items.len() != 3 {
+            continue;
+        }
+        if let Ok(actual) = items[1].parse::<u64>() {
+            // 0 means not presented yet, i64::MAX means the fence is still pending
+            if actual != 0 && actual != i64::MAX as u64 {
+                presents.push(actual);
+            }
+        }
+    }
+    Ok((refresh_period, presents))
+}
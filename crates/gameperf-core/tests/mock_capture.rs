@@ -0,0 +1,29 @@
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use gameperf_core::analysis::Stats;
+use gameperf_core::capture::mock::{self, Pattern};
+use gameperf_core::capture::Recorder;
+use gameperf_core::format;
+
+// A whole capture through the public API, no device or window needed
+#[test]
+fn test_mock_capture() -> Result<()> {
+    mock::select(Pattern::Steady);
+    let mut recorder = Recorder::new("com.example.game");
+    for _ in 0..3 {
+        thread::sleep(Duration::from_millis(100));
+        assert!(recorder.poll()?.is_some());
+    }
+    let recording = recorder.finish();
+    assert!(!recording.frametimes.is_empty());
+    assert_eq!(recording.samples.len(), 3);
+
+    let bytes = format::write_gpcap(serde_json::to_value(&recording)?)?;
+    let read = format::parse_gpcap(&bytes)?;
+    assert_eq!(read.frametimes.len(), recording.frametimes.len());
+    let stats = Stats::compute(&read);
+    assert!((stats.avg_fps - 60.0).abs() < 1.0);
+    Ok(())
+}
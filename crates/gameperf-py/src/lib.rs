@@ -0,0 +1,178 @@
+// Python bindings, `import gameperf` to script over captures and the session store in notebooks.
+// Everything besides recordings comes back as plain dicts and lists, laid out like the JSON the
+// app's commands return.
+#![warn(clippy::all)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use gameperf_core::analysis::gaps::GapPolicy;
+use gameperf_core::analysis::{segment, Stats};
+use gameperf_core::capture::{system, Recording};
+use gameperf_core::format;
+use pyo3::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+
+// Through the json module, so callers get dicts and lists rather than wrapper objects
+fn to_py(py: Python, value: &impl Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(anyhow::Error::from)?;
+    Ok(py.import("json")?.call_method1("loads", (json,))?.into())
+}
+
+// Where GamePerf keeps them, see `config::session_dir` in the app
+fn session_dir(dir: Option<PathBuf>) -> Result<PathBuf> {
+    dir.or_else(|| dirs::data_dir().map(|dir| dir.join("GamePerf").join("sessions")))
+        .context("No data directory")
+}
+
+// `.gpcap` files, session files, PresentMon and MangoHud CSVs and CapFrameX captures, like
+// importing them
+fn read(path: &Path) -> Result<Recording> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("csv")) {
+        let text = std::str::from_utf8(&bytes).context("Not UTF-8")?;
+        if format::is_mangohud(text) {
+            return format::parse_mangohud(text);
+        }
+        return format::parse_presentmon(text);
+    }
+    let json: Value = serde_json::from_slice(&bytes).context("Invalid JSON")?;
+    if json.get("Runs").is_some() {
+        format::parse_capframex(&json)
+    } else {
+        format::read_recording(json)
+    }
+}
+
+#[pyclass(name = "Recording")]
+struct PyRecording {
+    inner: Recording,
+}
+
+#[pymethods]
+impl PyRecording {
+    #[getter]
+    fn package(&self) -> &str {
+        &self.inner.package
+    }
+
+    // Unix seconds
+    #[getter]
+    fn started_at(&self) -> u64 {
+        self.inner.started_at
+    }
+
+    #[getter]
+    fn duration_ms(&self) -> u64 {
+        self.inner.duration_ms
+    }
+
+    #[getter]
+    fn frametimes(&self) -> Vec<f64> {
+        self.inner.frametimes.clone()
+    }
+
+    #[getter]
+    fn refresh_rate(&self) -> Option<f64> {
+        self.inner.refresh_rate
+    }
+
+    #[getter]
+    fn samples(&self, py: Python) -> PyResult<PyObject> {
+        to_py(py, &self.inner.samples)
+    }
+
+    #[getter]
+    fn markers(&self, py: Python) -> PyResult<PyObject> {
+        to_py(py, &self.inner.markers)
+    }
+
+    // Gaps are left out unless `interpolate_gaps`, see `GapPolicy`
+    #[pyo3(signature = (interpolate_gaps = false))]
+    fn stats(&self, py: Python, interpolate_gaps: bool) -> PyResult<PyObject> {
+        let policy = if interpolate_gaps { GapPolicy::Interpolate } else { GapPolicy::Exclude };
+        to_py(py, &Stats::compute_with(&self.inner, policy))
+    }
+
+    fn segments(&self, py: Python) -> PyResult<PyObject> {
+        to_py(py, &segment::segments(&self.inner))
+    }
+
+    fn to_dict(&self, py: Python) -> PyResult<PyObject> {
+        to_py(py, &self.inner)
+    }
+
+    // As a `.gpcap` file, which the app opens
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        let bytes =
+            format::write_gpcap(serde_json::to_value(&self.inner).map_err(anyhow::Error::from)?)?;
+        fs::write(&path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        let frames = self.inner.frametimes.len();
+        format!(
+            "<Recording {} {} ms, {} frames>",
+            self.inner.package, self.inner.duration_ms, frames
+        )
+    }
+}
+
+#[pyfunction]
+fn load(path: PathBuf) -> PyResult<PyRecording> {
+    Ok(PyRecording { inner: read(&path)? })
+}
+
+// The session list, newest first
+#[pyfunction]
+#[pyo3(signature = (dir = None))]
+fn sessions(py: Python, dir: Option<PathBuf>) -> PyResult<PyObject> {
+    let path = session_dir(dir)?.join("index.json");
+    let json = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let index: serde_json::Map<String, Value> =
+        serde_json::from_slice(&json).map_err(anyhow::Error::from)?;
+    let mut sessions: Vec<Value> = index.into_iter().map(|(_, info)| info).collect();
+    sessions.sort_by_key(|info| std::cmp::Reverse(info["started_at"].as_u64()));
+    to_py(py, &sessions)
+}
+
+#[pyfunction]
+#[pyo3(signature = (id, dir = None))]
+fn load_session(id: &str, dir: Option<PathBuf>) -> PyResult<PyRecording> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(anyhow!("Invalid session id: {}", id).into());
+    }
+    let path = session_dir(dir)?.join(format!("{}.json", id));
+    if !path.is_file() {
+        return Err(anyhow!("Unknown session {}", id).into());
+    }
+    load(path)
+}
+
+// Scenes matched by label across recordings
+#[pyfunction]
+fn compare_segments(py: Python, recordings: Vec<PyRef<PyRecording>>) -> PyResult<PyObject> {
+    let recordings: Vec<Recording> = recordings.iter().map(|r| r.inner.clone()).collect();
+    to_py(py, &segment::compare(&recordings))
+}
+
+// Recorded settings (Game Mode, HAGS, device build...) that differ between the recordings
+#[pyfunction]
+fn compare_environments(py: Python, recordings: Vec<PyRef<PyRecording>>) -> PyResult<PyObject> {
+    let snapshots: Vec<_> = recordings.iter().map(|r| r.inner.system.as_ref()).collect();
+    to_py(py, &system::differences(&snapshots))
+}
+
+#[pymodule]
+fn gameperf(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyRecording>()?;
+    m.add_function(wrap_pyfunction!(load, m)?)?;
+    m.add_function(wrap_pyfunction!(sessions, m)?)?;
+    m.add_function(wrap_pyfunction!(load_session, m)?)?;
+    m.add_function(wrap_pyfunction!(compare_segments, m)?)?;
+    m.add_function(wrap_pyfunction!(compare_environments, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,420 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::base::{state, ChannelMsg};
+use crate::capture::timesync::{self, Probe};
+use crate::config::CONFIG;
+use crate::discovery;
+use crate::rpc;
+use crate::util;
+
+pub const DEFAULT_PORT: u16 = 27183;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// Time for every agent to get the command before it is due
+const LEAD: Duration = Duration::from_millis(500);
+// A request or reply, far more than any command needs
+const MAX_LINE: u64 = 64 * 1024;
+
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+// Another instance (e.g. on a streaming client) whose captures follow this one's
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Peer {
+    pub name: String,
+    // `host:port`
+    pub address: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // Capture state and reads only, e.g. a dashboard, see `rpc::auth::permit`
+    Observer,
+    // Starts and stops captures and writes files too
+    Controller,
+}
+
+impl Role {
+    pub fn allows(self, required: Role) -> bool {
+        self == Role::Controller || required == Role::Observer
+    }
+}
+
+// Sent instead of the token by clients that aren't peers, and as `auth.key` over JSON-RPC
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentSettings {
+    // Take start/stop commands from other instances
+    pub listen: bool,
+    // On every interface (and advertised) for peers on other machines, localhost only otherwise
+    pub lan: bool,
+    pub port: u16,
+    // What peers must send, generated when listening without one. Controls captures.
+    pub token: String,
+    pub keys: Vec<ApiKey>,
+    pub peers: Vec<Peer>,
+}
+
+impl Default for AgentSettings {
+    fn default() -> Self {
+        AgentSettings {
+            listen: false,
+            lan: false,
+            port: DEFAULT_PORT,
+            token: String::new(),
+            keys: vec![],
+            peers: vec![],
+        }
+    }
+}
+
+impl AgentSettings {
+    // Who sent `token`, None for an unknown one
+    pub fn authorize(&self, token: &str) -> Option<(&str, Role)> {
+        let matches = |key: &str| {
+            !key.is_empty() && rpc::auth::constant_time_eq(token.as_bytes(), key.as_bytes())
+        };
+        if matches(&self.token) {
+            return Some(("peer", Role::Controller));
+        }
+        let key = self.keys.iter().find(|key| matches(&key.key))?;
+        Some((&key.name, key.role))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    Ping,
+    // Answered with the capture state, see `base::state`
+    State,
+    // `at_ms` on the agent's clock
+    Start { package: String, at_ms: u64 },
+    Stop { at_ms: u64 },
+}
+
+impl Command {
+    fn role(&self) -> Role {
+        match self {
+            Command::Ping | Command::State => Role::Observer,
+            Command::Start { .. } | Command::Stop { .. } => Role::Controller,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Request {
+    token: String,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Reply {
+    error: Option<String>,
+    now_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<Value>,
+}
+
+// Sent to the page as `tse_agent_status` after each synced start/stop
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStatus {
+    pub name: String,
+    pub ok: bool,
+    // Peer clock minus ours
+    pub offset_ms: Option<i64>,
+    pub error: Option<String>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+fn sleep_until(at_ms: u64) {
+    let now = now_ms();
+    if at_ms > now {
+        thread::sleep(Duration::from_millis(at_ms - now));
+    }
+}
+
+pub fn generate_token() -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+}
+
+// Once per run, turning listening off takes a restart
+pub fn listen(tx: Sender<ChannelMsg>) -> Result<()> {
+    let settings = CONFIG.read().agent.clone();
+    let port = settings.port;
+    if settings.token.is_empty() && settings.keys.iter().all(|key| key.key.is_empty()) {
+        bail!("Agent token or API key required");
+    }
+    if LISTENING.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let address = bind_address(&settings);
+    let listener = match TcpListener::bind((address, port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            LISTENING.store(false, Ordering::SeqCst);
+            return Err(err).with_context(|| format!("Failed to listen on port {}", port));
+        }
+    };
+    log::info!("agent listening on {}:{}", address, port);
+    if settings.lan {
+        if let Err(err) = discovery::advertise(port) {
+            log::warn!("agent: {}", err);
+        }
+    }
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(err) = handle(stream, &tx) {
+                log::warn!("agent: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn bind_address(settings: &AgentSettings) -> IpAddr {
+    if settings.lan {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv4Addr::LOCALHOST.into()
+    }
+}
+
+// Why `request` is refused, None when it may run
+fn refusal(settings: &AgentSettings, request: &Request) -> Option<String> {
+    let (name, role) = match settings.authorize(&request.token) {
+        Some(authorized) => authorized,
+        None => return Some("Invalid token".into()),
+    };
+    if !role.allows(request.command.role()) {
+        return Some(format!("Key {} is {:?}, not allowed to control", name, role));
+    }
+    match &request.command {
+        Command::Start { package, .. } if !util::valid_package(package) => {
+            Some(format!("Invalid package: {:?}", package))
+        }
+        _ => None,
+    }
+}
+
+// Keys are read for each request, changing them doesn't take a restart
+fn handle(mut stream: TcpStream, tx: &Sender<ChannelMsg>) -> Result<()> {
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let request: Request = serde_json::from_str(&read_line(&stream)?)?;
+    let refuse = |stream: &mut TcpStream, error: String| -> Result<()> {
+        let reply = Reply { error: Some(error.clone()), ..Default::default() };
+        stream.write_all(&serde_json::to_vec(&reply)?)?;
+        stream.write_all(b"\n")?;
+        bail!("{} from {:?}", error, stream.peer_addr().ok());
+    };
+    let refused = refusal(&CONFIG.read().agent, &request);
+    if let Some(error) = refused {
+        return refuse(&mut stream, error);
+    }
+
+    let mut reply = Reply { error: None, now_ms: now_ms(), state: None };
+    let (msg, at_ms) = match request.command {
+        Command::Ping => (None, 0),
+        Command::State => {
+            reply.state = serde_json::to_value(state::current()).ok();
+            (None, 0)
+        }
+        Command::Start { package, at_ms } => (Some(ChannelMsg::StartCapture(package)), at_ms),
+        Command::Stop { at_ms } => (Some(ChannelMsg::StopCapture), at_ms),
+    };
+    stream.write_all(&serde_json::to_vec(&reply)?)?;
+    stream.write_all(b"\n")?;
+    if let Some(msg) = msg {
+        log::info!("agent: capture command due at {}", at_ms);
+        let tx = tx.clone();
+        thread::spawn(move || {
+            sleep_until(at_ms);
+            let _ = tx.send(msg);
+        });
+    }
+    Ok(())
+}
+
+// One JSON object per line, longer ones are refused
+fn read_line(reader: impl Read) -> Result<String> {
+    let mut line = String::new();
+    BufReader::new(reader).take(MAX_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        bail!("Line too long or cut short");
+    }
+    Ok(line)
+}
+
+fn send(peer: &Peer, command: Command) -> Result<Reply> {
+    let addr = peer
+        .address
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| format!("Invalid address: {}", peer.address))?;
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    let request = Request { token: peer.token.clone(), command };
+    stream.write_all(&serde_json::to_vec(&request)?)?;
+    stream.write_all(b"\n")?;
+
+    let reply: Reply = serde_json::from_str(&read_line(&stream)?)?;
+    if let Some(error) = reply.error {
+        bail!("{}", error);
+    }
+    Ok(reply)
+}
+
+// Peer clock minus ours
+fn offset(peer: &Peer) -> Result<i64> {
+    let offset = timesync::probe_all(|| {
+        let sent = now_ms();
+        let reply = send(peer, Command::Ping)?;
+        Ok(Probe { sent, remote: reply.now_ms, received: now_ms() })
+    })?;
+    Ok(offset.offset)
+}
+
+fn command_all(peers: &[Peer], at_ms: u64, command: impl Fn(u64) -> Command) -> Vec<PeerStatus> {
+    peers
+        .iter()
+        .map(|peer| {
+            let result = offset(peer).and_then(|offset| {
+                let at = (at_ms as i64 + offset).max(0) as u64;
+                send(peer, command(at)).map(|_| offset)
+            });
+            PeerStatus {
+                name: peer.name.clone(),
+                ok: result.is_ok(),
+                offset_ms: result.as_ref().ok().copied(),
+                error: result.err().map(|err| err.to_string()),
+            }
+        })
+        .collect()
+}
+
+pub fn has_peers() -> bool {
+    !CONFIG.read().agent.peers.is_empty()
+}
+
+// Starts (or stops with None) the capture here and on every peer at the same moment, a peer
+// failing doesn't hold the others back
+pub fn synced(package: Option<String>, tx: Sender<ChannelMsg>, proxy: EventLoopProxy<rpc::Event>) {
+    let peers = CONFIG.read().agent.peers.clone();
+    thread::spawn(move || {
+        let at_ms = now_ms() + LEAD.as_millis() as u64;
+        let statuses = match &package {
+            Some(package) => command_all(&peers, at_ms, |at_ms| Command::Start {
+                package: package.clone(),
+                at_ms,
+            }),
+            None => command_all(&peers, at_ms, |at_ms| Command::Stop { at_ms }),
+        };
+        for status in statuses.iter().filter(|status| !status.ok) {
+            log::warn!("agent {}: {}", status.name, status.error.as_deref().unwrap_or_default());
+        }
+        let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+            "tse_agent_status",
+            json!({ "starting": package.is_some(), "peers": statuses }),
+        ));
+
+        sleep_until(at_ms);
+        let _ = tx.send(match package {
+            Some(package) => ChannelMsg::StartCapture(package),
+            None => ChannelMsg::StopCapture,
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let settings = AgentSettings {
+            token: "peer-token".into(),
+            keys: vec![ApiKey {
+                name: "dashboard".into(),
+                key: "watch".into(),
+                role: Role::Observer,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(settings.authorize("peer-token"), Some(("peer", Role::Controller)));
+        assert_eq!(settings.authorize("watch"), Some(("dashboard", Role::Observer)));
+        assert_eq!(settings.authorize(""), None);
+
+        let start = Command::Start { package: "com.example.game".into(), at_ms: 0 };
+        assert!(Role::Observer.allows(Command::State.role()));
+        assert!(!Role::Observer.allows(start.role()));
+        assert!(Role::Controller.allows(start.role()));
+    }
+
+    #[test]
+    fn test_request() {
+        let line = r#"{"token":"t","command":"start","package":"com.example.game","at_ms":5}"#;
+        let request: Request =
+            serde_json::from_str(&read_line(format!("{}\n", line).as_bytes()).unwrap()).unwrap();
+        assert!(matches!(request.command, Command::Start { at_ms: 5, .. }));
+        assert!(serde_json::from_str::<Request>(r#"{"token":"t","command":"reboot"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"command":"ping"}"#).is_err());
+
+        assert!(read_line(line.as_bytes()).is_err());
+        let long = format!("{}\n", " ".repeat(MAX_LINE as usize));
+        assert!(read_line(long.as_bytes()).is_err());
+
+        assert!(util::valid_package("com.example.game_2"));
+        for package in ["", "com.example;reboot", "com example", "../game", "$(id)"] {
+            assert!(!util::valid_package(package), "{:?}", package);
+        }
+    }
+
+    #[test]
+    fn test_refusal() {
+        let mut settings = AgentSettings {
+            token: "peer-token".into(),
+            keys: vec![ApiKey {
+                name: "dashboard".into(),
+                key: "watch".into(),
+                role: Role::Observer,
+            }],
+            ..Default::default()
+        };
+        let request = |token: &str, package: &str| Request {
+            token: token.into(),
+            command: Command::Start { package: package.into(), at_ms: 0 },
+        };
+        assert_eq!(refusal(&settings, &request("peer-token", "com.example.game")), None);
+        assert!(refusal(&settings, &request("", "com.example.game")).is_some());
+        assert!(refusal(&settings, &request("watch", "com.example.game")).is_some());
+        assert!(refusal(&settings, &request("peer-token", "com.example;reboot")).is_some());
+        let state = Request { token: "watch".into(), command: Command::State };
+        assert_eq!(refusal(&settings, &state), None);
+
+        assert_eq!(bind_address(&settings), IpAddr::from(Ipv4Addr::LOCALHOST));
+        settings.lan = true;
+        assert_eq!(bind_address(&settings), IpAddr::from(Ipv4Addr::UNSPECIFIED));
+    }
+}
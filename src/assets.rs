@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::net::TcpStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use rust_embed::RustEmbed;
+use serde_json::json;
+use url::Url;
+use walkdir::WalkDir;
+use wry::application::event_loop::EventLoopProxy;
+use wry::http::{self, status::StatusCode};
+
+use crate::rpc;
+
+// The built frontend, in the binary for release builds and read from `dist/` by debug builds
+#[derive(RustEmbed)]
+#[folder = "dist/"]
+struct Embedded;
+
+const ROOT: &str = "tse://localhost/";
+const DIST: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/dist");
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    // Set by `--dev-url`, pages then come from the dev server instead
+    static ref DEV_URL: RwLock<Option<Url>> = RwLock::new(None);
+}
+
+pub fn get(path: &str) -> Option<Vec<u8>> {
+    Embedded::get(path).map(|asset| asset.data.into_owned())
+}
+
+pub fn paths() -> Vec<String> {
+    Embedded::iter().map(|path| path.into_owned()).collect()
+}
+
+// A dev server on this machine (e.g. `npm run dev`), which reloads the page on changes. Debug
+// builds only, the pages it serves can call every command.
+pub fn use_dev_server(url: &str) -> Result<()> {
+    if !cfg!(debug_assertions) {
+        bail!("--dev-url is only supported by debug builds");
+    }
+    let url = Url::parse(url)?;
+    let local = matches!(url.host_str(), Some("localhost") | Some("127.0.0.1") | Some("[::1]"));
+    if !local || !matches!(url.scheme(), "http" | "https") {
+        bail!("Dev server must be on localhost, not {}", url);
+    }
+    let reachable = url
+        .socket_addrs(|| None)?
+        .iter()
+        .any(|addr| TcpStream::connect_timeout(addr, PROBE_TIMEOUT).is_ok());
+    if !reachable {
+        bail!("No dev server running at {}", url);
+    }
+    log::info!("serving pages from {}", url);
+    *DEV_URL.write() = Some(url);
+    Ok(())
+}
+
+// Where `page` is loaded from, "" for the main window
+pub fn url(page: &str) -> String {
+    match &*DEV_URL.read() {
+        Some(dev) => dev.join(page).map(String::from).unwrap_or_else(|_| dev.to_string()),
+        None => format!("{}{}", ROOT, page),
+    }
+}
+
+// Allowed to call commands besides the custom protocol, None without a dev server
+pub fn dev_origin() -> Option<String> {
+    DEV_URL.read().as_ref().map(|url| url.origin().ascii_serialization())
+}
+
+// Relative path to modification time
+fn snapshot(dir: &Path) -> BTreeMap<String, Option<SystemTime>> {
+    WalkDir::new(dir)
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(dir).ok()?.to_string_lossy().replace('\\', "/");
+            Some((path, entry.metadata().ok().and_then(|m| m.modified().ok())))
+        })
+        .collect()
+}
+
+// Added, modified and removed files
+fn changes(
+    before: &BTreeMap<String, Option<SystemTime>>,
+    after: &BTreeMap<String, Option<SystemTime>>,
+) -> Vec<String> {
+    let modified = after.iter().filter(|(path, time)| before.get(*path) != Some(*time));
+    let removed = before.keys().filter(|path| !after.contains_key(*path));
+    modified.map(|(path, _)| path).chain(removed).cloned().collect()
+}
+
+// Debug builds read `dist/` on every request, the page gets `tse_frontend_assets_changed` after
+// a rebuild and reloads unless it handles it. Capture state lives here and survives the reload.
+pub fn spawn_watcher(proxy: EventLoopProxy<rpc::Event>) {
+    if !cfg!(debug_assertions) || DEV_URL.read().is_some() {
+        return;
+    }
+    thread::spawn(move || {
+        let dir = Path::new(DIST);
+        let mut files = snapshot(dir);
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let current = snapshot(dir);
+            let changed = changes(&files, &current);
+            files = current;
+            if changed.is_empty() {
+                continue;
+            }
+            log::info!("frontend assets changed: {:?}", changed);
+            let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+                "tse_frontend_assets_changed",
+                json!({ "files": changed }),
+            ));
+        }
+    });
+}
+
+pub fn protocol(request: &http::Request) -> wry::Result<http::Response> {
+    let mut path = request.uri().trim_start_matches(ROOT);
+    if path.is_empty() {
+        path = "index.html"
+    }
+    log::debug!("{:?}", path);
+    let response = http::ResponseBuilder::new();
+    match Embedded::get(path) {
+        Some(asset) => {
+            let mime = mime_guess::from_path(path).first_or_octet_stream().to_string();
+            response.mimetype(&mime).body(asset.data.into())
+        }
+        None => response.status(StatusCode::NOT_FOUND).body(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dev_server() {
+        assert_eq!(url("overlay.html"), "tse://localhost/overlay.html");
+        assert!(use_dev_server("http://example.com:5173").is_err());
+        assert!(use_dev_server("file:///tmp/index.html").is_err());
+        assert_eq!(dev_origin(), None);
+    }
+
+    #[test]
+    fn test_changes() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(1);
+        let files = |list: &[(&str, SystemTime)]| -> BTreeMap<String, Option<SystemTime>> {
+            list.iter().map(|(path, time)| (path.to_string(), Some(*time))).collect()
+        };
+        let before = files(&[("app.js", now), ("index.html", now), ("old.css", now)]);
+        let after = files(&[("app.js", later), ("index.html", now), ("new.css", now)]);
+        assert_eq!(changes(&before, &after), vec!["app.js", "new.css", "old.css"]);
+        assert!(changes(&after, &after).is_empty());
+    }
+}
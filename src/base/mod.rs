@@ -1,5 +1,17 @@
 
+pub mod recording;
+pub mod state;
+
+use crate::capture::benchmark::Rule;
+
 pub enum ChannelMsg {
     StartCapture(String),
-    StopCapture
+    StopCapture,
+    PauseCapture,
+    ResumeCapture,
+    // Start and stop with the game's built-in benchmark, see `Rule`
+    ArmBenchmark(String, Rule),
+    DisarmBenchmark,
+    // Stop capturing and answer with `rpc::Event::ShutdownReady`
+    Shutdown,
 }
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::capture::sysfs::Host;
+use crate::capture::{mock, Recorder};
+use crate::config::CONFIG;
+use crate::session::{self, SessionInfo, Source};
+use crate::util;
+
+// Product name of this machine, `Jupiter` or `Galileo` on a Steam Deck
+const DMI_PRODUCT: &str = "/sys/class/dmi/id/product_name";
+
+// What a capture records besides memory and frames, for the GUI, agents and the command line
+// (see `Config::capture`) as well as CI plans
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureOptions {
+    // simpleperf counters per thread and the GPU's clock and utilization, see `Recorder::counters`
+    pub counters: bool,
+    // Per frame GPU statistics through the layer set in `gpu_layer`, the game is restarted
+    pub gpu_layer: bool,
+    // Unreal's CSV profiler, for engine side bound analysis
+    pub unreal: bool,
+    // The package is a bundle id on a tethered iPhone or iPad, the only one unless `udid` is set
+    pub ios: bool,
+    pub udid: Option<String>,
+}
+
+impl CaptureOptions {
+    pub fn android_only(&self) -> bool {
+        self.counters || self.gpu_layer || self.unreal
+    }
+}
+
+// Fails when the game (or the iOS device) doesn't answer. `restarting` is called before the game
+// is stopped to load the GPU layer.
+pub fn recorder(
+    package: &str,
+    options: &CaptureOptions,
+    restarting: impl FnOnce(&str),
+) -> Result<Recorder> {
+    let mocked = mock::selected().is_some();
+    if options.ios && options.android_only() {
+        bail!("iOS captures can't read counters or profile the engine");
+    }
+    let mut recorder = if options.ios && !mocked {
+        let bridge = CONFIG.read().ios_bridge.clone();
+        let bridge = bridge.unwrap_or_else(|| PathBuf::from("pymobiledevice3"));
+        Recorder::ios(package, options.udid.as_deref(), &bridge)?
+    } else {
+        if !mocked {
+            util::dump_pss(package)?;
+        }
+        Recorder::new(package)
+    };
+    if options.unreal {
+        recorder.unreal()?;
+    }
+    if options.gpu_layer {
+        let library = CONFIG.read().gpu_layer.clone().context("No GPU layer library set")?;
+        recorder.gpu_layer(&library, restarting)?;
+    }
+    if options.counters {
+        recorder.counters()?;
+    }
+    if let Some((host, tdp)) = handheld().filter(|_| !options.ios) {
+        if let Err(err) = recorder.battery(host, &tdp) {
+            log::warn!("battery: {}", err);
+        }
+    }
+    Ok(recorder)
+}
+
+// Where the handheld's battery is read and its power limit files. This machine comes first, a
+// Steam Deck running the game itself, then the connected device.
+fn handheld() -> Option<(Host, Vec<String>)> {
+    if mock::selected().is_some() {
+        return None;
+    }
+    let (host, model) = match fs::read_to_string(DMI_PRODUCT) {
+        Ok(product) if CONFIG.read().device_class(product.trim()).is_some() => {
+            (Host::Local, product)
+        }
+        _ => (Host::Device, util::get_android_prop("ro.product.model").ok()?),
+    };
+    let config = CONFIG.read();
+    let class = config.device_class(model.trim())?;
+    Some((host, class.tdp.clone())).filter(|_| class.handheld)
+}
+
+// Named after the package, the page can rename it
+pub fn save(recorder: Recorder) -> Result<SessionInfo> {
+    let recording = recorder.finish();
+    session::add(&recording.package, Source::GamePerf, &recording, None)
+}
@@ -0,0 +1,120 @@
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::json;
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::capture::{clocks, tuning};
+use crate::rpc;
+
+lazy_static! {
+    static ref STATE: RwLock<CaptureState> = RwLock::new(CaptureState::Idle);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CaptureState {
+    Idle,
+    // Waiting for the first sample of the process
+    Arming { package: String },
+    Capturing { package: String, started_at: u64 },
+    Paused { package: String, started_at: u64 },
+    Finalizing { package: String },
+    Error { message: String },
+}
+
+impl CaptureState {
+    fn can_become(&self, next: &CaptureState) -> bool {
+        use CaptureState::*;
+        matches!(
+            (self, next),
+            (Idle | Error { .. }, Arming { .. })
+                | (Arming { .. }, Capturing { .. } | Idle)
+                | (Capturing { .. }, Paused { .. })
+                | (Paused { .. }, Capturing { .. })
+                | (Capturing { .. } | Paused { .. }, Finalizing { .. })
+                | (Finalizing { .. } | Error { .. }, Idle)
+                | (_, Error { .. })
+        )
+    }
+
+    pub fn is_running(&self) -> bool {
+        !matches!(self, CaptureState::Idle | CaptureState::Error { .. })
+    }
+}
+
+pub fn current() -> CaptureState {
+    STATE.read().clone()
+}
+
+// Every transition is broadcast to the page as `tse_capture_state`
+pub fn transition(next: CaptureState, proxy: &EventLoopProxy<rpc::Event>) -> Result<()> {
+    let mut state = STATE.write();
+    if !state.can_become(&next) {
+        bail!("Invalid capture state transition from {:?} to {:?}", *state, next);
+    }
+    log::info!("capture state: {:?}", next);
+    *state = next;
+    let _ = proxy.send_event(rpc::Event::DispatchCustomEvent("tse_capture_state", json!(*state)));
+
+    // Process and clock overrides only last for the run
+    let ended = !state.is_running();
+    drop(state);
+    if ended {
+        if let Err(err) = tuning::restore() {
+            log::warn!("restoring process tuning: {}", err);
+        }
+        if let Err(err) = clocks::restore() {
+            log::warn!("restoring clock limits: {}", err);
+        }
+    }
+    Ok(())
+}
+
+pub fn fail(message: impl Into<String>, proxy: &EventLoopProxy<rpc::Event>) {
+    let _ = transition(CaptureState::Error { message: message.into() }, proxy);
+}
+
+// Capturing or Paused -> Finalizing -> Idle, nothing to do otherwise. `save` runs while
+// finalizing, the capture ends in Error when it fails.
+pub fn finalize(
+    proxy: &EventLoopProxy<rpc::Event>,
+    save: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let package = match current() {
+        CaptureState::Capturing { package, .. } | CaptureState::Paused { package, .. } => package,
+        _ => return Ok(()),
+    };
+    transition(CaptureState::Finalizing { package }, proxy)?;
+    if let Err(err) = save() {
+        fail(format!("Saving the capture failed: {}", err), proxy);
+        return Ok(());
+    }
+    transition(CaptureState::Idle, proxy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let package = String::from("com.example.game");
+        let arming = CaptureState::Arming { package: package.clone() };
+        let capturing = CaptureState::Capturing { package: package.clone(), started_at: 0 };
+        let paused = CaptureState::Paused { package: package.clone(), started_at: 0 };
+        let error = CaptureState::Error { message: "adb".into() };
+
+        assert!(CaptureState::Idle.can_become(&arming));
+        assert!(arming.can_become(&capturing));
+        assert!(capturing.can_become(&paused));
+        assert!(paused.can_become(&CaptureState::Finalizing { package }));
+        assert!(capturing.can_become(&error));
+        assert!(error.can_become(&arming));
+
+        assert!(!CaptureState::Idle.can_become(&capturing));
+        assert!(!capturing.can_become(&arming));
+        assert!(!capturing.can_become(&CaptureState::Idle));
+    }
+}
@@ -0,0 +1,152 @@
+// Charts rendered without the webview, for reports, the clipboard and anything sent elsewhere
+use std::io::Cursor;
+
+use anyhow::{anyhow, bail, Result};
+use plotters::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::smoothing::{self, Smoothing};
+use crate::capture::Recording;
+
+const MAX_SIZE: u32 = 4096;
+const LINE: RGBColor = RGBColor(0x3b, 0x82, 0xf6);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self {
+        ImageFormat::Png
+    }
+}
+
+// `frametime`, `fps` or a sampled metric such as `temp.gpu`, over the run in seconds
+fn points(recording: &Recording, series: &str, width: u32) -> Result<(String, Vec<(f64, f64)>)> {
+    let points = match series {
+        "fps" => {
+            let fps = smoothing::fps_series(
+                &recording.frametimes,
+                Smoothing::None,
+                (width as usize).clamp(2, smoothing::MAX_POINTS),
+            )?;
+            fps.raw.iter().map(|point| (point.elapsed_ms / 1000.0, point.fps)).collect()
+        }
+        "frametime" => {
+            // The longest frame per pixel, so no hitch disappears between two
+            let frames_per_point = (recording.frametimes.len() / width.max(1) as usize).max(1);
+            let mut elapsed_ms = 0.0;
+            recording
+                .frametimes
+                .chunks(frames_per_point)
+                .map(|chunk| {
+                    elapsed_ms += chunk.iter().sum::<f64>();
+                    (elapsed_ms / 1000.0, chunk.iter().copied().fold(0.0, f64::max))
+                })
+                .collect()
+        }
+        metric => recording
+            .samples
+            .iter()
+            .filter_map(|sample| {
+                Some((sample.elapsed_ms as f64 / 1000.0, *sample.metrics.get(metric)?))
+            })
+            .collect(),
+    };
+    let label = match series {
+        "fps" => "FPS".to_string(),
+        "frametime" => "Frame time (ms)".to_string(),
+        metric => metric.to_string(),
+    };
+    Ok((label, points))
+}
+
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    title: &str,
+    label: &str,
+    points: &[(f64, f64)],
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let x_max = points.iter().map(|p| p.0).fold(1.0, f64::max);
+    let y_max = points.iter().map(|p| p.1).fold(1.0, f64::max) * 1.1;
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 18))
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0.0..x_max, 0.0..y_max)?;
+    chart.configure_mesh().x_desc("Time (s)").y_desc(label).draw()?;
+    chart.draw_series(LineSeries::new(points.iter().copied(), &LINE))?;
+    root.present()?;
+    Ok(())
+}
+
+// One series of the recording as an image
+pub fn render(
+    recording: &Recording,
+    title: &str,
+    series: &str,
+    format: ImageFormat,
+    (width, height): (u32, u32),
+) -> Result<Vec<u8>> {
+    if !(100..=MAX_SIZE).contains(&width) || !(100..=MAX_SIZE).contains(&height) {
+        bail!("Charts must be between 100 and {} pixels wide and high", MAX_SIZE);
+    }
+    let (label, points) = points(recording, series, width)?;
+    if points.is_empty() {
+        bail!("No {} in this session", label);
+    }
+    match format {
+        ImageFormat::Svg => {
+            let mut svg = String::new();
+            draw(
+                SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area(),
+                title,
+                &label,
+                &points,
+            )?;
+            Ok(svg.into_bytes())
+        }
+        ImageFormat::Png => {
+            let mut rgb = vec![0; width as usize * height as usize * 3];
+            draw(
+                BitMapBackend::with_buffer(&mut rgb, (width, height)).into_drawing_area(),
+                title,
+                &label,
+                &points,
+            )?;
+            let image = image::RgbImage::from_raw(width, height, rgb)
+                .ok_or_else(|| anyhow!("Chart buffer doesn't match its size"))?;
+            let mut png = Cursor::new(vec![]);
+            image::DynamicImage::ImageRgb8(image)
+                .write_to(&mut png, image::ImageOutputFormat::Png)?;
+            Ok(png.into_inner())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_svg() -> Result<()> {
+        let mut frametimes = vec![1000.0 / 60.0; 600];
+        frametimes[300] = 100.0;
+        let recording = Recording { frametimes, ..Default::default() };
+        let svg = render(&recording, "city run", "frametime", ImageFormat::Svg, (400, 200))?;
+        let svg = String::from_utf8(svg)?;
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("city run"));
+        assert!(render(&recording, "", "temp.gpu", ImageFormat::Svg, (400, 200)).is_err());
+        assert!(render(&recording, "", "fps", ImageFormat::Png, (10, 10)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fs, thread, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::Stats;
+use crate::base::recording::{self, CaptureOptions};
+use crate::capture::clocks::{self, ClockLimits};
+use crate::capture::interrupt::Interruption;
+use crate::capture::mock;
+use crate::capture::replay;
+use crate::capture::thermal::{self, Soak};
+use crate::capture::trace::TraceSettings;
+use crate::capture::tuning::{self, Tuning};
+use crate::capture::Recording;
+use crate::util;
+
+pub const EXIT_PASSED: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_ERROR: i32 = 2;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Plan {
+    pub package: String,
+    pub duration_secs: u64,
+    pub warmup_secs: u64,
+    pub interval_ms: u64,
+    pub runs: u32,
+    pub fail_if: Vec<String>,
+    pub tuning: Option<Tuning>,
+    pub clock_limits: Option<ClockLimits>,
+    // Runs once temperatures settled, after the warmup
+    pub soak: Option<Soak>,
+    // Input script replayed from the start of each run, see `replay::stop_recording`
+    pub replay: Option<String>,
+    // The game's own trace markers, aligned with the frame times
+    pub trace: Option<TraceSettings>,
+    // Counters, GPU layer, Unreal profiler and iOS, same as for captures started elsewhere
+    #[serde(flatten)]
+    pub options: CaptureOptions,
+}
+
+impl Default for Plan {
+    fn default() -> Self {
+        Plan {
+            package: String::new(),
+            duration_secs: 60,
+            warmup_secs: 0,
+            interval_ms: 1000,
+            runs: 1,
+            fail_if: vec![],
+            tuning: None,
+            clock_limits: None,
+            soak: None,
+            replay: None,
+            trace: None,
+            options: CaptureOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug)]
+pub struct Threshold {
+    expr: String,
+    key: String,
+    op: Op,
+    value: f64,
+}
+
+impl Threshold {
+    pub fn parse(expr: &str) -> Result<Threshold> {
+        // Two chars operators first so `<=` isn't read as `<`
+        const OPS: [(&str, Op); 6] = [
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        for (token, op) in OPS {
+            if let Some((key, value)) = expr.split_once(token) {
+                let key = key.trim();
+                if key.is_empty() {
+                    bail!("Missing stat in `{}`", expr);
+                }
+                let value = value
+                    .trim()
+                    .parse::<f64>()
+                    .with_context(|| format!("Invalid number in `{}`", expr))?;
+                return Ok(Threshold { expr: expr.trim().into(), key: key.into(), op, value });
+            }
+        }
+        bail!("Missing comparison operator in `{}`", expr)
+    }
+
+    // Returns true when the failure condition is met
+    pub fn is_met(&self, stats: &Stats) -> Result<bool> {
+        let actual =
+            stats.get(&self.key).with_context(|| format!("Unknown stat `{}`", self.key))?;
+        let met = match self.op {
+            Op::Lt => actual < self.value,
+            Op::Le => actual <= self.value,
+            Op::Gt => actual > self.value,
+            Op::Ge => actual >= self.value,
+            Op::Eq => (actual - self.value).abs() < f64::EPSILON,
+            Op::Ne => (actual - self.value).abs() >= f64::EPSILON,
+        };
+        Ok(met)
+    }
+}
+
+#[derive(Serialize)]
+struct RunSummary {
+    run: u32,
+    stats: Stats,
+    tuning: Option<Tuning>,
+    clock_limits: Option<ClockLimits>,
+    interrupted: Option<Interruption>,
+    failures: Vec<String>,
+}
+
+#[derive(Serialize, Default)]
+struct Summary {
+    passed: bool,
+    package: String,
+    thresholds: Vec<String>,
+    runs: Vec<RunSummary>,
+    error: Option<String>,
+}
+
+pub fn run(args: &ArgMatches) -> i32 {
+    let mut summary = Summary::default();
+    let code = match execute(args, &mut summary) {
+        Ok(true) => EXIT_PASSED,
+        Ok(false) => EXIT_FAILED,
+        Err(err) => {
+            log::error!("{}", err);
+            summary.error = Some(err.to_string());
+            EXIT_ERROR
+        }
+    };
+    summary.passed = code == EXIT_PASSED;
+    println!("{}", serde_json::to_string_pretty(&summary).unwrap_or_default());
+    code
+}
+
+fn execute(args: &ArgMatches, summary: &mut Summary) -> Result<bool> {
+    let path = args.value_of("plan").context("--plan required")?;
+    let plan = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let mut plan: Plan = serde_json::from_str(&plan)?;
+    if plan.package.is_empty() {
+        bail!("Plan has no package");
+    }
+    let android_only = plan.tuning.is_some()
+        || plan.clock_limits.is_some()
+        || plan.soak.is_some()
+        || plan.replay.is_some()
+        || plan.trace.is_some();
+    if plan.options.ios && android_only {
+        bail!("iOS plans can't tune, limit clocks, soak, replay or trace");
+    }
+    if let Some(fail_if) = args.values_of("fail-if") {
+        plan.fail_if.extend(fail_if.map(String::from));
+    }
+
+    let thresholds =
+        plan.fail_if.iter().map(|expr| Threshold::parse(expr)).collect::<Result<Vec<_>>>()?;
+    summary.package = plan.package.clone();
+    summary.thresholds = thresholds.iter().map(|t| t.expr.clone()).collect();
+
+    let mut passed = true;
+    for run in 1..=plan.runs.max(1) {
+        let recording = capture(&plan)?;
+        let stats = Stats::compute(&recording);
+        let mut failures = vec![];
+        // Stats of a crashed run say nothing about the thresholds
+        if let Some(interruption) = &recording.interrupted {
+            failures.push(format!("interrupted: {}", interruption.detail));
+        }
+        for threshold in &thresholds {
+            if threshold.is_met(&stats)? {
+                failures.push(threshold.expr.clone());
+            }
+        }
+        log::info!("run {} done, {} failure(s)", run, failures.len());
+        passed &= failures.is_empty();
+        summary.runs.push(RunSummary {
+            run,
+            stats,
+            tuning: recording.tuning,
+            clock_limits: recording.clock_limits,
+            interrupted: recording.interrupted,
+            failures,
+        });
+    }
+    Ok(passed)
+}
+
+fn capture(plan: &Plan) -> Result<Recording> {
+    if mock::selected().is_none() && !plan.options.ios {
+        util::fuzzy_runing(&plan.package)?;
+    }
+    thread::sleep(Duration::from_secs(plan.warmup_secs));
+    let soak = plan.soak.as_ref().map(thermal::soak).transpose()?;
+
+    let recording = with_overrides(plan, || record(plan));
+    if let Err(err) = tuning::restore() {
+        log::warn!("restoring process tuning: {}", err);
+    }
+    if let Err(err) = clocks::restore() {
+        log::warn!("restoring clock limits: {}", err);
+    }
+    let mut recording = recording?;
+    recording.soak = soak;
+    Ok(recording)
+}
+
+// Restoring is up to the caller, also when applying fails halfway
+fn with_overrides(plan: &Plan, record: impl FnOnce() -> Result<Recording>) -> Result<Recording> {
+    // The recorder keeps what was applied
+    if let Some(tuning) = &plan.tuning {
+        tuning::apply(&plan.package, tuning)?;
+    }
+    if let Some(limits) = &plan.clock_limits {
+        clocks::apply(limits)?;
+    }
+    record()
+}
+
+fn record(plan: &Plan) -> Result<Recording> {
+    let interval = Duration::from_millis(plan.interval_ms.max(100));
+    let duration = Duration::from_secs(plan.duration_secs);
+    let mut recorder = recording::recorder(&plan.package, &plan.options, |_| {})?;
+    recorder.interval(interval);
+    if let Some(settings) = &plan.trace {
+        recorder.trace(settings)?;
+    }
+    if let Some(name) = &plan.replay {
+        replay::start_replay(name)?;
+    }
+    let started = std::time::Instant::now();
+    while started.elapsed() < duration {
+        if let Err(err) = recorder.poll() {
+            replay::stop_replay();
+            return Err(err);
+        }
+        if recorder.interrupted().is_some() {
+            break;
+        }
+        thread::sleep(interval);
+    }
+    replay::stop_replay();
+    Ok(recorder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() -> Result<()> {
+        let stats = Stats { p1_low: 40.0, avg_fps: 60.0, ..Default::default() };
+
+        assert!(Threshold::parse("p1_low < 45")?.is_met(&stats)?);
+        assert!(!Threshold::parse("avg_fps<=59.5")?.is_met(&stats)?);
+        assert!(Threshold::parse("avg_fps >= 60")?.is_met(&stats)?);
+        assert!(Threshold::parse("unknown > 1")?.is_met(&stats).is_err());
+        assert!(Threshold::parse("p1_low 45").is_err());
+        assert!(Threshold::parse("< 45").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use gameperf_core::settings::Settings;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::agent::AgentSettings;
+use crate::analysis::gaps::GapPolicy;
+use crate::analysis::smoothing::Smoothing;
+use crate::base::recording::CaptureOptions;
+use crate::capture::benchmark;
+use crate::grpc;
+use crate::mqtt::MqttSettings;
+use crate::overlay::{self, Layout};
+use crate::session::anonymize;
+use crate::session::share::ShareSettings;
+
+const SETTINGS_FORMAT: &str = "gameperf-settings";
+const SETTINGS_VERSION: u32 = 1;
+
+lazy_static! {
+    pub static ref CONFIG: RwLock<Config> = RwLock::new(Config::load());
+    // Set by `redirect`
+    static ref ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Synced captures with other instances, see `agent`
+    pub agent: AgentSettings,
+    // For captures started from the page, by agents or on the command line
+    pub capture: CaptureOptions,
+    // Applied to FPS charts when the page doesn't ask for a smoothing of its own
+    pub chart_smoothing: Smoothing,
+    // Extra directories file commands may read/write, besides the built-in ones
+    pub allowed_paths: Vec<PathBuf>,
+    // Named databases loaded at startup, e.g. modded game data
+    pub databases: BTreeMap<String, PathBuf>,
+    // Named kinds of devices, matched by model when a capture starts
+    pub device_classes: BTreeMap<String, DeviceClass>,
+    // Packages whose focus is ignored, e.g. an overlay app, on top of screen readers and the
+    // game modes' overlays
+    pub foreground_exclusions: Vec<String>,
+    // `POST /events` on localhost for the game's own markers, see `events`. None keeps it off.
+    pub events_port: Option<u16>,
+    // Whether stats leave out stretches without samples or fill them in
+    pub gap_policy: GapPolicy,
+    // Vulkan layer injected into debuggable games for per frame GPU statistics, opt-in per plan
+    pub gpu_layer: Option<PathBuf>,
+    // Serve `proto/gameperf.proto` on this port for build-farm tooling, None keeps it off
+    pub grpc_port: Option<u16>,
+    // Certificate and key for gRPC from other machines, without them it only listens on localhost
+    pub grpc_tls: Option<grpc::TlsFiles>,
+    // Bridge to the Instruments service of iOS devices, `pymobiledevice3` on PATH when unset
+    pub ios_bridge: Option<PathBuf>,
+    // Stop live charts while capturing so the page doesn't compete with the benchmark
+    pub low_impact: bool,
+    // Live stats and the capture state for home-lab dashboards, None keeps it off
+    pub mqtt: Option<MqttSettings>,
+    // Overlay window behavior, the layouts are per profile
+    pub overlay: overlay::Settings,
+    // Skip the OS trash when deleting sessions and files
+    pub permanently_delete: bool,
+    // PresentMon executable converting imported ETL traces, looked up on PATH when unset
+    pub presentmon: Option<PathBuf>,
+    // Sessions, logs, gRPC, the viewer and MQTT keep hashed package and process names only, no
+    // window titles or paths, for pre-release builds under NDA. The hashes are keyed per install.
+    pub privacy: bool,
+    // Named capture presets, picked with `--profile`
+    pub profiles: BTreeMap<String, Profile>,
+    // Saved on exit
+    pub window: Option<WindowState>,
+    pub retention: Retention,
+    // Storage backend for `share_session` links
+    pub share: ShareSettings,
+    // NTP server the host clock is checked against when a capture starts, None skips it
+    pub time_server: Option<String>,
+    // Read-only web page on this port to follow a capture from a phone or another PC, takes the
+    // agent's keys. None keeps it off.
+    pub viewer_port: Option<u16>,
+}
+
+// Sessions older than `raw_days` keep only their stats and samples averaged per
+// `downsample_secs`, None keeps everything
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retention {
+    pub raw_days: Option<u64>,
+    pub downsample_secs: u64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention { raw_days: None, downsample_secs: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub maximized: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub package: Option<String>,
+    pub duration_secs: Option<u64>,
+    // None shows the default HUD
+    pub overlay: Option<Layout>,
+    // Built-in benchmark sequence to measure, see `arm_benchmark`
+    pub benchmark: Option<benchmark::Rule>,
+}
+
+// Handhelds such as the Steam Deck or an Odin are captured with the battery, see
+// `Recorder::battery`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceClass {
+    // `ro.product.model` values, or the DMI product name of this machine (`Jupiter`)
+    pub models: Vec<String>,
+    pub handheld: bool,
+    // Power limit files recorded with each run, e.g. `/sys/class/hwmon/hwmon*/power1_cap`
+    pub tdp: Vec<String>,
+}
+
+impl Config {
+    pub fn device_class(&self, model: &str) -> Option<&DeviceClass> {
+        self.device_classes.values().find(|class| class.models.iter().any(|m| m == model))
+    }
+
+    fn load() -> Config {
+        let config = match Config::read() {
+            Some(Ok(config)) => config,
+            Some(Err(err)) => {
+                log::error!("Invalid config, using defaults: {}", err);
+                Config::default()
+            }
+            None => Config::default(),
+        };
+        anonymize::set_private(config.privacy);
+        config
+    }
+
+    // None when there is no config file yet
+    fn read() -> Option<serde_json::Result<Config>> {
+        config_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|json| serde_json::from_str(&json))
+    }
+
+    fn save(&self) -> Result<()> {
+        anonymize::set_private(self.privacy);
+        let path = config_path().context("No config directory")?;
+        fs::create_dir_all(path.parent().context("Invalid config path")?)?;
+        let partial = path.with_extension("tmp");
+        fs::write(&partial, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(partial, path)?;
+        Ok(())
+    }
+}
+
+// Read-modify-write under the lock: the change is applied to the file as it is now, so what
+// another instance or a hand edit saved meanwhile is kept. An unreadable file is replaced with
+// the copy in memory.
+pub fn update<T>(change: impl FnOnce(&mut Config) -> T) -> Result<T> {
+    let mut config = CONFIG.write();
+    let mut latest = match Config::read() {
+        Some(Ok(latest)) => latest,
+        _ => config.clone(),
+    };
+    let result = change(&mut latest);
+    latest.save()?;
+    *config = latest;
+    Ok(result)
+}
+
+// Whole configuration in one portable file, to set up lab machines the same way
+#[derive(Serialize, Deserialize)]
+struct SettingsFile {
+    format: String,
+    version: u32,
+    config: Config,
+}
+
+pub fn export_settings(path: &Path) -> Result<()> {
+    let config = exportable(&CONFIG.read());
+    let file = SettingsFile { format: SETTINGS_FORMAT.into(), version: SETTINGS_VERSION, config };
+    fs::write(path, serde_json::to_vec_pretty(&file)?)?;
+    Ok(())
+}
+
+// Without the window state and the secrets: tokens, API keys and passwords
+fn exportable(config: &Config) -> Config {
+    let mut config = config.clone();
+    config.window = None;
+    config.agent.token.clear();
+    config.agent.keys.clear();
+    config.agent.peers.iter_mut().for_each(|peer| peer.token.clear());
+    if let Some(mqtt) = &mut config.mqtt {
+        mqtt.password = None;
+    }
+    config.share.token = None;
+    config
+}
+
+// Replaces the configuration, except what stays local: the window state, access grants (a
+// settings file must not widen what file commands may touch), the secrets and whatever listens
+// on the network.
+pub fn import_settings(path: &Path) -> Result<Config> {
+    let file: SettingsFile = serde_json::from_slice(&fs::read(path)?)
+        .with_context(|| format!("{} is not a settings file", path.display()))?;
+    if file.format != SETTINGS_FORMAT {
+        bail!("{} is not a settings file", path.display());
+    }
+    if file.version > SETTINGS_VERSION {
+        bail!("Settings file version {} is newer than this GamePerf", file.version);
+    }
+
+    update(|config| {
+        *config = imported(config, file.config);
+        config.clone()
+    })
+}
+
+fn imported(local: &Config, file: Config) -> Config {
+    let mut config = Config {
+        window: local.window,
+        allowed_paths: local.allowed_paths.clone(),
+        agent: local.agent.clone(),
+        mqtt: local.mqtt.clone(),
+        events_port: local.events_port,
+        grpc_port: local.grpc_port,
+        grpc_tls: local.grpc_tls.clone(),
+        viewer_port: local.viewer_port,
+        ..file
+    };
+    config.share.token = local.share.token.clone();
+    config
+}
+
+// What the capture engine reads from the config, see `gameperf_core::settings`
+pub fn engine_settings() -> Settings {
+    let config = CONFIG.read();
+    Settings {
+        data_dir: data_dir(),
+        foreground_exclusions: config.foreground_exclusions.clone(),
+        gap_policy: config.gap_policy,
+        gpu_layer: config.gpu_layer.clone(),
+        time_server: config.time_server.clone(),
+    }
+}
+
+// Config and data under `root` instead of the user's directories, e.g. for `replay-rpc`. Before
+// anything reads `CONFIG`.
+pub fn redirect(root: &Path) {
+    *ROOT.write() = Some(root.to_path_buf());
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(root) = ROOT.read().as_ref() {
+        return Some(root.join("config"));
+    }
+    dirs::config_dir().map(|dir| dir.join("GamePerf"))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.json"))
+}
+
+// User report templates, see `session::template`
+pub fn template_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("templates"))
+}
+
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(root) = ROOT.read().as_ref() {
+        return Some(root.join("data"));
+    }
+    dirs::data_dir().map(|dir| dir.join("GamePerf"))
+}
+
+pub fn session_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("sessions"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{ApiKey, Peer, Role};
+
+    fn with_secrets() -> Config {
+        let mut config = Config::default();
+        config.agent.listen = true;
+        config.agent.token = "peer-token".into();
+        config.agent.keys.push(ApiKey {
+            name: "dashboard".into(),
+            key: "api-key".into(),
+            role: Role::Observer,
+        });
+        config.agent.peers.push(Peer {
+            name: "rig".into(),
+            address: "10.0.0.2:7780".into(),
+            token: "rig-token".into(),
+        });
+        config.mqtt = Some(MqttSettings { password: Some("hunter2".into()), ..Default::default() });
+        config.share.token = Some("share-token".into());
+        config.grpc_port = Some(50051);
+        config.allowed_paths.push("/data".into());
+        config
+    }
+
+    #[test]
+    fn test_exportable() {
+        let json = serde_json::to_string(&exportable(&with_secrets())).unwrap();
+        for secret in ["peer-token", "api-key", "rig-token", "hunter2", "share-token"] {
+            assert!(!json.contains(secret), "{} exported", secret);
+        }
+        assert!(json.contains("10.0.0.2:7780"));
+    }
+
+    #[test]
+    fn test_imported() {
+        let local = Config::default();
+        let mut file = with_secrets();
+        file.low_impact = true;
+        file.viewer_port = Some(8080);
+        let config = imported(&local, file);
+
+        assert!(config.low_impact);
+        assert!(!config.agent.listen && config.agent.keys.is_empty());
+        assert!(config.mqtt.is_none() && config.share.token.is_none());
+        assert_eq!((config.grpc_port, config.viewer_port), (None, None));
+        assert!(config.allowed_paths.is_empty());
+    }
+
+    // Refused before anything is replaced
+    #[test]
+    fn test_import_refused() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("gameperf_settings_{}", std::process::id()));
+        let mut file = SettingsFile {
+            format: "other".into(),
+            version: SETTINGS_VERSION,
+            config: Config::default(),
+        };
+        fs::write(&path, serde_json::to_vec(&file)?)?;
+        assert!(import_settings(&path).is_err());
+
+        file.format = SETTINGS_FORMAT.into();
+        file.version = SETTINGS_VERSION + 1;
+        fs::write(&path, serde_json::to_vec(&file)?)?;
+        let err = import_settings(&path).unwrap_err();
+        assert!(err.to_string().contains("newer"));
+
+        fs::write(&path, b"{}")?;
+        assert!(import_settings(&path).is_err());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+// Layout (little endian):
+//   magic "GPDB", version u32, entry count u32
+//   index: per entry key len u16, key, value offset u64, value len u32
+//   data: JSON encoded values, offsets relative to the start of the data section
+const MAGIC: &[u8; 4] = b"GPDB";
+const VERSION: u32 = 1;
+// Key length, offset and value length of an empty key
+const MIN_ENTRY: u64 = 2 + 8 + 4;
+
+pub const EXTENSION: &str = "gpdb";
+
+pub struct Index {
+    path: PathBuf,
+    data_offset: u64,
+    entries: HashMap<String, (u64, u32)>,
+}
+
+impl Index {
+    // Only reads the index, values are read on demand
+    // Lengths come from the file, they are checked against its size before anything is allocated
+    pub fn open(path: &Path) -> Result<Index> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("Not a GamePerf database: {}", path.display());
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            bail!("Unsupported database version {}: {}", version, path.display());
+        }
+
+        let count = read_u32(&mut reader)?;
+        let mut entries = HashMap::with_capacity((count as u64).min(size / MIN_ENTRY) as usize);
+        for _ in 0..count {
+            let key_len = read_u16(&mut reader)?;
+            let mut key = vec![0; key_len as usize];
+            reader.read_exact(&mut key)?;
+            let key = String::from_utf8(key).context("Invalid key")?;
+            let offset = read_u64(&mut reader)?;
+            let len = read_u32(&mut reader)?;
+            entries.insert(key, (offset, len));
+        }
+        let data_offset = reader.stream_position()?;
+        let data_len = size.saturating_sub(data_offset);
+        if entries.values().any(|&(offset, len)| !fits(offset, len, data_len)) {
+            bail!("Corrupt database, values past the end: {}", path.display());
+        }
+
+        Ok(Index { path: path.to_owned(), data_offset, entries })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Value>> {
+        let (offset, len) = match self.entries.get(key) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        let mut file = File::open(&self.path)?;
+        // Replaced since it was opened
+        let data_len = file.metadata()?.len().saturating_sub(self.data_offset);
+        if !fits(offset, len, data_len) {
+            bail!("Database changed on disk: {}", self.path.display());
+        }
+        file.seek(SeekFrom::Start(self.data_offset + offset))?;
+        let mut value = vec![0; len as usize];
+        file.read_exact(&mut value)?;
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+}
+
+pub fn write(path: &Path, entries: &BTreeMap<String, Value>) -> Result<()> {
+    let mut index = vec![];
+    let mut data = vec![];
+    for (key, value) in entries {
+        let value = serde_json::to_vec(value)?;
+        if key.len() > u16::MAX as usize {
+            bail!("Key too long: {}", key);
+        }
+        index.extend((key.len() as u16).to_le_bytes());
+        index.extend(key.as_bytes());
+        index.extend((data.len() as u64).to_le_bytes());
+        index.extend((value.len() as u32).to_le_bytes());
+        data.extend(value);
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(entries.len() as u32).to_le_bytes())?;
+    writer.write_all(&index)?;
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Top-level object keys become entries, arrays are keyed by position
+pub fn compile_json(json_path: &Path, path: &Path) -> Result<()> {
+    let json: Value = serde_json::from_slice(&fs::read(json_path)?)?;
+    let entries: BTreeMap<String, Value> = match json {
+        Value::Object(object) => object.into_iter().collect(),
+        Value::Array(array) => {
+            array.into_iter().enumerate().map(|(i, v)| (i.to_string(), v)).collect()
+        }
+        _ => bail!("Database must be a JSON object or array: {}", json_path.display()),
+    };
+    write(path, &entries)
+}
+
+fn fits(offset: u64, len: u32, data_len: u64) -> bool {
+    offset.checked_add(len as u64).map_or(false, |end| end <= data_len)
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("gameperf_test_{}.gpdb", std::process::id()));
+        let mut entries = BTreeMap::new();
+        entries.insert("plot".to_string(), json!({ "id": 1, "name": "Normandy" }));
+        entries.insert("empty".to_string(), json!(null));
+        write(&path, &entries)?;
+
+        let index = Index::open(&path)?;
+        assert_eq!(index.get("plot")?, Some(json!({ "id": 1, "name": "Normandy" })));
+        assert_eq!(index.get("empty")?, Some(json!(null)));
+        assert_eq!(index.get("missing")?, None);
+        assert_eq!(index.keys().count(), 2);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_lengths() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("gameperf_corrupt_{}.gpdb", std::process::id()));
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.push(b'a');
+        bytes.extend(0u64.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend(b"null");
+        fs::write(&path, &bytes)?;
+        assert!(Index::open(&path).is_err());
+
+        // A count far beyond what the file holds
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &bytes)?;
+        assert!(Index::open(&path).is_err());
+
+        fs::remove_file(path)?;
+        assert!(fits(0, 4, 4) && !fits(1, 4, 4) && !fits(u64::MAX, 1, 4));
+        Ok(())
+    }
+}
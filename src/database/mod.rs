@@ -0,0 +1,155 @@
+mod indexed;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{fs, thread};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::{json, Value};
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::config::{self, CONFIG};
+use crate::rpc;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    static ref DATABASES: RwLock<BTreeMap<String, Database>> = RwLock::new(BTreeMap::new());
+    static ref INDEXES: RwLock<HashMap<String, Arc<indexed::Index>>> = RwLock::new(HashMap::new());
+}
+
+struct Database {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Serialize)]
+pub struct DatabaseInfo {
+    name: String,
+    path: PathBuf,
+    size: u64,
+    loaded_at_modified: Option<u64>,
+}
+
+// Databases ship next to the exe in release, relative to the working dir in debug
+pub fn resolve(path: PathBuf) -> PathBuf {
+    #[cfg(not(debug_assertions))]
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|parent| parent.join(&path)))
+        .unwrap_or(path);
+
+    path
+}
+
+pub fn register(name: &str, path: PathBuf) {
+    let modified = modified(&path);
+    DATABASES.write().insert(name.into(), Database { path, modified });
+    INDEXES.write().remove(name);
+}
+
+pub fn query(name: &str, key: &str) -> Result<Option<Value>> {
+    index(name)?.get(key)
+}
+
+pub fn keys(name: &str) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = index(name)?.keys().map(String::from).collect();
+    keys.sort();
+    Ok(keys)
+}
+
+fn index(name: &str) -> Result<Arc<indexed::Index>> {
+    if let Some(index) = INDEXES.read().get(name) {
+        return Ok(Arc::clone(index));
+    }
+
+    let path = path_of(name)?;
+    let is_indexed = path.extension().map_or(false, |ext| ext == indexed::EXTENSION);
+    let index = if is_indexed {
+        indexed::Index::open(&path)?
+    } else {
+        // JSON databases are compiled once into the cache and queried from there
+        let cache_dir = config::data_dir().context("No data directory")?.join("cache");
+        let cache = cache_dir.join(format!("{}.{}", name, indexed::EXTENSION));
+        let is_stale = match (modified(&cache), modified(&path)) {
+            (Some(cache), Some(source)) => cache < source,
+            _ => true,
+        };
+        if is_stale {
+            fs::create_dir_all(&cache_dir)?;
+            indexed::compile_json(&path, &cache)?;
+        }
+        indexed::Index::open(&cache)?
+    };
+
+    let index = Arc::new(index);
+    INDEXES.write().insert(name.into(), Arc::clone(&index));
+    Ok(index)
+}
+
+pub fn path_of(name: &str) -> Result<PathBuf> {
+    DATABASES
+        .read()
+        .get(name)
+        .map(|db| db.path.clone())
+        .with_context(|| format!("Unknown database: {}", name))
+}
+
+pub fn name_of(path: &Path) -> String {
+    path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+pub fn list() -> Vec<DatabaseInfo> {
+    DATABASES
+        .read()
+        .iter()
+        .map(|(name, db)| DatabaseInfo {
+            name: name.clone(),
+            path: db.path.clone(),
+            size: fs::metadata(&db.path).map(|m| m.len()).unwrap_or_default(),
+            loaded_at_modified: db
+                .modified
+                .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+        })
+        .collect()
+}
+
+// Registers the configured databases and notifies the frontend when one changes on disk
+pub fn spawn_watcher(proxy: EventLoopProxy<rpc::Event>) {
+    for (name, path) in CONFIG.read().databases.clone() {
+        register(&name, resolve(path));
+    }
+
+    thread::spawn(move || loop {
+        thread::sleep(WATCH_INTERVAL);
+
+        let mut changed = vec![];
+        for (name, db) in DATABASES.write().iter_mut() {
+            let modified = modified(&db.path);
+            if modified != db.modified {
+                db.modified = modified;
+                changed.push(name.clone());
+            }
+        }
+        for name in &changed {
+            INDEXES.write().remove(name);
+        }
+        for name in changed {
+            log::info!("database changed: {}", name);
+            let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+                "tse_database_changed",
+                json!({ "name": name }),
+            ));
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::thread;
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use parking_lot::{Mutex, RwLock};
+
+use crate::agent::Peer;
+
+// Agents on the LAN, see `agent::listen`
+const SERVICE_TYPE: &str = "_gameperf._tcp.local.";
+
+lazy_static! {
+    static ref DAEMON: Mutex<Option<ServiceDaemon>> = Mutex::new(None);
+    static ref BROWSING: Mutex<bool> = Mutex::new(false);
+    // By full service name, removed again when the agent says goodbye
+    static ref DISCOVERED: RwLock<BTreeMap<String, Peer>> = RwLock::new(BTreeMap::new());
+}
+
+// One daemon for advertising and browsing
+fn daemon() -> Result<ServiceDaemon> {
+    let mut daemon = DAEMON.lock();
+    if daemon.is_none() {
+        *daemon = Some(ServiceDaemon::new().context("Failed to start mDNS")?);
+    }
+    Ok(daemon.clone().unwrap())
+}
+
+fn machine_name() -> String {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "gameperf".into())
+}
+
+// Name and port only, peers still need the token
+pub fn advertise(port: u16) -> Result<()> {
+    let name = machine_name();
+    daemon()?.register(service(&name, port)?)?;
+    log::info!("agent advertised as {}", name);
+    Ok(())
+}
+
+// The service records alone. The host's address records are the system's mDNS responder's,
+// publishing our own for `<name>.local.` would compete with it.
+fn service(name: &str, port: u16) -> Result<ServiceInfo> {
+    let properties: HashMap<String, String> =
+        vec![("version".to_string(), env!("CARGO_PKG_VERSION").to_string())].into_iter().collect();
+    let host = format!("{}.local.", name);
+    Ok(ServiceInfo::new(SERVICE_TYPE, name, &host, "", port, Some(properties))?)
+}
+
+// Starts browsing on the first call, then returns what answered so far. Discovered agents come
+// without a token, adding one makes them peers.
+pub fn agents() -> Result<Vec<Peer>> {
+    let mut browsing = BROWSING.lock();
+    if !*browsing {
+        let events = daemon()?.browse(SERVICE_TYPE)?;
+        thread::spawn(move || {
+            while let Ok(event) = events.recv() {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        if let Some(peer) = peer(&info) {
+                            DISCOVERED.write().insert(info.get_fullname().to_string(), peer);
+                        }
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        DISCOVERED.write().remove(&fullname);
+                    }
+                    _ => {}
+                }
+            }
+        });
+        *browsing = true;
+    }
+    Ok(DISCOVERED.read().values().cloned().collect())
+}
+
+// By address when the answer came with one, by host name for the system to resolve otherwise
+fn peer(info: &ServiceInfo) -> Option<Peer> {
+    let host = match info.get_addresses().iter().min() {
+        Some(address) => address.to_string(),
+        None => info.get_hostname().trim_end_matches('.').to_string(),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let name = info.get_fullname().trim_end_matches(SERVICE_TYPE).trim_end_matches('.');
+    Some(Peer {
+        name: name.into(),
+        address: format!("{}:{}", host, info.get_port()),
+        token: String::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service() -> Result<()> {
+        let info = service("studio-pc", 7878)?;
+        assert_eq!(info.get_fullname(), "studio-pc._gameperf._tcp.local.");
+        assert!(info.get_addresses().is_empty());
+
+        let advertised = peer(&info).unwrap();
+        assert_eq!(advertised.name, "studio-pc");
+        assert_eq!(advertised.address, "studio-pc.local:7878");
+
+        let resolved =
+            ServiceInfo::new(SERVICE_TYPE, "rig", "rig.local.", "192.168.1.20", 7878, None)?;
+        assert_eq!(peer(&resolved).unwrap().address, "192.168.1.20:7878");
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::agent::Role;
+use crate::capture::annotation::{self, ExternalEvent};
+use crate::config::CONFIG;
+use crate::http;
+use crate::session;
+
+const MAX_CONNECTIONS: usize = 8;
+// `annotation::MAX_EVENTS` events with long labels
+const MAX_BODY: usize = 4 * 1024 * 1024;
+
+// The events alone go to the running capture
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Body {
+    Events(Vec<ExternalEvent>),
+    Session { session: Option<String>, events: Vec<ExternalEvent> },
+}
+
+// `ingest_events` over HTTP for game telemetry and mod hooks running next to the game:
+// `POST /events` with a controller key of the agent, answers how many events were kept.
+// Localhost only.
+pub fn serve(port: u16) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .with_context(|| format!("Failed to listen on port {}", port))?;
+    log::info!("events on http://127.0.0.1:{}/events", port);
+    http::serve(listener, "events", MAX_CONNECTIONS, handle);
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream) -> Result<()> {
+    let request = http::read(&stream, MAX_BODY)?;
+    if request.path != "/events" {
+        return http::respond(&mut stream, "404 Not Found", "text/plain", "Not found");
+    }
+    if request.method != "POST" {
+        return http::respond(&mut stream, "405 Method Not Allowed", "text/plain", "POST only");
+    }
+    let key = request.key.unwrap_or_default();
+    let role = CONFIG.read().agent.authorize(&key).map(|(_, role)| role);
+    if !role.map_or(false, |role| role.allows(Role::Controller)) {
+        return http::respond(&mut stream, "401 Unauthorized", "text/plain", "Invalid key");
+    }
+
+    let kept = serde_json::from_slice::<Body>(&request.body).map_err(anyhow::Error::from).and_then(
+        |body| match body {
+            Body::Events(events) | Body::Session { session: None, events } => {
+                annotation::send(events)
+            }
+            Body::Session { session: Some(id), events } => session::annotate(&id, &events),
+        },
+    );
+    match kept {
+        Ok(kept) => {
+            let body = json!({ "kept": kept }).to_string();
+            http::respond(&mut stream, "200 OK", "application/json", &body)
+        }
+        Err(err) => http::respond(&mut stream, "400 Bad Request", "text/plain", &err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let events = r#"[{ "channel": "game", "label": "level loaded", "elapsed_ms": 10 }]"#;
+        let body: Body = serde_json::from_str(events).unwrap();
+        assert!(matches!(body, Body::Events(events) if events.len() == 1));
+
+        let body: Body = serde_json::from_str(r#"{ "session": "00ff", "events": [] }"#).unwrap();
+        assert!(matches!(body, Body::Session { session: Some(id), .. } if id == "00ff"));
+        let body: Body = serde_json::from_str(r#"{ "events": [] }"#).unwrap();
+        assert!(matches!(body, Body::Session { session: None, .. }));
+        assert!(serde_json::from_str::<Body>(r#"{ "events": 1 }"#).is_err());
+    }
+}
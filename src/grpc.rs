@@ -0,0 +1,289 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+use crate::agent::Role;
+use crate::analysis::summary;
+use crate::analysis::Stats;
+use crate::base::state::{self, CaptureState};
+use crate::base::ChannelMsg;
+use crate::config::CONFIG;
+use crate::session::{self, anonymize, Changes, SessionInfo};
+use crate::util;
+
+pub mod proto {
+    tonic::include_proto!("gameperf.v1");
+}
+
+use proto::game_perf_server::{GamePerf, GamePerfServer};
+
+// PEM files, see `Config::grpc_tls`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+// The command surface for typed clients, see `proto/gameperf.proto`. Keys and roles are the
+// agent's, see `agent::AgentSettings`. Plaintext stays on localhost, other machines need TLS.
+pub fn serve(port: u16, tls: Option<&TlsFiles>, tx: Sender<ChannelMsg>) -> Result<()> {
+    let (address, mut server): (IpAddr, _) = match tls {
+        Some(tls) => {
+            let cert = fs::read(&tls.cert)
+                .with_context(|| format!("Failed to read {}", tls.cert.display()))?;
+            let key = fs::read(&tls.key)
+                .with_context(|| format!("Failed to read {}", tls.key.display()))?;
+            let config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+            (Ipv4Addr::UNSPECIFIED.into(), Server::builder().tls_config(config)?)
+        }
+        None => (Ipv4Addr::LOCALHOST.into(), Server::builder()),
+    };
+    let addr = SocketAddr::from((address, port));
+    let service = GamePerfServer::new(Service { tx: Mutex::new(tx) });
+    log::info!("gRPC listening on {}{}", addr, if tls.is_some() { " with TLS" } else { "" });
+    tokio::spawn(async move {
+        if let Err(err) = server.add_service(service).serve(addr).await {
+            log::error!("gRPC: {}", err);
+        }
+    });
+    Ok(())
+}
+
+struct Service {
+    tx: Mutex<Sender<ChannelMsg>>,
+}
+
+impl Service {
+    fn send(&self, msg: ChannelMsg) -> Result<Response<proto::Empty>, Status> {
+        self.tx.lock().send(msg).map_err(|_| Status::unavailable("Shutting down"))?;
+        Ok(Response::new(proto::Empty {}))
+    }
+}
+
+// `authorization: Bearer <key>`
+fn authorize<T>(request: &Request<T>, required: Role) -> Result<(), Status> {
+    let header = request.metadata().get("authorization").and_then(|value| value.to_str().ok());
+    let key = header.unwrap_or_default().trim_start_matches("Bearer ").trim();
+    let settings = CONFIG.read().agent.clone();
+    match settings.authorize(key) {
+        Some((_, role)) if role.allows(required) => Ok(()),
+        Some((name, role)) => Err(Status::permission_denied(format!("Key {} is {:?}", name, role))),
+        None => Err(Status::unauthenticated("Invalid key")),
+    }
+}
+
+fn internal(err: anyhow::Error) -> Status {
+    Status::internal(err.to_string())
+}
+
+fn not_found(err: anyhow::Error) -> Status {
+    Status::not_found(err.to_string())
+}
+
+// Session files are read and written off the runtime's workers
+async fn blocking<T, F>(job: F) -> Result<T, Status>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Status> + Send + 'static,
+{
+    tokio::task::spawn_blocking(job).await.map_err(|err| Status::internal(err.to_string()))?
+}
+
+fn capture_state(state: CaptureState) -> proto::CaptureState {
+    let name = serde_json::to_value(&state).ok().and_then(|value| match value["state"].take() {
+        Value::String(name) => Some(name),
+        _ => None,
+    });
+    let mut reply = proto::CaptureState { state: name.unwrap_or_default(), ..Default::default() };
+    match state {
+        CaptureState::Idle => {}
+        CaptureState::Arming { package } | CaptureState::Finalizing { package } => {
+            reply.package = package
+        }
+        CaptureState::Capturing { package, started_at }
+        | CaptureState::Paused { package, started_at } => {
+            reply.package = package;
+            reply.started_at = started_at;
+        }
+        CaptureState::Error { message } => reply.message = message,
+    }
+    reply
+}
+
+fn session(info: SessionInfo) -> proto::Session {
+    let source = serde_json::to_value(info.source).ok();
+    proto::Session {
+        id: info.id,
+        name: info.name,
+        package: info.package,
+        source: source.as_ref().and_then(Value::as_str).unwrap_or_default().into(),
+        started_at: info.started_at,
+        duration_ms: info.duration_ms,
+        frames: info.frames as u64,
+        tags: info.tags,
+    }
+}
+
+// Goes into an adb command line, see `util::valid_package`
+fn package(request: proto::StartCaptureRequest) -> Result<String, Status> {
+    if !util::valid_package(&request.package) {
+        return Err(Status::invalid_argument(format!("Invalid package: {:?}", request.package)));
+    }
+    Ok(request.package)
+}
+
+fn changes(request: proto::UpdateSessionRequest) -> Changes {
+    Changes {
+        name: Some(request.name).filter(|name| !name.trim().is_empty()),
+        tags: request.tags.map(|tags| tags.tags),
+        notes: None,
+    }
+}
+
+#[tonic::async_trait]
+impl GamePerf for Service {
+    async fn get_capture_state(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::CaptureState>, Status> {
+        authorize(&request, Role::Observer)?;
+        Ok(Response::new(capture_state(anonymize::private_state(state::current()))))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::SessionList>, Status> {
+        authorize(&request, Role::Observer)?;
+        let sessions = blocking(|| session::list().map_err(internal)).await?;
+        Ok(Response::new(proto::SessionList {
+            sessions: sessions.into_iter().map(anonymize::private_session).map(session).collect(),
+        }))
+    }
+
+    async fn get_report(
+        &self,
+        request: Request<proto::ReportRequest>,
+    ) -> Result<Response<proto::Report>, Status> {
+        authorize(&request, Role::Observer)?;
+        let id = request.into_inner().session_id;
+        let report = blocking(move || session::report(&id).map_err(not_found)).await?;
+        let report = anonymize::private_report(report);
+        let json = serde_json::to_string(&report).map_err(|err| internal(err.into()))?;
+        Ok(Response::new(proto::Report { json }))
+    }
+
+    async fn summarize_session(
+        &self,
+        request: Request<proto::SessionRequest>,
+    ) -> Result<Response<proto::Summary>, Status> {
+        authorize(&request, Role::Observer)?;
+        let id = request.into_inner().session_id;
+        let recording = blocking(move || session::load(&id).map_err(not_found)).await?;
+        let summary = summary::summarize(&recording, &Stats::compute(&recording));
+        let findings_json =
+            serde_json::to_string(&summary.findings).map_err(|err| internal(err.into()))?;
+        Ok(Response::new(proto::Summary { verdict: summary.verdict, findings_json }))
+    }
+
+    async fn start_capture(
+        &self,
+        request: Request<proto::StartCaptureRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, Role::Controller)?;
+        let package = package(request.into_inner())?;
+        if state::current().is_running() {
+            return Err(Status::failed_precondition("A capture is already running"));
+        }
+        self.send(ChannelMsg::StartCapture(package))
+    }
+
+    async fn stop_capture(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, Role::Controller)?;
+        self.send(ChannelMsg::StopCapture)
+    }
+
+    async fn pause_capture(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, Role::Controller)?;
+        self.send(ChannelMsg::PauseCapture)
+    }
+
+    async fn resume_capture(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, Role::Controller)?;
+        self.send(ChannelMsg::ResumeCapture)
+    }
+
+    async fn update_session(
+        &self,
+        request: Request<proto::UpdateSessionRequest>,
+    ) -> Result<Response<proto::Session>, Status> {
+        authorize(&request, Role::Controller)?;
+        let request = request.into_inner();
+        let id = request.session_id.clone();
+        let changes = changes(request);
+        let info = blocking(move || session::update(&id, changes).map_err(not_found)).await?;
+        Ok(Response::new(session(anonymize::private_session(info))))
+    }
+
+    async fn delete_session(
+        &self,
+        request: Request<proto::DeleteSessionRequest>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        authorize(&request, Role::Controller)?;
+        let request = request.into_inner();
+        let to_trash = !request.permanently && !CONFIG.read().permanently_delete;
+        blocking(move || session::delete(&request.session_id, to_trash).map_err(not_found)).await?;
+        Ok(Response::new(proto::Empty {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let state = CaptureState::Capturing { package: "com.example.game".into(), started_at: 5 };
+        let reply = capture_state(state);
+        assert_eq!(
+            (reply.state.as_str(), reply.package.as_str(), reply.started_at),
+            ("capturing", "com.example.game", 5)
+        );
+        let reply = capture_state(CaptureState::Error { message: "adb gone".into() });
+        assert_eq!((reply.state.as_str(), reply.message.as_str()), ("error", "adb gone"));
+
+        let start = |name: &str| package(proto::StartCaptureRequest { package: name.into() });
+        assert_eq!(start("com.example.game").unwrap(), "com.example.game");
+        assert!(start("").is_err());
+        let invalid = start("com.example.game; reboot").unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+
+        let update = proto::UpdateSessionRequest {
+            session_id: "00ff".into(),
+            name: " ".into(),
+            tags: Some(proto::Tags { tags: vec!["nightly".into()] }),
+        };
+        let changes = changes(update);
+        assert_eq!(changes.name, None);
+        assert_eq!(changes.tags, Some(vec!["nightly".to_string()]));
+        let unset = proto::UpdateSessionRequest { name: "Run 2".into(), ..Default::default() };
+        assert_eq!(super::changes(unset).tags, None);
+    }
+}
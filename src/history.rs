@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config;
+use crate::save::{self, SaveReport};
+
+// Changes closer than this are reported as one region
+const MERGE_GAP: usize = 16;
+// Bytes of each side included per region
+const PREVIEW_LEN: usize = 64;
+
+lazy_static! {
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveVersion {
+    pub id: String,
+    pub saved_at: u64,
+    pub size: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedRegion {
+    pub offset: usize,
+    pub len_a: usize,
+    pub len_b: usize,
+    pub preview_a: String,
+    pub preview_b: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SaveDiff {
+    pub a: SaveReport,
+    pub b: SaveReport,
+    pub identical: bool,
+    pub regions: Vec<ChangedRegion>,
+}
+
+type Index = BTreeMap<String, Vec<SaveVersion>>;
+
+fn history_dir() -> Result<PathBuf> {
+    Ok(config::data_dir().context("No data directory")?.join("history"))
+}
+
+fn object_path(id: &str) -> Result<PathBuf> {
+    if id.len() != 64 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid version id: {}", id);
+    }
+    Ok(history_dir()?.join("objects").join(id))
+}
+
+fn load_index() -> Result<Index> {
+    let path = history_dir()?.join("index.json");
+    match fs::read(&path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(_) => Ok(Index::new()),
+    }
+}
+
+fn save_index(index: &Index) -> Result<()> {
+    let dir = history_dir()?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("index.json"), serde_json::to_vec_pretty(index)?)?;
+    Ok(())
+}
+
+fn key(path: &Path) -> String {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned()).to_string_lossy().into_owned()
+}
+
+// Stores `bytes` as the newest version of `path`, keeping the file it replaces on first use
+pub fn record(path: &Path, previous: Option<&[u8]>, bytes: &[u8]) -> Result<()> {
+    let _lock = INDEX_LOCK.lock();
+    let mut index = load_index()?;
+    let versions = index.entry(key(path)).or_default();
+
+    if versions.is_empty() {
+        if let Some(previous) = previous {
+            versions.push(store(previous)?);
+        }
+    }
+    let version = store(bytes)?;
+    if versions.last().map(|v| &v.id) != Some(&version.id) {
+        versions.push(version);
+    }
+    save_index(&index)
+}
+
+fn store(bytes: &[u8]) -> Result<SaveVersion> {
+    let id = format!("{:x}", Sha256::digest(bytes));
+    let path = object_path(&id)?;
+    if !path.exists() {
+        fs::create_dir_all(path.parent().context("Invalid history path")?)?;
+        fs::write(&path, bytes)?;
+    }
+    let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    Ok(SaveVersion { id, saved_at, size: bytes.len() })
+}
+
+pub fn list(path: &Path) -> Result<Vec<SaveVersion>> {
+    let _lock = INDEX_LOCK.lock();
+    Ok(load_index()?.remove(&key(path)).unwrap_or_default())
+}
+
+// Both versions have to be in `path`'s history, objects are shared between all saves
+pub fn diff(path: &Path, a: &str, b: &str) -> Result<SaveDiff> {
+    let versions = list(path)?;
+    for id in [a, b] {
+        if !versions.iter().any(|version| version.id == id) {
+            bail!("Version {} is not in the history of {}", id, path.display());
+        }
+    }
+    let bytes_a = fs::read(object_path(a)?).with_context(|| format!("Unknown version {}", a))?;
+    let bytes_b = fs::read(object_path(b)?).with_context(|| format!("Unknown version {}", b))?;
+    Ok(SaveDiff {
+        a: save::verify(path, &bytes_a),
+        b: save::verify(path, &bytes_b),
+        identical: bytes_a == bytes_b,
+        regions: changed_regions(&bytes_a, &bytes_b),
+    })
+}
+
+fn changed_regions(a: &[u8], b: &[u8]) -> Vec<ChangedRegion> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let max_suffix = a.len().min(b.len()) - prefix;
+    let suffix =
+        a.iter().rev().zip(b.iter().rev()).take(max_suffix).take_while(|(x, y)| x == y).count();
+    let (mid_a, mid_b) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let region = |offset: usize, len_a: usize, len_b: usize| ChangedRegion {
+        offset,
+        len_a,
+        len_b,
+        preview_a: hex(&a[offset..offset + len_a]),
+        preview_b: hex(&b[offset..offset + len_b]),
+    };
+
+    if mid_a.is_empty() && mid_b.is_empty() {
+        return vec![];
+    }
+    // Insertions shift everything, only in-place edits can be split further
+    if mid_a.len() != mid_b.len() {
+        return vec![region(prefix, mid_a.len(), mid_b.len())];
+    }
+
+    let mut regions: Vec<(usize, usize)> = vec![];
+    for (i, _) in mid_a.iter().zip(mid_b).enumerate().filter(|(_, (x, y))| x != y) {
+        let offset = prefix + i;
+        match regions.last_mut() {
+            Some((start, len)) if offset - (*start + *len) <= MERGE_GAP => {
+                *len = offset - *start + 1
+            }
+            _ => regions.push((offset, 1)),
+        }
+    }
+    regions.into_iter().map(|(offset, len)| region(offset, len, len)).collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().take(PREVIEW_LEN).map(|b| format!("{:02x}", b)).collect()
+}
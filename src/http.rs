@@ -0,0 +1,136 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+// Request line and headers together
+const MAX_HEAD: usize = 16 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Just enough HTTP/1.1 for the small servers on the side: one request per connection
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    // From `Authorization: Bearer`
+    pub key: Option<String>,
+    pub body: Vec<u8>,
+}
+
+// At most `max_connections` handled at once, the others are dropped right away
+pub fn serve(
+    listener: TcpListener,
+    name: &'static str,
+    max_connections: usize,
+    handle: fn(TcpStream) -> Result<()>,
+) {
+    let open = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if open.fetch_add(1, Ordering::SeqCst) >= max_connections {
+                open.fetch_sub(1, Ordering::SeqCst);
+                log::debug!("{}: too many connections", name);
+                continue;
+            }
+            let open = open.clone();
+            thread::spawn(move || {
+                if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                    log::debug!("{}: {}", name, err);
+                } else if let Err(err) = handle(stream) {
+                    log::debug!("{}: {}", name, err);
+                }
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+// Bodies over `max_body` bytes are refused without being read
+pub fn read(stream: &TcpStream, max_body: usize) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut budget = MAX_HEAD;
+    let line = read_line(&mut reader, &mut budget)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => bail!("Invalid request line: {}", line.trim()),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request =
+        Request { method, path: path.into(), query: query.into(), key: None, body: vec![] };
+
+    let mut length = 0;
+    loop {
+        let header = read_line(&mut reader, &mut budget)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        let (name, value) = match header.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case("authorization") {
+            request.key = value.strip_prefix("Bearer ").map(String::from);
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse()?;
+        }
+    }
+    if length > max_body {
+        bail!("Body too large: {} bytes", length);
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+// Counted against `budget`, a head that doesn't fit is refused
+fn read_line(reader: &mut impl BufRead, budget: &mut usize) -> Result<String> {
+    let mut line = String::new();
+    let read = reader.by_ref().take(*budget as u64).read_line(&mut line)?;
+    *budget -= read;
+    if !line.ends_with('\n') {
+        bail!("Request head too large or cut short");
+    }
+    Ok(line)
+}
+
+pub fn param(query: &str, name: &str) -> Option<String> {
+    let mut pairs = url::form_urlencoded::parse(query.as_bytes());
+    pairs.find(|(key, _)| key == name).map(|(_, value)| value.into_owned())
+}
+
+pub fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_line() {
+        let mut budget = 12;
+        let mut input = "GET / HTTP/1.1\r\n".as_bytes();
+        assert!(read_line(&mut input, &mut budget).is_err());
+
+        let mut budget = MAX_HEAD;
+        let mut input = "Host: a\r\n\r\n".as_bytes();
+        assert_eq!(read_line(&mut input, &mut budget).unwrap(), "Host: a\r\n");
+        assert_eq!(read_line(&mut input, &mut budget).unwrap(), "\r\n");
+        assert_eq!(budget, MAX_HEAD - 11);
+        assert_eq!(param("since=12&key=a%20b", "key").as_deref(), Some("a b"));
+    }
+}
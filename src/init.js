@@ -1,4 +1,92 @@
 (() => {
+    // Backend -> page messages, the payload is always a JSON string
+    const dispatchMessage = (data) => {
+        const event = document.createEvent("Event");
+        event.initEvent("message", false, true);
+        event.data = data;
+        window.dispatchEvent(event);
+    };
+    // Published messages carry a `seq`: duplicates (redelivered) are dropped, a gap asks the
+    // backend to send again, and the last one handled is acknowledged every so often. Until the
+    // backend says where this page starts (`messages_reset`) they were meant for the page before.
+    const ACK_INTERVAL_MS = 500;
+    let lastSeq = null;
+    let droppedMessages = 0;
+    let ackTimer = null;
+    const acknowledge = () => {
+        if (ackTimer === null) {
+            ackTimer = setTimeout(() => {
+                ackTimer = null;
+                window.rpc.notify("acknowledge_messages", lastSeq);
+            }, ACK_INTERVAL_MS);
+        }
+    };
+    const receiveMessages = (messages) => {
+        if (lastSeq === null) {
+            return;
+        }
+        const fresh = messages.filter((m) => m.seq === undefined || m.seq > lastSeq);
+        if (fresh.length && fresh[0].seq > lastSeq + 1) {
+            window.rpc.notify("resync_messages", lastSeq);
+            return;
+        }
+        fresh.forEach((m) => {
+            lastSeq = m.seq === undefined ? lastSeq : m.seq;
+            dispatchMessage(m);
+        });
+        if (fresh.length) {
+            acknowledge();
+        }
+    };
+    // The backend no longer has the messages before `seq`, the ones skipped are counted
+    const resetMessages = (seq) => {
+        if (lastSeq !== null && seq > lastSeq + 1) {
+            droppedMessages += seq - lastSeq - 1;
+            document.dispatchEvent(new CustomEvent("tse_messages_dropped", { detail: { dropped: droppedMessages } }));
+        }
+        lastSeq = lastSeq === null ? seq - 1 : Math.max(lastSeq, seq - 1);
+    };
+    Object.defineProperty(window, "__gameperf_bridge", {
+        value: Object.freeze({
+            deliver: (raw) => {
+                const message = JSON.parse(raw);
+                switch (message.kind) {
+                    case "custom_event":
+                        document.dispatchEvent(new CustomEvent(message.name, { detail: message.detail, cancelable: true }));
+                        break;
+                    case "messages":
+                        receiveMessages(message.messages);
+                        break;
+                    case "messages_reset":
+                        resetMessages(message.seq);
+                        break;
+                    case "rpc_response": {
+                        const { id, result, error } = message.response;
+                        if (error !== undefined) {
+                            window.rpc._error(id, error);
+                        } else {
+                            window.rpc._result(id, result);
+                        }
+                        break;
+                    }
+                    default:
+                        console.warn("Unknown bridge message", message.kind);
+                }
+            },
+        }),
+        writable: false,
+        configurable: false,
+    });
+
+    // Frontend rebuilt in a debug build, pages keeping their own state call preventDefault
+    document.addEventListener("tse_frontend_assets_changed", (e) => {
+        setTimeout(() => {
+            if (!e.defaultPrevented) {
+                window.location.reload();
+            }
+        });
+    });
+
     // Prevent user to reload the page
     document.addEventListener("keydown", (e) => {
         if (e.key === "F5" ||
@@ -57,11 +145,49 @@
             window.rpc.notify("close");
         });
 
+        // Start receiving published messages, see `resync_messages`
+        window.rpc.notify("resync_messages", 0);
+        // Live samples are only published to subscribed topics
+        window.rpc.notify("subscribe", "samples.live");
+        // Dropped samples and frames, so the page can flag incomplete captures
+        window.rpc.notify("subscribe", "capture_health");
+
         // Show the window when initialized
         window.rpc.notify("init");
+
+        // Command line `--open-session` (or a `.gpcap` file) and `--capture`
+        window.rpc.call("open_command_line_session").then((session) => {
+            if (session) {
+                document.dispatchEvent(new CustomEvent("tse_open_session", { detail: session }));
+            }
+        });
+        window.rpc.call("start_command_line_capture");
+        // `gameperf://` link the app was opened with
+        window.rpc.call("open_command_line_link").then((link) => {
+            if (link) {
+                document.dispatchEvent(new CustomEvent("tse_open_view", { detail: link }));
+            }
+        });
     });
 })();
 
+// Topic subscriptions, callback receives the published payload
+window.gameperf = {
+    subscribe: (topic, callback) => {
+        const listener = (e) => {
+            if (e.data && e.data.topic === topic) {
+                callback(e.data.msg);
+            }
+        };
+        window.addEventListener("message", listener);
+        window.rpc.notify("subscribe", topic);
+        return () => {
+            window.removeEventListener("message", listener);
+            window.rpc.notify("unsubscribe", topic);
+        };
+    },
+};
+
 if (typeof(window) == "undefined") {
     window = {}
 }
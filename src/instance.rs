@@ -0,0 +1,266 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::{base, config, rpc};
+
+// Whoever claims the instance first (a named mutex on Windows, an owner file created atomically
+// elsewhere) listens on localhost, the lock file tells later launches the port and token to use.
+const LOCK_FILE: &str = "instance.lock";
+#[cfg(not(target_os = "windows"))]
+const OWNER_FILE: &str = "instance.owner";
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+// A later launch waits this long for the first one to start listening
+const FORWARD_ATTEMPTS: u32 = 10;
+const FORWARD_RETRY: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Deserialize)]
+struct Lock {
+    port: u16,
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Forwarded {
+    token: String,
+    cwd: PathBuf,
+    args: Vec<String>,
+}
+
+pub struct Instance {
+    listener: TcpListener,
+    token: String,
+    _claim: Claim,
+}
+
+fn lock_path() -> Result<PathBuf> {
+    Ok(config::data_dir().context("No data directory")?.join(LOCK_FILE))
+}
+
+// None when the arguments were handed to an instance already running
+pub fn acquire() -> Result<Option<Instance>> {
+    let claim = match Claim::take()? {
+        Taken::Claimed(claim) => claim,
+        Taken::Forwarded => return Ok(None),
+    };
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let token: String =
+        rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+    let lock = Lock { port: listener.local_addr()?.port(), token: token.clone() };
+
+    // Renamed into place, a launch reading it never sees half of it
+    let path = lock_path()?;
+    fs::create_dir_all(path.parent().context("Invalid lock path")?)?;
+    let partial = path.with_extension("tmp");
+    fs::write(&partial, serde_json::to_vec(&lock)?)?;
+    fs::rename(partial, path)?;
+    Ok(Some(Instance { listener, token, _claim: claim }))
+}
+
+enum Taken {
+    Claimed(Claim),
+    // The arguments went to the instance holding the claim, exactly once
+    Forwarded,
+}
+
+// The first instance may have claimed without listening yet
+fn forward_retrying(mut forward: impl FnMut() -> Result<()>) -> Result<()> {
+    let mut attempts = 0;
+    while let Err(err) = forward() {
+        attempts += 1;
+        if attempts >= FORWARD_ATTEMPTS {
+            return Err(err.context("Another instance is running but doesn't answer"));
+        }
+        thread::sleep(FORWARD_RETRY);
+    }
+    Ok(())
+}
+
+// Held for as long as the instance runs
+#[cfg(target_os = "windows")]
+struct Claim(winapi::shared::ntdef::HANDLE);
+
+#[cfg(target_os = "windows")]
+impl Claim {
+    // Per data directory, so portable installs don't see each other
+    fn take() -> Result<Taken> {
+        use std::os::windows::ffi::OsStrExt;
+        use winapi::shared::winerror::ERROR_ALREADY_EXISTS;
+        use winapi::um::synchapi::CreateMutexW;
+        use winapi::um::{errhandlingapi::GetLastError, handleapi::CloseHandle};
+
+        let dir = config::data_dir().context("No data directory")?;
+        let digest = crc32fast::hash(dir.to_string_lossy().to_lowercase().as_bytes());
+        let name = format!("Local\\GamePerf-{:08x}", digest);
+        let wide: Vec<u16> = std::ffi::OsStr::new(&name).encode_wide().chain(Some(0)).collect();
+        // The mutex is created atomically, a second creator gets ERROR_ALREADY_EXISTS
+        let handle = unsafe { CreateMutexW(std::ptr::null_mut(), 0, wide.as_ptr()) };
+        if handle.is_null() {
+            bail!("Failed to create the instance mutex");
+        }
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            unsafe { CloseHandle(handle) };
+            forward_retrying(forward)?;
+            return Ok(Taken::Forwarded);
+        }
+        Ok(Taken::Claimed(Claim(handle)))
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for Claim {
+    fn drop(&mut self) {
+        unsafe { winapi::um::handleapi::CloseHandle(self.0) };
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+struct Claim(PathBuf);
+
+#[cfg(not(target_os = "windows"))]
+impl Claim {
+    fn take() -> Result<Taken> {
+        Claim::take_in(&config::data_dir().context("No data directory")?, forward)
+    }
+
+    // `create_new` fails when the file exists, only one launch gets it. The owner gets the
+    // arguments once it listens, a file left by a crashed instance (nobody answers even after
+    // `forward_retrying`) is removed once.
+    fn take_in(dir: &std::path::Path, mut forward: impl FnMut() -> Result<()>) -> Result<Taken> {
+        let path = dir.join(OWNER_FILE);
+        fs::create_dir_all(dir)?;
+        for attempt in 0..2 {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(Taken::Claimed(Claim(path)));
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    match forward_retrying(&mut forward) {
+                        Ok(()) => return Ok(Taken::Forwarded),
+                        Err(err) if attempt > 0 => return Err(err),
+                        Err(_) => {
+                            log::warn!("removing stale {}", path.display());
+                            let _ = fs::remove_file(&path);
+                        }
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        unreachable!()
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn forward() -> Result<()> {
+    let lock: Lock = serde_json::from_slice(&fs::read(lock_path()?)?)?;
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, lock.port));
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+
+    let forwarded = Forwarded {
+        token: lock.token,
+        cwd: std::env::current_dir()?,
+        // Without the program name
+        args: std::env::args().skip(1).collect(),
+    };
+    stream.write_all(&serde_json::to_vec(&forwarded)?)?;
+    stream.write_all(b"\n")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.trim() != "ok" {
+        bail!("Unexpected reply from running instance: {}", reply.trim());
+    }
+    Ok(())
+}
+
+impl Instance {
+    pub fn listen(self, proxy: EventLoopProxy<rpc::Event>, tx: Sender<base::ChannelMsg>) {
+        thread::spawn(move || {
+            for stream in self.listener.incoming().flatten() {
+                if let Err(err) = self.handle(stream, &proxy, &tx) {
+                    log::warn!("forwarded instance: {}", err);
+                }
+            }
+        });
+    }
+
+    fn handle(
+        &self,
+        mut stream: TcpStream,
+        proxy: &EventLoopProxy<rpc::Event>,
+        tx: &Sender<base::ChannelMsg>,
+    ) -> Result<()> {
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let forwarded: Forwarded = serde_json::from_str(&line)?;
+        if !rpc::auth::constant_time_eq(forwarded.token.as_bytes(), self.token.as_bytes()) {
+            bail!("Invalid token");
+        }
+        stream.write_all(b"ok\n")?;
+
+        let program = std::iter::once(String::from("GamePerf"));
+        let args = crate::app().try_get_matches_from(program.chain(forwarded.args))?;
+        rpc::handle_forwarded_args(&args, &forwarded.cwd, proxy, tx)
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(target_os = "windows"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("gameperf_instance_{}", std::process::id()));
+        let first = match Claim::take_in(&dir, || bail!("Unexpected forward"))? {
+            Taken::Claimed(claim) => claim,
+            Taken::Forwarded => bail!("Not claimed"),
+        };
+
+        // The first instance answers on the third attempt, the arguments go over once
+        let mut forwards = 0;
+        let taken = Claim::take_in(&dir, || {
+            forwards += 1;
+            if forwards < 3 {
+                bail!("Not listening yet");
+            }
+            Ok(())
+        })?;
+        assert!(matches!(taken, Taken::Forwarded));
+        assert_eq!(forwards, 3);
+        assert!(dir.join(OWNER_FILE).exists());
+
+        // Nobody answers at all, the file was left by a crashed instance: it is removed once
+        std::mem::forget(first);
+        let mut forwards = 0;
+        let taken = Claim::take_in(&dir, || {
+            forwards += 1;
+            bail!("Connection refused")
+        })?;
+        assert!(matches!(taken, Taken::Claimed(_)));
+        assert_eq!(forwards, FORWARD_ATTEMPTS);
+        drop(taken);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::database;
+use crate::rpc;
+
+const MANIFEST: &str = "integrity.json";
+// Embedded webview assets, the rest of the paths are relative to the install directory
+const ASSET_PREFIX: &str = "dist/";
+// Set by release builds, base64. Without it manifests can't be trusted, only compared.
+const PUBLIC_KEY: Option<&str> = option_env!("GAMEPERF_MANIFEST_KEY");
+
+lazy_static! {
+    static ref LAST_REPORT: RwLock<Option<Report>> = RwLock::new(None);
+}
+
+// Written by `write`, `signature` covers `files` serialized as is (sorted keys)
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    // Path to SHA-256, hex
+    files: BTreeMap<String, String>,
+    signature: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestStatus {
+    Valid,
+    // Built without the release key, hashes were compared all the same
+    Unsigned,
+    Invalid,
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub manifest: ManifestStatus,
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        matches!(self.manifest, ManifestStatus::Valid | ManifestStatus::Unsigned)
+            && self.mismatched.is_empty()
+            && self.missing.is_empty()
+    }
+}
+
+fn verify_signature(manifest: &Manifest, key: Option<&str>) -> Result<bool> {
+    let key = match key {
+        Some(key) => base64::decode(key).context("Invalid built-in key")?,
+        None => return Ok(false),
+    };
+    let key = PublicKey::from_bytes(&key).context("Invalid built-in key")?;
+    let signature = base64::decode(&manifest.signature).context("Invalid signature")?;
+    let signature = Signature::try_from(signature.as_slice()).context("Invalid signature")?;
+    let signed = serde_json::to_vec(&manifest.files)?;
+    key.verify(&signed, &signature).context("Manifest signature mismatch")?;
+    Ok(true)
+}
+
+// `asset` reads an embedded webview asset
+pub fn check(asset: impl Fn(&str) -> Option<Vec<u8>>) -> Report {
+    let manifest = fs::read(database::resolve(PathBuf::from(MANIFEST)))
+        .ok()
+        .and_then(|json| serde_json::from_slice::<Manifest>(&json).ok());
+    match manifest {
+        Some(manifest) => verify(&manifest, PUBLIC_KEY, asset, |path| {
+            fs::read(database::resolve(PathBuf::from(path))).ok()
+        }),
+        None => Report { manifest: ManifestStatus::Missing, mismatched: vec![], missing: vec![] },
+    }
+}
+
+fn verify(
+    manifest: &Manifest,
+    key: Option<&str>,
+    asset: impl Fn(&str) -> Option<Vec<u8>>,
+    file: impl Fn(&str) -> Option<Vec<u8>>,
+) -> Report {
+    let status = match verify_signature(manifest, key) {
+        Ok(true) => ManifestStatus::Valid,
+        Ok(false) => ManifestStatus::Unsigned,
+        Err(err) => {
+            log::warn!("integrity: {}", err);
+            ManifestStatus::Invalid
+        }
+    };
+    let mut report = Report { manifest: status, mismatched: vec![], missing: vec![] };
+
+    for (path, expected) in &manifest.files {
+        let bytes = match path.strip_prefix(ASSET_PREFIX) {
+            Some(asset_path) => asset(asset_path),
+            None => file(path),
+        };
+        match bytes {
+            Some(bytes) => {
+                if !hash(&bytes).eq_ignore_ascii_case(expected) {
+                    report.mismatched.push(path.clone());
+                }
+            }
+            None => report.missing.push(path.clone()),
+        }
+    }
+    report
+}
+
+fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+// `secret` is a base64 ed25519 secret key, None leaves the manifest unsigned
+fn sign(files: BTreeMap<String, String>, secret: Option<&str>) -> Result<Manifest> {
+    let secret = match secret {
+        Some(secret) => base64::decode(secret.trim()).context("Invalid signing key")?,
+        None => return Ok(Manifest { files, signature: String::new() }),
+    };
+    let secret = SecretKey::from_bytes(&secret).context("Invalid signing key")?;
+    let keypair = Keypair { public: PublicKey::from(&secret), secret };
+    let signature = keypair.sign(&serde_json::to_vec(&files)?);
+    Ok(Manifest { files, signature: base64::encode(signature.to_bytes()) })
+}
+
+// The release step after the build (`write-manifest`): every embedded asset and `files` of the
+// install directory `dir`, signed with the secret half of the build's `GAMEPERF_MANIFEST_KEY`
+pub fn write(
+    dir: &Path,
+    files: &[String],
+    assets: &[String],
+    asset: impl Fn(&str) -> Option<Vec<u8>>,
+    secret: Option<&str>,
+) -> Result<PathBuf> {
+    if secret.is_none() && PUBLIC_KEY.is_some() {
+        bail!("Built with GAMEPERF_MANIFEST_KEY, the manifest must be signed");
+    }
+    let mut hashes = BTreeMap::new();
+    for path in assets {
+        let bytes = asset(path).with_context(|| format!("No embedded asset {}", path))?;
+        hashes.insert(format!("{}{}", ASSET_PREFIX, path), hash(&bytes));
+    }
+    for path in files {
+        let bytes = fs::read(dir.join(path)).with_context(|| format!("Failed to read {}", path))?;
+        hashes.insert(path.replace('\\', "/"), hash(&bytes));
+    }
+    let manifest = sign(hashes, secret)?;
+    if let Some(key) = PUBLIC_KEY {
+        if verify_signature(&manifest, Some(key)).is_err() {
+            bail!("Signing key doesn't match the built-in GAMEPERF_MANIFEST_KEY");
+        }
+    }
+    let path = dir.join(MANIFEST);
+    fs::write(&path, serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(path)
+}
+
+// In the background, the page gets `tse_integrity_warning` when something is off. Debug builds
+// have no manifest and aren't checked.
+pub fn spawn_check(
+    asset: impl Fn(&str) -> Option<Vec<u8>> + Send + 'static,
+    proxy: EventLoopProxy<rpc::Event>,
+) {
+    if cfg!(debug_assertions) {
+        return;
+    }
+    thread::spawn(move || {
+        let report = check(asset);
+        if !report.is_ok() {
+            log::warn!("integrity: {:?}", report);
+            let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+                "tse_integrity_warning",
+                json!(report),
+            ));
+        }
+        *LAST_REPORT.write() = Some(report);
+    });
+}
+
+// None until the startup check finished
+pub fn last_report() -> Option<Report> {
+    LAST_REPORT.read().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 32] = [7; 32];
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let secret = base64::encode(SECRET);
+        let public = base64::encode(PublicKey::from(&SecretKey::from_bytes(&SECRET)?).as_bytes());
+        let files: BTreeMap<String, String> =
+            vec![("dist/index.html".into(), hash(b"page")), ("app.exe".into(), hash(b"exe"))]
+                .into_iter()
+                .collect();
+        let asset = |path: &str| (path == "index.html").then(|| b"page".to_vec());
+        let file = |path: &str| (path == "app.exe").then(|| b"exe".to_vec());
+        let manifest = sign(files.clone(), Some(&secret))?;
+
+        let report = verify(&manifest, Some(&public), asset, file);
+        assert_eq!(report.manifest, ManifestStatus::Valid);
+        assert!(report.is_ok());
+        let report = verify(&manifest, None, asset, file);
+        assert_eq!(report.manifest, ManifestStatus::Unsigned);
+
+        let mut tampered = sign(files, Some(&secret))?;
+        tampered.files.insert("app.exe".into(), hash(b"patched"));
+        let report = verify(&tampered, Some(&public), asset, |_| None);
+        assert_eq!(report.manifest, ManifestStatus::Invalid);
+        assert_eq!(report.missing, vec!["app.exe".to_string()]);
+
+        let report = verify(&manifest, Some(&public), asset, |_| Some(b"patched".to_vec()));
+        assert_eq!(report.mismatched, vec!["app.exe".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write() -> Result<()> {
+        // Release builds only take their own key
+        if PUBLIC_KEY.is_some() {
+            return Ok(());
+        }
+        let secret = base64::encode(SECRET);
+        let public = base64::encode(PublicKey::from(&SecretKey::from_bytes(&SECRET)?).as_bytes());
+        let dir = std::env::temp_dir().join(format!("gameperf_integrity_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("app.exe"), b"exe")?;
+        let asset = |path: &str| (path == "index.html").then(|| b"page".to_vec());
+        let files = vec!["app.exe".to_string()];
+        let assets = vec!["index.html".to_string()];
+
+        let path = write(&dir, &files, &assets, asset, Some(&secret))?;
+        let manifest: Manifest = serde_json::from_slice(&fs::read(&path)?)?;
+        let file = |path: &str| fs::read(dir.join(path)).ok();
+        let report = verify(&manifest, Some(&public), asset, file);
+        assert_eq!(report.manifest, ManifestStatus::Valid);
+        assert!(report.is_ok());
+        assert!(manifest.files.contains_key("dist/index.html"));
+
+        // Everything listed must exist, the key must be one
+        assert!(write(&dir, &["gone.dll".to_string()], &assets, asset, None).is_err());
+        assert!(write(&dir, &files, &["app.js".to_string()], asset, None).is_err());
+        assert!(write(&dir, &files, &assets, asset, Some("not a key")).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
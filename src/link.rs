@@ -0,0 +1,88 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use url::Url;
+
+pub const SCHEME: &str = "gameperf";
+
+// `gameperf://session/<id>` and `gameperf://compare?sessions=<id>,<id>` (or `?a=<id>&b=<id>`)
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "view", rename_all = "lowercase")]
+pub enum Link {
+    Session { id: String },
+    Compare { sessions: Vec<String> },
+}
+
+pub fn is_link(arg: &str) -> bool {
+    arg.get(..SCHEME.len() + 1)
+        .map_or(false, |prefix| prefix.eq_ignore_ascii_case(&format!("{}:", SCHEME)))
+}
+
+pub fn parse(link: &str) -> Result<Link> {
+    let url = Url::parse(link).with_context(|| format!("Invalid link: {}", link))?;
+    if url.scheme() != SCHEME {
+        bail!("Not a GamePerf link: {}", link);
+    }
+
+    // The view is the host part, e.g. `compare` in `gameperf://compare?...`
+    let link = match url.host_str() {
+        Some("session") => {
+            let id = url.path().trim_matches('/').to_string();
+            check_id(&id)?;
+            Link::Session { id }
+        }
+        Some("compare") => {
+            let mut sessions = vec![];
+            for (key, value) in url.query_pairs() {
+                match key.as_ref() {
+                    "sessions" => sessions.extend(value.split(',').map(String::from)),
+                    "a" | "b" => sessions.push(value.into_owned()),
+                    _ => (),
+                }
+            }
+            sessions.retain(|id| !id.is_empty());
+            for id in &sessions {
+                check_id(id)?;
+            }
+            if sessions.len() < 2 {
+                bail!("Compare link needs at least two sessions: {}", link);
+            }
+            Link::Compare { sessions }
+        }
+        _ => bail!("Unknown link: {}", link),
+    };
+    Ok(link)
+}
+
+fn check_id(id: &str) -> Result<()> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        bail!("Invalid session id: {}", id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(parse("gameperf://session/abc123")?, Link::Session { id: "abc123".into() });
+        assert_eq!(
+            parse("gameperf://compare?sessions=a1,b2,c3")?,
+            Link::Compare { sessions: vec!["a1".into(), "b2".into(), "c3".into()] }
+        );
+        assert_eq!(
+            parse("gameperf://compare/?a=a1&b=b2")?,
+            Link::Compare { sessions: vec!["a1".into(), "b2".into()] }
+        );
+
+        assert!(parse("gameperf://compare?a=a1").is_err());
+        assert!(parse("gameperf://session/a%2Fb").is_err());
+        assert!(parse("https://session/abc").is_err());
+        assert!(parse("gameperf://settings").is_err());
+
+        assert!(is_link("GamePerf://session/abc"));
+        assert!(!is_link("save.pcsav"));
+        Ok(())
+    }
+}
@@ -2,44 +2,224 @@
 #![cfg_attr(debug_assertions, windows_subsystem = "console")]
 #![warn(clippy::all)]
 
+mod agent;
+mod assets;
 mod base;
+mod chart;
+mod ci;
+mod config;
+mod database;
+mod discovery;
+mod events;
+mod grpc;
+mod history;
+mod http;
+mod instance;
+mod integrity;
+mod link;
+mod migrate;
+mod mqtt;
+mod overlay;
 mod rpc;
+mod save;
+mod selftest;
+mod session;
 mod util;
+mod viewer;
+mod watchdog;
 #[cfg(target_os = "windows")]
 mod windows;
 mod ws;
 //use rand::Rng;
 use anyhow::Result;
+use base::recording;
+use base::state::{self, CaptureState};
+use capture::{benchmark, health, mock, power, Recorder};
 use clap::{Arg, ArgMatches};
+use gameperf_core::{analysis, capture, format, morph, settings};
 use image::GenericImageView;
-use rust_embed::RustEmbed;
 use serde_json::json;
-use std::time;
+use std::collections::BTreeMap;
+use std::time::{self, SystemTime, UNIX_EPOCH};
 use wry::{
     application::{
-        dpi::LogicalSize,
+        dpi::{LogicalSize, PhysicalPosition},
         event::{Event, WindowEvent},
-        event_loop::{ControlFlow, EventLoop},
+        event_loop::{ControlFlow, EventLoop, EventLoopProxy},
         window::{Icon, WindowBuilder},
     },
-    http::{self, status::StatusCode},
     webview::WebViewBuilder,
 };
 
-#[derive(RustEmbed)]
-#[folder = "dist/"]
-struct Asset;
+// Live samples while capturing
+const SAMPLE_INTERVAL: time::Duration = time::Duration::from_secs(1);
 
 fn parse_args() -> ArgMatches {
-    let app = clap::App::new("GamePerf")
+    app().get_matches()
+}
+
+fn app() -> clap::App<'static> {
+    clap::App::new("GamePerf")
         .version(env!("CARGO_PKG_VERSION"))
         .author("nzcv")
-        .about("GamePerf"); 
-    app.get_matches()
+        .about("GamePerf")
+        .arg(Arg::new("SAVE").index(1).help("Save file to open"))
+        .arg(
+            Arg::new("open-session")
+                .long("open-session")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Capture file to open, imported into the session list"),
+        )
+        .arg(
+            Arg::new("capture")
+                .long("capture")
+                .takes_value(true)
+                .value_name("PROCESS")
+                .help("Start capturing this process on startup"),
+        )
+        .arg(
+            Arg::new("duration")
+                .long("duration")
+                .takes_value(true)
+                .value_name("SECS")
+                .validator(|secs| secs.parse::<u64>())
+                .help("Stop the startup capture after this many seconds"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Capture profile from the config, --capture and --duration override it"),
+        )
+        .arg(
+            Arg::new("mock-capture")
+                .long("mock-capture")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .possible_values(["steady", "stutter", "throttle"])
+                .global(true)
+                .help("Capture scripted frame times and sensors instead of a game"),
+        )
+        .arg(
+            Arg::new("trace-rpc")
+                .long("trace-rpc")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Record RPC requests, responses and events, see replay-rpc"),
+        )
+        .arg(
+            Arg::new("dev-url")
+                .long("dev-url")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Load the pages from a frontend dev server, e.g. http://localhost:5173"),
+        )
+        .subcommand(
+            clap::App::new("ci")
+                .about("Run headless captures and fail on thresholds")
+                .arg(
+                    Arg::new("plan")
+                        .long("plan")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Capture plan (json)"),
+                )
+                .arg(
+                    Arg::new("fail-if")
+                        .long("fail-if")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Threshold expression, e.g. \"p1_low < 45\""),
+                ),
+        )
+        .subcommand(
+            clap::App::new("replay-rpc")
+                .about("Run the requests of an RPC trace again and compare the responses")
+                .arg(Arg::new("trace").index(1).required(true).help("Trace from --trace-rpc"))
+                .arg(
+                    Arg::new("realtime")
+                        .long("realtime")
+                        .help("Keep the time between requests from the trace"),
+                ),
+        )
+        .subcommand(
+            clap::App::new("generate-report")
+                .about("Print the report of a session or capture file")
+                .arg(Arg::new("session").index(1).required(true).help("Session id or file"))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .default_value("json")
+                        .help("json (versioned, see get_report_schema) or a report template"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write the report to this file instead of stdout"),
+                ),
+        )
+        .subcommand(
+            clap::App::new("write-manifest")
+                .about("Write integrity.json for a release, signed with GAMEPERF_MANIFEST_SECRET")
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Install directory, where the manifest goes"),
+                )
+                .arg(
+                    Arg::new("files")
+                        .index(1)
+                        .multiple_values(true)
+                        .help("Files to check besides the frontend, relative to --dir"),
+                ),
+        )
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = parse_args();
+    settings::provide(config::engine_settings);
+    if let Some(pattern) = args.value_of("mock-capture") {
+        mock::select(pattern.parse()?);
+    }
+    if let Some(ci_args) = args.subcommand_matches("ci") {
+        util::init_debug_logger();
+        std::process::exit(ci::run(ci_args));
+    }
+    if let Some(replay_args) = args.subcommand_matches("replay-rpc") {
+        util::init_debug_logger();
+        std::process::exit(rpc::trace::replay(&args, replay_args));
+    }
+    if let Some(report_args) = args.subcommand_matches("generate-report") {
+        util::init_debug_logger();
+        let code = match generate_report(report_args) {
+            Ok(()) => ci::EXIT_PASSED,
+            Err(err) => {
+                log::error!("{:#}", err);
+                ci::EXIT_ERROR
+            }
+        };
+        std::process::exit(code);
+    }
+    if let Some(manifest_args) = args.subcommand_matches("write-manifest") {
+        util::init_debug_logger();
+        let code = match write_manifest(manifest_args) {
+            Ok(()) => ci::EXIT_PASSED,
+            Err(err) => {
+                log::error!("{:#}", err);
+                ci::EXIT_ERROR
+            }
+        };
+        std::process::exit(code);
+    }
+
     #[cfg(target_os = "windows")]
     {
         // Install WebView2
@@ -53,10 +233,33 @@ async fn main() -> Result<()> {
             }
         }
     }
-    let args = parse_args();
     // let server = ws::AwesomeRpc::new(vec!["tse://localhost", "ws://localhost", "http://localhost:*"]);
     // server.start();
     util::init_debug_logger();
+    #[cfg(target_os = "windows")]
+    if let Err(err) = windows::association::register() {
+        log::warn!("file association failed: {}", err);
+    }
+    // A second launch hands its arguments to the running instance and exits
+    let instance = match instance::acquire() {
+        Ok(Some(instance)) => Some(instance),
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            log::warn!("single instance check failed: {}", err);
+            None
+        }
+    };
+    migrate::upgrade_config();
+    if let Some(path) = args.value_of("trace-rpc") {
+        if let Err(err) = rpc::trace::start(std::path::Path::new(path)) {
+            log::warn!("{}", err);
+        }
+    }
+    if let Some(url) = args.value_of("dev-url") {
+        if let Err(err) = assets::use_dev_server(url) {
+            log::warn!("{}, using the built frontend", err);
+        }
+    }
     let event_loop = EventLoop::<rpc::Event>::with_user_event();
     let window = WindowBuilder::new()
         .with_title(format!("Trilogy Save Editor - v{} by Karlitos", env!("CARGO_PKG_VERSION")))
@@ -68,14 +271,61 @@ async fn main() -> Result<()> {
         .with_decorations(false)
         .build(&event_loop)?;
 
+    // Where the window was left on exit
+    if let Some(state) = config::CONFIG.read().window {
+        window.set_outer_position(PhysicalPosition::new(state.x, state.y));
+        window.set_maximized(state.maximized);
+    }
+
     let mut last_maximized_state = window.is_maximized();
 
     let proxy = event_loop.create_proxy();
     let (tx, rx) = std::sync::mpsc::channel();
 
+    let mut shutdown = rpc::Shutdown::new(tx.clone());
+
+    database::spawn_watcher(proxy.clone());
+    assets::spawn_watcher(proxy.clone());
+    migrate::spawn(proxy.clone());
+    integrity::spawn_check(assets::get, proxy.clone());
+    watchdog::spawn(proxy.clone());
+    let agent_listen = config::CONFIG.read().agent.listen;
+    if agent_listen {
+        if let Err(err) = agent::listen(tx.clone()) {
+            log::warn!("agent: {}", err);
+        }
+    }
+    let (grpc_port, grpc_tls) = {
+        let config = config::CONFIG.read();
+        (config.grpc_port, config.grpc_tls.clone())
+    };
+    if let Some(port) = grpc_port {
+        if let Err(err) = grpc::serve(port, grpc_tls.as_ref(), tx.clone()) {
+            log::warn!("gRPC: {}", err);
+        }
+    }
+    let mqtt_settings = config::CONFIG.read().mqtt.clone();
+    if let Some(settings) = mqtt_settings {
+        mqtt::start(settings);
+    }
+    if let Some(port) = config::CONFIG.read().events_port {
+        if let Err(err) = events::serve(port) {
+            log::warn!("events: {}", err);
+        }
+    }
+    if let Some(port) = config::CONFIG.read().viewer_port {
+        if let Err(err) = viewer::serve(port) {
+            log::warn!("viewer: {}", err);
+        }
+    }
+    if let Some(instance) = instance {
+        instance.listen(proxy.clone(), tx.clone());
+    }
+
     let ipcproxy = proxy.clone();
     let webview = WebViewBuilder::new(window)?
         //.with_initialization_script(&server.initialization_script())
+        .with_initialization_script(&rpc::auth::initialization_script())
         .with_initialization_script(include_str!("init.js"))
         .with_rpc_handler(move |window, req| {
             rpc::rpc_handler(
@@ -83,59 +333,238 @@ async fn main() -> Result<()> {
                 rpc::RpcUtils { window, event_proxy: &proxy, args: &args, tx: &tx },
             )
         })
-        .with_custom_protocol(String::from("tse"), protocol)
-        .with_url("tse://localhost/")?
+        .with_custom_protocol(String::from("tse"), assets::protocol)
+        .with_url(&assets::url(""))?
         .build()?;
 
     #[allow(unused_variables)]
     let server_thread = std::thread::spawn(move || {
         // thread code
         // let _ = webview.evaluate_script("console.log('hello')");
-        let mut cur_status = "idle";
-        let mut package_name: String = "".into();
+        let mut foreground = capture::foreground::Foreground::default();
+        let mut last_tick: Option<time::Instant> = None;
+        let mut low_impact = false;
+        // The running capture, saved as a session when it ends
+        let mut recorder: Option<Recorder> = None;
+        // Whether the page was told about the capture's background audit
+        let mut audited = false;
+        let mut benchmark: Option<benchmark::Timer> = None;
+        // Paused because the device went to sleep, rather than by the user
+        let mut auto_paused = false;
         loop {
+            if rpc::subscription::is_active(rpc::subscription::FOREGROUND_APP) {
+                // The capture target, GamePerf or the launcher in front on one display doesn't
+                // change it while a game runs on the other
+                if let Ok(current) = capture::foreground::poll() {
+                    if current != foreground {
+                        foreground = current;
+                        let _ = ipcproxy.send_event(rpc::Event::Publish(
+                            rpc::subscription::FOREGROUND_APP,
+                            json!({
+                                "package": foreground.target.clone().unwrap_or_default(),
+                                "resumed": foreground.resumed,
+                                "pinned": foreground.pinned,
+                            }),
+                        ));
+                    }
+                }
+            }
+
             if let Ok(msg) = rx.try_recv() {
-                match msg {
-                    base::ChannelMsg::StartCapture(name) => {
-                        cur_status = "runing";
-                        package_name = name;
+                let result = match msg {
+                    base::ChannelMsg::StartCapture(package) => {
+                        start_capture(&package, &ipcproxy).map(|started| {
+                            // A refused start keeps the running capture's audit
+                            if started.is_some() {
+                                audited = false;
+                            }
+                            recorder = started;
+                        })
                     }
                     base::ChannelMsg::StopCapture => {
-                        cur_status = "idle";
+                        state::finalize(&ipcproxy, || save_capture(recorder.take(), &ipcproxy))
+                    }
+                    base::ChannelMsg::PauseCapture => match state::current() {
+                        CaptureState::Capturing { package, started_at } => state::transition(
+                            CaptureState::Paused { package, started_at },
+                            &ipcproxy,
+                        ),
+                        _ => Ok(()),
+                    },
+                    base::ChannelMsg::ResumeCapture => match state::current() {
+                        CaptureState::Paused { package, started_at } => {
+                            if let Some(recorder) = &mut recorder {
+                                recorder.skip("pause");
+                            }
+                            state::transition(
+                                CaptureState::Capturing { package, started_at },
+                                &ipcproxy,
+                            )
+                        }
+                        _ => Ok(()),
+                    },
+                    base::ChannelMsg::ArmBenchmark(package, rule) => {
+                        benchmark::Timer::arm(&package, rule).map(|timer| {
+                            benchmark = Some(timer);
+                            dispatch_benchmark("armed", &package, &ipcproxy);
+                        })
+                    }
+                    base::ChannelMsg::DisarmBenchmark => {
+                        benchmark = None;
+                        Ok(())
+                    }
+                    base::ChannelMsg::Shutdown => {
+                        let saved =
+                            state::finalize(&ipcproxy, || save_capture(recorder.take(), &ipcproxy));
+                        if let Err(err) = saved {
+                            log::warn!("{}", err);
+                        }
+                        let _ = ipcproxy.send_event(rpc::Event::ShutdownReady);
+                        break;
                     }
+                };
+                if let Err(err) = result {
+                    log::warn!("{}", err);
                 }
             }
 
-            match cur_status {
-                "idle" => {
-                    std::thread::sleep(time::Duration::from_millis(200));
+            // Built-in benchmark sequences start and stop the capture, once
+            let edge = match &mut benchmark {
+                Some(timer) => timer.poll().unwrap_or_else(|err| {
+                    log::debug!("benchmark: {}", err);
+                    None
+                }),
+                None => None,
+            };
+            if let (Some(edge), Some(timer)) = (edge, &benchmark) {
+                let package = timer.package().to_string();
+                let result = match edge {
+                    benchmark::Edge::Start => {
+                        dispatch_benchmark("started", &package, &ipcproxy);
+                        start_capture(&package, &ipcproxy).map(|started| {
+                            if started.is_some() {
+                                audited = false;
+                            }
+                            recorder = started;
+                        })
+                    }
+                    benchmark::Edge::Stop => {
+                        dispatch_benchmark("finished", &package, &ipcproxy);
+                        benchmark = None;
+                        state::finalize(&ipcproxy, || save_capture(recorder.take(), &ipcproxy))
+                    }
+                };
+                if let Err(err) = result {
+                    log::warn!("{}", err);
+                }
+            }
+
+            if !state::current().is_running() {
+                auto_paused = false;
+            }
+
+            // Low-impact mode suspends the live charts for as long as a capture runs
+            let suspend = state::current().is_running() && config::CONFIG.read().low_impact;
+            if suspend != low_impact {
+                low_impact = suspend;
+                let _ = ipcproxy.send_event(rpc::Event::DispatchCustomEvent(
+                    "tse_low_impact",
+                    json!({ "active": low_impact }),
+                ));
+            }
+
+            // Paused by sleep is still polled, the recorder notices the device waking up
+            let polled = match state::current() {
+                CaptureState::Capturing { .. } => true,
+                CaptureState::Paused { .. } => auto_paused,
+                _ => false,
+            };
+            if polled {
+                watchdog::beat(watchdog::CAPTURE);
+                // Ticks missed because adb (or the whole system) was too slow
+                let now = time::Instant::now();
+                if let Some(last) = last_tick {
+                    let missed = (now - last).as_millis() / SAMPLE_INTERVAL.as_millis();
+                    health::live_dropped(health::SAMPLES, (missed as u64).saturating_sub(1));
                 }
-                "runing" => {
-                    if !package_name.is_empty() {
-                        let pss = util::dump_pss(&package_name);
-                        if let Ok(pss) = pss {
-                            // let mut rng = rand::thread_rng();
-                            // let pss = rng.gen_range(0..20);
-                            let _ = ipcproxy
-                                .send_event(rpc::Event::BoardCastToJs(json!({ "msg": pss })));
+                last_tick = Some(now);
+
+                if let Some(capture) = &mut recorder {
+                    if watchdog::should_restart(watchdog::FRAMES) {
+                        capture.restart_frames();
+                    }
+                    let metrics = match capture.poll() {
+                        Ok(sample) => sample.map(overlay::sample),
+                        Err(err) => {
+                            // What was recorded until then is still worth keeping
+                            if let Err(err) = save_capture(recorder.take(), &ipcproxy) {
+                                log::warn!("{}", err);
+                            }
+                            state::fail(err.to_string(), &ipcproxy);
+                            continue;
+                        }
+                    };
+                    // The device sleeping pauses the capture until it wakes up
+                    for change in capture.device_changes() {
+                        if let Err(err) = follow_device(change, &mut auto_paused, &ipcproxy) {
+                            log::warn!("{}", err);
+                        }
+                    }
+                    // The game crashed or was closed, see `Recorder::interrupted`
+                    if capture.interrupted().is_some() {
+                        let saved =
+                            state::finalize(&ipcproxy, || save_capture(recorder.take(), &ipcproxy));
+                        if let Err(err) = saved {
+                            log::warn!("{}", err);
+                        }
+                        continue;
+                    }
+                    // Warns the page when other processes compete with the game
+                    if let Some(audit) = capture.background().filter(|_| !audited) {
+                        audited = true;
+                        if audit.heavy {
+                            log::warn!("background load {:.0}% CPU", audit.total_cpu);
+                            let _ = ipcproxy.send_event(rpc::Event::DispatchCustomEvent(
+                                "tse_background_load",
+                                json!(audit),
+                            ));
+                        }
+                    }
+                    if let Some(metrics) = metrics {
+                        watchdog::beat(watchdog::MEMORY);
+                        // Not stalled when no frames are expected, e.g. during loading screens
+                        if metrics.contains_key("fps") || !capture.expects_frames() {
+                            watchdog::beat(watchdog::FRAMES);
+                        }
+                        if !low_impact {
+                            publish_sample(capture, metrics, &ipcproxy);
                         }
                     }
-                    std::thread::sleep(time::Duration::from_secs(1));
-                }
-                &_ => {
-                    todo!()
                 }
+                let _ = ipcproxy.send_event(rpc::Event::Publish(
+                    rpc::subscription::CAPTURE_HEALTH,
+                    json!(health::live()),
+                ));
+                std::thread::sleep(SAMPLE_INTERVAL);
+            } else {
+                last_tick = None;
+                std::thread::sleep(time::Duration::from_millis(200));
             }
         }
     });
 
     // block on main thread
     let proxy = event_loop.create_proxy();
-    event_loop.run(move |event, _, control_flow| {
+    let mut coalescer = rpc::Coalescer::default();
+    let mut overlay = overlay::Overlay::new(assets::protocol, proxy.clone());
+    event_loop.run(move |event, target, control_flow| {
         *control_flow = ControlFlow::Wait;
         match event {
+            Event::WindowEvent { window_id, event, .. } if overlay.owns(window_id) => {
+                overlay.window_event(&event)
+            }
             Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::CloseRequested => shutdown.begin(&webview, control_flow),
                 WindowEvent::Resized(_) => {
                     let _ = webview.resize();
                     let is_maximized = webview.window().is_maximized();
@@ -149,7 +578,19 @@ async fn main() -> Result<()> {
                 }
                 _ => (),
             },
-            Event::UserEvent(event) => rpc::event_handler(event, &webview, control_flow),
+            Event::UserEvent(event) => rpc::event_handler(
+                event,
+                &webview,
+                target,
+                control_flow,
+                &mut coalescer,
+                &mut shutdown,
+                &mut overlay,
+            ),
+            Event::MainEventsCleared => {
+                coalescer.flush_if_due(&webview);
+                shutdown.check_timeout(&webview, control_flow);
+            }
             Event::LoopDestroyed => {
                 // Clear WebView2 Code Cache
                 #[cfg(target_os = "windows")]
@@ -160,6 +601,12 @@ async fn main() -> Result<()> {
                 // let _ = webview.evaluate_script("console.log('hello')");
             }
         }
+
+        // Wake up for the next coalesced flush or the shutdown timeout
+        let deadline = coalescer.deadline().into_iter().chain(shutdown.deadline()).min();
+        if let (Some(deadline), ControlFlow::Wait) = (deadline, *control_flow) {
+            *control_flow = ControlFlow::WaitUntil(deadline);
+        }
     });
 
     // server_thread.join().unwrap();
@@ -168,20 +615,128 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-fn protocol(request: &http::Request) -> wry::Result<http::Response> {
-    let mut path = request.uri().trim_start_matches("tse://localhost/");
-    if path.is_empty() {
-        path = "index.html"
+// Arming until the process answers, then capturing with the configured `CaptureOptions`. None
+// when the capture failed to start, the state tells why.
+fn start_capture(package: &str, proxy: &EventLoopProxy<rpc::Event>) -> Result<Option<Recorder>> {
+    state::transition(CaptureState::Arming { package: package.into() }, proxy)?;
+    health::reset_live();
+    watchdog::reset();
+    let options = config::CONFIG.read().capture.clone();
+    let restarting = |package: &str| {
+        let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+            "tse_game_restart",
+            json!({ "package": package, "reason": "gpu_layer" }),
+        ));
+    };
+    let mut recorder = match recording::recorder(package, &options, restarting) {
+        Ok(recorder) => recorder,
+        Err(err) => {
+            state::fail(err.to_string(), proxy);
+            return Ok(None);
+        }
+    };
+    recorder.interval(SAMPLE_INTERVAL);
+    let started_at =
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    state::transition(CaptureState::Capturing { package: package.into(), started_at }, proxy)?;
+    Ok(Some(recorder))
+}
+
+// The capture state and the page follow what the recorder saw, see `Recorder::device_changes`
+fn follow_device(
+    change: power::Change,
+    auto_paused: &mut bool,
+    proxy: &EventLoopProxy<rpc::Event>,
+) -> Result<()> {
+    match (change, state::current()) {
+        (power::Change::Sleep, CaptureState::Capturing { package, started_at }) => {
+            *auto_paused = true;
+            state::transition(CaptureState::Paused { package, started_at }, proxy)
+        }
+        (power::Change::Wake, CaptureState::Paused { package, started_at }) if *auto_paused => {
+            *auto_paused = false;
+            state::transition(CaptureState::Capturing { package, started_at }, proxy)
+        }
+        (power::Change::Display(display), _) => {
+            let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+                "tse_display_changed",
+                json!({ "display": display }),
+            ));
+            Ok(())
+        }
+        _ => Ok(()),
     }
-    log::debug!("{:?}", path);
-    let response = http::ResponseBuilder::new();
-    match Asset::get(path) {
-        Some(asset) => {
-            let mime = mime_guess::from_path(path).first_or_octet_stream().to_string();
-            response.mimetype(&mime).body(asset.data.into())
+}
+
+// `tse_capture_saved` with the new session
+fn save_capture(recorder: Option<Recorder>, proxy: &EventLoopProxy<rpc::Event>) -> Result<()> {
+    let recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return Ok(()),
+    };
+    let info = recording::save(recorder)?;
+    log::info!("capture saved as {}", info.id);
+    let _ = proxy.send_event(rpc::Event::DispatchCustomEvent("tse_capture_saved", json!(info)));
+    Ok(())
+}
+
+// Live metrics for the page, the HUD, the viewer and MQTT, whichever are watching
+fn publish_sample(
+    recorder: &Recorder,
+    metrics: BTreeMap<String, f64>,
+    proxy: &EventLoopProxy<rpc::Event>,
+) {
+    if rpc::subscription::is_active(rpc::subscription::SAMPLES_LIVE) {
+        if let Some(pss) = recorder.pss() {
+            let _ =
+                proxy.send_event(rpc::Event::Publish(rpc::subscription::SAMPLES_LIVE, json!(pss)));
         }
-        None => response.status(StatusCode::NOT_FOUND).body(vec![]),
     }
+    if viewer::is_watching() {
+        viewer::sample(&metrics);
+    }
+    if mqtt::is_enabled() {
+        mqtt::sample(metrics.clone());
+    }
+    if overlay::is_visible() {
+        let _ = proxy.send_event(rpc::Event::Overlay(overlay::Command::Sample(metrics)));
+    }
+}
+
+// `tse_benchmark` with the phase: armed, started or finished
+fn dispatch_benchmark(phase: &str, package: &str, proxy: &EventLoopProxy<rpc::Event>) {
+    let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+        "tse_benchmark",
+        json!({ "phase": phase, "package": package }),
+    ));
+}
+
+// The secret is base64, the public half must be built in as GAMEPERF_MANIFEST_KEY
+fn write_manifest(args: &ArgMatches) -> Result<()> {
+    let dir = std::path::Path::new(args.value_of("dir").unwrap_or_default());
+    let files: Vec<String> =
+        args.values_of("files").map(|files| files.map(String::from).collect()).unwrap_or_default();
+    let secret = std::env::var("GAMEPERF_MANIFEST_SECRET").ok();
+    let path = integrity::write(dir, &files, &assets::paths(), assets::get, secret.as_deref())?;
+    log::info!("manifest written to {}", path.display());
+    Ok(())
+}
+
+// Sessions by id, anything else is read like an import without adding it
+fn generate_report(args: &ArgMatches) -> Result<()> {
+    let session = args.value_of("session").unwrap_or_default();
+    let path = std::path::Path::new(session);
+    let report =
+        if path.is_file() { session::report_file(path)? } else { session::report(session)? };
+    let content = match args.value_of("format").unwrap_or("json") {
+        "json" => serde_json::to_string_pretty(&report)?,
+        template => session::template::render(&report, template)?.content,
+    };
+    match args.value_of("output") {
+        Some(output) => std::fs::write(output, content)?,
+        None => println!("{}", content),
+    }
+    Ok(())
 }
 
 fn load_icon() -> Option<Icon> {
@@ -0,0 +1,240 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use gameperf_core::migrate::{current, unversioned, upgrade, Step, RECORDING_STEPS};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::config;
+use crate::rpc;
+use crate::session;
+
+// Versioned like recordings, see `Step`
+const CONFIG_STEPS: &[Step] = &[unversioned];
+const VERSIONS: &str = "versions.json";
+
+lazy_static! {
+    static ref REPORT: RwLock<Report> = RwLock::new(Report::default());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Config,
+    Sessions,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Migration {
+    pub target: Target,
+    pub from: u32,
+    pub to: u32,
+    // Copies of the files before the upgrade, put back when it failed
+    pub backup: PathBuf,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub migrations: Vec<Migration>,
+    // Sessions are upgraded in the background
+    pub running: bool,
+}
+
+// Versions of what's in the config and data directories
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Versions {
+    config: u32,
+    sessions: u32,
+    // Recordings already upgraded past `sessions`, an interrupted migration goes on from there
+    migrated: BTreeSet<String>,
+}
+
+fn versions_path() -> Result<PathBuf> {
+    Ok(config::data_dir().context("No data directory")?.join(VERSIONS))
+}
+
+fn load_versions() -> Result<Versions> {
+    match fs::read(versions_path()?) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(_) => Ok(Versions::default()),
+    }
+}
+
+fn save_versions(versions: &Versions) -> Result<()> {
+    let path = versions_path()?;
+    fs::create_dir_all(path.parent().context("Invalid data directory")?)?;
+    fs::write(path, serde_json::to_vec_pretty(versions)?)?;
+    Ok(())
+}
+
+fn backup_dir(target: Target, from: u32) -> Result<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let name = format!("{}-v{}-{}", json!(target).as_str().unwrap_or_default(), from, now);
+    let dir = config::data_dir().context("No data directory")?.join("backups").join(name);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// The files a migration rewrites
+fn files_in(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+// Backs `files` up, runs `apply` and puts them back if it fails
+fn migrate(
+    target: Target,
+    from: u32,
+    to: u32,
+    files: &[PathBuf],
+    apply: impl FnOnce() -> Result<()>,
+) -> Migration {
+    let mut migration = Migration { target, from, to, backup: PathBuf::new(), error: None };
+    let copy = |file: &Path, backup: &Path| -> Result<()> {
+        fs::copy(file, backup.join(file.file_name().context("Invalid file")?))?;
+        Ok(())
+    };
+    let result = backup_dir(target, from).and_then(|backup| {
+        for file in files {
+            copy(file, &backup)?;
+        }
+        migration.backup = backup;
+        apply()
+    });
+    if let Err(err) = result {
+        log::error!("migrating {:?} from version {}: {:?}", target, from, err);
+        if migration.backup.as_os_str().is_empty() {
+            migration.error = Some(format!("Backup failed, nothing was changed: {}", err));
+            return migration;
+        }
+        for file in files {
+            let name = file.file_name().unwrap_or_default();
+            if let Err(err) = fs::copy(migration.backup.join(name), file) {
+                log::error!("restoring {}: {}", file.display(), err);
+            }
+        }
+        migration.error = Some(err.to_string());
+    }
+    migration
+}
+
+// Before anything reads the config, the page isn't there yet to show progress
+pub fn upgrade_config() {
+    if let Err(err) = try_upgrade_config() {
+        log::error!("config migration: {}", err);
+    }
+}
+
+fn try_upgrade_config() -> Result<()> {
+    let mut versions = load_versions()?;
+    let to = current(CONFIG_STEPS);
+    let path = config::config_path().context("No config directory")?;
+    if versions.config >= to {
+        if versions.config > to {
+            log::warn!("config version {} is newer than this GamePerf", versions.config);
+        }
+        return Ok(());
+    }
+    if path.is_file() {
+        let files = [path.clone()];
+        let migration = migrate(Target::Config, versions.config, to, &files, || {
+            let mut value: Value = serde_json::from_slice(&fs::read(&path)?)?;
+            upgrade(&mut value, CONFIG_STEPS, versions.config)?;
+            fs::write(&path, serde_json::to_vec_pretty(&value)?)?;
+            Ok(())
+        });
+        let failed = migration.error.is_some();
+        REPORT.write().migrations.push(migration);
+        if failed {
+            return Ok(());
+        }
+    }
+    versions.config = to;
+    save_versions(&versions)
+}
+
+// In the background, with `tse_migration_progress` events, then `tse_migration_finished` when
+// something was upgraded. Session commands only wait for the recording being upgraded.
+pub fn spawn(proxy: EventLoopProxy<rpc::Event>) {
+    REPORT.write().running = true;
+    thread::spawn(move || {
+        if let Err(err) = upgrade_sessions(&proxy) {
+            log::error!("session migration: {}", err);
+        }
+        let report = {
+            let mut report = REPORT.write();
+            report.running = false;
+            report.clone()
+        };
+        if !report.migrations.is_empty() {
+            let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+                "tse_migration_finished",
+                json!(report),
+            ));
+        }
+        // Compaction rewrites recordings, they must be upgraded first
+        session::spawn_compactor();
+    });
+}
+
+fn upgrade_sessions(proxy: &EventLoopProxy<rpc::Event>) -> Result<()> {
+    let mut versions = load_versions()?;
+    let (from, to) = (versions.sessions, current(RECORDING_STEPS));
+    if from >= to {
+        if from > to {
+            log::warn!("session store version {} is newer than this GamePerf", from);
+        }
+        return Ok(());
+    }
+    let dir = config::session_dir().context("No data directory")?;
+    if dir.join("index.json").is_file() {
+        // What the backup holds, put back along with it when the migration fails
+        let resumed = versions.migrated.clone();
+        let migration = migrate(Target::Sessions, from, to, &files_in(&dir)?, || {
+            let upgrade = |value: &mut Value| upgrade(value, RECORDING_STEPS, from);
+            let mark = |id: &str| {
+                versions.migrated.insert(id.into());
+                save_versions(&versions)
+            };
+            session::migrate(upgrade, &resumed, mark, |done, total| {
+                let progress = json!({
+                    "target": Target::Sessions,
+                    "from": from,
+                    "to": to,
+                    "done": done,
+                    "total": total,
+                });
+                let event = rpc::Event::DispatchCustomEvent("tse_migration_progress", progress);
+                let _ = proxy.send_event(event);
+            })
+        });
+        let failed = migration.error.is_some();
+        REPORT.write().migrations.push(migration);
+        if failed {
+            versions.migrated = resumed;
+            return save_versions(&versions);
+        }
+    }
+    versions.sessions = to;
+    versions.migrated.clear();
+    save_versions(&versions)
+}
+
+pub fn report() -> Report {
+    REPORT.read().clone()
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::base::state::{self, CaptureState};
+use crate::session::anonymize;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+// Live stats are rounded to this, dashboards don't need the sampling rate
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref LATEST: Mutex<Option<BTreeMap<String, f64>>> = Mutex::new(None);
+}
+
+// Broker for home-lab dashboards (Home Assistant, Node-RED), see `start`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // Topics are `<prefix>/status`, `<prefix>/state` and `<prefix>/live`
+    pub topic_prefix: String,
+}
+
+impl Default for MqttSettings {
+    fn default() -> Self {
+        MqttSettings {
+            host: "localhost".into(),
+            port: 1883,
+            username: None,
+            password: None,
+            topic_prefix: "gameperf".into(),
+        }
+    }
+}
+
+// The metrics of a live tick, published with the next interval
+pub fn sample(metrics: BTreeMap<String, f64>) {
+    *LATEST.lock() = Some(metrics);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Retained `online`/`offline` on `status` (the broker sends `offline` when we drop), the retained
+// capture state on `state` when it changes, and the latest live metrics on `live` every second
+// while capturing. Connects in the background and keeps retrying.
+pub fn start(settings: MqttSettings) {
+    let topic = |name: &str| format!("{}/{}", settings.topic_prefix.trim_end_matches('/'), name);
+    let status = topic("status");
+    let mut options =
+        MqttOptions::new(format!("gameperf-{}", std::process::id()), &settings.host, settings.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(&status, "offline", QoS::AtLeastOnce, true));
+    if let Some(username) = &settings.username {
+        options.set_credentials(username, settings.password.as_deref().unwrap_or_default());
+    }
+    let (client, mut connection) = Client::new(options, 16);
+
+    // `online` again on every reconnect, the broker published the will meanwhile
+    let mut online = client.clone();
+    thread::spawn(move || {
+        for notification in connection.iter() {
+            match notification {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    let _ = online.try_publish(&status, QoS::AtLeastOnce, true, "online");
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    log::warn!("mqtt: {}", err);
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        }
+    });
+    let (state_topic, live_topic) = (topic("state"), topic("live"));
+    thread::spawn(move || publish(client, state_topic, live_topic));
+    ENABLED.store(true, Ordering::Relaxed);
+    log::info!("mqtt publishing to {}:{}", settings.host, settings.port);
+}
+
+// Never blocks the loop on a slow or unreachable broker, what doesn't fit the queue is dropped
+fn publish(mut client: Client, state_topic: String, live_topic: String) {
+    let mut last_state = None;
+    loop {
+        // `anonymize::private_state` when privacy mode is on, as everywhere the state leaves
+        let current = anonymize::private_state(state::current());
+        publish_state(&mut client, &state_topic, &mut last_state, &current);
+        let latest = LATEST.lock().take();
+        if let Some(payload) = latest.and_then(|metrics| live_payload(&current, metrics)) {
+            let _ = client.try_publish(&live_topic, QoS::AtMostOnce, false, payload);
+        }
+        thread::sleep(PUBLISH_INTERVAL);
+    }
+}
+
+// Only a state that made it into the queue counts as published, the others are tried again
+fn publish_state(
+    client: &mut Client,
+    topic: &str,
+    last_state: &mut Option<CaptureState>,
+    current: &CaptureState,
+) -> bool {
+    if last_state.as_ref() == Some(current) {
+        return false;
+    }
+    let payload = json!(current).to_string();
+    let published = client.try_publish(topic, QoS::AtLeastOnce, true, payload).is_ok();
+    if published {
+        *last_state = Some(current.clone());
+    }
+    published
+}
+
+// `fps`, `frametime` and the other metrics of `overlay::sample`, while capturing
+fn live_payload(state: &CaptureState, metrics: BTreeMap<String, f64>) -> Option<String> {
+    match state {
+        CaptureState::Capturing { package, .. } => {
+            Some(json!({ "package": package, "metrics": metrics }).to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_state() {
+        // Nothing drains the queue of one
+        let (mut client, _connection) = Client::new(MqttOptions::new("test", "localhost", 1883), 1);
+        let mut last_state = None;
+        let mut send = |state: &CaptureState| {
+            publish_state(&mut client, "gameperf/state", &mut last_state, state)
+        };
+        assert!(send(&CaptureState::Idle));
+        assert!(!send(&CaptureState::Idle));
+
+        let capturing =
+            CaptureState::Capturing { package: "com.example.game".into(), started_at: 5 };
+        assert!(!send(&capturing));
+        assert_eq!(last_state, Some(CaptureState::Idle));
+    }
+
+    #[test]
+    fn test_live_payload() {
+        let metrics: BTreeMap<String, f64> =
+            [("fps".to_string(), 60.0), ("frametime".to_string(), 1000.0 / 60.0)].into();
+        let capturing =
+            CaptureState::Capturing { package: "com.example.game".into(), started_at: 5 };
+        let payload: serde_json::Value =
+            serde_json::from_str(&live_payload(&capturing, metrics.clone()).unwrap()).unwrap();
+        assert_eq!(payload["package"], "com.example.game");
+        assert_eq!(payload["metrics"]["fps"], 60.0);
+        assert_eq!(live_payload(&CaptureState::Idle, metrics), None);
+    }
+}
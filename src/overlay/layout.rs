@@ -0,0 +1,167 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+const MAX_WIDGETS: usize = 32;
+const MAX_SIZE: u32 = 4096;
+const MAX_WINDOW_SECS: u32 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WidgetKind {
+    // Latest value
+    Text,
+    // Values over the last `window_secs`
+    Graph,
+    // Latest value against `max`
+    Bar,
+}
+
+// One metric drawn at a fixed spot, `metric` is a sample key (`fps`, `frametime`, `mem.total`, ...)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Widget {
+    pub kind: WidgetKind,
+    pub metric: String,
+    pub label: String,
+    // In px from the top left of the overlay
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // Any CSS color
+    pub color: String,
+    pub window_secs: u32,
+    // Full scale of bars and graphs, None scales to the values shown
+    pub max: Option<f64>,
+    pub decimals: u8,
+}
+
+impl Default for Widget {
+    fn default() -> Self {
+        Widget {
+            kind: WidgetKind::Text,
+            metric: String::new(),
+            label: String::new(),
+            x: 0,
+            y: 0,
+            width: 160,
+            height: 20,
+            color: "#ffffff".into(),
+            window_secs: 30,
+            max: None,
+            decimals: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub background: String,
+    pub widgets: Vec<Widget>,
+}
+
+// Basic HUD: fps, a frame time graph and memory
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            width: 220,
+            height: 120,
+            background: "rgba(0, 0, 0, 0.5)".into(),
+            widgets: vec![
+                Widget {
+                    metric: "fps".into(),
+                    label: "FPS".into(),
+                    x: 8,
+                    y: 6,
+                    ..Default::default()
+                },
+                Widget {
+                    kind: WidgetKind::Graph,
+                    metric: "frametime".into(),
+                    label: "ms".into(),
+                    x: 8,
+                    y: 30,
+                    width: 204,
+                    height: 56,
+                    color: "#7cfc00".into(),
+                    decimals: 1,
+                    ..Default::default()
+                },
+                Widget {
+                    metric: "mem.total".into(),
+                    label: "MB".into(),
+                    x: 8,
+                    y: 92,
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+}
+
+impl Layout {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=MAX_SIZE).contains(&self.width) || !(1..=MAX_SIZE).contains(&self.height) {
+            bail!("Invalid overlay size: {}x{}", self.width, self.height);
+        }
+        if self.widgets.len() > MAX_WIDGETS {
+            bail!("Too many overlay widgets: {} (max {})", self.widgets.len(), MAX_WIDGETS);
+        }
+        check_color(&self.background)?;
+        for widget in &self.widgets {
+            if widget.metric.is_empty() {
+                bail!("Overlay widget without a metric");
+            }
+            if widget.x.saturating_add(widget.width) > self.width
+                || widget.y.saturating_add(widget.height) > self.height
+            {
+                bail!("Overlay widget `{}` doesn't fit in the overlay", widget.metric);
+            }
+            if widget.kind == WidgetKind::Graph
+                && !(1..=MAX_WINDOW_SECS).contains(&widget.window_secs)
+            {
+                bail!("Invalid graph window for `{}`: {}s", widget.metric, widget.window_secs);
+            }
+            if matches!(widget.max, Some(max) if !max.is_finite() || max <= 0.0) {
+                bail!("Invalid maximum for `{}`", widget.metric);
+            }
+            check_color(&widget.color)?;
+        }
+        Ok(())
+    }
+}
+
+// Colors end up in the overlay's styles, keep them to what a CSS color can contain
+fn check_color(color: &str) -> Result<()> {
+    let valid = !color.is_empty()
+        && color.chars().all(|c| c.is_ascii_alphanumeric() || "#(),.% ".contains(c));
+    if !valid {
+        bail!("Invalid color: {}", color);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut layout = Layout::default();
+        assert!(layout.validate().is_ok());
+
+        layout.widgets[0].x = layout.width;
+        assert!(layout.validate().is_err());
+
+        let mut layout = Layout::default();
+        layout.widgets[1].window_secs = 0;
+        assert!(layout.validate().is_err());
+
+        let mut layout = Layout::default();
+        layout.widgets[0].color = "red;} body{display:none".into();
+        assert!(layout.validate().is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use wry::{
+    application::{
+        dpi::{LogicalSize, PhysicalPosition},
+        event::WindowEvent,
+        event_loop::{EventLoopProxy, EventLoopWindowTarget},
+        monitor::MonitorHandle,
+        window::{Window, WindowBuilder, WindowId},
+    },
+    http,
+    webview::{WebView, WebViewBuilder},
+};
+
+use crate::assets;
+use crate::capture::Sample;
+use crate::config::CONFIG;
+use crate::rpc;
+
+pub mod layout;
+
+pub use layout::Layout;
+
+// Logical px between the overlay and the screen edges
+const MARGIN: f64 = 8.0;
+
+// Read by the capture thread, live samples are only gathered for the overlay while it is shown
+static VISIBLE: AtomicBool = AtomicBool::new(false);
+
+type Protocol = fn(&http::Request) -> wry::Result<http::Response>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    // Transparent and above the game, exclusive fullscreen games hide it
+    Topmost,
+    // Opaque borderless window in a corner of `monitor`, e.g. next to a fullscreen game on a
+    // second screen
+    Docked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub mode: Mode,
+    pub corner: Corner,
+    // Index in the monitor list, None for the primary monitor
+    pub monitor: Option<usize>,
+    pub click_through: bool,
+    // Left out of screenshots and recordings, Windows only
+    pub hide_from_capture: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mode: Mode::Topmost,
+            corner: Corner::TopLeft,
+            monitor: None,
+            click_through: true,
+            hide_from_capture: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    // None shows the default layout
+    Show { profile: Option<String>, layout: Layout },
+    Hide,
+    // Only applied when the overlay shows that profile
+    SetLayout { profile: String, layout: Layout },
+    // Rebuilds a shown overlay with the saved `Settings`
+    Reconfigure,
+    Sample(BTreeMap<String, f64>),
+}
+
+// Borderless window rendering `dist/overlay.html`, see `Settings`
+pub struct Overlay {
+    protocol: Protocol,
+    proxy: EventLoopProxy<rpc::Event>,
+    profile: Option<String>,
+    layout: Layout,
+    webview: Option<WebView>,
+    // Last state sent as `tse_overlay_fullscreen`
+    fullscreen: bool,
+}
+
+impl Overlay {
+    pub fn new(protocol: Protocol, proxy: EventLoopProxy<rpc::Event>) -> Self {
+        Overlay {
+            protocol,
+            proxy,
+            profile: None,
+            layout: Layout::default(),
+            webview: None,
+            fullscreen: false,
+        }
+    }
+
+    pub fn owns(&self, id: WindowId) -> bool {
+        self.webview.as_ref().map_or(false, |webview| webview.window().id() == id)
+    }
+
+    pub fn handle(
+        &mut self,
+        command: Command,
+        target: &EventLoopWindowTarget<rpc::Event>,
+    ) -> Result<()> {
+        match command {
+            Command::Show { profile, layout } => {
+                self.profile = profile;
+                self.layout = layout;
+                match &self.webview {
+                    Some(webview) => apply_layout(webview, &self.layout)?,
+                    None => self.webview = Some(self.build(target)?),
+                }
+                VISIBLE.store(true, Ordering::Relaxed);
+            }
+            Command::Hide => self.hide(),
+            Command::SetLayout { profile, layout } => {
+                if self.profile == Some(profile) {
+                    self.layout = layout;
+                    if let Some(webview) = &self.webview {
+                        apply_layout(webview, &self.layout)?;
+                    }
+                }
+            }
+            Command::Reconfigure => {
+                if self.webview.take().is_some() {
+                    self.webview = Some(self.build(target)?);
+                }
+            }
+            Command::Sample(metrics) => {
+                if let Some(webview) = &self.webview {
+                    let metrics = serde_json::to_string(&metrics)?;
+                    webview.evaluate_script(&format!(
+                        "window.overlay && window.overlay.update({})",
+                        metrics
+                    ))?;
+                    self.check_fullscreen();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn window_event(&mut self, event: &WindowEvent) {
+        if let WindowEvent::CloseRequested = event {
+            self.hide();
+        }
+    }
+
+    fn hide(&mut self) {
+        self.webview = None;
+        self.profile = None;
+        self.fullscreen = false;
+        VISIBLE.store(false, Ordering::Relaxed);
+    }
+
+    fn build(&self, target: &EventLoopWindowTarget<rpc::Event>) -> Result<WebView> {
+        let settings = CONFIG.read().overlay.clone();
+        let topmost = settings.mode == Mode::Topmost;
+        let window = WindowBuilder::new()
+            .with_title("GamePerf overlay")
+            .with_inner_size(LogicalSize::new(self.layout.width, self.layout.height))
+            .with_decorations(false)
+            .with_transparent(topmost)
+            .with_always_on_top(topmost)
+            .with_resizable(false)
+            .build(target)?;
+
+        let monitor = match settings.monitor {
+            Some(index) => target.available_monitors().nth(index),
+            None => target.primary_monitor(),
+        };
+        dock(&window, monitor, settings.corner);
+        apply_settings(&window, &settings);
+
+        let webview = WebViewBuilder::new(window)?
+            .with_transparent(topmost)
+            .with_initialization_script(&format!(
+                "window.OVERLAY_LAYOUT = {};",
+                serde_json::to_string(&self.layout)?
+            ))
+            .with_custom_protocol(String::from("tse"), self.protocol)
+            .with_url(&assets::url("overlay.html"))?
+            .build()?;
+        Ok(webview)
+    }
+
+    // Suggests the docked mode to the page when a fullscreen game hides the overlay
+    fn check_fullscreen(&mut self) {
+        let fullscreen =
+            CONFIG.read().overlay.mode == Mode::Topmost && exclusive_fullscreen_running();
+        if fullscreen != self.fullscreen {
+            self.fullscreen = fullscreen;
+            let _ = self.proxy.send_event(rpc::Event::DispatchCustomEvent(
+                "tse_overlay_fullscreen",
+                json!({ "active": fullscreen }),
+            ));
+        }
+    }
+}
+
+fn apply_layout(webview: &WebView, layout: &Layout) -> Result<()> {
+    let window = webview.window();
+    window.set_inner_size(LogicalSize::new(layout.width, layout.height));
+    let corner = CONFIG.read().overlay.corner;
+    dock(window, window.current_monitor(), corner);
+    webview.evaluate_script(&format!(
+        "window.overlay && window.overlay.setLayout({})",
+        serde_json::to_string(layout)?
+    ))?;
+    Ok(())
+}
+
+fn dock(window: &Window, monitor: Option<MonitorHandle>, corner: Corner) {
+    let monitor = match monitor.or_else(|| window.current_monitor()) {
+        Some(monitor) => monitor,
+        None => return,
+    };
+    let (origin, size, outer) = (monitor.position(), monitor.size(), window.outer_size());
+    let margin = (MARGIN * monitor.scale_factor()) as i32;
+    let left = origin.x + margin;
+    let top = origin.y + margin;
+    let right = origin.x + size.width as i32 - outer.width as i32 - margin;
+    let bottom = origin.y + size.height as i32 - outer.height as i32 - margin;
+    let (x, y) = match corner {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomLeft => (left, bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+    window.set_outer_position(PhysicalPosition::new(x, y));
+}
+
+#[cfg(target_os = "windows")]
+fn apply_settings(window: &Window, settings: &Settings) {
+    use crate::windows::overlay;
+
+    if let Err(err) = overlay::set_click_through(window, settings.click_through) {
+        log::warn!("overlay click-through: {}", err);
+    }
+    if let Err(err) = overlay::set_capture_excluded(window, settings.hide_from_capture) {
+        log::warn!("overlay capture exclusion: {}", err);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn apply_settings(_: &Window, settings: &Settings) {
+    if settings.click_through || settings.hide_from_capture {
+        log::debug!("overlay click-through and capture exclusion are Windows only");
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn exclusive_fullscreen_running() -> bool {
+    crate::windows::overlay::exclusive_fullscreen()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn exclusive_fullscreen_running() -> bool {
+    false
+}
+
+pub fn is_visible() -> bool {
+    VISIBLE.load(Ordering::Relaxed)
+}
+
+// Metrics of a live tick: the recorded sample's plus the average `frametime`
+pub fn sample(sample: &Sample) -> BTreeMap<String, f64> {
+    let mut metrics = sample.metrics.clone();
+    if let Some(fps) = metrics.get("fps").copied().filter(|fps| *fps > 0.0) {
+        metrics.insert("frametime".into(), 1000.0 / fps);
+    }
+    metrics
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use crate::config::{self, CONFIG};
+
+use super::dialog;
+
+lazy_static! {
+    // Paths granted at runtime: dialog results, command line, user confirmed
+    static ref GRANTED: RwLock<HashSet<PathBuf>> = RwLock::new(HashSet::new());
+    // Files exported on the page's behalf since the app started, see `check_delete`
+    static ref EXPORTED: RwLock<HashSet<PathBuf>> = RwLock::new(HashSet::new());
+}
+
+pub fn grant(path: &Path) {
+    match normalize(path) {
+        Ok(path) => {
+            GRANTED.write().insert(path);
+        }
+        Err(err) => log::error!("Unable to grant {}: {}", path.display(), err),
+    }
+}
+
+// Returns the normalized path if file commands may read it
+pub fn check(path: &Path) -> Result<PathBuf> {
+    let normalized = normalize(path)?;
+    if !is_installed(&normalized) && !is_allowed(&normalized) {
+        bail!("Access denied: {}, use grant_path_access first", path.display());
+    }
+    Ok(normalized)
+}
+
+// Same for writes, the app's own files (databases ship next to the exe) are read-only
+pub fn check_write(path: &Path) -> Result<PathBuf> {
+    let normalized = normalize(path)?;
+    if is_installed(&normalized) {
+        bail!("Access denied: {} belongs to the installation", path.display());
+    }
+    if !is_allowed(&normalized) {
+        bail!("Access denied: {}, use grant_path_access first", path.display());
+    }
+    Ok(normalized)
+}
+
+// Backups next to saves and files the app exported, never directories or the roots themselves
+pub fn check_delete(path: &Path) -> Result<PathBuf> {
+    let normalized = check_write(path)?;
+    if roots().contains(&normalized) || !is_deletable(&normalized) {
+        bail!("Only backups and exported files can be deleted: {}", path.display());
+    }
+    Ok(normalized)
+}
+
+pub fn exported(path: &Path) {
+    if let Ok(path) = normalize(path) {
+        EXPORTED.write().insert(path);
+    }
+}
+
+// On the canonical path, a link to a directory counts as the directory
+fn is_deletable(normalized: &Path) -> bool {
+    let is_backup = normalized.extension().map_or(false, |ext| ext == "bak");
+    normalized.is_file() && (is_backup || EXPORTED.read().contains(normalized))
+}
+
+fn is_allowed(normalized: &Path) -> bool {
+    roots().iter().any(|root| normalized.starts_with(root))
+        || GRANTED.read().iter().any(|granted| normalized.starts_with(granted))
+}
+
+fn is_installed(normalized: &Path) -> bool {
+    let exe = std::env::current_exe().ok();
+    let dir = exe.as_deref().and_then(Path::parent).and_then(|dir| dir.canonicalize().ok());
+    dir.map_or(false, |dir| normalized.starts_with(dir))
+}
+
+fn roots() -> Vec<PathBuf> {
+    let mut roots = vec![];
+    roots.extend(config::session_dir());
+    roots.extend(dialog::bioware_dir());
+    roots.extend(CONFIG.read().allowed_paths.iter().cloned());
+    #[cfg(debug_assertions)]
+    roots.extend(std::env::current_dir().ok());
+    roots.into_iter().filter_map(|root| root.canonicalize().ok()).collect()
+}
+
+// Canonicalizes the path, or its parent for files not created yet
+fn normalize(path: &Path) -> Result<PathBuf> {
+    if path.exists() {
+        return Ok(path.canonicalize()?);
+    }
+    let file_name = path.file_name().context("Invalid path")?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(parent.canonicalize()?.join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_write() -> Result<()> {
+        let exe = std::env::current_exe()?;
+        assert!(check(&exe).is_ok());
+        assert!(check_write(&exe).is_err());
+        let dll = exe.with_file_name("WebView2Loader.dll");
+        assert!(check_write(&dll).is_err());
+
+        // `..` is resolved before the roots are compared
+        let dir = std::env::temp_dir().join(format!("gameperf_access_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let escaped = normalize(&dir.join("..").join("outside.sav"))?;
+        assert_eq!(escaped, dir.canonicalize()?.parent().unwrap().join("outside.sav"));
+        std::fs::remove_dir(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_deletable() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("gameperf_delete_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("saves.bak"))?;
+        let (save, backup, export) =
+            (dir.join("save.pcsav"), dir.join("save.pcsav.bak"), dir.join("report.xlsx"));
+        for file in [&save, &backup, &export] {
+            std::fs::write(file, b"")?;
+        }
+        let dir = dir.canonicalize()?;
+
+        assert!(is_deletable(&backup.canonicalize()?));
+        assert!(!is_deletable(&save.canonicalize()?));
+        assert!(!is_deletable(&dir));
+        assert!(!is_deletable(&dir.join("saves.bak")));
+        assert!(!is_deletable(&export.canonicalize()?));
+        exported(&export);
+        assert!(is_deletable(&export.canonicalize()?));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
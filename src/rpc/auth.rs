@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rand::{distributions::Alphanumeric, Rng};
+use serde_json::Value;
+
+use crate::agent::Role;
+use crate::assets;
+use crate::config::CONFIG;
+
+use super::jsonrpc::{Request, RpcError, FORBIDDEN, UNAUTHORIZED};
+
+// Origins of the `tse` custom protocol depending on the platform webview
+const PROTOCOL_ORIGINS: &[&str] = &["tse://localhost", "https://tse.localhost"];
+// What an observer key may call: reads that change nothing and write no files. Everything else
+// takes a controller key or our own pages.
+const OBSERVER_METHODS: &[&str] = &[
+    "get_capture_state",
+    "get_front_app",
+    "get_foreground",
+    "get_low_impact",
+    "get_capture_options",
+    "get_process_tuning",
+    "get_clock_limits",
+    "get_overlay_settings",
+    "get_overlay_layout",
+    "get_report_schema",
+    "get_event_history",
+    "get_segment_stats",
+    "get_frametime_histogram",
+    "get_frametime_cdf",
+    "get_percentile_curve",
+    "get_frametime_heatmap",
+    "get_fps_series",
+    "list_sessions",
+    "list_report_templates",
+    "list_input_scripts",
+    "search_sessions",
+    "summarize_session",
+    "correlate_metrics",
+    "compare_segments",
+    "compare_sessions",
+    "compare_session_environments",
+    "subscribe",
+    "unsubscribe",
+    "acknowledge_messages",
+    "resync_messages",
+];
+
+lazy_static! {
+    // One per allowed origin, the origin of a request is the one its token was issued to
+    static ref TOKENS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+fn tokens() -> HashMap<String, String> {
+    let mut tokens = TOKENS.lock();
+    for origin in allowed_origins() {
+        tokens.entry(origin).or_insert_with(|| {
+            rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+        });
+    }
+    tokens.clone()
+}
+
+// Wraps the wry transport so every request carries `{ auth, args }` as params, only for our own
+// pages: each page keeps the token of its own origin and drops the others, a navigated-away page
+// never sees one.
+pub fn initialization_script() -> String {
+    format!(
+        r#"
+        (() => {{
+            const token = {tokens}[window.location.origin];
+            if (typeof token !== "string" || !window.external || !window.external.invoke) {{
+                return;
+            }}
+            const invoke = window.external.invoke;
+            const auth = Object.freeze({{ token: token }});
+            window.external.invoke = (message) => {{
+                const request = JSON.parse(message);
+                request.params = {{ auth: auth, args: request.params || [] }};
+                invoke(JSON.stringify(request));
+            }};
+        }})();
+        "#,
+        tokens = serde_json::to_string(&tokens()).unwrap_or_default(),
+    )
+}
+
+// Checks the auth envelope and replaces it by the actual params. The origin comes from the token,
+// an origin the page reports isn't trusted. Our pages control everything, other clients send one
+// of the agent's keys instead (`{ auth: { key } }`), read when the request comes in.
+pub fn authenticate(req: &mut Request) -> Result<Role, RpcError> {
+    let unauthorized = || RpcError::new(UNAUTHORIZED, "Unauthorized");
+
+    let mut envelope = match req.params.take() {
+        Some(Value::Object(envelope)) => envelope,
+        _ => return Err(unauthorized()),
+    };
+    let auth = envelope.remove("auth").ok_or_else(unauthorized)?;
+    let token = auth.get("token").and_then(Value::as_str).unwrap_or_default();
+    let role = if origin_of(token, &tokens()).is_some() {
+        Role::Controller
+    } else {
+        let key = auth.get("key").and_then(Value::as_str).unwrap_or_default();
+        let authorized = CONFIG.read().agent.authorize(key).map(|(_, role)| role);
+        authorized.ok_or_else(unauthorized)?
+    };
+
+    req.params = envelope.remove("args");
+    Ok(role)
+}
+
+pub fn permit(role: Role, method: &str) -> Result<(), RpcError> {
+    let required =
+        if OBSERVER_METHODS.contains(&method) { Role::Observer } else { Role::Controller };
+    if !role.allows(required) {
+        return Err(RpcError::new(FORBIDDEN, format!("{:?} keys can't call {}", role, method)));
+    }
+    Ok(())
+}
+
+// Plus the dev server's with `--dev-url`
+fn allowed_origins() -> Vec<String> {
+    let mut origins: Vec<String> =
+        PROTOCOL_ORIGINS.iter().map(|origin| origin.to_string()).collect();
+    origins.extend(assets::dev_origin());
+    origins
+}
+
+// Origins stop being allowed when the dev server goes away, their tokens stay issued
+fn origin_of<'a>(token: &str, tokens: &'a HashMap<String, String>) -> Option<&'a str> {
+    let allowed = allowed_origins();
+    let (origin, _) = tokens.iter().find(|(origin, issued)| {
+        constant_time_eq(token.as_bytes(), issued.as_bytes()) && allowed.contains(origin)
+    })?;
+    Some(origin)
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(auth: Value) -> Request {
+        Request {
+            id: Some(json!(1)),
+            method: "get_low_impact".into(),
+            params: Some(json!({ "auth": auth, "args": [true] })),
+        }
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let tokens = tokens();
+        let token = &tokens[PROTOCOL_ORIGINS[0]];
+        let mut req = request(json!({ "token": token }));
+        assert_eq!(authenticate(&mut req).unwrap(), Role::Controller);
+        assert_eq!(req.params, Some(json!([true])));
+
+        // A reported origin changes nothing, only the token counts
+        let forged = json!({ "token": "x".repeat(32), "origin": PROTOCOL_ORIGINS[0] });
+        assert!(authenticate(&mut request(forged)).is_err());
+        assert_eq!(origin_of(token, &tokens), Some(PROTOCOL_ORIGINS[0]));
+        assert!(authenticate(&mut request(json!({ "key": "" }))).is_err());
+    }
+
+    #[test]
+    fn test_refused() {
+        // No envelope at all, or the bare arguments of an unwrapped page
+        let mut bare = request(json!({}));
+        bare.params = Some(json!([true]));
+        assert_eq!(authenticate(&mut bare).unwrap_err().code, UNAUTHORIZED);
+        bare.params = None;
+        assert!(authenticate(&mut bare).is_err());
+
+        // A token issued to a dev server that went away
+        let mut tokens = tokens();
+        tokens.insert("http://localhost:5173".into(), "y".repeat(32));
+        assert_eq!(origin_of(&"y".repeat(32), &tokens), None);
+        assert_eq!(origin_of("", &tokens), None);
+    }
+
+    #[test]
+    fn test_permit() {
+        assert!(permit(Role::Observer, "get_capture_state").is_ok());
+        assert!(permit(Role::Observer, "start_capture").is_err());
+        assert!(permit(Role::Observer, "delete_file").is_err());
+        assert_eq!(permit(Role::Observer, "save_file").unwrap_err().code, FORBIDDEN);
+        assert!(permit(Role::Controller, "start_capture").is_ok());
+    }
+}
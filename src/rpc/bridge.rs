@@ -0,0 +1,76 @@
+use serde_json::{json, Value};
+
+use super::jsonrpc::Response;
+
+// Messages are handed to the `__gameperf_bridge` registered by init.js. Payloads only ever appear
+// as one JSON string literal that the page `JSON.parse`s, never as script code.
+pub fn custom_event_script(event: &str, detail: &Value) -> String {
+    deliver_script(&json!({ "kind": "custom_event", "name": event, "detail": detail }))
+}
+
+pub fn messages_script(messages: Vec<Value>) -> String {
+    deliver_script(&json!({ "kind": "messages", "messages": messages }))
+}
+
+// Published messages before `seq` are gone, the page continues from there
+pub fn reset_script(seq: u64) -> String {
+    deliver_script(&json!({ "kind": "messages_reset", "seq": seq }))
+}
+
+// Resolves the `window.rpc.call` promise of a deferred request
+pub fn rpc_response_script(response: &Response) -> String {
+    deliver_script(&json!({ "kind": "rpc_response", "response": response }))
+}
+
+fn deliver_script(message: &Value) -> String {
+    format!("window.__gameperf_bridge && window.__gameperf_bridge.deliver({});", encode(message))
+}
+
+// Encodes a value as a JS string literal containing its JSON text
+pub fn encode(value: &Value) -> String {
+    let json = value.to_string();
+    let literal = serde_json::to_string(&json).unwrap_or_else(|_| "\"null\"".into());
+    // Valid in JSON but line terminators in older JS engines, `<` avoids closing a <script>
+    literal.replace('\u{2028}', "\\u2028").replace('\u{2029}', "\\u2029").replace('<', "\\u003c")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: &[&str] = &[
+        r#""); alert(1); (""#,
+        "`${alert(1)}`",
+        "</script><script>alert(1)</script>",
+        "line\nbreak\r\u{2028}\u{2029}",
+        r#"\"); alert(1); //"#,
+        "'; alert(1); '",
+    ];
+
+    fn decode(literal: &str) -> Value {
+        let json: String = serde_json::from_str(literal).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_hostile_payloads() {
+        for hostile in HOSTILE {
+            let value = json!({ "name": hostile, "nested": [hostile] });
+            let literal = encode(&value);
+
+            assert!(literal.starts_with('"') && literal.ends_with('"'));
+            assert!(!literal.contains('\n') && !literal.contains('\u{2028}'));
+            assert!(!literal.contains('<'));
+            assert_eq!(decode(&literal), value);
+        }
+    }
+
+    #[test]
+    fn test_event_name_is_not_interpolated() {
+        let script = custom_event_script(r#"x", {}); alert(1); ("#, &json!(null));
+        let literal = script
+            .trim_start_matches("window.__gameperf_bridge && window.__gameperf_bridge.deliver(")
+            .trim_end_matches(");");
+        assert_eq!(decode(literal)["name"], r#"x", {}); alert(1); ("#);
+    }
+}
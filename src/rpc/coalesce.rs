@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use wry::webview::WebView;
+
+use crate::capture::health;
+
+use super::{bridge, subscription};
+
+// Roughly one animation frame
+const FLUSH_INTERVAL: Duration = Duration::from_millis(16);
+// When the page stops draining (busy or hidden window) the oldest messages are dropped and counted
+const MAX_PENDING: usize = 256;
+// Sent messages the page hasn't acknowledged are sent again after this, `evaluate_script` drops
+// scripts silently when the page is busy
+const REDELIVER_AFTER: Duration = Duration::from_secs(1);
+// Beyond this the page is gone or stuck, the oldest unacknowledged messages are dropped and
+// counted. A page that asks for them again is told to skip to the first one still kept.
+const MAX_UNACKED: usize = 4096;
+
+#[derive(Default)]
+pub struct Coalescer {
+    pending: Vec<(&'static str, Vec<Value>)>,
+    deadline: Option<Instant>,
+    outbox: Outbox,
+}
+
+// Every delivered message gets the next `seq`, the page acknowledges the last one it handled and
+// drops the ones it already has
+#[derive(Default)]
+struct Outbox {
+    last_seq: u64,
+    unacked: VecDeque<(u64, &'static str, Value)>,
+    sent_at: Option<Instant>,
+}
+
+impl Outbox {
+    fn send(&mut self, topic: &'static str, msg: Value, now: Instant) -> Value {
+        self.last_seq += 1;
+        let message = json!({ "seq": self.last_seq, "topic": topic, "msg": msg });
+        if self.unacked.len() >= MAX_UNACKED {
+            if let Some((_, topic, _)) = self.unacked.pop_front() {
+                health::live_dropped(topic, 1);
+            }
+        }
+        self.unacked.push_back((self.last_seq, topic, message.clone()));
+        self.sent_at.get_or_insert(now);
+        message
+    }
+
+    fn acknowledge(&mut self, seq: u64) {
+        while self.unacked.front().map_or(false, |(unacked, _, _)| *unacked <= seq) {
+            self.unacked.pop_front();
+        }
+        if self.unacked.is_empty() {
+            self.sent_at = None;
+        }
+    }
+
+    fn redelivery(&self) -> Option<Instant> {
+        self.sent_at.map(|sent_at| sent_at + REDELIVER_AFTER)
+    }
+
+    // Seq of the oldest message still kept, the next one when everything was acknowledged
+    fn first_seq(&self) -> u64 {
+        self.unacked.front().map_or(self.last_seq + 1, |(seq, _, _)| *seq)
+    }
+
+    // The page saw a gap after `seq`, 0 for a page that just loaded: unacknowledged messages were
+    // for the page before it. Returns where the page has to skip to, if it can't continue at `seq`.
+    fn resync(&mut self, seq: u64) -> Option<u64> {
+        if seq == 0 {
+            self.unacked.clear();
+            self.sent_at = None;
+            return Some(self.first_seq());
+        }
+        self.acknowledge(seq);
+        Some(self.first_seq()).filter(|first| *first > seq + 1)
+    }
+
+    // Everything not acknowledged, in order
+    fn resend(&mut self, now: Instant) -> Vec<Value> {
+        self.sent_at = Some(now).filter(|_| !self.unacked.is_empty());
+        self.unacked.iter().map(|(_, _, message)| message.clone()).collect()
+    }
+}
+
+impl Coalescer {
+    pub fn push(&mut self, topic: &'static str, payload: Value) {
+        match self.pending.iter_mut().find(|(t, _)| *t == topic) {
+            Some((_, payloads)) => {
+                if subscription::latest_only(topic) {
+                    payloads.clear();
+                } else if payloads.len() >= MAX_PENDING {
+                    payloads.remove(0);
+                    health::live_dropped(topic, 1);
+                }
+                payloads.push(payload);
+            }
+            None => self.pending.push((topic, vec![payload])),
+        }
+        if self.deadline.is_none() {
+            self.deadline = Some(Instant::now() + FLUSH_INTERVAL);
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.into_iter().chain(self.outbox.redelivery()).min()
+    }
+
+    pub fn flush_if_due(&mut self, webview: &WebView) {
+        let now = Instant::now();
+        match self.deadline {
+            Some(deadline) if deadline <= now => self.flush(webview),
+            _ => (),
+        }
+        match self.outbox.redelivery() {
+            Some(redelivery) if redelivery <= now => self.resend(webview),
+            _ => (),
+        }
+    }
+
+    // The page handled everything up to `seq`
+    pub fn acknowledge(&mut self, seq: u64) {
+        self.outbox.acknowledge(seq);
+    }
+
+    // The page saw a gap after `seq` or just loaded, see `Outbox::resync`
+    pub fn resync(&mut self, seq: u64, webview: &WebView) {
+        if let Some(first) = self.outbox.resync(seq) {
+            let _ = webview.evaluate_script(&bridge::reset_script(first));
+        }
+        self.resend(webview);
+    }
+
+    fn resend(&mut self, webview: &WebView) {
+        let messages = self.outbox.resend(Instant::now());
+        if !messages.is_empty() {
+            let _ = webview.evaluate_script(&bridge::messages_script(messages));
+        }
+    }
+
+    pub fn flush(&mut self, webview: &WebView) {
+        self.deadline = None;
+        let now = Instant::now();
+        let outbox = &mut self.outbox;
+        let batch: Vec<Value> = self
+            .pending
+            .drain(..)
+            .filter(|(topic, _)| subscription::is_active(topic))
+            .inspect(|(topic, payloads)| {
+                if !subscription::latest_only(topic) {
+                    health::live_delivered(topic, payloads.len() as u64);
+                }
+            })
+            .flat_map(|(topic, payloads)| payloads.into_iter().map(move |msg| (topic, msg)))
+            .map(|(topic, msg)| outbox.send(topic, msg, now))
+            .collect();
+        if batch.is_empty() {
+            return;
+        }
+
+        let _ = webview.evaluate_script(&bridge::messages_script(batch));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outbox() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        assert_eq!(outbox.redelivery(), None);
+        for i in 0..3 {
+            let message = outbox.send("samples.live", json!(i), now);
+            assert_eq!(message["seq"], i + 1);
+        }
+        assert_eq!(outbox.redelivery(), Some(now + REDELIVER_AFTER));
+
+        outbox.acknowledge(1);
+        let later = now + REDELIVER_AFTER;
+        let resent = outbox.resend(later);
+        assert_eq!(resent.iter().map(|message| message["seq"].clone()).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(outbox.redelivery(), Some(later + REDELIVER_AFTER));
+
+        outbox.acknowledge(3);
+        assert_eq!(outbox.redelivery(), None);
+        assert!(outbox.resend(later).is_empty());
+    }
+
+    #[test]
+    fn test_resync() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        for i in 0..MAX_UNACKED + 2 {
+            outbox.send("samples.live", json!(i), now);
+        }
+        // 1 and 2 were evicted, the page skips to 3 instead of asking again and again
+        assert_eq!(outbox.resync(1), Some(3));
+        assert_eq!(outbox.resend(now)[0]["seq"], 3);
+        assert_eq!(outbox.resync(3), None);
+
+        // A reloaded page gets nothing sent to the page before it
+        assert_eq!(outbox.resync(0), Some(MAX_UNACKED as u64 + 3));
+        assert!(outbox.resend(now).is_empty());
+        assert_eq!(outbox.redelivery(), None);
+        assert_eq!(outbox.send("samples.live", json!(0), now)["seq"], MAX_UNACKED + 3);
+    }
+}
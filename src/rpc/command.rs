@@ -1,16 +1,67 @@
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
+    thread,
 };
 
-use anyhow::{Error, Result};
+use anyhow::{bail, Context, Error, Result};
+use gameperf_core::report::{self, Report};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use wry::application::window::Window;
 
+use crate::agent::{self, AgentSettings, Peer};
+use crate::analysis::correlation::{self, Correlation};
+use crate::analysis::distribution::{self, CdfPoint, Heatmap, Histogram, PercentilePoint};
+use crate::analysis::normalize::{self, Basis, Comparison, Normalization};
+use crate::analysis::segment::{self, Segment, SegmentComparison};
+use crate::analysis::smoothing::{self, FpsSeries, Smoothing};
+use crate::analysis::summary::{self, Summary};
+use crate::analysis::Stats;
+use crate::base;
+use crate::base::recording::CaptureOptions;
+use crate::base::state::{self, CaptureState};
+use crate::capture::annotation::{self, ExternalEvent};
+use crate::capture::benchmark;
+use crate::capture::capabilities::{self, Capability};
+use crate::capture::clocks::{self, ClockLimits};
+use crate::capture::foreground::{self, Foreground};
+use crate::capture::ios::{self, DeviceInfo};
+use crate::capture::replay::{self, InputScriptInfo};
+use crate::capture::resolution;
+use crate::capture::system::{self, Difference};
+use crate::capture::tuning::{self, Tuning};
+use crate::capture::unity;
+use crate::capture::Marker;
+use crate::chart::{self, ImageFormat};
+use crate::config::{self, Config, CONFIG};
+use crate::database::{self, DatabaseInfo};
+use crate::discovery;
+use crate::history::{self, SaveDiff, SaveVersion};
+use crate::integrity;
+use crate::migrate;
+use crate::morph::{self, Format, HeadMorphInfo};
+use crate::overlay::{self, Layout};
+use crate::save::{self, SaveReport};
+use crate::selftest;
+use crate::session::anonymize::Redaction;
+use crate::session::share;
+use crate::session::template::{self, Rendered, TemplateInfo};
+use crate::session::{self, SessionInfo};
 use crate::util;
 
-use super::{dialog, Event, RpcUtils};
-use super::{base};
+use super::dialog::{self, Dialog};
+use super::event_log::{self, History};
+use super::{access, subscription, Event, RpcUtils};
+
+// Enough for a smooth curve at any chart size
+const DISTRIBUTION_POINTS: usize = 100;
+const HEATMAP_COLUMNS: usize = 200;
+// Samples, half a minute at the default rate
+const CORRELATION_WINDOW: usize = 30;
+const CHART_SIZE: (u32, u32) = (1200, 600);
+
 // Commands
 pub fn init(utils: &RpcUtils) {
     utils.window.set_visible(true);
@@ -57,6 +108,19 @@ pub fn download_and_install_update(utils: &RpcUtils) -> Result<()> {
     Ok(())
 }
 
+// Reinstalls the running version, e.g. after `tse_integrity_warning`
+#[cfg(target_os = "windows")]
+pub fn repair_installation(utils: &RpcUtils) -> Result<()> {
+    use crate::windows::auto_update::AUTO_UPDATE;
+
+    let proxy = utils.event_proxy.clone();
+    tokio::spawn(async move {
+        AUTO_UPDATE.repair(proxy).await;
+    });
+
+    Ok(())
+}
+
 #[cfg(not(target_os = "windows"))]
 pub fn check_for_update(_: &RpcUtils) -> Result<()> {
     Ok(())
@@ -67,78 +131,829 @@ pub fn download_and_install_update(_: &RpcUtils) -> Result<()> {
     Ok(())
 }
 
+#[cfg(not(target_os = "windows"))]
+pub fn repair_installation(_: &RpcUtils) -> Result<()> {
+    bail!("Reinstall the package to repair it")
+}
+
+// Result of the startup integrity check, None while it runs (or in debug builds)
+pub fn get_integrity_report(_: &RpcUtils) -> Result<Option<integrity::Report>> {
+    Ok(integrity::last_report())
+}
+
+// What will and won't work with this PC and device, for the first-run checklist. Seconds of adb
+// calls, answered once done.
+pub fn probe_capabilities(_: &Window) -> Dialog<Result<Vec<Capability>>> {
+    Box::pin(async { Ok(capabilities::probe()) })
+}
+
+// Tethered iPhones and iPads, captured through `ci` plans with `ios` set
+pub fn list_ios_devices(_: &RpcUtils) -> Result<Vec<DeviceInfo>> {
+    ios::list()
+}
+
+// Captures the notification shade under load and checks frame times, sensors and exports, the
+// report comes with `tse_self_test_finished`
+pub fn run_self_test(utils: &RpcUtils) -> Result<()> {
+    let proxy = utils.event_proxy.clone();
+    thread::spawn(move || {
+        let report = selftest::run_all();
+        log::info!("self-test: {:?}", report);
+        let _ =
+            proxy.send_event(Event::DispatchCustomEvent("tse_self_test_finished", json!(report)));
+    });
+    Ok(())
+}
+
+// Upgrades of older config and session formats done at startup, see `tse_migration_finished`
+pub fn get_migration_report(_: &RpcUtils) -> Result<migrate::Report> {
+    Ok(migrate::report())
+}
+
 pub fn open_external_link(_: &RpcUtils, link: PathBuf) -> Result<()> {
+    let is_url = link
+        .to_str()
+        .map(|link| ["https://", "http://", "mailto:"].iter().any(|s| link.starts_with(s)))
+        .unwrap_or_default();
+    let link = if is_url { link } else { access::check(&link)? };
     opener::open(link).map_err(Error::from)
 }
 
 pub fn save_file(_: &RpcUtils, rpc_file: RpcFile) -> Result<()> {
-    write_file(rpc_file)
-}
+    let RpcFile { path, file } = rpc_file;
+    let path = access::check_write(&path)?;
+    let bytes = file.decode()?;
+    let previous = fs::read(&path).ok();
+    write_with_backup(&path, &bytes)?;
 
-pub fn open_save(utils: &RpcUtils, last_dir: bool) -> Result<Option<RpcFile>> {
-    match dialog::open_save(utils.window, last_dir) {
-        Some(path) => open_file(path).map(Some),
-        None => Ok(None),
+    // History is a safety net, it must not fail the save itself
+    if let Err(err) = history::record(&path, previous.as_deref(), &bytes) {
+        log::error!("save history: {}", err);
     }
+    Ok(())
+}
+
+pub fn list_save_versions(_: &RpcUtils, path: PathBuf) -> Result<Vec<SaveVersion>> {
+    access::check(&path)?;
+    history::list(&path)
+}
+
+pub fn diff_save_versions(_: &RpcUtils, params: DiffSaveVersionsParams) -> Result<SaveDiff> {
+    access::check(&params.path)?;
+    history::diff(&params.path, &params.a, &params.b)
+}
+
+// A bare `last_dir` flag opens a single save, full params may select several files
+pub fn open_save(window: &Window, params: OpenSaveParams) -> Dialog<Result<OpenedFiles>> {
+    let params = match params {
+        OpenSaveParams::LastDir(last_dir) => DialogParams { last_dir, ..Default::default() },
+        OpenSaveParams::Dialog(params) => params,
+    };
+    let multiple = params.multiple;
+    let picked = dialog::open_save(window, params);
+
+    Box::pin(async move {
+        let mut files = vec![];
+        for path in picked.await {
+            access::grant(&path);
+            files.push(open_file(path)?);
+        }
+
+        if multiple {
+            Ok(OpenedFiles::Multiple(files))
+        } else {
+            Ok(OpenedFiles::Single(files.pop()))
+        }
+    })
+}
+
+pub fn save_save_dialog(window: &Window, params: DialogParams) -> Dialog<Result<Option<PathBuf>>> {
+    granted(dialog::save_save(window, params))
+}
+
+// Access to a path the user picked
+fn granted(picked: Dialog<Option<PathBuf>>) -> Dialog<Result<Option<PathBuf>>> {
+    Box::pin(async move {
+        let result = picked.await;
+        if let Some(path) = &result {
+            access::grant(path);
+        }
+        Ok(result)
+    })
 }
 
-pub fn save_save_dialog(utils: &RpcUtils, params: DialogParams) -> Result<Option<PathBuf>> {
-    let result = dialog::save_save(utils.window, params);
-    Ok(result)
+pub fn verify_save(_: &RpcUtils, params: VerifySaveParams) -> Result<SaveReport> {
+    let VerifySaveParams { path, repair } = params;
+    let path = if repair { access::check_write(&path)? } else { access::check(&path)? };
+    let mut bytes = fs::read(&path)?;
+    let report = save::verify(&path, &bytes);
+
+    if repair && save::repair_checksum(&path, &mut bytes)? {
+        write_with_backup(&path, &bytes)?;
+        let mut report = save::verify(&path, &bytes);
+        report.repaired = true;
+        return Ok(report);
+    }
+    Ok(report)
 }
 
 pub fn reload_save(_: &RpcUtils, path: PathBuf) -> Result<RpcFile> {
+    access::check(&path)?;
     open_file(path)
 }
 
-pub fn import_head_morph(utils: &RpcUtils) -> Result<Option<RpcFile>> {
-    match dialog::import_head_morph(utils.window) {
-        Some(path) => open_file(path).map(Some),
-        None => Ok(None),
+pub fn import_head_morph(window: &Window) -> Dialog<Result<Option<RpcFile>>> {
+    let picked = dialog::import_head_morph(window);
+    Box::pin(async move {
+        match picked.await {
+            Some(path) => {
+                access::grant(&path);
+                let bytes = fs::read(&path)?;
+                morph::validate(&path, &bytes)
+                    .with_context(|| format!("Invalid head morph: {}", path.display()))?;
+                Ok(Some(RpcFile { path, file: Base64File::encode(&bytes) }))
+            }
+            None => Ok(None),
+        }
+    })
+}
+
+pub fn export_all_head_morphs(
+    window: &Window,
+    params: ExportHeadMorphsParams,
+) -> Dialog<Result<Option<ExportedHeadMorphs>>> {
+    let picked = dialog::pick_export_directory(window);
+    Box::pin(async move {
+        match picked.await {
+            Some(directory) => write_head_morphs(directory, params).map(Some),
+            None => Ok(None),
+        }
+    })
+}
+
+fn write_head_morphs(
+    directory: PathBuf,
+    params: ExportHeadMorphsParams,
+) -> Result<ExportedHeadMorphs> {
+    access::grant(&directory);
+
+    let mut files = vec![];
+    for NamedHeadMorph { name, file } in params.morphs {
+        let bytes = file.decode()?;
+        let format = morph::detect(Path::new(&name), &bytes)?;
+        morph::validate(Path::new(&name), &bytes)
+            .with_context(|| format!("Invalid head morph: {}", name))?;
+
+        let stem = sanitize_file_name(&name);
+        let mut path = directory.join(&stem).with_extension(format.extension());
+        let mut n = 2;
+        while path.exists() || files.contains(&path) {
+            path = directory.join(format!("{}_{}", stem, n)).with_extension(format.extension());
+            n += 1;
+        }
+        fs::write(&path, bytes)?;
+        files.push(path);
     }
+
+    Ok(ExportedHeadMorphs { directory, files })
 }
 
-pub fn export_head_morph_dialog(utils: &RpcUtils) -> Result<Option<PathBuf>> {
-    let result = dialog::export_head_morph(utils.window);
-    Ok(result)
+pub fn validate_head_morph(_: &RpcUtils, rpc_file: RpcFile) -> Result<HeadMorphInfo> {
+    let RpcFile { path, file } = rpc_file;
+    morph::validate(&path, &file.decode()?)
+}
+
+pub fn convert_head_morph(_: &RpcUtils, params: ConvertHeadMorphParams) -> Result<RpcFile> {
+    let target = params.target.context("target format required")?;
+    let RpcFile { path, file } = params.file;
+    let converted = morph::convert(&path, &file.decode()?, target)?;
+    let path = path.with_extension(target.extension());
+    Ok(RpcFile { path, file: Base64File::encode(&converted) })
+}
+
+pub fn export_head_morph_dialog(window: &Window) -> Dialog<Result<Option<PathBuf>>> {
+    granted(dialog::export_head_morph(window))
 }
 
 pub fn load_database(_: &RpcUtils, path: PathBuf) -> Result<RpcFile> {
-    #[cfg(not(debug_assertions))]
-    let path = std::env::current_exe()?.parent().map(|parent| parent.join(&path)).unwrap_or(path);
+    let path = database::resolve(path);
+    access::check(&path)?;
+    // Watched from now on
+    database::register(&database::name_of(&path), path.clone());
+    open_file(path)
+}
+
+pub fn register_database(
+    _: &RpcUtils,
+    params: RegisterDatabaseParams,
+) -> Result<Vec<DatabaseInfo>> {
+    let path = database::resolve(params.path);
+    access::check(&path)?;
+    database::register(&params.name, path);
+    Ok(database::list())
+}
+
+pub fn list_databases(_: &RpcUtils) -> Result<Vec<DatabaseInfo>> {
+    Ok(database::list())
+}
 
+pub fn query_database(_: &RpcUtils, params: QueryDatabaseParams) -> Result<Option<Value>> {
+    access::check(&database::path_of(&params.db)?)?;
+    database::query(&params.db, &params.key)
+}
+
+pub fn list_database_keys(_: &RpcUtils, db: String) -> Result<Vec<String>> {
+    access::check(&database::path_of(&db)?)?;
+    database::keys(&db)
+}
+
+pub fn reload_database(_: &RpcUtils, name: String) -> Result<RpcFile> {
+    let path = database::path_of(&name)?;
+    access::check(&path)?;
     open_file(path)
 }
 
+pub fn pick_directory(window: &Window, params: DialogParams) -> Dialog<Result<Option<PathBuf>>> {
+    granted(dialog::pick_directory(window, params))
+}
+
+pub fn list_sessions(_: &RpcUtils) -> Result<Vec<SessionInfo>> {
+    session::list()
+}
+
+// Recorded settings (Game Mode, HAGS, device build...) that differ between the sessions
+pub fn compare_session_environments(_: &RpcUtils, ids: Vec<String>) -> Result<Vec<Difference>> {
+    let recordings = ids.iter().map(|id| session::load(id)).collect::<Result<Vec<_>>>()?;
+    let snapshots: Vec<_> = recordings.iter().map(|r| r.system.as_ref()).collect();
+    Ok(system::differences(&snapshots))
+}
+
+pub fn delete_session(_: &RpcUtils, params: DeleteSessionParams) -> Result<Vec<SessionInfo>> {
+    session::delete(&params.id, use_trash(params.to_trash))?;
+    session::list()
+}
+
+// Name, tags and notes
+pub fn update_session(_: &RpcUtils, params: UpdateSessionParams) -> Result<SessionInfo> {
+    session::update(&params.id, params.changes)
+}
+
+// Two new sessions, cut at the closest marker when one is near `at_ms`
+pub fn split_session(_: &RpcUtils, params: SplitSessionParams) -> Result<Vec<SessionInfo>> {
+    session::split(&params.id, params.at_ms)
+}
+
+pub fn merge_sessions(_: &RpcUtils, ids: Vec<String>) -> Result<SessionInfo> {
+    session::merge(&ids)
+}
+
+pub fn set_session_markers(_: &RpcUtils, params: SessionMarkersParams) -> Result<Vec<Segment>> {
+    session::set_markers(&params.id, params.markers)?;
+    Ok(segment::segments(&session::load(&params.id)?))
+}
+
+// Custom events from the game or its tools, into a stored session or the running capture.
+// Returns how many were kept, events from before the start are dropped.
+pub fn ingest_events(_: &RpcUtils, params: IngestEventsParams) -> Result<usize> {
+    match &params.session {
+        Some(id) => session::annotate(id, &params.events),
+        None => annotation::send(params.events),
+    }
+}
+
+// Unity FrameTimingManager timings recorded along the session, see `unity::parse_csv`
+pub fn import_unity_timings(_: &RpcUtils, params: UnityTimingsParams) -> Result<()> {
+    let path = access::check(&params.path)?;
+    let csv = fs::read_to_string(&path)?;
+    session::set_engine(&params.id, |recording| unity::parse_csv(&csv, recording.started_at * 1000))
+}
+
+pub fn export_session(_: &RpcUtils, params: ExportSessionParams) -> Result<()> {
+    let path = access::check_write(&params.path)?;
+    session::export(&params.id, &path, params.anonymize)?;
+    access::exported(&path);
+    Ok(())
+}
+
+// Summary, comparison and sample sheets for spreadsheet users
+pub fn export_xlsx(_: &RpcUtils, params: ExportXlsxParams) -> Result<()> {
+    let path = access::check_write(&params.path)?;
+    session::xlsx::export(&params.session_ids, &path)?;
+    access::exported(&path);
+    Ok(())
+}
+
+// Every value `export_session` with `anonymize` would strip or rewrite, before and after
+pub fn preview_anonymized_metadata(_: &RpcUtils, id: String) -> Result<Vec<Redaction>> {
+    session::preview_anonymized(&id)
+}
+
+// Encrypted and uploaded in the background, the link (with its key) comes with
+// `tse_session_shared`. Anonymized unless `keep_metadata` is set.
+pub fn share_session(utils: &RpcUtils, params: ShareSessionParams) -> Result<()> {
+    let proxy = utils.event_proxy.clone();
+    tokio::spawn(async move {
+        let payload = match share::share(&params.id, params.keep_metadata).await {
+            Ok(link) => json!({ "id": params.id, "link": link }),
+            Err(err) => json!({ "id": params.id, "error": err.to_string() }),
+        };
+        let _ = proxy.send_event(Event::DispatchCustomEvent("tse_session_shared", payload));
+    });
+    Ok(())
+}
+
+// Downloaded in the background, the new session comes with `tse_shared_session_imported`
+pub fn import_shared_session(utils: &RpcUtils, link: String) -> Result<()> {
+    let proxy = utils.event_proxy.clone();
+    tokio::spawn(async move {
+        let payload = match share::import(&link).await {
+            Ok(session) => json!({ "session": session }),
+            Err(err) => json!({ "error": err.to_string() }),
+        };
+        let _ =
+            proxy.send_event(Event::DispatchCustomEvent("tse_shared_session_imported", payload));
+    });
+    Ok(())
+}
+
+// Distributions are computed here, charts don't need every frame time
+pub fn get_frametime_histogram(_: &RpcUtils, params: HistogramParams) -> Result<Histogram> {
+    let recording = session::load(&params.id)?;
+    distribution::histogram(&recording.frametimes, params.bin_width_ms.unwrap_or(1.0))
+}
+
+pub fn get_frametime_cdf(_: &RpcUtils, params: DistributionParams) -> Result<Vec<CdfPoint>> {
+    let recording = session::load(&params.id)?;
+    distribution::cdf(&recording.frametimes, params.points.unwrap_or(DISTRIBUTION_POINTS))
+}
+
+pub fn get_percentile_curve(
+    _: &RpcUtils,
+    params: DistributionParams,
+) -> Result<Vec<PercentilePoint>> {
+    let recording = session::load(&params.id)?;
+    let points = params.points.unwrap_or(DISTRIBUTION_POINTS);
+    distribution::percentile_curve(&recording.frametimes, points)
+}
+
+pub fn get_frametime_heatmap(_: &RpcUtils, params: HeatmapParams) -> Result<Heatmap> {
+    let recording = session::load(&params.id)?;
+    let columns = params.columns.unwrap_or(HEATMAP_COLUMNS);
+    distribution::heatmap(&recording.frametimes, columns, params.frametime_bin_ms.unwrap_or(1.0))
+}
+
+// Raw and smoothed, thinned to what a chart can show
+pub fn get_fps_series(_: &RpcUtils, params: FpsSeriesParams) -> Result<FpsSeries> {
+    let recording = session::load(&params.id)?;
+    let smoothing = params.smoothing.unwrap_or_else(|| CONFIG.read().chart_smoothing);
+    let points = params.points.unwrap_or(smoothing::MAX_POINTS);
+    smoothing::fps_series(&recording.frametimes, smoothing, points)
+}
+
+// `frametime` stands for the frames between samples, see `correlation::FRAMETIME`
+pub fn correlate_metrics(_: &RpcUtils, params: CorrelateParams) -> Result<Correlation> {
+    let recording = session::load(&params.id)?;
+    let window = params.window.unwrap_or(CORRELATION_WINDOW);
+    correlation::correlate(&recording, &params.metric_a, &params.metric_b, window)
+}
+
+// Server-side image of one series, e.g. to copy or attach without the webview
+pub fn render_chart(_: &RpcUtils, params: RenderChartParams) -> Result<Base64File> {
+    let recording = session::load(&params.id)?;
+    let series = params.series.as_deref().unwrap_or("fps");
+    let size = match (params.width, params.height) {
+        (Some(width), Some(height)) => (width, height),
+        _ => CHART_SIZE,
+    };
+    let title = recording.package.clone();
+    let image = chart::render(&recording, &title, series, params.format.unwrap_or_default(), size)?;
+    Ok(Base64File::encode(&image))
+}
+
+// Bottleneck, throttling and stutters in a few words, also the `summary` of reports
+pub fn summarize_session(_: &RpcUtils, id: String) -> Result<Summary> {
+    let recording = session::load(&id)?;
+    Ok(summary::summarize(&recording, &Stats::compute(&recording)))
+}
+
+// Stats per marker-delimited scene
+pub fn get_segment_stats(_: &RpcUtils, id: String) -> Result<Vec<Segment>> {
+    Ok(segment::segments(&session::load(&id)?))
+}
+
+// FPS stats of each session against what its device spent on it, see `normalize`. Flagged when
+// the sessions didn't render at the same resolution.
+pub fn compare_sessions(_: &RpcUtils, params: CompareSessionsParams) -> Result<Comparison> {
+    let mut sessions = vec![];
+    let mut resolutions = vec![];
+    for id in &params.ids {
+        let recording = session::load(id)?;
+        let stats = Stats::compute(&recording);
+        let given = params.overrides.get(id).copied().unwrap_or_default();
+        let basis = given.or(Basis::measure(&recording, &stats));
+        sessions.push((stats, basis));
+        resolutions.push(resolution::sizes(&recording));
+    }
+    let normalized = normalize::normalize(&sessions, params.normalization.unwrap_or_default());
+    Ok(Comparison::new(normalized, resolutions))
+}
+
+// Scenes matched by label across sessions
+pub fn compare_segments(_: &RpcUtils, ids: Vec<String>) -> Result<Vec<SegmentComparison>> {
+    let recordings = ids.iter().map(|id| session::load(id)).collect::<Result<Vec<_>>>()?;
+    Ok(segment::compare(&recordings))
+}
+
+// Layout guaranteed across releases, see `get_report_schema`. JSON is the only format so far.
+pub fn generate_report(_: &RpcUtils, params: GenerateReportParams) -> Result<Report> {
+    let format = params.format.as_deref().unwrap_or("json");
+    if format != "json" {
+        bail!("Unsupported report format {}, expected json", format);
+    }
+    session::report(&params.id)
+}
+
+// JSON Schema of what `generate_report` returns
+pub fn get_report_schema(_: &RpcUtils) -> Result<Value> {
+    report::schema()
+}
+
+// Built-in and user templates, see `session::template`
+pub fn list_report_templates(_: &RpcUtils) -> Result<Vec<TemplateInfo>> {
+    template::list()
+}
+
+pub fn render_report(_: &RpcUtils, params: RenderReportParams) -> Result<Rendered> {
+    template::render(&session::report(&params.id)?, &params.template)
+}
+
+// Compaction otherwise runs hourly in the background
+pub fn compact_now(_: &RpcUtils) -> Result<Vec<SessionInfo>> {
+    session::compact_expired()
+}
+
+pub fn search_sessions(_: &RpcUtils, query: session::Query) -> Result<Vec<SessionInfo>> {
+    session::search(&query)
+}
+
+pub fn export_settings(_: &RpcUtils, path: PathBuf) -> Result<()> {
+    let path = access::check_write(&path)?;
+    config::export_settings(&path)?;
+    access::exported(&path);
+    Ok(())
+}
+
+pub fn import_settings(_: &RpcUtils, path: PathBuf) -> Result<Config> {
+    config::import_settings(&access::check(&path)?)
+}
+
+// Backups and files exported since the app started, see `access::check_delete`
+pub fn delete_file(_: &RpcUtils, params: DeleteFileParams) -> Result<()> {
+    let path = access::check_delete(&params.path)?;
+    util::delete_path(&path, use_trash(params.to_trash))
+}
+
+// Runs in the background, each file is reported with `tse_session_import_progress`
+pub fn import_sessions_from_directory(utils: &RpcUtils, path: PathBuf) -> Result<ImportStarted> {
+    let path = access::check(&path)?;
+    let files = session::import::scan(&path);
+    let total = files.len();
+
+    let proxy = utils.event_proxy.clone();
+    thread::spawn(move || {
+        let (mut imported, mut skipped, mut failed) = (0, 0, 0);
+        for (i, file) in files.into_iter().enumerate() {
+            let mut progress = match session::import_file(&file) {
+                Ok(Some(session)) => {
+                    imported += 1;
+                    json!({ "status": "imported", "session": session })
+                }
+                Ok(None) => {
+                    skipped += 1;
+                    json!({ "status": "skipped" })
+                }
+                Err(err) => {
+                    failed += 1;
+                    log::warn!("import {}: {}", file.display(), err);
+                    json!({ "status": "failed", "error": err.to_string() })
+                }
+            };
+            progress["path"] = json!(file);
+            progress["done"] = json!(i + 1);
+            progress["total"] = json!(total);
+            let _ = proxy
+                .send_event(Event::DispatchCustomEvent("tse_session_import_progress", progress));
+        }
+        let _ = proxy.send_event(Event::DispatchCustomEvent(
+            "tse_session_import_finished",
+            json!({ "imported": imported, "skipped": skipped, "failed": failed }),
+        ));
+    });
+
+    Ok(ImportStarted { total })
+}
+
+// Explicit user confirmation for paths outside the allow-list
+pub fn grant_path_access(_: &Window, path: PathBuf) -> Dialog<Result<bool>> {
+    let confirmation = access::check(&path).err().map(|_| dialog::confirm_path_access(&path));
+    Box::pin(async move {
+        let confirmation = match confirmation {
+            Some(confirmation) => confirmation,
+            None => return Ok(true),
+        };
+        let granted = confirmation.await;
+        if granted {
+            access::grant(&path);
+        }
+        Ok(granted)
+    })
+}
+
 // Utils
-fn open_file(path: PathBuf) -> Result<RpcFile> {
+fn use_trash(to_trash: Option<bool>) -> bool {
+    to_trash.unwrap_or_else(|| !CONFIG.read().permanently_delete)
+}
+
+pub fn open_file(path: PathBuf) -> Result<RpcFile> {
     let file = fs::read(path.canonicalize()?)?;
-    let unencoded_size = file.len();
-    let base64 = base64::encode(file);
-    Ok(RpcFile { path, file: Base64File { unencoded_size, base64 } })
+    Ok(RpcFile { path, file: Base64File::encode(&file) })
 }
 
-fn write_file(rpc_file: RpcFile) -> Result<()> {
-    let RpcFile { path, file } = rpc_file;
+fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    if name.is_empty() {
+        "head_morph".into()
+    } else {
+        name
+    }
+}
 
+fn write_with_backup(path: &Path, bytes: &[u8]) -> Result<()> {
     // Backup if file exists
     if path.exists() {
         if let Some(ext) = path.extension() {
             let mut ext = ext.to_owned();
             ext.push(".bak");
-            let to = Path::with_extension(&path, ext);
-            fs::copy(&path, to)?;
+            let to = Path::with_extension(path, ext);
+            fs::copy(path, to)?;
         }
     }
-    fs::write(path, file.decode()?)?;
+    fs::write(path, bytes)?;
 
     Ok(())
 }
 
 #[derive(Deserialize, Default)]
+#[serde(default)]
 pub struct DialogParams {
     pub path: PathBuf,
+    pub file_name: Option<String>,
     pub filters: Vec<(String, Vec<String>)>,
+    pub preset: Option<dialog::Preset>,
+    pub multiple: bool,
+    // Let the OS reopen the last used directory instead of the BioWare one
+    pub last_dir: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum OpenSaveParams {
+    LastDir(bool),
+    Dialog(DialogParams),
+}
+
+impl Default for OpenSaveParams {
+    fn default() -> Self {
+        OpenSaveParams::LastDir(false)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum OpenedFiles {
+    Single(Option<RpcFile>),
+    Multiple(Vec<RpcFile>),
+}
+
+// `to_trash` defaults to the `permanently_delete` setting
+#[derive(Deserialize, Default)]
+pub struct DeleteSessionParams {
+    pub id: String,
+    #[serde(default)]
+    pub to_trash: Option<bool>,
+}
+
+// `format` defaults to json
+#[derive(Deserialize, Default)]
+pub struct GenerateReportParams {
+    pub id: String,
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ExportXlsxParams {
+    pub session_ids: Vec<String>,
+    pub path: PathBuf,
+}
+
+// `series` defaults to fps, `format` to png, the size to `CHART_SIZE`
+#[derive(Deserialize, Default)]
+pub struct RenderChartParams {
+    pub id: String,
+    #[serde(default)]
+    pub series: Option<String>,
+    #[serde(default)]
+    pub format: Option<ImageFormat>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct RenderReportParams {
+    pub id: String,
+    pub template: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct UpdateSessionParams {
+    pub id: String,
+    #[serde(flatten)]
+    pub changes: session::Changes,
+}
+
+// `bin_width_ms` defaults to 1 ms
+#[derive(Deserialize, Default)]
+pub struct HistogramParams {
+    pub id: String,
+    #[serde(default)]
+    pub bin_width_ms: Option<f64>,
+}
+
+// `points` defaults to `DISTRIBUTION_POINTS`
+#[derive(Deserialize, Default)]
+pub struct DistributionParams {
+    pub id: String,
+    #[serde(default)]
+    pub points: Option<usize>,
+}
+
+// `columns` defaults to `HEATMAP_COLUMNS`, `frametime_bin_ms` to 1 ms
+#[derive(Deserialize, Default)]
+pub struct HeatmapParams {
+    pub id: String,
+    #[serde(default)]
+    pub columns: Option<usize>,
+    #[serde(default)]
+    pub frametime_bin_ms: Option<f64>,
+}
+
+// `smoothing` defaults to the configured `chart_smoothing`, `points` to `smoothing::MAX_POINTS`
+#[derive(Deserialize, Default)]
+pub struct FpsSeriesParams {
+    pub id: String,
+    #[serde(default)]
+    pub smoothing: Option<Smoothing>,
+    #[serde(default)]
+    pub points: Option<usize>,
+}
+
+// Raw numbers unless `normalization` is set. `overrides` by session id, for what wasn't recorded
+// such as a desktop's resolution.
+#[derive(Deserialize, Default)]
+pub struct CompareSessionsParams {
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub normalization: Option<Normalization>,
+    #[serde(default)]
+    pub overrides: BTreeMap<String, Basis>,
+}
+
+// `window` defaults to `CORRELATION_WINDOW` samples
+#[derive(Deserialize, Default)]
+pub struct CorrelateParams {
+    pub id: String,
+    pub metric_a: String,
+    pub metric_b: String,
+    #[serde(default)]
+    pub window: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct SplitSessionParams {
+    pub id: String,
+    // From the start of the session
+    pub at_ms: u64,
+}
+
+#[derive(Deserialize, Default)]
+pub struct SessionMarkersParams {
+    pub id: String,
+    pub markers: Vec<Marker>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct IngestEventsParams {
+    // The running capture when None
+    #[serde(default)]
+    pub session: Option<String>,
+    pub events: Vec<ExternalEvent>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ExportSessionParams {
+    pub id: String,
+    pub path: PathBuf,
+    // Without user and machine names, paths and serials
+    #[serde(default)]
+    pub anonymize: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ShareSessionParams {
+    pub id: String,
+    #[serde(default)]
+    pub keep_metadata: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct UnityTimingsParams {
+    pub id: String,
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize, Default)]
+pub struct DeleteFileParams {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub to_trash: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct OverlayLayoutParams {
+    pub profile: String,
+    pub layout: Layout,
+}
+
+#[derive(Serialize)]
+pub struct ImportStarted {
+    pub total: usize,
+}
+
+#[derive(Deserialize, Default)]
+pub struct RegisterDatabaseParams {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize, Default)]
+pub struct DiffSaveVersionsParams {
+    pub path: PathBuf,
+    pub a: String,
+    pub b: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct VerifySaveParams {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct NamedHeadMorph {
+    pub name: String,
+    pub file: Base64File,
+}
+
+// Morphs extracted by the frontend from the currently open save
+#[derive(Deserialize, Default)]
+pub struct ExportHeadMorphsParams {
+    pub morphs: Vec<NamedHeadMorph>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedHeadMorphs {
+    pub directory: PathBuf,
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct ConvertHeadMorphParams {
+    pub file: RpcFile,
+    pub target: Option<Format>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct QueryDatabaseParams {
+    pub db: String,
+    pub key: String,
 }
 
 #[derive(Deserialize, Serialize, Default)]
@@ -154,36 +969,286 @@ pub struct Base64File {
 }
 
 impl Base64File {
+    pub fn encode(bytes: &[u8]) -> Self {
+        Base64File { unencoded_size: bytes.len(), base64: base64::encode(bytes) }
+    }
+
+    // The size comes from the page, it's only checked against what was decoded
     pub fn decode(self) -> Result<Vec<u8>> {
-        let mut vec = vec![0; self.unencoded_size];
-        base64::decode_config_slice(self.base64, base64::STANDARD, &mut vec)?;
-        Ok(vec)
+        let bytes = base64::decode_config(self.base64, base64::STANDARD)?;
+        if bytes.len() != self.unencoded_size {
+            bail!("Corrupted file: {} bytes, expected {}", bytes.len(), self.unencoded_size);
+        }
+        Ok(bytes)
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct StartCaptureArgs {
-  name: String,
+    name: String,
 }
 
-pub fn start_capture(utils: &RpcUtils, args: StartCaptureArgs) -> Result<String>{
+pub fn start_capture(utils: &RpcUtils, args: StartCaptureArgs) -> Result<String> {
     log::info!("start_capture {:?}......", args);
-    // check 
-    let topapp = util::current_app()?;
-    if topapp != args.name {
-        return Ok("结束采集(请打开游戏)".into())
+    // check
+    // The game may be on another display than the one in front, see `foreground`
+    if !foreground::is_target(&args.name)? {
+        return Ok("结束采集(请打开游戏)".into());
+    }
+    if state::current().is_running() {
+        bail!("A capture is already running");
+    }
+    if agent::has_peers() {
+        agent::synced(Some(args.name), utils.tx.clone(), utils.event_proxy.clone());
+    } else {
+        let _ = utils.tx.send(base::ChannelMsg::StartCapture(args.name));
     }
-    log::info!("{:?}", topapp);
-    let _ = utils.tx.send(base::ChannelMsg::StartCapture(args.name));
     Ok("结束采集".into())
 }
 
 pub fn stop_capture(utils: &RpcUtils) -> Result<String> {
-    let _ = utils.tx.send(base::ChannelMsg::StopCapture);
+    if agent::has_peers() {
+        agent::synced(None, utils.tx.clone(), utils.event_proxy.clone());
+    } else {
+        let _ = utils.tx.send(base::ChannelMsg::StopCapture);
+    }
     log::info!("stop_capture ......");
     Ok("开始采集".into())
 }
 
-pub fn get_front_app(rpc: &RpcUtils) -> Result<String> {
-    util::current_app()    
-}
\ No newline at end of file
+// Capture starts and stops with the profile's benchmark rule, progress is sent as `tse_benchmark`
+pub fn arm_benchmark(utils: &RpcUtils, profile: String) -> Result<benchmark::Rule> {
+    let (package, rule) = {
+        let config = CONFIG.read();
+        let settings = config.profiles.get(&profile);
+        let settings = settings.with_context(|| format!("Unknown profile: {}", profile))?;
+        match (&settings.package, &settings.benchmark) {
+            (Some(package), Some(rule)) => (package.clone(), rule.clone()),
+            _ => bail!("Profile `{}` needs a package and a benchmark rule", profile),
+        }
+    };
+    rule.validate()?;
+    if state::current().is_running() {
+        bail!("A capture is already running");
+    }
+    let _ = utils.tx.send(base::ChannelMsg::ArmBenchmark(package, rule.clone()));
+    Ok(rule)
+}
+
+pub fn disarm_benchmark(utils: &RpcUtils) -> Result<()> {
+    let _ = utils.tx.send(base::ChannelMsg::DisarmBenchmark);
+    Ok(())
+}
+
+// Device inputs (touch, keys, gamepads) until `stop_input_recording`
+pub fn start_input_recording(_: &RpcUtils) -> Result<()> {
+    replay::start_recording()
+}
+
+pub fn stop_input_recording(_: &RpcUtils, name: String) -> Result<InputScriptInfo> {
+    replay::stop_recording(&name)
+}
+
+pub fn list_input_scripts(_: &RpcUtils) -> Result<Vec<InputScriptInfo>> {
+    replay::list()
+}
+
+pub fn delete_input_script(_: &RpcUtils, name: String) -> Result<()> {
+    replay::delete(&name)
+}
+
+// Plays the recorded inputs with their timing, e.g. right after `start_capture`
+pub fn replay_input(_: &RpcUtils, name: String) -> Result<InputScriptInfo> {
+    replay::start_replay(&name)
+}
+
+pub fn stop_input_replay(_: &RpcUtils) -> Result<()> {
+    replay::stop_replay();
+    Ok(())
+}
+
+pub fn pause_capture(utils: &RpcUtils) -> Result<CaptureState> {
+    let _ = utils.tx.send(base::ChannelMsg::PauseCapture);
+    Ok(state::current())
+}
+
+pub fn resume_capture(utils: &RpcUtils) -> Result<CaptureState> {
+    let _ = utils.tx.send(base::ChannelMsg::ResumeCapture);
+    Ok(state::current())
+}
+
+// Transitions are also pushed as `tse_capture_state`
+pub fn get_capture_state(_: &RpcUtils) -> Result<CaptureState> {
+    Ok(state::current())
+}
+
+// Priority/affinity of the game for the current run, restored when the capture ends
+pub fn set_process_tuning(_: &RpcUtils, tuning: Tuning) -> Result<Tuning> {
+    match state::current() {
+        CaptureState::Capturing { package, .. } | CaptureState::Paused { package, .. } => {
+            tuning::apply(&package, &tuning)
+        }
+        _ => bail!("No capture running"),
+    }
+}
+
+pub fn get_process_tuning(_: &RpcUtils) -> Result<Option<Tuning>> {
+    Ok(tuning::applied())
+}
+
+// CPU/GPU clock caps for the current run, restored when the capture ends
+pub fn set_clock_limits(_: &RpcUtils, limits: ClockLimits) -> Result<ClockLimits> {
+    if !state::current().is_running() {
+        bail!("No capture running");
+    }
+    clocks::apply(&limits)
+}
+
+pub fn get_clock_limits(_: &RpcUtils) -> Result<Option<ClockLimits>> {
+    Ok(clocks::applied())
+}
+
+// While capturing, live charts are suspended and the page gets `tse_low_impact`
+pub fn set_low_impact(_: &RpcUtils, enabled: bool) -> Result<bool> {
+    config::update(|config| config.low_impact = enabled)?;
+    Ok(enabled)
+}
+
+pub fn get_low_impact(_: &RpcUtils) -> Result<bool> {
+    Ok(CONFIG.read().low_impact)
+}
+
+// Taken when the next capture starts, from the page, an agent or the command line
+pub fn set_capture_options(_: &RpcUtils, options: CaptureOptions) -> Result<CaptureOptions> {
+    if options.ios && options.android_only() {
+        bail!("iOS captures can't read counters or profile the engine");
+    }
+    if options.gpu_layer {
+        let library = CONFIG.read().gpu_layer.clone().context("No GPU layer library set")?;
+        if !library.is_file() {
+            bail!("Layer library not found: {}", library.display());
+        }
+    }
+    config::update(|config| config.capture = options.clone())?;
+    Ok(options)
+}
+
+pub fn get_capture_options(_: &RpcUtils) -> Result<CaptureOptions> {
+    Ok(CONFIG.read().capture.clone())
+}
+
+// Listening starts right away, stopping it takes a restart
+pub fn set_agent_settings(utils: &RpcUtils, mut settings: AgentSettings) -> Result<AgentSettings> {
+    if settings.listen && settings.token.is_empty() {
+        settings.token = agent::generate_token();
+    }
+    config::update(|config| config.agent = settings.clone())?;
+    if settings.listen {
+        agent::listen(utils.tx.clone())?;
+    }
+    Ok(settings)
+}
+
+pub fn get_agent_settings(_: &RpcUtils) -> Result<AgentSettings> {
+    Ok(CONFIG.read().agent.clone())
+}
+
+// Agents advertising on the LAN, the list grows while the page polls
+pub fn discover_agents(_: &RpcUtils) -> Result<Vec<Peer>> {
+    discovery::agents()
+}
+
+// The profile's layout, or the default HUD
+pub fn get_overlay_layout(_: &RpcUtils, profile: String) -> Result<Layout> {
+    let config = CONFIG.read();
+    Ok(config.profiles.get(&profile).and_then(|p| p.overlay.clone()).unwrap_or_default())
+}
+
+// Creates the profile when needed, a shown overlay picks up the change right away
+pub fn set_overlay_layout(utils: &RpcUtils, params: OverlayLayoutParams) -> Result<Layout> {
+    params.layout.validate()?;
+    config::update(|config| {
+        config.profiles.entry(params.profile.clone()).or_default().overlay =
+            Some(params.layout.clone())
+    })?;
+    let _ = utils.event_proxy.send_event(Event::Overlay(overlay::Command::SetLayout {
+        profile: params.profile,
+        layout: params.layout.clone(),
+    }));
+    Ok(params.layout)
+}
+
+pub fn show_overlay(utils: &RpcUtils, profile: Option<String>) -> Result<()> {
+    let layout = match &profile {
+        Some(name) => {
+            let config = CONFIG.read();
+            let profile =
+                config.profiles.get(name).with_context(|| format!("Unknown profile: {}", name))?;
+            profile.overlay.clone().unwrap_or_default()
+        }
+        None => Layout::default(),
+    };
+    let _ =
+        utils.event_proxy.send_event(Event::Overlay(overlay::Command::Show { profile, layout }));
+    Ok(())
+}
+
+pub fn hide_overlay(utils: &RpcUtils) -> Result<()> {
+    let _ = utils.event_proxy.send_event(Event::Overlay(overlay::Command::Hide));
+    Ok(())
+}
+
+// Applies to a shown overlay right away, `tse_overlay_fullscreen` hints at the docked mode
+pub fn set_overlay_settings(
+    utils: &RpcUtils,
+    settings: overlay::Settings,
+) -> Result<overlay::Settings> {
+    config::update(|config| config.overlay = settings.clone())?;
+    let _ = utils.event_proxy.send_event(Event::Overlay(overlay::Command::Reconfigure));
+    Ok(settings)
+}
+
+pub fn get_overlay_settings(_: &RpcUtils) -> Result<overlay::Settings> {
+    Ok(CONFIG.read().overlay.clone())
+}
+
+// The capture target rather than whatever has focus, empty until a game was in front
+pub fn get_front_app(_: &RpcUtils) -> Result<String> {
+    Ok(foreground::poll()?.target.unwrap_or_default())
+}
+
+pub fn get_foreground(_: &RpcUtils) -> Result<Foreground> {
+    foreground::poll()
+}
+
+// Captures go for `package` whatever is in front, None follows the foreground again
+pub fn pin_capture_target(_: &RpcUtils, package: Option<String>) -> Result<Foreground> {
+    foreground::pin(package)
+}
+
+pub fn subscribe(_: &RpcUtils, topic: String) -> Result<Vec<&'static str>> {
+    subscription::subscribe(&topic)?;
+    Ok(subscription::active_topics())
+}
+
+pub fn unsubscribe(_: &RpcUtils, topic: String) -> Result<Vec<&'static str>> {
+    subscription::unsubscribe(&topic);
+    Ok(subscription::active_topics())
+}
+
+pub fn acknowledge_messages(utils: &RpcUtils, seq: u64) -> Result<()> {
+    let _ = utils.event_proxy.send_event(Event::Acknowledge(seq));
+    Ok(())
+}
+
+// Published messages after `seq` again, for a page that saw a gap. 0 for a page that just loaded,
+// it only gets what is published from then on.
+pub fn resync_messages(utils: &RpcUtils, seq: u64) -> Result<()> {
+    let _ = utils.event_proxy.send_event(Event::Resync(seq));
+    Ok(())
+}
+
+// Events after `since_seq`, 0 for all kept, so a reloaded page catches up
+pub fn get_event_history(_: &RpcUtils, since_seq: u64) -> Result<History> {
+    Ok(event_log::since(since_seq))
+}
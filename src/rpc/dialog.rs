@@ -1,66 +1,147 @@
-use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 
+use rfd::{AsyncFileDialog, AsyncMessageDialog, FileHandle};
+use serde::Deserialize;
 use wry::application::window::Window;
 
-use super::command::DialogParams;
+use crate::session;
 
-pub fn open_save(window: &Window, last_dir: bool) -> Option<PathBuf> {
-    let mut dialog = rfd::FileDialog::new()
-        .add_filter("Mass Effect Trilogy Save", &["pcsav", "xbsav", "ps4sav", "MassEffectSave"])
-        .add_filter("All Files", &["*"]);
+use super::command::DialogParams;
 
-    dialog = with_parent(dialog, window);
+const SAVE_EXTENSIONS: &[&str] = &["pcsav", "xbsav", "ps4sav", "MassEffectSave"];
+const CAPTURE_EXTENSIONS: &[&str] = &[session::EXTENSION, "json", "csv"];
+const CSV_EXTENSIONS: &[&str] = &["csv"];
+const MORPH_EXTENSIONS: &[&str] = &["ron", "me2headmorph", "me3headmorph"];
+
+// Filter lists shared with the frontend by name
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Saves,
+    Captures,
+    Csv,
+    Morphs,
+}
 
-    if !last_dir {
-        if let Some(bioware_dir) = bioware_dir() {
-            dialog = dialog.set_directory(bioware_dir);
+impl Preset {
+    fn filters(self) -> &'static [(&'static str, &'static [&'static str])] {
+        match self {
+            Preset::Saves => &[("Mass Effect Trilogy Save", SAVE_EXTENSIONS)],
+            Preset::Captures => &[("Capture", CAPTURE_EXTENSIONS)],
+            Preset::Csv => &[("CSV", CSV_EXTENSIONS)],
+            Preset::Morphs => &[("Head Morph", MORPH_EXTENSIONS)],
         }
     }
+}
 
-    dialog.pick_file()
+// Native dialogs are created on the main thread (macOS requires it) and only awaited elsewhere
+pub type Dialog<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+fn path(handle: Option<FileHandle>) -> Option<PathBuf> {
+    handle.map(|handle| handle.path().to_owned())
 }
 
-pub fn save_save(window: &Window, params: DialogParams) -> Option<PathBuf> {
-    let DialogParams { path, filters } = params;
+pub fn open_save(window: &Window, params: DialogParams) -> Dialog<Vec<PathBuf>> {
+    let multiple = params.multiple;
+    let dialog = build(window, params, Some(Preset::Saves)).add_filter("All Files", &["*"]);
+
+    if multiple {
+        let picked = dialog.pick_files();
+        Box::pin(async move {
+            picked.await.unwrap_or_default().iter().map(|handle| handle.path().to_owned()).collect()
+        })
+    } else {
+        let picked = dialog.pick_file();
+        Box::pin(async move { path(picked.await).into_iter().collect() })
+    }
+}
 
-    let file_name = path.file_name().map(OsStr::to_string_lossy).unwrap_or_default();
+pub fn save_save(window: &Window, params: DialogParams) -> Dialog<Option<PathBuf>> {
+    let picked = build(window, params, None).save_file();
+    Box::pin(async move { path(picked.await) })
+}
 
-    let mut dialog = rfd::FileDialog::new().set_file_name(&file_name);
-    dialog = with_parent(dialog, window);
+pub fn import_head_morph(window: &Window) -> Dialog<Option<PathBuf>> {
+    let params =
+        DialogParams { preset: Some(Preset::Morphs), last_dir: true, ..Default::default() };
+    let picked = build(window, params, None).add_filter("All Files", &["*"]).pick_file();
+    Box::pin(async move { path(picked.await) })
+}
 
-    for (filter, extensions) in filters {
-        let extensions: Vec<&str> = extensions.iter().map(String::as_str).collect();
-        dialog = dialog.add_filter(&filter, &extensions);
-    }
+pub fn export_head_morph(window: &Window) -> Dialog<Option<PathBuf>> {
+    let dialog = AsyncFileDialog::new().add_filter("Head Morph", &["ron"]);
+    let picked = with_parent(dialog, window).save_file();
+    Box::pin(async move { path(picked.await) })
+}
 
-    let directory = path
-        .parent()
-        .and_then(|parent| parent.is_dir().then(|| parent.to_owned()))
-        .or_else(bioware_dir);
+pub fn pick_export_directory(window: &Window) -> Dialog<Option<PathBuf>> {
+    let mut dialog = AsyncFileDialog::new();
+    dialog = with_parent(dialog, window);
 
-    if let Some(directory) = directory {
-        dialog = dialog.set_directory(directory);
+    if let Some(bioware_dir) = bioware_dir() {
+        dialog = dialog.set_directory(bioware_dir);
     }
 
-    dialog.save_file()
+    let picked = dialog.pick_folder();
+    Box::pin(async move { path(picked.await) })
 }
 
-pub fn import_head_morph(window: &Window) -> Option<PathBuf> {
-    let dialog = rfd::FileDialog::new()
-        .add_filter("Head Morph", &["ron", "me2headmorph", "me3headmorph"])
-        .add_filter("All Files", &["*"]);
+pub fn pick_directory(window: &Window, params: DialogParams) -> Dialog<Option<PathBuf>> {
+    let picked = build(window, params, None).pick_folder();
+    Box::pin(async move { path(picked.await) })
+}
 
-    with_parent(dialog, window).pick_file()
+pub fn confirm_path_access(path: &Path) -> Dialog<bool> {
+    let shown = AsyncMessageDialog::new()
+        .set_title("GamePerf")
+        .set_description(&format!("Allow GamePerf to access this location?\n\n{}", path.display()))
+        .set_level(rfd::MessageLevel::Warning)
+        .set_buttons(rfd::MessageButtons::YesNo)
+        .show();
+    Box::pin(shown)
 }
 
-pub fn export_head_morph(window: &Window) -> Option<PathBuf> {
-    let dialog = rfd::FileDialog::new().add_filter("Head Morph", &["ron"]);
-    with_parent(dialog, window).save_file()
+// `fallback` applies when neither a preset nor custom filters are given
+fn build(window: &Window, params: DialogParams, fallback: Option<Preset>) -> AsyncFileDialog {
+    let DialogParams { path, file_name, filters, preset, last_dir, .. } = params;
+
+    let mut dialog = with_parent(AsyncFileDialog::new(), window);
+
+    let preset = preset.or_else(|| fallback.filter(|_| filters.is_empty()));
+    if let Some(preset) = preset {
+        for (name, extensions) in preset.filters() {
+            dialog = dialog.add_filter(name, extensions);
+        }
+    }
+    for (filter, extensions) in &filters {
+        let extensions: Vec<&str> = extensions.iter().map(String::as_str).collect();
+        dialog = dialog.add_filter(filter, &extensions);
+    }
+
+    // `path` is either the starting directory or a file to preselect
+    let (directory, path_file_name) = if path.is_dir() {
+        (Some(path), None)
+    } else {
+        let directory = path.parent().filter(|parent| parent.is_dir()).map(Path::to_owned);
+        (directory, path.file_name().map(|name| name.to_string_lossy().into_owned()))
+    };
+
+    if let Some(file_name) = file_name.or(path_file_name) {
+        dialog = dialog.set_file_name(&file_name);
+    }
+
+    let directory = if last_dir { directory } else { directory.or_else(bioware_dir) };
+    if let Some(directory) = directory {
+        dialog = dialog.set_directory(directory);
+    }
+
+    dialog
 }
 
 #[cfg(target_os = "windows")]
-fn bioware_dir() -> Option<PathBuf> {
+pub fn bioware_dir() -> Option<PathBuf> {
     dirs::document_dir().and_then(|mut path| {
         path.push("BioWare\\");
         path.is_dir().then(|| path)
@@ -72,7 +153,7 @@ fn bioware_dir() -> Option<PathBuf> {
 // Mass Effect games installed in the default steam library, in
 // the user's home directory.
 #[cfg(target_os = "linux")]
-fn bioware_dir() -> Option<PathBuf> {
+pub fn bioware_dir() -> Option<PathBuf> {
     dirs::home_dir().and_then(|mut path| {
         path.push(".steam/root/steamapps/compatdata/1328670/pfx/drive_c/users/steamuser/My Documents/BioWare/");
         path.is_dir().then(|| path)
@@ -80,18 +161,18 @@ fn bioware_dir() -> Option<PathBuf> {
 }
 
 #[cfg(all(not(target_os = "linux"), not(target_os = "windows")))]
-fn bioware_dir() -> Option<PathBuf> {
+pub fn bioware_dir() -> Option<PathBuf> {
     None
 }
 
 // FIXME: Remove this and set directly `set_parent` when `tao` will implement `raw_window_handle` for linux
 #[cfg(not(target_os = "linux"))]
-fn with_parent(dialog: rfd::FileDialog, window: &Window) -> rfd::FileDialog {
+fn with_parent(dialog: AsyncFileDialog, window: &Window) -> AsyncFileDialog {
     dialog.set_parent(window)
     // dialog
 }
 
 #[cfg(target_os = "linux")]
-fn with_parent(dialog: rfd::FileDialog, _: &Window) -> rfd::FileDialog {
+fn with_parent(dialog: AsyncFileDialog, _: &Window) -> AsyncFileDialog {
     dialog
 }
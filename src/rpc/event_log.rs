@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::subscription;
+
+// A few minutes of state transitions and topic updates
+const CAPACITY: usize = 512;
+
+lazy_static! {
+    static ref LOG: Mutex<EventLog> = Mutex::new(EventLog::default());
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub at_ms: u64,
+    pub event: &'static str,
+    pub detail: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct History {
+    pub entries: Vec<Entry>,
+    // Seq of the latest entry, what to ask from next time
+    pub last_seq: u64,
+    // Entries after `since_seq` were already dropped, the page should reload its state
+    pub truncated: bool,
+}
+
+// Seqs start at 1, 0 asks for everything kept
+#[derive(Default)]
+struct EventLog {
+    entries: VecDeque<Entry>,
+    last_seq: u64,
+}
+
+impl EventLog {
+    fn push(&mut self, event: &'static str, detail: Value, at_ms: u64) {
+        if self.entries.len() >= CAPACITY {
+            self.entries.pop_front();
+        }
+        self.last_seq += 1;
+        self.entries.push_back(Entry { seq: self.last_seq, at_ms, event, detail });
+    }
+
+    fn since(&self, since_seq: u64) -> History {
+        let oldest = self.entries.front().map_or(self.last_seq + 1, |entry| entry.seq);
+        History {
+            entries: self.entries.iter().filter(|entry| entry.seq > since_seq).cloned().collect(),
+            last_seq: self.last_seq,
+            truncated: since_seq + 1 < oldest,
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+// Custom events as they are dispatched
+pub fn record(event: &'static str, detail: &Value) {
+    LOG.lock().push(event, detail.clone(), now_ms());
+}
+
+// Published topics only when they carry state, live samples would flush everything else out
+pub fn record_published(topic: &'static str, detail: &Value) {
+    if subscription::latest_only(topic) {
+        record(topic, detail);
+    }
+}
+
+pub fn since(since_seq: u64) -> History {
+    LOG.lock().since(since_seq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_since() {
+        let mut log = EventLog::default();
+        assert!(!log.since(0).truncated);
+        for i in 0..CAPACITY + 10 {
+            log.push("tse_capture_state", json!({ "i": i }), i as u64);
+        }
+        let history = log.since(0);
+        assert_eq!(history.entries.len(), CAPACITY);
+        assert_eq!(history.entries[0].seq, 11);
+        assert!(history.truncated);
+
+        let history = log.since(history.last_seq - 2);
+        assert_eq!(history.entries.len(), 2);
+        assert!(!history.truncated);
+        assert!(log.since(log.last_seq).entries.is_empty());
+        assert!(!log.since(10).truncated);
+    }
+}
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+// Implementation-defined server error, used for failing commands
+pub const SERVER_ERROR: i64 = -32000;
+pub const UNAUTHORIZED: i64 = -32001;
+// Authenticated, but the key's role doesn't allow the method
+pub const FORBIDDEN: i64 = -32002;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into(), data: None }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))
+    }
+
+    pub fn invalid_params(err: impl ToString) -> Self {
+        Self::new(INVALID_PARAMS, format!("Invalid params: {}", err.to_string()))
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        let causes: Vec<String> = err.chain().skip(1).map(ToString::to_string).collect();
+        let mut error = Self::new(SERVER_ERROR, err.to_string());
+        if !causes.is_empty() {
+            error.data = Some(json!({ "causes": causes }));
+        }
+        error
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(INTERNAL_ERROR, err.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    // `None` for notifications, which never get a response
+    pub id: Option<Value>,
+    pub method: String,
+    pub params: Option<Value>,
+}
+
+impl Request {
+    fn from_value(value: Value) -> Result<Request, Response> {
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => return Err(Response::error(Value::Null, invalid_request("not an object"))),
+        };
+
+        let id = object.remove("id");
+        let response_id = id.clone().unwrap_or(Value::Null);
+        if !matches!(id, None | Some(Value::Null | Value::String(_) | Value::Number(_))) {
+            return Err(Response::error(Value::Null, invalid_request("invalid id")));
+        }
+        if object.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(Response::error(response_id, invalid_request("jsonrpc must be \"2.0\"")));
+        }
+        let method = match object.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return Err(Response::error(response_id, invalid_request("missing method"))),
+        };
+        let params = object.remove("params");
+        if !matches!(params, None | Some(Value::Array(_) | Value::Object(_))) {
+            return Err(Response::error(response_id, invalid_request("invalid params")));
+        }
+
+        Ok(Request { id, method, params })
+    }
+
+    // Commands take a single argument, either positional (`[value]`) or by-name (`{...}`)
+    pub fn single_param<T: serde::de::DeserializeOwned + Default>(
+        &mut self,
+    ) -> Result<T, RpcError> {
+        let value = match self.params.take() {
+            Some(Value::Array(array)) => {
+                let value: [T; 1] = serde_json::from_value(Value::Array(array))
+                    .map_err(RpcError::invalid_params)?;
+                value.into_iter().next().unwrap_or_default()
+            }
+            Some(object) => serde_json::from_value(object).map_err(RpcError::invalid_params)?,
+            None => return Err(RpcError::invalid_params("argument required")),
+        };
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Response {
+    jsonrpc: &'static str,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn result(id: Value, result: Value) -> Self {
+        Response { jsonrpc: "2.0", id, result: Some(result), error: None }
+    }
+
+    pub fn error(id: Value, error: RpcError) -> Self {
+        Response { jsonrpc: "2.0", id, result: None, error: Some(error) }
+    }
+}
+
+fn invalid_request(reason: &str) -> RpcError {
+    RpcError::new(INVALID_REQUEST, format!("Invalid request: {}", reason))
+}
+
+// Handles a raw JSON-RPC message, single or batch, for clients not going through wry
+pub fn handle_message(
+    message: &str,
+    mut handle: impl FnMut(Request) -> Option<Response>,
+) -> Option<String> {
+    let value: Value = match serde_json::from_str(message) {
+        Ok(value) => value,
+        Err(err) => {
+            let response =
+                Response::error(Value::Null, RpcError::new(PARSE_ERROR, err.to_string()));
+            return serde_json::to_string(&response).ok();
+        }
+    };
+
+    let mut process = |value: Value| match Request::from_value(value) {
+        Ok(request) => handle(request),
+        Err(response) => Some(response),
+    };
+
+    match value {
+        Value::Array(batch) if batch.is_empty() => {
+            serde_json::to_string(&Response::error(Value::Null, invalid_request("empty batch")))
+                .ok()
+        }
+        Value::Array(batch) => {
+            let responses: Vec<Response> = batch.into_iter().filter_map(&mut process).collect();
+            // A batch of notifications gets no response at all
+            if responses.is_empty() {
+                None
+            } else {
+                serde_json::to_string(&responses).ok()
+            }
+        }
+        value => process(value).and_then(|response| serde_json::to_string(&response).ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(request: Request) -> Option<Response> {
+        let id = request.id?;
+        if request.method == "echo" {
+            Some(Response::result(id, request.params.unwrap_or(Value::Null)))
+        } else {
+            Some(Response::error(id, RpcError::method_not_found(&request.method)))
+        }
+    }
+
+    fn handle(message: &str) -> Option<Value> {
+        handle_message(message, echo).map(|response| serde_json::from_str(&response).unwrap())
+    }
+
+    #[test]
+    fn test_single_and_notification() {
+        let response = handle(r#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[42]}"#);
+        assert_eq!(response, Some(json!({ "jsonrpc": "2.0", "id": 1, "result": [42] })));
+
+        assert_eq!(handle(r#"{"jsonrpc":"2.0","method":"echo"}"#), None);
+
+        let response = handle(r#"{"jsonrpc":"2.0","id":"a","method":"nope"}"#).unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_errors() {
+        let response = handle("{").unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+
+        let response = handle(r#"{"jsonrpc":"1.0","id":1,"method":"echo"}"#).unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        assert_eq!(response["id"], 1);
+
+        let response = handle("[]").unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[test]
+    fn test_batch() {
+        let response = handle(
+            r#"[
+                {"jsonrpc":"2.0","id":1,"method":"echo","params":{"a":1}},
+                {"jsonrpc":"2.0","method":"echo"},
+                1
+            ]"#,
+        )
+        .unwrap();
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["result"], json!({ "a": 1 }));
+        assert_eq!(responses[1]["error"]["code"], INVALID_REQUEST);
+
+        assert_eq!(handle(r#"[{"jsonrpc":"2.0","method":"echo"}]"#), None);
+    }
+}
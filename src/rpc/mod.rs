@@ -1,27 +1,55 @@
+mod access;
+pub mod auth;
+mod bridge;
+mod coalesce;
 mod command;
 mod dialog;
+mod event_log;
+pub mod jsonrpc;
+mod shutdown;
+pub mod subscription;
+pub mod trace;
 
 use std::env;
-use std::path::PathBuf;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use clap::ArgMatches;
-use serde_json::{json, Value};
+use serde::Serialize;
+use serde_json::Value;
 use wry::{
     application::{
-        event_loop::{ControlFlow, EventLoopProxy},
+        event_loop::{ControlFlow, EventLoopProxy, EventLoopWindowTarget},
         window::Window,
     },
     webview::{RpcRequest, RpcResponse, WebView},
 };
 
 use crate::base;
+use crate::base::state::{self, CaptureState};
+use crate::config::{Profile, CONFIG};
+use crate::link;
+use crate::overlay::{self, Overlay};
+use crate::session::{self, SessionInfo};
+pub use coalesce::Coalescer;
+use jsonrpc::{Request, Response, RpcError};
+pub use shutdown::Shutdown;
+
+static COMMAND_LINE_CAPTURE_STARTED: AtomicBool = AtomicBool::new(false);
+// `--duration` gives up on a capture still idle after this, the start was refused
+const START_GRACE: Duration = Duration::from_secs(5);
+const STATE_POLL: Duration = Duration::from_millis(200);
 
 macro_rules! notify_commands {
     ($req:ident, $utils:ident => [$(command::$command:ident),* $(,)?]) => {
         $(
             if $req.method == stringify!($command) {
-                command::$command(&$utils);
+                command::$command($utils);
                 return Ok(None);
             }
         )*
@@ -32,7 +60,7 @@ macro_rules! call_commands {
     ($req:ident, $utils:ident => [$(command::$command:ident),* $(,)?]) => {
         $(
             if $req.method == stringify!($command) {
-                let response = command::$command(&$utils)?;
+                let response = command::$command($utils)?;
                 let js_value = serde_json::to_value(&response).map(Some)?;
                 return Ok(js_value);
             }
@@ -44,10 +72,8 @@ macro_rules! call_commands_with_param {
     ($req:ident, $utils:ident => [$(command::$command:ident),* $(,)?]) => {
         $(
             if $req.method == stringify!($command) {
-                let params = $req.params.take().context("argument required")?;
-                let value: [_; 1] = serde_json::from_value(params)?;
-                let value = value.into_iter().next().unwrap_or_default();
-                let response = command::$command(&$utils, value)?;
+                let value = $req.single_param()?;
+                let response = command::$command($utils, value)?;
                 let js_value = serde_json::to_value(&response).map(Some)?;
                 return Ok(js_value);
             }
@@ -55,109 +81,480 @@ macro_rules! call_commands_with_param {
     };
 }
 
+// Commands showing a native dialog create it here on the main thread and are awaited on a worker
+// so the event loop keeps going, their response is sent back through `Event::RpcResponse` once
+// the dialog closes. Commands taking seconds go the same way.
+macro_rules! deferred_commands {
+    ($req:ident, $utils:ident => [$(command::$command:ident),* $(,)?]) => {
+        $(
+            if $req.method == stringify!($command) {
+                defer($req, $utils, command::$command($utils.window));
+                return Ok(None);
+            }
+        )*
+    };
+}
+
+macro_rules! deferred_commands_with_param {
+    ($req:ident, $utils:ident => [$(command::$command:ident),* $(,)?]) => {
+        $(
+            if $req.method == stringify!($command) {
+                let value = $req.single_param()?;
+                defer($req, $utils, command::$command($utils.window, value));
+                return Ok(None);
+            }
+        )*
+    };
+}
+
 pub struct RpcUtils<'a> {
     pub window: &'a Window,
     pub event_proxy: &'a EventLoopProxy<Event>,
     pub args: &'a ArgMatches,
-    pub tx: &'a std::sync::mpsc::Sender<base::ChannelMsg>
+    pub tx: &'a std::sync::mpsc::Sender<base::ChannelMsg>,
+}
+
+pub fn rpc_handler(req: RpcRequest, utils: RpcUtils) -> Option<RpcResponse> {
+    let request = Request { id: req.id, method: req.method, params: req.params };
+    let Response { id, result, error, .. } = handle_request(request, &utils)?;
+    match error {
+        Some(error) => Some(RpcResponse::new_error(Some(id), serde_json::to_value(error).ok())),
+        None => Some(RpcResponse::new_result(Some(id), result)),
+    }
+}
+
+// Raw JSON-RPC entry point (batches included) for clients not going through wry
+#[allow(dead_code)]
+pub fn handle_message(message: &str, utils: &RpcUtils) -> Option<String> {
+    jsonrpc::handle_message(message, |request| handle_request(request, utils))
 }
 
-pub fn rpc_handler(mut req: RpcRequest, utils: RpcUtils) -> Option<RpcResponse> {
+pub fn handle_request(mut req: Request, utils: &RpcUtils) -> Option<Response> {
     log::info!("rpc_handler: {:?}", &req.method);
-    let mut handle_request = || -> Result<Option<Value>> {
-        if req.method == "open_command_line_save" {
-            let response = if let Some(path) = utils.args.value_of("SAVE") {
-                let mut path = PathBuf::from(path);
-                if path.is_relative() {
-                    path = env::current_dir()?.join(path);
-                }
-                command::reload_save(&utils, path).map(Some)?
-            } else {
-                None
-            };
-            let js_value = serde_json::to_value(&response).map(Some)?;
-            return Ok(js_value);
+    let result = auth::authenticate(&mut req).and_then(|role| {
+        auth::permit(role, &req.method)?;
+        trace::request(&req);
+        dispatch(&mut req, utils)
+    });
+    if let Err(error) = &result {
+        log::error!("{}: {}", req.method, error.message);
+    }
+
+    // Notifications never get a response, even on error. Deferred requests have their id taken.
+    let id = req.id.take()?;
+    let response = match result {
+        Ok(response) => Response::result(id, response.unwrap_or(Value::Null)),
+        Err(error) => Response::error(id, error),
+    };
+    trace::response(&response);
+    Some(response)
+}
+
+fn dispatch(req: &mut Request, utils: &RpcUtils) -> Result<Option<Value>, RpcError> {
+    if req.method == "open_command_line_save" {
+        let response = match save_arg(utils.args) {
+            Some(path) => Some(command_line_save(utils, path)?),
+            None => None,
+        };
+        let js_value = serde_json::to_value(&response).map(Some)?;
+        return Ok(js_value);
+    }
+
+    if req.method == "open_command_line_session" {
+        let response = match session_arg(utils.args) {
+            Some(path) => Some(command_line_session(path)?),
+            None => None,
+        };
+        let js_value = serde_json::to_value(&response).map(Some)?;
+        return Ok(js_value);
+    }
+
+    if req.method == "open_command_line_link" {
+        let response = match link_arg(utils.args) {
+            Some(link) => Some(link::parse(link)?),
+            None => None,
+        };
+        let js_value = serde_json::to_value(&response).map(Some)?;
+        return Ok(js_value);
+    }
+
+    if req.method == "start_command_line_capture" {
+        let response = command_line_capture(utils)?;
+        let js_value = serde_json::to_value(&response).map(Some)?;
+        return Ok(js_value);
+    }
+
+    notify_commands!(req, utils => [
+        command::init,
+        command::minimize,
+        command::toggle_maximize,
+        command::drag_window,
+        command::close,
+    ]);
+
+    call_commands!(req, utils => [
+        command::check_for_update,
+        command::download_and_install_update,
+        command::repair_installation,
+        command::get_integrity_report,
+        command::get_migration_report,
+        command::get_report_schema,
+        command::list_report_templates,
+        command::list_ios_devices,
+        command::run_self_test,
+        command::stop_capture,
+        command::get_front_app,
+        command::get_foreground,
+        command::list_databases,
+        command::list_sessions,
+        command::pause_capture,
+        command::resume_capture,
+        command::get_capture_state,
+        command::get_low_impact,
+        command::get_capture_options,
+        command::get_process_tuning,
+        command::get_clock_limits,
+        command::compact_now,
+        command::hide_overlay,
+        command::get_overlay_settings,
+        command::disarm_benchmark,
+        command::start_input_recording,
+        command::list_input_scripts,
+        command::stop_input_replay,
+        command::get_agent_settings,
+        command::discover_agents,
+    ]);
+
+    call_commands_with_param!(req, utils => [
+        command::open_external_link,
+        command::save_file,
+        command::reload_save,
+        command::load_database,
+        command::start_capture,
+        command::pin_capture_target,
+        command::subscribe,
+        command::unsubscribe,
+        command::get_event_history,
+        command::acknowledge_messages,
+        command::resync_messages,
+        command::register_database,
+        command::reload_database,
+        command::query_database,
+        command::list_database_keys,
+        command::validate_head_morph,
+        command::convert_head_morph,
+        command::verify_save,
+        command::list_save_versions,
+        command::diff_save_versions,
+        command::import_sessions_from_directory,
+        command::delete_session,
+        command::compare_session_environments,
+        command::update_session,
+        command::search_sessions,
+        command::split_session,
+        command::merge_sessions,
+        command::set_session_markers,
+        command::ingest_events,
+        command::import_unity_timings,
+        command::export_session,
+        command::export_xlsx,
+        command::preview_anonymized_metadata,
+        command::share_session,
+        command::import_shared_session,
+        command::summarize_session,
+        command::render_chart,
+        command::get_segment_stats,
+        command::get_frametime_histogram,
+        command::get_frametime_cdf,
+        command::get_percentile_curve,
+        command::get_frametime_heatmap,
+        command::get_fps_series,
+        command::correlate_metrics,
+        command::compare_segments,
+        command::compare_sessions,
+        command::generate_report,
+        command::render_report,
+        command::export_settings,
+        command::import_settings,
+        command::delete_file,
+        command::set_low_impact,
+        command::set_capture_options,
+        command::set_process_tuning,
+        command::set_clock_limits,
+        command::get_overlay_layout,
+        command::set_overlay_layout,
+        command::show_overlay,
+        command::set_overlay_settings,
+        command::arm_benchmark,
+        command::stop_input_recording,
+        command::delete_input_script,
+        command::replay_input,
+        command::set_agent_settings,
+    ]);
+
+    deferred_commands!(req, utils => [
+        command::import_head_morph,
+        command::export_head_morph_dialog,
+        command::probe_capabilities,
+    ]);
+
+    deferred_commands_with_param!(req, utils => [
+        command::open_save,
+        command::save_save_dialog,
+        command::grant_path_access,
+        command::export_all_head_morphs,
+        command::pick_directory,
+    ]);
+
+    Err(RpcError::method_not_found(&req.method))
+}
+
+fn defer<T, F>(req: &mut Request, utils: &RpcUtils, job: F)
+where
+    T: Serialize,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    let id = req.id.take();
+    let method = req.method.clone();
+    let proxy = utils.event_proxy.clone();
+    // What follows the dialog reads and writes files, not on the runtime's workers
+    let runtime = tokio::runtime::Handle::current();
+    thread::spawn(move || {
+        let result = runtime
+            .block_on(job)
+            .map_err(RpcError::from)
+            .and_then(|response| serde_json::to_value(&response).map_err(RpcError::from));
+        if let Err(error) = &result {
+            log::error!("{}: {}", method, error.message);
         }
 
-        notify_commands!(req, utils => [
-            command::init,
-            command::minimize,
-            command::toggle_maximize,
-            command::drag_window,
-            command::close,            
-        ]);
-
-        call_commands!(req, utils => [
-            command::check_for_update,
-            command::download_and_install_update,
-            command::import_head_morph,
-            command::export_head_morph_dialog,
-            command::stop_capture,
-            command::get_front_app
-        ]);
-
-        call_commands_with_param!(req, utils => [
-            command::open_external_link,
-            command::open_save,
-            command::save_file,
-            command::save_save_dialog,
-            command::reload_save,
-            command::load_database,
-            command::start_capture,
-        ]);
-
-        bail!("Wrong RPC method, got: {}", req.method)
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+        let response = match result {
+            Ok(value) => Response::result(id, value),
+            Err(error) => Response::error(id, error),
+        };
+        let _ = proxy.send_event(Event::RpcResponse(response));
+    });
+}
+
+// The OS opens `.gpcap` files and `gameperf://` links through the SAVE positional too
+fn save_arg(args: &ArgMatches) -> Option<&str> {
+    args.value_of("SAVE").filter(|arg| !link::is_link(arg) && !session::is_session_file(arg))
+}
+
+fn session_arg(args: &ArgMatches) -> Option<&str> {
+    let file = args.value_of("SAVE").filter(|arg| session::is_session_file(arg));
+    args.value_of("open-session").or(file)
+}
+
+fn link_arg(args: &ArgMatches) -> Option<&str> {
+    args.value_of("SAVE").filter(|arg| link::is_link(arg))
+}
+
+fn command_line_save(utils: &RpcUtils, path: &str) -> Result<command::RpcFile> {
+    let mut path = PathBuf::from(path);
+    if path.is_relative() {
+        path = env::current_dir()?.join(path);
+    }
+    // Given by the user on the command line
+    access::grant(&path);
+    command::reload_save(utils, path)
+}
+
+fn command_line_session(path: &str) -> Result<SessionInfo> {
+    session::open_file(&command_line_path(&env::current_dir()?, path))
+}
+
+// Given by the user on the command line, relative to the directory GamePerf was started from
+fn command_line_path(cwd: &Path, path: &str) -> PathBuf {
+    let path = cwd.join(path);
+    access::grant(&path);
+    path
+}
+
+#[derive(Serialize)]
+struct CommandLineCapture {
+    package: String,
+    duration_secs: Option<u64>,
+}
+
+// Only the first call starts a capture, a reloaded page must not restart it
+fn command_line_capture(utils: &RpcUtils) -> Result<Option<CommandLineCapture>> {
+    if COMMAND_LINE_CAPTURE_STARTED.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+    capture_from_args(utils.args, utils.tx)
+}
+
+fn capture_from_args(
+    args: &ArgMatches,
+    tx: &Sender<base::ChannelMsg>,
+) -> Result<Option<CommandLineCapture>> {
+    let profile = match args.value_of("profile") {
+        Some(name) => CONFIG
+            .read()
+            .profiles
+            .get(name)
+            .cloned()
+            .with_context(|| format!("Unknown profile: {}", name))?,
+        None => Profile::default(),
     };
+    let package = match args.value_of("capture").map(String::from).or(profile.package) {
+        Some(package) => package,
+        None => return Ok(None),
+    };
+    let duration_secs = match args.value_of("duration") {
+        Some(secs) => Some(secs.parse().context("Invalid --duration")?),
+        None => profile.duration_secs,
+    };
+
+    let _ = tx.send(base::ChannelMsg::StartCapture(package.clone()));
+    if let Some(secs) = duration_secs {
+        let (tx, package) = (tx.clone(), package.clone());
+        thread::spawn(move || stop_after(&package, Duration::from_secs(secs), &tx));
+    }
+    Ok(Some(CommandLineCapture { package, duration_secs }))
+}
 
-    match handle_request() {
-        Ok(None) => None,
-        Ok(Some(response)) => Some(RpcResponse::new_result(req.id.take(), Some(response))),
-        Err(error) => {
-            log::error!("{}", error.to_string());
-            Some(RpcResponse::new_error(req.id.take(), Some(json!(error.to_string()))))
+// `duration` after the capture of `package` started, unless the user stopped it first: a capture
+// they started since then is left alone. Arming waits for the game as long as it takes.
+fn stop_after(package: &str, duration: Duration, tx: &Sender<base::ChannelMsg>) {
+    let waiting = Instant::now();
+    let started_at = loop {
+        match state::current() {
+            CaptureState::Capturing { package: current, started_at } if current == package => {
+                break started_at
+            }
+            CaptureState::Idle | CaptureState::Error { .. } if waiting.elapsed() > START_GRACE => {
+                return
+            }
+            _ => thread::sleep(STATE_POLL),
         }
+    };
+    thread::sleep(duration);
+    if is_capture(&state::current(), package, started_at) {
+        let _ = tx.send(base::ChannelMsg::StopCapture);
+    }
+}
+
+fn is_capture(state: &CaptureState, package: &str, started_at: u64) -> bool {
+    match state {
+        CaptureState::Capturing { package: current, started_at: current_started_at }
+        | CaptureState::Paused { package: current, started_at: current_started_at } => {
+            current == package && *current_started_at == started_at
+        }
+        _ => false,
+    }
+}
+
+// Arguments handed over by a second instance, see `instance`
+pub fn handle_forwarded_args(
+    args: &ArgMatches,
+    cwd: &Path,
+    proxy: &EventLoopProxy<Event>,
+    tx: &Sender<base::ChannelMsg>,
+) -> Result<()> {
+    let _ = proxy.send_event(Event::FocusWindow);
+
+    if let Some(path) = save_arg(args) {
+        let file = command::open_file(command_line_path(cwd, path))?;
+        let detail = serde_json::to_value(file)?;
+        let _ = proxy.send_event(Event::DispatchCustomEvent("tse_open_save", detail));
     }
+    if let Some(path) = session_arg(args) {
+        let session = session::open_file(&command_line_path(cwd, path))?;
+        let detail = serde_json::to_value(session)?;
+        let _ = proxy.send_event(Event::DispatchCustomEvent("tse_open_session", detail));
+    }
+    if let Some(link) = link_arg(args) {
+        let detail = serde_json::to_value(link::parse(link)?)?;
+        let _ = proxy.send_event(Event::DispatchCustomEvent("tse_open_view", detail));
+    }
+    capture_from_args(args, tx)?;
+    Ok(())
 }
 
 pub enum Event {
     CloseWindow,
+    // Raise the window, e.g. when a second instance was started
+    FocusWindow,
     DispatchCustomEvent(&'static str, serde_json::Value),
-    BoardCastToJs(serde_json::Value), // notify to js no replay
+    // Notify js without reply, dropped when nobody subscribed to the topic
+    Publish(&'static str, serde_json::Value),
+    // Completion of a deferred request
+    RpcResponse(Response),
+    // The page handled published messages up to this seq, see `Coalescer`
+    Acknowledge(u64),
+    // The page missed published messages after this seq, 0 when it just loaded
+    Resync(u64),
+    // Capture thread stopped, see `Shutdown`
+    ShutdownReady,
+    Overlay(overlay::Command),
 }
 
-pub fn event_handler(event: Event, webview: &WebView, control_flow: &mut ControlFlow) {
+pub fn event_handler(
+    event: Event,
+    webview: &WebView,
+    target: &EventLoopWindowTarget<Event>,
+    control_flow: &mut ControlFlow,
+    coalescer: &mut Coalescer,
+    shutdown: &mut Shutdown,
+    overlay: &mut Overlay,
+) {
     match event {
-        Event::CloseWindow => *control_flow = ControlFlow::Exit,
+        Event::CloseWindow => shutdown.begin(webview, control_flow),
+        Event::ShutdownReady => shutdown.finish(webview, control_flow),
+        Event::FocusWindow => {
+            let window = webview.window();
+            window.set_visible(true);
+            window.set_minimized(false);
+            window.set_focus();
+        }
         Event::DispatchCustomEvent(event, detail) => {
-            let _ = webview.evaluate_script(&format!(
-                r#"
-                (() => {{
-                    const event = new CustomEvent("{event}", {{
-                        detail: {detail}
-                    }});
-                    document.dispatchEvent(event);
-                }})();
-                "#,
-                event = event,
-                detail = detail,
-            ));
+            trace::event(event, &detail);
+            event_log::record(event, &detail);
+            let _ = webview.evaluate_script(&bridge::custom_event_script(event, &detail));
         }
-        Event::BoardCastToJs(detail) => {
-            let _ = webview.evaluate_script(&format!(
-                r#"
-                (() => {{
-                    var event = document.createEvent('Event');      
-                    event.initEvent('message', false, true);         
-                    event.data = {data};     
-                    window.dispatchEvent(event);
-                }})();
-                "#,                
-                data = detail,
-            ));
-            // match webview.evaluate_script(r"window.ShibaApp.receive({kinde:'debug'})") {
-            //     Ok(_) => {},
-            //     Err(e) => { println!("rpc error: {:?}", e)}
-            // }
+        Event::Publish(topic, detail) => {
+            event_log::record_published(topic, &detail);
+            if subscription::is_active(topic) {
+                trace::event(topic, &detail);
+                coalescer.push(topic, detail);
+            }
+        }
+        Event::RpcResponse(response) => {
+            trace::response(&response);
+            let _ = webview.evaluate_script(&bridge::rpc_response_script(&response));
         }
+        Event::Acknowledge(seq) => coalescer.acknowledge(seq),
+        Event::Resync(seq) => coalescer.resync(seq, webview),
+        Event::Overlay(command) => {
+            if let Err(err) = overlay.handle(command, target) {
+                log::warn!("overlay: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_capture() {
+        let capture = |package: &str, started_at| CaptureState::Capturing {
+            package: package.into(),
+            started_at,
+        };
+        assert!(is_capture(&capture("com.example.game", 5), "com.example.game", 5));
+        let paused = CaptureState::Paused { package: "com.example.game".into(), started_at: 5 };
+        assert!(is_capture(&paused, "com.example.game", 5));
+        // Stopped and started again before the timer ran out
+        assert!(!is_capture(&capture("com.example.game", 9), "com.example.game", 5));
+        assert!(!is_capture(&capture("com.example.other", 5), "com.example.game", 5));
+        assert!(!is_capture(&CaptureState::Idle, "com.example.game", 5));
     }
 }
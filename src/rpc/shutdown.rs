@@ -0,0 +1,68 @@
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use wry::{
+    application::{event_loop::ControlFlow, window::Window},
+    webview::WebView,
+};
+
+use crate::base::ChannelMsg;
+use crate::config::{self, WindowState};
+
+use super::bridge;
+
+// Exit anyway when the capture thread does not answer in time
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Closing first stops the capture and lets the frontend know, the event loop exits once the
+// capture thread answered with `Event::ShutdownReady` (or after `TIMEOUT`)
+pub struct Shutdown {
+    tx: Sender<ChannelMsg>,
+    deadline: Option<Instant>,
+}
+
+impl Shutdown {
+    pub fn new(tx: Sender<ChannelMsg>) -> Self {
+        Shutdown { tx, deadline: None }
+    }
+
+    pub fn begin(&mut self, webview: &WebView, control_flow: &mut ControlFlow) {
+        if self.deadline.is_some() {
+            return;
+        }
+        log::info!("shutting down");
+        let detail = json!({ "timeout_ms": TIMEOUT.as_millis() as u64 });
+        let _ = webview.evaluate_script(&bridge::custom_event_script("shutting_down", &detail));
+
+        // No capture thread left to wait for
+        if self.tx.send(ChannelMsg::Shutdown).is_err() {
+            return self.finish(webview, control_flow);
+        }
+        self.deadline = Some(Instant::now() + TIMEOUT);
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn check_timeout(&mut self, webview: &WebView, control_flow: &mut ControlFlow) {
+        if matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+            log::warn!("capture did not stop in time, exiting anyway");
+            self.finish(webview, control_flow);
+        }
+    }
+
+    pub fn finish(&mut self, webview: &WebView, control_flow: &mut ControlFlow) {
+        let window = window_state(webview.window());
+        if let Err(err) = config::update(|config| config.window = window) {
+            log::error!("saving config: {}", err);
+        }
+        *control_flow = ControlFlow::Exit;
+    }
+}
+
+fn window_state(window: &Window) -> Option<WindowState> {
+    let position = window.outer_position().ok()?;
+    Some(WindowState { x: position.x, y: position.y, maximized: window.is_maximized() })
+}
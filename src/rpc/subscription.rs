@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+pub const SAMPLES_LIVE: &str = "samples.live";
+pub const FOREGROUND_APP: &str = "foreground_app";
+pub const CAPTURE_HEALTH: &str = "capture_health";
+
+pub const TOPICS: &[&str] = &[SAMPLES_LIVE, FOREGROUND_APP, CAPTURE_HEALTH];
+// State-like topics only need their latest value when coalesced
+const LATEST_ONLY: &[&str] = &[FOREGROUND_APP, CAPTURE_HEALTH];
+
+lazy_static! {
+    // Subscribers per topic, a topic stays active until the last one unsubscribed
+    static ref SUBSCRIPTIONS: RwLock<HashMap<&'static str, usize>> = RwLock::new(HashMap::new());
+}
+
+pub fn subscribe(topic: &str) -> Result<()> {
+    match TOPICS.iter().find(|t| **t == topic) {
+        Some(topic) => {
+            *SUBSCRIPTIONS.write().entry(topic).or_default() += 1;
+            Ok(())
+        }
+        None => bail!("Unknown topic: {}", topic),
+    }
+}
+
+pub fn unsubscribe(topic: &str) {
+    let mut subscriptions = SUBSCRIPTIONS.write();
+    if let Some(count) = subscriptions.get_mut(topic) {
+        *count -= 1;
+        if *count == 0 {
+            subscriptions.remove(topic);
+        }
+    }
+}
+
+pub fn is_active(topic: &str) -> bool {
+    SUBSCRIPTIONS.read().contains_key(topic)
+}
+
+pub fn active_topics() -> Vec<&'static str> {
+    SUBSCRIPTIONS.read().keys().copied().collect()
+}
+
+pub fn latest_only(topic: &str) -> bool {
+    LATEST_ONLY.contains(&topic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriptions() -> Result<()> {
+        subscribe(FOREGROUND_APP)?;
+        subscribe(FOREGROUND_APP)?;
+        unsubscribe(FOREGROUND_APP);
+        assert!(is_active(FOREGROUND_APP));
+        unsubscribe(FOREGROUND_APP);
+        assert!(!is_active(FOREGROUND_APP));
+        unsubscribe(FOREGROUND_APP);
+        assert!(subscribe("unknown").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use wry::application::{event_loop::EventLoop, window::WindowBuilder};
+
+use crate::ci::{EXIT_ERROR, EXIT_FAILED, EXIT_PASSED};
+use crate::config;
+
+use super::jsonrpc::{Request, Response, RpcError};
+use super::{dispatch, Event, RpcUtils};
+
+// Not replayed, they open dialogs or act on the window
+const SKIPPED: &[&str] = &[
+    "init",
+    "minimize",
+    "toggle_maximize",
+    "drag_window",
+    "close",
+    "import_head_morph",
+    "export_head_morph_dialog",
+    "open_save",
+    "save_save_dialog",
+    "grant_path_access",
+    "export_all_head_morphs",
+    "pick_directory",
+];
+
+lazy_static! {
+    static ref TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+}
+
+// One JSON object per line, requests are traced once authenticated so the token never is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Entry {
+    Request { at_ms: u64, id: Option<Value>, method: String, params: Option<Value> },
+    Response { at_ms: u64, id: Value, result: Option<Value>, error: Option<RpcError> },
+    Event { at_ms: u64, name: String, detail: Value },
+}
+
+struct Tracer {
+    file: File,
+    started: Instant,
+}
+
+// `--trace-rpc`, everything going through the bridge from now on
+pub fn start(path: &Path) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    *TRACER.lock() = Some(Tracer { file, started: Instant::now() });
+    log::info!("tracing rpc to {}", path.display());
+    Ok(())
+}
+
+fn write(entry: impl FnOnce(u64) -> Entry) {
+    let mut tracer = TRACER.lock();
+    let tracer = match tracer.as_mut() {
+        Some(tracer) => tracer,
+        None => return,
+    };
+    let entry = entry(tracer.started.elapsed().as_millis() as u64);
+    let result = serde_json::to_string(&entry)
+        .map_err(io::Error::from)
+        .and_then(|line| writeln!(tracer.file, "{}", line));
+    if let Err(err) = result {
+        log::warn!("rpc trace: {}", err);
+    }
+}
+
+pub fn request(req: &Request) {
+    write(|at_ms| Entry::Request {
+        at_ms,
+        id: req.id.clone(),
+        method: req.method.clone(),
+        params: req.params.clone(),
+    });
+}
+
+pub fn response(response: &Response) {
+    write(|at_ms| Entry::Response {
+        at_ms,
+        id: response.id.clone(),
+        result: response.result.clone(),
+        error: response.error.clone(),
+    });
+}
+
+pub fn event(name: &str, detail: &Value) {
+    write(|at_ms| Entry::Event { at_ms, name: name.into(), detail: detail.clone() });
+}
+
+fn load(path: &Path) -> Result<Vec<Entry>> {
+    let trace =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    trace
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).with_context(|| format!("Line {}", index + 1))
+        })
+        .collect()
+}
+
+// Errors compare by message, their data may hold paths or timings
+fn outcome(result: &Result<Option<Value>, RpcError>) -> Value {
+    match result {
+        Ok(value) => json!({ "result": value.clone().unwrap_or(Value::Null) }),
+        Err(error) => json!({ "error": error.message }),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Matched,
+    Differs,
+    Skipped,
+    // A notification, or the trace ended before the response
+    Unanswered,
+}
+
+#[derive(Serialize)]
+struct Replayed {
+    method: String,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recorded: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replayed: Option<Value>,
+}
+
+#[derive(Serialize, Default)]
+struct Summary {
+    passed: bool,
+    requests: usize,
+    differs: usize,
+    replays: Vec<Replayed>,
+    error: Option<String>,
+}
+
+// A scratch config and data directory, starting from a copy of the user's config. Whatever the
+// replayed commands write or delete stays in there.
+fn sandbox() -> Result<PathBuf> {
+    let root = env::temp_dir().join(format!("gameperf-replay-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let user_config = config::config_path();
+    config::redirect(&root);
+    let copy = config::config_path().context("No config directory")?;
+    fs::create_dir_all(copy.parent().context("Invalid config path")?)?;
+    if let Some(user_config) = user_config.filter(|path| path.is_file()) {
+        fs::copy(user_config, copy)?;
+    }
+    Ok(root)
+}
+
+// `replay-rpc`, runs the traced requests again in order without the UI and prints how the
+// responses compare. Commands reach no capture thread, captures aren't started, and the settings
+// and sessions they see are a sandbox (see `sandbox`).
+pub fn replay(args: &ArgMatches, replay_args: &ArgMatches) -> i32 {
+    let mut summary = Summary::default();
+    let result = sandbox().and_then(|root| {
+        let result = execute(args, replay_args, &mut summary);
+        if let Err(err) = fs::remove_dir_all(&root) {
+            log::warn!("{}: {}", root.display(), err);
+        }
+        result
+    });
+    let code = match result {
+        Ok(()) if summary.differs == 0 => EXIT_PASSED,
+        Ok(()) => EXIT_FAILED,
+        Err(err) => {
+            log::error!("{}", err);
+            summary.error = Some(err.to_string());
+            EXIT_ERROR
+        }
+    };
+    summary.passed = code == EXIT_PASSED;
+    println!("{}", serde_json::to_string_pretty(&summary).unwrap_or_default());
+    code
+}
+
+fn execute(args: &ArgMatches, replay_args: &ArgMatches, summary: &mut Summary) -> Result<()> {
+    let path = replay_args.value_of("trace").context("Trace file required")?;
+    let entries = load(Path::new(path))?;
+    // In order per id, the page starts counting over when reloaded
+    let mut responses: HashMap<String, VecDeque<Value>> = HashMap::new();
+    for entry in &entries {
+        if let Entry::Response { id, result, error, .. } = entry {
+            let recorded = match error {
+                Some(error) => Err(error.clone()),
+                None => Ok(result.clone()),
+            };
+            responses.entry(id.to_string()).or_default().push_back(outcome(&recorded));
+        }
+    }
+
+    // Commands take the window, it's never shown
+    let event_loop = EventLoop::<Event>::with_user_event();
+    let window = WindowBuilder::new().with_visible(false).build(&event_loop)?;
+    let proxy = event_loop.create_proxy();
+    let (tx, _rx) = mpsc::channel();
+    let utils = RpcUtils { window: &window, event_proxy: &proxy, args, tx: &tx };
+
+    let realtime = replay_args.is_present("realtime");
+    let started = Instant::now();
+    for entry in entries {
+        let (at_ms, id, method, params) = match entry {
+            Entry::Request { at_ms, id, method, params } => (at_ms, id, method, params),
+            _ => continue,
+        };
+        summary.requests += 1;
+        if realtime {
+            let at = Duration::from_millis(at_ms);
+            thread::sleep(at.saturating_sub(started.elapsed()));
+        }
+        let recorded = id.as_ref().and_then(|id| responses.get_mut(&id.to_string())?.pop_front());
+        if SKIPPED.contains(&method.as_str()) {
+            let status = Status::Skipped;
+            summary.replays.push(Replayed { method, status, recorded: None, replayed: None });
+            continue;
+        }
+
+        let mut req = Request { id, method: method.clone(), params };
+        let replayed = outcome(&dispatch(&mut req, &utils));
+        let replay = match recorded {
+            Some(recorded) if recorded == replayed => {
+                Replayed { method, status: Status::Matched, recorded: None, replayed: None }
+            }
+            Some(recorded) => {
+                summary.differs += 1;
+                log::warn!("{}: response differs", method);
+                let (recorded, replayed) = (Some(recorded), Some(replayed));
+                Replayed { method, status: Status::Differs, recorded, replayed }
+            }
+            None => {
+                let status = Status::Unanswered;
+                Replayed { method, status, recorded: None, replayed: Some(replayed) }
+            }
+        };
+        summary.replays.push(replay);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() -> Result<()> {
+        let line = r#"{"kind":"request","at_ms":12,"id":3,"method":"list_sessions","params":[]}"#;
+        let entry: Entry = serde_json::from_str(line)?;
+        let method = match entry {
+            Entry::Request { at_ms: 12, method, .. } => method,
+            _ => panic!("Not a request"),
+        };
+        assert_eq!(method, "list_sessions");
+
+        let error = RpcError::new(-32000, "No such session");
+        assert_eq!(outcome(&Err(error)), json!({ "error": "No such session" }));
+        assert_eq!(outcome(&Ok(None)), json!({ "result": null }));
+        Ok(())
+    }
+}
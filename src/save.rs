@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+// PC save versions, the file ends with a CRC32 of everything before it
+const KNOWN_VERSIONS: &[(u32, &str)] = &[
+    (29, "Mass Effect 2"),
+    (30, "Mass Effect 2 LE"),
+    (59, "Mass Effect 3"),
+    (60, "Mass Effect 3 LE"),
+];
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Checksum {
+    pub stored: u32,
+    pub computed: u32,
+    pub valid: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SaveReport {
+    pub path: PathBuf,
+    pub size: usize,
+    pub game: Option<&'static str>,
+    pub version: Option<u32>,
+    pub checksum: Option<Checksum>,
+    pub problems: Vec<Problem>,
+    pub valid: bool,
+    pub repaired: bool,
+}
+
+impl SaveReport {
+    fn problem(&mut self, severity: Severity, message: impl Into<String>) {
+        if severity == Severity::Error {
+            self.valid = false;
+        }
+        self.problems.push(Problem { severity, message: message.into() });
+    }
+}
+
+pub fn verify(path: &Path, bytes: &[u8]) -> SaveReport {
+    let mut report = SaveReport {
+        path: path.to_owned(),
+        size: bytes.len(),
+        game: None,
+        version: None,
+        checksum: None,
+        problems: vec![],
+        valid: true,
+        repaired: false,
+    };
+
+    let is_me1 = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("MassEffectSave"));
+    if is_me1 {
+        report.game = Some("Mass Effect");
+        report.problem(Severity::Warning, "Mass Effect saves have no checksum");
+        return report;
+    }
+
+    if bytes.len() < 8 {
+        report.problem(
+            Severity::Error,
+            format!("File too small to be a save ({} bytes)", bytes.len()),
+        );
+        return report;
+    }
+
+    let version = read_u32(bytes, 0);
+    report.version = Some(version);
+    report.game = KNOWN_VERSIONS.iter().find(|(v, _)| *v == version).map(|(_, game)| *game);
+    if report.game.is_none() {
+        report.problem(Severity::Warning, format!("Unknown save version {}", version));
+    }
+
+    let stored = read_u32(bytes, bytes.len() - 4);
+    let computed = crc32fast::hash(&bytes[..bytes.len() - 4]);
+    let valid = stored == computed;
+    if !valid {
+        report.problem(
+            Severity::Error,
+            format!("Checksum mismatch: stored {:08x}, computed {:08x}", stored, computed),
+        );
+    }
+    report.checksum = Some(Checksum { stored, computed, valid });
+    report
+}
+
+// Rewrites the CRC32 trailer, returns false when the file has no checksum to repair. Only for
+// known versions, the trailer of anything else may not be a CRC and a mismatch may be real damage.
+pub fn repair_checksum(path: &Path, bytes: &mut [u8]) -> Result<bool> {
+    let report = verify(path, bytes);
+    if let (Some(version), None) = (report.version, report.game) {
+        bail!("Not repairing save version {}, unknown format", version);
+    }
+    match report.checksum {
+        Some(Checksum { computed, valid: false, .. }) => {
+            let len = bytes.len();
+            bytes[len - 4..].copy_from_slice(&computed.to_le_bytes());
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save(version: u32) -> Vec<u8> {
+        let mut bytes = version.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"save data");
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_repair_checksum() {
+        let path = Path::new("Save_0001.pcsav");
+        let mut known = save(59);
+        assert!(repair_checksum(path, &mut known).unwrap());
+        assert!(verify(path, &known).valid);
+        assert!(!repair_checksum(path, &mut known).unwrap());
+
+        let mut unknown = save(12);
+        assert!(repair_checksum(path, &mut unknown).is_err());
+        assert_eq!(unknown, save(12));
+    }
+}
@@ -0,0 +1,217 @@
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::Child;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+use crate::analysis::Stats;
+use crate::capture::{thermal, Recorder, Recording};
+use crate::session::{self, import, Source};
+use crate::util;
+
+// The notification shade is on every device and animates without touching the user's apps
+const WORKLOAD_PACKAGE: &str = "com.android.systemui";
+const DURATION: Duration = Duration::from_secs(6);
+const INTERVAL: Duration = Duration::from_millis(500);
+// Busy loop on the device, ended by `timeout` even if adb goes away
+const STRESS: &str = "shell timeout 8 sh -c 'while :; do :; done'";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    // What was measured, or what went wrong
+    pub detail: String,
+    pub duration_ms: u64,
+}
+
+// For support triage, with the version that ran it
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    pub passed: bool,
+    pub checks: Vec<Check>,
+}
+
+// CPU load and the notification shade opening and closing, the shade is closed when dropped
+struct Workload {
+    stress: Child,
+    open: bool,
+}
+
+impl Workload {
+    fn start() -> Result<Self> {
+        Ok(Workload { stress: util::adb_spawn(STRESS)?, open: false })
+    }
+
+    fn toggle(&mut self) {
+        self.open = !self.open;
+        let command = if self.open { "expand-notifications" } else { "collapse" };
+        let _ = util::adb(format!("shell cmd statusbar {}", command));
+    }
+}
+
+impl Drop for Workload {
+    fn drop(&mut self) {
+        if self.open {
+            self.toggle();
+        }
+        let _ = self.stress.kill();
+    }
+}
+
+// A panicking check (e.g. `util::cmd` without adb) fails like any other
+fn run(checks: &mut Vec<Check>, name: &'static str, check: impl FnOnce() -> Result<String>) {
+    let started = Instant::now();
+    let (passed, detail) = match panic::catch_unwind(AssertUnwindSafe(check)) {
+        Ok(Ok(detail)) => (true, detail),
+        Ok(Err(err)) => (false, format!("{:#}", err)),
+        Err(panic) => (false, panic_message(panic.as_ref())),
+    };
+    let duration_ms = started.elapsed().as_millis() as u64;
+    checks.push(Check { name, passed, detail, duration_ms });
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error");
+    format!("Crashed: {}", message)
+}
+
+fn skip(checks: &mut Vec<Check>, name: &'static str, reason: &str) {
+    let detail = format!("Skipped, {}", reason);
+    checks.push(Check { name, passed: false, detail, duration_ms: 0 });
+}
+
+fn record() -> Result<Recording> {
+    let mut workload = Workload::start()?;
+    let mut recorder = Recorder::new(WORKLOAD_PACKAGE);
+    recorder.interval(INTERVAL);
+    let started = Instant::now();
+    while started.elapsed() < DURATION {
+        workload.toggle();
+        recorder.poll()?;
+        thread::sleep(INTERVAL);
+    }
+    Ok(recorder.finish())
+}
+
+// Steady 60 FPS, for the export check when nothing could be captured
+fn synthetic() -> Recording {
+    Recording {
+        package: WORKLOAD_PACKAGE.into(),
+        duration_ms: 2000,
+        frametimes: vec![1000.0 / 60.0; 120],
+        ..Default::default()
+    }
+}
+
+// Saved, exported anonymized, read back and deleted again
+fn round_trip(recording: &Recording) -> Result<String> {
+    let info = session::add("Self-test", Source::GamePerf, recording, None)?;
+    let path = env::temp_dir().join(format!("gameperf-self-test.{}", session::EXTENSION));
+    let result = session::export(&info.id, &path, true).and_then(|_| import::import(&path));
+    let _ = fs::remove_file(&path);
+    session::delete(&info.id, false)?;
+
+    let imported = result?.recording;
+    if imported.frametimes != recording.frametimes {
+        bail!(
+            "{} frames exported, {} read back",
+            recording.frametimes.len(),
+            imported.frametimes.len()
+        );
+    }
+    Ok(format!("{} frames exported and read back", imported.frametimes.len()))
+}
+
+// About ten seconds with a device connected
+pub fn run_all() -> Report {
+    let mut checks = vec![];
+    let mut connected = false;
+    run(&mut checks, "device", || {
+        let (_, state, _) = util::adb("get-state".into()).context("No device connected")?;
+        if state.trim() != "device" {
+            bail!("Device {}", state.trim());
+        }
+        connected = true;
+        Ok(util::adb("shell getprop ro.product.model".into())?.1.trim().to_string())
+    });
+
+    let mut recording = None;
+    if connected {
+        run(&mut checks, "capture", || {
+            let captured = record()?;
+            let detail = format!("{} samples of {}", captured.samples.len(), WORKLOAD_PACKAGE);
+            recording = Some(captured);
+            Ok(detail)
+        });
+        run(&mut checks, "sensors", || {
+            let temperatures = thermal::read()?;
+            let celsius = |value: Option<f64>| value.map_or("-".into(), |c| format!("{:.1} °C", c));
+            Ok(format!("CPU {}, GPU {}", celsius(temperatures.cpu), celsius(temperatures.gpu)))
+        });
+    } else {
+        skip(&mut checks, "capture", "no device");
+        skip(&mut checks, "sensors", "no device");
+    }
+
+    match &recording {
+        Some(recording) => {
+            run(&mut checks, "frametimes", || {
+                let frametimes = &recording.frametimes;
+                if frametimes.is_empty() {
+                    bail!("No frames from SurfaceFlinger");
+                }
+                if frametimes.iter().any(|&ms| !ms.is_finite() || ms <= 0.0) {
+                    bail!("Invalid frame times");
+                }
+                let stats = Stats::compute(recording);
+                Ok(format!("{} frames, {:.1} FPS average", stats.frames, stats.avg_fps))
+            });
+            run(&mut checks, "memory", || {
+                let sampled = recording
+                    .samples
+                    .iter()
+                    .filter(|sample| sample.metrics.keys().any(|key| key.starts_with("mem.")))
+                    .count();
+                if sampled == 0 {
+                    bail!("No memory readings");
+                }
+                Ok(format!("{} of {} samples", sampled, recording.samples.len()))
+            });
+        }
+        None => {
+            skip(&mut checks, "frametimes", "nothing captured");
+            skip(&mut checks, "memory", "nothing captured");
+        }
+    }
+
+    let exported = recording.unwrap_or_else(synthetic);
+    run(&mut checks, "export", || round_trip(&exported));
+
+    let passed = checks.iter().all(|check| check.passed);
+    Report { version: env!("CARGO_PKG_VERSION"), passed, checks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut checks = vec![];
+        run(&mut checks, "ok", || Ok("fine".into()));
+        run(&mut checks, "panics", || panic!("adb not found"));
+        assert!(checks[0].passed);
+        assert!(!checks[1].passed);
+        assert_eq!(checks[1].detail, "Crashed: adb not found");
+    }
+}
@@ -0,0 +1,363 @@
+use std::env;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gameperf_core::report::Report;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::base::state::CaptureState;
+use crate::capture::syslog;
+use crate::config;
+use crate::util;
+
+use super::SessionInfo;
+
+lazy_static! {
+    // `C:\Users\...`, `\\server\share\...` and home directories, up to the next separator
+    static ref PATH: Regex = Regex::new(concat!(
+        r#"(?:[A-Za-z]:[\\/]|\\\\|/(?:home|Users|root|storage|sdcard|data)\b)"#,
+        r#"[^\s"',;|]*"#
+    ))
+    .unwrap();
+    // Android packages and other dotted identifiers, `com.example.game`
+    static ref PACKAGE: Regex =
+        Regex::new(r"\b[A-Za-z][A-Za-z0-9_]*(?:\.[A-Za-z][A-Za-z0-9_]*){2,}\b").unwrap();
+    // PC games and their modules, `game.exe`
+    static ref EXECUTABLE: Regex =
+        Regex::new(r"(?i)\b[A-Za-z0-9_][A-Za-z0-9_\-]*\.(?:exe|dll|apk)\b").unwrap();
+    // Window titles, `Window{8a1c2 u0 Some Title}` in Android's focus lines and `title="..."`
+    static ref WINDOW: Regex = Regex::new(r"(Window\{[0-9a-f]+ u\d+ )([^}]+)\}").unwrap();
+    static ref TITLE: Regex =
+        Regex::new(r#"(?i)\b(title\s*[=:]\s*)("[^"]*"|'[^']*')"#).unwrap();
+    // Keys the hashes, so they can't be looked up from a list of known packages
+    static ref SECRET: Vec<u8> = secret();
+}
+
+// Mirrors `Config::privacy`, for the logger which can't take the config lock
+static PRIVATE: AtomicBool = AtomicBool::new(false);
+
+// Replaced wherever they appear in a string
+const USER: &str = "<user>";
+const MACHINE: &str = "<machine>";
+const SERIAL: &str = "<serial>";
+const PATH_PLACEHOLDER: &str = "<path>";
+// Next to the config, created on first use
+const SECRET_FILE: &str = "privacy.key";
+const SECRET_LEN: usize = 32;
+const HASH_PREFIX: &str = "app-";
+const HASH_LEN: usize = 12;
+
+// What identifies the people and machines behind a session
+#[derive(Debug, Clone, Default)]
+pub struct Identity {
+    pub users: Vec<String>,
+    pub machines: Vec<String>,
+    pub serials: Vec<String>,
+}
+
+impl Identity {
+    // This PC's user and host names and the connected device's serial
+    pub fn current() -> Self {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+        let mut identity = Identity {
+            users: ["USERNAME", "USER"].iter().filter_map(|name| var(name)).collect(),
+            machines: ["COMPUTERNAME", "HOSTNAME"].iter().filter_map(|name| var(name)).collect(),
+            serials: vec![],
+        };
+        if let Ok((true, serial, _)) = util::adb("get-serialno".into()) {
+            identity.serials.push(serial.trim().to_string());
+        }
+        for values in [&mut identity.users, &mut identity.machines, &mut identity.serials] {
+            // Too short to replace without mangling everything else
+            values.retain(|value| value.len() >= 3 && value != "unknown");
+            values.sort();
+            values.dedup();
+        }
+        identity
+    }
+
+    fn redact(&self, text: &str) -> String {
+        let mut text = PATH.replace_all(text, PATH_PLACEHOLDER).into_owned();
+        // Machine names often contain the user name
+        let replacements =
+            [(&self.serials, SERIAL), (&self.machines, MACHINE), (&self.users, USER)];
+        for (values, placeholder) in replacements {
+            for value in values {
+                text = replace_ignore_case(&text, value, placeholder);
+            }
+        }
+        text
+    }
+}
+
+fn replace_ignore_case(text: &str, from: &str, to: &str) -> String {
+    match Regex::new(&format!("(?i){}", regex::escape(from))) {
+        Ok(re) => re.replace_all(text, to).into_owned(),
+        Err(_) => text.to_string(),
+    }
+}
+
+// One value changed or removed, `field` is a JSON pointer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Redaction {
+    pub field: String,
+    pub before: String,
+    pub after: Option<String>,
+}
+
+// Strips `value` in place. Fields named after serials go, every other string loses paths,
+// user, machine names and serials.
+pub fn anonymize(value: &mut Value, identity: &Identity) -> Vec<Redaction> {
+    let mut redactions = vec![];
+    walk(value, String::new(), identity, &mut redactions);
+    redactions
+}
+
+fn pointer(parent: &str, key: &str) -> String {
+    format!("{}/{}", parent, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn walk(value: &mut Value, field: String, identity: &Identity, redactions: &mut Vec<Redaction>) {
+    match value {
+        Value::String(text) => {
+            let redacted = identity.redact(text);
+            if redacted != *text {
+                let before = std::mem::replace(text, redacted.clone());
+                redactions.push(Redaction { field, before, after: Some(redacted) });
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                walk(value, format!("{}/{}", field, i), identity, redactions);
+            }
+        }
+        Value::Object(object) => {
+            let serials: Vec<String> = object
+                .keys()
+                .filter(|key| key.to_lowercase().contains("serial"))
+                .cloned()
+                .collect();
+            for key in serials {
+                if let Some(removed) = object.remove(&key) {
+                    let before = match removed {
+                        Value::String(text) => text,
+                        other => other.to_string(),
+                    };
+                    let field = pointer(&field, &key);
+                    redactions.push(Redaction { field, before, after: None });
+                }
+            }
+            for (key, value) in object.iter_mut() {
+                walk(value, pointer(&field, key), identity, redactions);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn set_private(private: bool) {
+    PRIVATE.store(private, Ordering::Relaxed);
+}
+
+pub fn is_private() -> bool {
+    PRIVATE.load(Ordering::Relaxed)
+}
+
+// This install's key, a new one each run when it can't be stored. Nothing is logged here, the
+// logger hashes too.
+fn secret() -> Vec<u8> {
+    if cfg!(test) {
+        return vec![0; SECRET_LEN];
+    }
+    let path = config::config_dir().map(|dir| dir.join(SECRET_FILE));
+    let stored = path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+    if let Some(secret) = stored.and_then(|text| base64::decode(text.trim()).ok()) {
+        if secret.len() == SECRET_LEN {
+            return secret;
+        }
+    }
+    let secret: [u8; SECRET_LEN] = rand::random();
+    if let Some(path) = path {
+        let _ = path.parent().map(fs::create_dir_all);
+        let _ = fs::write(path, base64::encode(secret));
+    }
+    secret.to_vec()
+}
+
+// Stands in for a package or process name, the same name always gets the same one on this
+// install. Names that are hashes already are kept.
+pub fn hashed(name: &str) -> String {
+    if is_hashed(name) {
+        return name.to_string();
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(&SECRET).expect("HMAC takes any key length");
+    mac.update(name.as_bytes());
+    let digest = format!("{:x}", mac.finalize().into_bytes());
+    format!("{}{}", HASH_PREFIX, &digest[..HASH_LEN])
+}
+
+fn is_hashed(name: &str) -> bool {
+    name.strip_prefix(HASH_PREFIX).map_or(false, |digest| {
+        digest.len() == HASH_LEN && digest.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+// Paths gone, `package`, executables, window titles and anything named like a package hashed
+pub fn private_text(text: &str, package: &str) -> String {
+    let mut text = PATH.replace_all(text, PATH_PLACEHOLDER).into_owned();
+    if !package.is_empty() {
+        text = replace_ignore_case(&text, package, &hashed(package));
+    }
+    let text = WINDOW.replace_all(&text, |captures: &regex::Captures| {
+        format!("{}{}}}", &captures[1], hashed(&captures[2]))
+    });
+    let text = TITLE.replace_all(&text, |captures: &regex::Captures| {
+        let title = captures[2].trim_matches(|c| c == '"' || c == '\'');
+        format!("{}\"{}\"", &captures[1], hashed(title))
+    });
+    let text = EXECUTABLE.replace_all(&text, |captures: &regex::Captures| hashed(&captures[0]));
+    PACKAGE.replace_all(&text, |captures: &regex::Captures| hashed(&captures[0])).into_owned()
+}
+
+// What the gRPC API, the viewer and MQTT show in privacy mode
+pub fn private_state(state: CaptureState) -> CaptureState {
+    if !is_private() {
+        return state;
+    }
+    match state {
+        CaptureState::Arming { package } => CaptureState::Arming { package: hashed(&package) },
+        CaptureState::Capturing { package, started_at } => {
+            CaptureState::Capturing { package: hashed(&package), started_at }
+        }
+        CaptureState::Paused { package, started_at } => {
+            CaptureState::Paused { package: hashed(&package), started_at }
+        }
+        CaptureState::Finalizing { package } => {
+            CaptureState::Finalizing { package: hashed(&package) }
+        }
+        CaptureState::Error { message } => {
+            CaptureState::Error { message: private_text(&message, "") }
+        }
+        CaptureState::Idle => CaptureState::Idle,
+    }
+}
+
+// Sessions recorded before privacy mode was turned on still have their names
+pub fn private_session(mut info: SessionInfo) -> SessionInfo {
+    if !is_private() {
+        return info;
+    }
+    info.name = private_text(&info.name, &info.package);
+    info.notes = private_text(&info.notes, &info.package);
+    info.package = hashed(&info.package);
+    info.imported_from = None;
+    info
+}
+
+pub fn private_report(mut report: Report) -> Report {
+    if !is_private() {
+        return report;
+    }
+    let package = std::mem::take(&mut report.session.package);
+    report.session.name = private_text(&report.session.name, &package);
+    report.session.package = hashed(&package);
+    for value in report.system.iter_mut().flat_map(|system| system.values_mut()) {
+        *value = private_text(value, &package);
+    }
+    report
+}
+
+// Privacy mode for pre-release builds under NDA, applied before a session is stored: process
+// identities hashed, paths removed, and the device log left out as its lines carry window titles
+// and paths
+pub fn private(recording: &mut Value) {
+    let package = recording["package"].as_str().unwrap_or_default().to_string();
+    if let Some(annotations) = recording["annotations"].as_object_mut() {
+        annotations.remove(syslog::CHANNEL);
+    }
+    if let Some(processes) = recording.pointer_mut("/background/processes") {
+        for name in processes.as_array_mut().into_iter().flatten().map(|p| &mut p["name"]) {
+            if let Some(text) = name.as_str() {
+                *name = Value::String(hashed(text));
+            }
+        }
+    }
+    hash_strings(recording, &package);
+}
+
+fn hash_strings(value: &mut Value, package: &str) {
+    match value {
+        Value::String(text) => *text = private_text(text, package),
+        Value::Array(values) => values.iter_mut().for_each(|value| hash_strings(value, package)),
+        Value::Object(object) => object.values_mut().for_each(|value| hash_strings(value, package)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_anonymize() {
+        let identity = Identity {
+            users: vec!["alice".into()],
+            machines: vec!["ALICE-PC".into()],
+            serials: vec!["R58M123ABC".into()],
+        };
+        let mut value = json!({
+            "package": "com.example.game",
+            "frametimes": [16.6, 16.7],
+            "system": { "device": {
+                "ro.product.model": "Pixel 7",
+                "ro.serialno": "R58M123ABC"
+            }},
+            "annotations": { "game": [
+                { "label": "loaded C:\\Users\\alice\\save.dat on alice-pc" },
+                { "label": "device r58m123abc ready" }
+            ]}
+        });
+        let redactions = anonymize(&mut value, &identity);
+        assert_eq!(redactions.len(), 3);
+        assert_eq!(value["system"]["device"].get("ro.serialno"), None);
+        assert_eq!(value["annotations"]["game"][0]["label"], "loaded <path> on <machine>");
+        assert_eq!(value["annotations"]["game"][1]["label"], "device <serial> ready");
+        assert_eq!(value["package"], "com.example.game");
+        assert_eq!(redactions[0].field, "/annotations/game/0/label");
+
+        let mut value = json!({
+            "package": "game.exe",
+            "annotations": {
+                "system": [{ "label": "ANR in com.example.game" }],
+                "game": [{ "label": "Loaded D:\\Builds\\game.exe level 3" }]
+            },
+            "background": { "processes": [{ "pid": 42, "name": "Discord.exe", "cpu": 8.0 }] }
+        });
+        private(&mut value);
+        assert_eq!(value["package"], hashed("game.exe"));
+        assert_eq!(value["annotations"].get("system"), None);
+        assert_eq!(value["annotations"]["game"][0]["label"], "Loaded <path> level 3");
+        assert_eq!(value["background"]["processes"][0]["name"], hashed("Discord.exe"));
+        assert_eq!(
+            private_text("com.example.game died", ""),
+            format!("{} died", hashed("com.example.game"))
+        );
+        assert_eq!(private_text("game.exe exited", ""), format!("{} exited", hashed("game.exe")));
+        assert_eq!(
+            private_text("mCurrentFocus=Window{8a1c2 u0 Secret Game}", ""),
+            format!("mCurrentFocus=Window{{8a1c2 u0 {}}}", hashed("Secret Game"))
+        );
+        assert_eq!(
+            private_text(r#"focus title="Secret Game" pid=4"#, ""),
+            format!(r#"focus title="{}" pid=4"#, hashed("Secret Game"))
+        );
+        assert!(is_hashed(&hashed("game.exe")));
+        assert_eq!(hashed(&hashed("game.exe")), hashed("game.exe"));
+    }
+}
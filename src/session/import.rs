@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use walkdir::WalkDir;
+
+use crate::capture::{system, Recording};
+use crate::format;
+
+use super::Source;
+
+// Anything else in a scanned folder is ignored
+const EXTENSIONS: &[&str] = &[super::EXTENSION, "json", "csv", "etl"];
+
+pub struct Imported {
+    pub name: String,
+    pub source: Source,
+    pub recording: Recording,
+}
+
+pub fn scan(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn etl_to_csv(path: &Path) -> Result<String> {
+    crate::windows::etl::to_presentmon_csv(path)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn etl_to_csv(_: &Path) -> Result<String> {
+    bail!("ETL traces can only be imported on Windows")
+}
+
+pub fn import(path: &Path) -> Result<Imported> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    // Traces run into gigabytes, PresentMon reads them itself
+    let (source, mut recording) = if extension.eq_ignore_ascii_case("etl") {
+        (Source::Etl, format::parse_presentmon(&etl_to_csv(path)?)?)
+    } else if extension.eq_ignore_ascii_case("csv") {
+        let bytes = fs::read(path)?;
+        let text = std::str::from_utf8(&bytes).context("CSV is not valid UTF-8")?;
+        if format::is_mangohud(text) {
+            (Source::MangoHud, format::parse_mangohud(text)?)
+        } else {
+            (Source::PresentMon, format::parse_presentmon(text)?)
+        }
+    } else {
+        let json: Value = serde_json::from_slice(&fs::read(path)?).context("Invalid JSON")?;
+        if json.get("Runs").is_some() {
+            (Source::CapFrameX, format::parse_capframex(&json)?)
+        } else if json.get("frametimes").is_some() || super::is_session_file(path) {
+            (Source::GamePerf, format::read_recording(json)?)
+        } else {
+            bail!("Not a capture file");
+        }
+    };
+
+    // PC logs are mostly imported on the machine that recorded them, MangoHud's bring their own
+    if source != Source::GamePerf && recording.system.is_none() {
+        recording.system = Some(system::host_snapshot(&recording.package));
+    }
+
+    // Third party logs have no start time, the file date is the closest
+    if recording.started_at == 0 {
+        recording.started_at = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+    }
+
+    let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(Imported { name, source, recording })
+}
@@ -0,0 +1,575 @@
+pub mod anonymize;
+pub mod import;
+pub mod retention;
+pub mod share;
+pub mod template;
+pub mod xlsx;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use gameperf_core::edit;
+use gameperf_core::report::{self, Report};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::capture::annotation::{self, ExternalEvent};
+use crate::capture::engine::EngineTimings;
+use crate::capture::interrupt::Reason;
+use crate::capture::{Marker, Recording};
+use crate::config;
+use crate::format;
+use crate::util;
+
+// GamePerf's own capture files, associated with the app on Windows
+pub const EXTENSION: &str = "gpcap";
+const COMPACT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    GamePerf,
+    PresentMon,
+    CapFrameX,
+    MangoHud,
+    // WPR, xperf or Game Bar trace
+    Etl,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub name: String,
+    pub package: String,
+    pub source: Source,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub frames: usize,
+    // Samples and frames lost during the capture, see `Recording::health`
+    #[serde(default)]
+    pub dropped: u64,
+    // Only stats and downsampled samples left
+    #[serde(default)]
+    pub compacted: bool,
+    // Why the capture ended early, see `Recording::interrupted`
+    #[serde(default)]
+    pub interrupted: Option<Reason>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Markdown
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub imported_from: Option<PathBuf>,
+}
+
+type Index = BTreeMap<String, SessionInfo>;
+
+fn sessions_dir() -> Result<PathBuf> {
+    config::session_dir().context("No data directory")
+}
+
+fn load_index() -> Result<Index> {
+    let path = sessions_dir()?.join("index.json");
+    match fs::read(&path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(_) => Ok(Index::new()),
+    }
+}
+
+fn save_index(index: &Index) -> Result<()> {
+    let dir = sessions_dir()?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("index.json"), serde_json::to_vec_pretty(index)?)?;
+    Ok(())
+}
+
+fn recording_path(id: &str) -> Result<PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        bail!("Invalid session id: {}", id);
+    }
+    Ok(sessions_dir()?.join(format!("{}.json", id)))
+}
+
+pub fn add(
+    name: &str,
+    source: Source,
+    recording: &Recording,
+    imported_from: Option<&Path>,
+) -> Result<SessionInfo> {
+    let _lock = INDEX_LOCK.lock();
+    insert(name, source, recording, imported_from)
+}
+
+// With `INDEX_LOCK` held
+fn insert(
+    name: &str,
+    source: Source,
+    recording: &Recording,
+    imported_from: Option<&Path>,
+) -> Result<SessionInfo> {
+    let mut index = load_index()?;
+
+    let id = format!("{:016x}", rand::thread_rng().gen::<u64>());
+    let path = recording_path(&id)?;
+    fs::create_dir_all(sessions_dir()?)?;
+    let private = anonymize::is_private();
+    if private {
+        let mut value = serde_json::to_value(recording)?;
+        anonymize::private(&mut value);
+        fs::write(&path, serde_json::to_vec(&value)?)?;
+    } else {
+        fs::write(&path, serde_json::to_vec(recording)?)?;
+    }
+
+    let info = SessionInfo {
+        id: id.clone(),
+        name: if private { anonymize::private_text(name, &recording.package) } else { name.into() },
+        package: if private {
+            anonymize::hashed(&recording.package)
+        } else {
+            recording.package.clone()
+        },
+        source,
+        started_at: recording.started_at,
+        duration_ms: recording.duration_ms,
+        frames: recording.frametimes.len(),
+        dropped: recording.health.total_dropped(),
+        compacted: recording.summary.is_some(),
+        interrupted: recording.interrupted.as_ref().map(|interruption| interruption.reason),
+        tags: vec![],
+        notes: String::new(),
+        imported_from: imported_from.filter(|_| !private).map(Path::to_owned),
+    };
+    index.insert(id, info.clone());
+    save_index(&index)?;
+    Ok(info)
+}
+
+// Newest first
+pub fn list() -> Result<Vec<SessionInfo>> {
+    let _lock = INDEX_LOCK.lock();
+    let mut sessions: Vec<_> = load_index()?.into_values().collect();
+    sessions.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+    Ok(sessions)
+}
+
+// Fields left to None are kept
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Changes {
+    pub name: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub notes: Option<String>,
+}
+
+pub fn update(id: &str, changes: Changes) -> Result<SessionInfo> {
+    let _lock = INDEX_LOCK.lock();
+    let mut index = load_index()?;
+    let info = index.get_mut(id).with_context(|| format!("Unknown session {}", id))?;
+    changes.apply(info, anonymize::is_private());
+    let info = info.clone();
+    save_index(&index)?;
+    Ok(info)
+}
+
+impl Changes {
+    // Stored the way `add` stores names in privacy mode
+    fn apply(self, info: &mut SessionInfo, private: bool) {
+        let text = |text: String| {
+            if private {
+                anonymize::private_text(&text, &info.package)
+            } else {
+                text
+            }
+        };
+        if let Some(name) = self.name {
+            info.name = text(name);
+        }
+        if let Some(notes) = self.notes {
+            info.notes = text(notes);
+        }
+        if let Some(tags) = self.tags {
+            let mut tags: Vec<String> = tags
+                .iter()
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            tags.sort();
+            tags.dedup();
+            info.tags = tags;
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Query {
+    // Every word has to appear in the name, package, tags or notes
+    pub query: String,
+    // Every tag has to be set
+    pub tags: Vec<String>,
+    // Part of the package name
+    pub game: Option<String>,
+    // `started_at` range in seconds, inclusive
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl Query {
+    fn matches(&self, info: &SessionInfo) -> bool {
+        let text = format!("{} {} {} {}", info.name, info.package, info.tags.join(" "), info.notes)
+            .to_lowercase();
+        let has_tag = |tag: &String| info.tags.iter().any(|t| t.eq_ignore_ascii_case(tag));
+        self.query.to_lowercase().split_whitespace().all(|word| text.contains(word))
+            && self.tags.iter().all(has_tag)
+            && self
+                .game
+                .as_ref()
+                .map_or(true, |game| info.package.to_lowercase().contains(&game.to_lowercase()))
+            && self.from.map_or(true, |from| info.started_at >= from)
+            && self.to.map_or(true, |to| info.started_at <= to)
+    }
+}
+
+// Newest first
+pub fn search(query: &Query) -> Result<Vec<SessionInfo>> {
+    Ok(list()?.into_iter().filter(|info| query.matches(info)).collect())
+}
+
+fn find_imported(path: &Path) -> Result<Option<SessionInfo>> {
+    let _lock = INDEX_LOCK.lock();
+    Ok(imported(&load_index()?, path).cloned())
+}
+
+fn imported<'a>(index: &'a Index, path: &Path) -> Option<&'a SessionInfo> {
+    index.values().find(|info| info.imported_from.as_deref() == Some(path))
+}
+
+// None when the file was imported before
+pub fn import_file(path: &Path) -> Result<Option<SessionInfo>> {
+    let path = path.canonicalize()?;
+    // Spares parsing files imported long ago
+    if find_imported(&path)?.is_some() {
+        return Ok(None);
+    }
+    let import::Imported { name, source, recording } = import::import(&path)?;
+    // Looked up again under the same lock as the add, two imports of one file add it once
+    let _lock = INDEX_LOCK.lock();
+    if imported(&load_index()?, &path).is_some() {
+        return Ok(None);
+    }
+    insert(&name, source, &recording, Some(&path)).map(Some)
+}
+
+// Not halfway through a write or a migration
+pub fn load(id: &str) -> Result<Recording> {
+    let _lock = INDEX_LOCK.lock();
+    read(id)
+}
+
+// With `INDEX_LOCK` held
+fn read(id: &str) -> Result<Recording> {
+    let path = recording_path(id)?;
+    let json = fs::read(&path).with_context(|| format!("Unknown session {}", id))?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+// As a `.gpcap` file, for sharing when anonymized (see `anonymize`)
+pub fn export(id: &str, path: &Path, anonymized: bool) -> Result<()> {
+    let mut recording = serde_json::to_value(load(id)?)?;
+    if anonymized {
+        anonymize::anonymize(&mut recording, &anonymize::Identity::current());
+    }
+    fs::write(path, format::write_gpcap(recording)?)?;
+    Ok(())
+}
+
+// See `gameperf_core::report` for the layout
+pub fn report(id: &str) -> Result<Report> {
+    let recording = load(id)?;
+    let name = list()?.into_iter().find(|info| info.id == id).map(|info| info.name);
+    Ok(report::generate(&recording, Some(id), &name.unwrap_or_default()))
+}
+
+// A capture file outside the session store, nothing is imported
+pub fn report_file(path: &Path) -> Result<Report> {
+    let import::Imported { name, recording, .. } = import::import(path)?;
+    Ok(report::generate(&recording, None, &name))
+}
+
+// What anonymizing the session would change, nothing is written
+pub fn preview_anonymized(id: &str) -> Result<Vec<anonymize::Redaction>> {
+    let mut recording = serde_json::to_value(load(id)?)?;
+    Ok(anonymize::anonymize(&mut recording, &anonymize::Identity::current()))
+}
+
+// Scene boundaries, replacing the ones the session had
+pub fn set_markers(id: &str, mut markers: Vec<Marker>) -> Result<()> {
+    let _lock = INDEX_LOCK.lock();
+    let mut recording = read(id)?;
+    markers.sort_by_key(|marker| marker.elapsed_ms);
+    recording.markers = markers;
+    fs::write(recording_path(id)?, serde_json::to_vec(&recording)?)?;
+    Ok(())
+}
+
+// Replaces the engine timings and their per sample metrics
+pub fn set_engine(id: &str, parse: impl FnOnce(&Recording) -> Result<EngineTimings>) -> Result<()> {
+    let _lock = INDEX_LOCK.lock();
+    let mut recording = read(id)?;
+    if recording.summary.is_some() {
+        bail!("Compacted sessions have no frames left to line up with");
+    }
+    let engine = parse(&recording)?;
+    if let Some(previous) = &recording.engine {
+        let prefix = format!("{}.", previous.engine);
+        for sample in recording.samples.iter_mut() {
+            sample.metrics.retain(|name, _| !name.starts_with(&prefix));
+        }
+    }
+    engine.add_metrics(&mut recording.samples);
+    recording.engine = Some(engine);
+    fs::write(recording_path(id)?, serde_json::to_vec(&recording)?)?;
+    Ok(())
+}
+
+// Wall clock times are placed from `started_at`, only to the second
+pub fn annotate(id: &str, events: &[ExternalEvent]) -> Result<usize> {
+    annotation::validate(events)?;
+    let _lock = INDEX_LOCK.lock();
+    let mut recording = read(id)?;
+    let kept = annotation::merge(&mut recording.annotations, events, recording.started_at * 1000);
+    fs::write(recording_path(id)?, serde_json::to_vec(&recording)?)?;
+    Ok(kept)
+}
+
+// Sessions past the retention period, compacted in place
+pub fn compact_expired() -> Result<Vec<SessionInfo>> {
+    let retention = config::CONFIG.read().retention.clone();
+    let raw_days = match retention.raw_days {
+        Some(days) => days,
+        None => return Ok(vec![]),
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let cutoff = now.saturating_sub(raw_days * 24 * 60 * 60);
+
+    let _lock = INDEX_LOCK.lock();
+    let mut index = load_index()?;
+    let mut compacted = vec![];
+    for info in index.values_mut().filter(|info| !info.compacted && info.started_at < cutoff) {
+        let path = recording_path(&info.id)?;
+        let mut recording = read(&info.id)?;
+        retention::compact(&mut recording, retention.downsample_secs * 1000);
+        fs::write(&path, serde_json::to_vec(&recording)?)?;
+        info.compacted = true;
+        compacted.push(info.clone());
+    }
+    if !compacted.is_empty() {
+        log::info!("compacted {} session(s)", compacted.len());
+        save_index(&index)?;
+    }
+    Ok(compacted)
+}
+
+// Rewrites the recordings through `upgrade` one at a time, refreshing what the index keeps of
+// them, see `migrate`. Those in `done` are skipped, `mark` gets each one once it's written and
+// `progress` the recordings done and the total.
+pub fn migrate(
+    upgrade: impl Fn(&mut serde_json::Value) -> Result<()>,
+    done: &BTreeSet<String>,
+    mut mark: impl FnMut(&str) -> Result<()>,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<()> {
+    let ids: Vec<String> = {
+        let _lock = INDEX_LOCK.lock();
+        load_index()?.into_keys().collect()
+    };
+    let total = ids.len();
+    for (i, id) in ids.iter().enumerate() {
+        if !done.contains(id) {
+            migrate_one(id, &upgrade).with_context(|| format!("Session {}", id))?;
+            mark(id)?;
+        }
+        progress(i + 1, total);
+    }
+    Ok(())
+}
+
+fn migrate_one(id: &str, upgrade: impl Fn(&mut serde_json::Value) -> Result<()>) -> Result<()> {
+    let _lock = INDEX_LOCK.lock();
+    let mut index = load_index()?;
+    let path = recording_path(id)?;
+    // Deleted meanwhile or by hand, the entry stays as it was
+    let (info, json) = match (index.get_mut(id), fs::read(&path)) {
+        (Some(info), Ok(json)) => (info, json),
+        _ => return Ok(()),
+    };
+    let mut value = serde_json::from_slice(&json)?;
+    upgrade(&mut value)?;
+    let recording: Recording = serde_json::from_value(value)?;
+    fs::write(&path, serde_json::to_vec(&recording)?)?;
+    // Older indexes didn't have these
+    info.dropped = recording.health.total_dropped();
+    info.compacted |= recording.summary.is_some();
+    save_index(&index)
+}
+
+pub fn spawn_compactor() {
+    thread::spawn(|| loop {
+        if let Err(err) = compact_expired() {
+            log::warn!("compacting sessions: {}", err);
+        }
+        thread::sleep(COMPACT_INTERVAL);
+    });
+}
+
+pub fn get(id: &str) -> Result<SessionInfo> {
+    let _lock = INDEX_LOCK.lock();
+    load_index()?.remove(id).with_context(|| format!("Unknown session {}", id))
+}
+
+// The original is kept, the parts are new sessions with its name, tags and notes
+pub fn split(id: &str, at_ms: u64) -> Result<Vec<SessionInfo>> {
+    let info = get(id)?;
+    let (first, second) = edit::split(&load(id)?, at_ms)?;
+    let parts = [first, second];
+    let mut added = vec![];
+    for (i, recording) in parts.iter().enumerate() {
+        let name = format!("{} ({})", info.name, i + 1);
+        added.push(add_like(&info, &name, recording)?);
+    }
+    Ok(added)
+}
+
+// The merged session takes name, source, tags and notes from the earliest one
+pub fn merge(ids: &[String]) -> Result<SessionInfo> {
+    let mut unique = ids.to_vec();
+    unique.sort();
+    unique.dedup();
+    if unique.len() != ids.len() {
+        bail!("A session can't be merged with itself");
+    }
+    let mut infos = ids.iter().map(|id| get(id)).collect::<Result<Vec<_>>>()?;
+    let recordings = ids.iter().map(|id| load(id)).collect::<Result<Vec<_>>>()?;
+    infos.sort_by_key(|info| info.started_at);
+    let merged = edit::merge(recordings)?;
+    let mut tags: Vec<String> = infos.iter().flat_map(|info| info.tags.clone()).collect();
+    tags.sort();
+    tags.dedup();
+    let first = SessionInfo { tags, ..infos[0].clone() };
+    add_like(&first, &format!("{} (merged)", first.name), &merged)
+}
+
+fn add_like(info: &SessionInfo, name: &str, recording: &Recording) -> Result<SessionInfo> {
+    let added = add(name, info.source, recording, None)?;
+    let changes =
+        Changes { tags: Some(info.tags.clone()), notes: Some(info.notes.clone()), name: None };
+    update(&added.id, changes)
+}
+
+pub fn delete(id: &str, to_trash: bool) -> Result<()> {
+    let _lock = INDEX_LOCK.lock();
+    let mut index = load_index()?;
+    if index.remove(id).is_none() {
+        bail!("Unknown session {}", id);
+    }
+    let path = recording_path(id)?;
+    if path.exists() {
+        util::delete_path(&path, to_trash)?;
+    }
+    save_index(&index)
+}
+
+pub fn is_session_file(path: impl AsRef<Path>) -> bool {
+    let ext = path.as_ref().extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    ext.eq_ignore_ascii_case(EXTENSION)
+}
+
+// Imported on first open, later opens reuse the same session
+pub fn open_file(path: &Path) -> Result<SessionInfo> {
+    let path = path.canonicalize()?;
+    if let Some(info) = find_imported(&path)? {
+        return Ok(info);
+    }
+    // Imported by someone else meanwhile
+    match import_file(&path)? {
+        Some(info) => Ok(info),
+        None => {
+            find_imported(&path)?.with_context(|| format!("Could not import {}", path.display()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> SessionInfo {
+        SessionInfo {
+            id: "0123456789abcdef".into(),
+            name: "Boss fight".into(),
+            package: "com.example.game".into(),
+            source: Source::GamePerf,
+            started_at: 1_700_000_000,
+            duration_ms: 60_000,
+            frames: 3600,
+            dropped: 0,
+            compacted: false,
+            interrupted: None,
+            tags: vec!["patch-1.2".into(), "ultra".into()],
+            notes: "Fans at **max**".into(),
+            imported_from: None,
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let info = info();
+        let query = |json| serde_json::from_value::<Query>(json).unwrap().matches(&info);
+
+        assert!(query(serde_json::json!({})));
+        assert!(query(serde_json::json!({ "query": "boss MAX", "tags": ["Ultra"] })));
+        assert!(query(serde_json::json!({ "game": "example", "from": 1_700_000_000u64 })));
+        assert!(!query(serde_json::json!({ "query": "forest" })));
+        assert!(!query(serde_json::json!({ "tags": ["ultra", "low"] })));
+        assert!(!query(serde_json::json!({ "to": 1_600_000_000u64 })));
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut info = info();
+        let changes = |name: &str| Changes {
+            name: Some(name.into()),
+            notes: Some(r"Log in C:\Users\alice\game.log".into()),
+            tags: Some(vec![" ultra ".into(), "ultra".into(), "".into()]),
+        };
+        changes("Run 2").apply(&mut info, false);
+        assert_eq!((info.name.as_str(), info.tags.clone()), ("Run 2", vec!["ultra".to_string()]));
+
+        changes("com.example.game boss fight").apply(&mut info, true);
+        assert!(!info.name.contains("com.example.game"));
+        assert!(info.name.ends_with("boss fight"));
+        assert!(!info.notes.contains("alice"));
+    }
+
+    #[test]
+    fn test_imported() {
+        let path = Path::new("/captures/run.csv");
+        let mut index = Index::new();
+        index.insert("a".into(), info());
+        assert!(imported(&index, path).is_none());
+        index.insert("b".into(), SessionInfo { imported_from: Some(path.into()), ..info() });
+        assert_eq!(imported(&index, path).map(|info| info.imported_from.is_some()), Some(true));
+    }
+}
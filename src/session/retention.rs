@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use crate::analysis::Stats;
+use crate::capture::{Recording, Sample};
+
+// Frames and samples are dropped, samples averaged per `bucket_ms`. Stats are computed first
+// and kept in `summary`, later analysis of the session uses them.
+pub fn compact(recording: &mut Recording, bucket_ms: u64) {
+    if recording.summary.is_none() {
+        recording.summary = Some(Stats::compute(recording));
+    }
+    recording.samples = downsample(&recording.samples, bucket_ms.max(1));
+    recording.frametimes = vec![];
+    recording.gpu_busy = vec![];
+    recording.queue_depth = vec![];
+    recording.generated = vec![];
+    recording.input_latency = vec![];
+}
+
+fn downsample(samples: &[Sample], bucket_ms: u64) -> Vec<Sample> {
+    let mut buckets: BTreeMap<u64, Vec<&Sample>> = BTreeMap::new();
+    for sample in samples {
+        buckets.entry(sample.elapsed_ms / bucket_ms).or_default().push(sample);
+    }
+    buckets
+        .into_iter()
+        .map(|(bucket, samples)| {
+            let mut sums: BTreeMap<&String, (f64, usize)> = BTreeMap::new();
+            for (name, value) in samples.iter().flat_map(|sample| &sample.metrics) {
+                let sum = sums.entry(name).or_default();
+                sum.0 += value;
+                sum.1 += 1;
+            }
+            let metrics =
+                sums.into_iter().map(|(name, (sum, count))| (name.clone(), sum / count as f64));
+            Sample { elapsed_ms: bucket * bucket_ms, metrics: metrics.collect() }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact() {
+        let sample = |elapsed_ms, fps| {
+            let metrics = [("fps".to_string(), fps)].into_iter().collect();
+            Sample { elapsed_ms, metrics }
+        };
+        let mut recording = Recording {
+            duration_ms: 3000,
+            frametimes: vec![20.0; 150],
+            samples: vec![sample(0, 40.0), sample(1000, 60.0), sample(2000, 50.0)],
+            ..Default::default()
+        };
+
+        compact(&mut recording, 2000);
+        assert!(recording.frametimes.is_empty());
+        assert_eq!(recording.samples.len(), 2);
+        assert_eq!(recording.samples[0].metrics["fps"], 50.0);
+        assert_eq!(recording.samples[1].elapsed_ms, 2000);
+        assert_eq!(Stats::compute(&recording).avg_fps, 50.0);
+    }
+}
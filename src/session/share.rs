@@ -0,0 +1,137 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use gameperf_core::migrate;
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::CONFIG;
+use crate::format;
+
+use super::{anonymize, SessionInfo, Source};
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+// Bigger uploads are refused by most backends anyway
+const MAX_DOWNLOAD: usize = 256 * 1024 * 1024;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("Failed to initialize http client");
+}
+
+// Where shared sessions go: any server taking `PUT <upload_url>/<name>` and serving the file back
+// at the same URL (WebDAV, an S3 bucket behind a proxy...)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShareSettings {
+    pub upload_url: Option<String>,
+    // Sent as a bearer token with uploads
+    pub token: Option<String>,
+}
+
+// What the link points to, once decrypted
+#[derive(Serialize, Deserialize)]
+struct Shared {
+    name: String,
+    recording: serde_json::Value,
+}
+
+// Nonce then ciphertext, and the key for the link's fragment
+fn seal(plain: &[u8]) -> Result<(Vec<u8>, String)> {
+    let key: [u8; KEY_LEN] = rand::thread_rng().gen();
+    let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let mut sealed = nonce.to_vec();
+    sealed.extend(
+        cipher
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|_| anyhow!("Encryption failed"))?,
+    );
+    Ok((sealed, base64::encode_config(key, base64::URL_SAFE_NO_PAD)))
+}
+
+fn open(sealed: &[u8], key: &str) -> Result<Vec<u8>> {
+    let key = base64::decode_config(key, base64::URL_SAFE_NO_PAD).context("Invalid link key")?;
+    if key.len() != KEY_LEN || sealed.len() < NONCE_LEN {
+        bail!("Invalid shared session");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Wrong key or corrupted shared session"))
+}
+
+// Encrypted here, the backend only ever sees ciphertext. The key is in the link's fragment,
+// which browsers and HTTP clients don't send.
+pub async fn share(id: &str, keep_metadata: bool) -> Result<String> {
+    let ShareSettings { upload_url, token } = CONFIG.read().share.clone();
+    let upload_url = upload_url.context("No storage backend set, see `share` in the settings")?;
+    let info = super::list()?.into_iter().find(|info| info.id == id);
+    let name = info.map(|info| info.name).unwrap_or_default();
+
+    let mut recording = serde_json::to_value(super::load(id)?)?;
+    if !keep_metadata {
+        anonymize::anonymize(&mut recording, &anonymize::Identity::current());
+    }
+    migrate::stamp_recording(&mut recording);
+    let (sealed, key) = seal(&serde_json::to_vec(&Shared { name, recording })?)?;
+
+    let file = format!("{:032x}.{}", rand::thread_rng().gen::<u128>(), super::EXTENSION);
+    let mut url = Url::parse(&format!("{}/", upload_url.trim_end_matches('/')))?.join(&file)?;
+    let mut request = CLIENT.put(url.clone()).body(sealed);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request.send().await?.error_for_status()?;
+
+    url.set_fragment(Some(&key));
+    Ok(url.into())
+}
+
+pub async fn import(link: &str) -> Result<SessionInfo> {
+    let mut url = Url::parse(link).context("Invalid link")?;
+    if !matches!(url.scheme(), "https" | "http") {
+        bail!("Invalid link");
+    }
+    let key = url.fragment().context("Link without its key")?.to_string();
+    url.set_fragment(None);
+
+    let mut response = CLIENT.get(url).send().await?.error_for_status()?;
+    if response.content_length().map_or(false, |len| len as usize > MAX_DOWNLOAD) {
+        bail!("Shared session too large");
+    }
+    // Counted as it arrives too, for servers that don't send the length
+    let mut sealed = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        if sealed.len() + chunk.len() > MAX_DOWNLOAD {
+            bail!("Shared session too large");
+        }
+        sealed.extend_from_slice(&chunk);
+    }
+    let shared: Shared = serde_json::from_slice(&open(&sealed, &key)?)?;
+    let recording = format::read_recording(shared.recording)?;
+    super::add(&shared.name, Source::GamePerf, &recording, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal() -> Result<()> {
+        let (mut sealed, key) = seal(b"session")?;
+        assert_eq!(open(&sealed, &key)?, b"session");
+
+        let (_, other) = seal(b"session")?;
+        assert!(open(&sealed, &other).is_err());
+        sealed[NONCE_LEN] ^= 1;
+        assert!(open(&sealed, &key).is_err());
+        Ok(())
+    }
+}
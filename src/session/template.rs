@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use gameperf_core::report::Report;
+use handlebars::{handlebars_helper, Handlebars};
+use serde::Serialize;
+
+use crate::config;
+
+// Handlebars templates in `config::template_dir()`, `<name>.<extension>.hbs` such as `lab.html.hbs`.
+// They get the JSON report as context and can include the built-in ones, e.g. `{{> markdown}}`
+// under a lab's own header.
+const SUFFIX: &str = ".hbs";
+const BUILT_IN: &[(&str, &str, &str)] = &[
+    ("markdown", "md", include_str!("report.md.hbs")),
+    // Perf per watt and battery life first, for handheld runs
+    ("handheld", "md", include_str!("handheld.md.hbs")),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateInfo {
+    pub name: String,
+    // Of the rendered file
+    pub extension: String,
+    // None for built-in templates
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rendered {
+    pub content: String,
+    pub extension: String,
+}
+
+handlebars_helper!(round: |value: f64, digits: u64| format!("{:.*}", digits as usize, value));
+handlebars_helper!(div: |a: f64, b: f64| if b == 0.0 { 0.0 } else { a / b });
+
+fn user_templates() -> Result<Vec<TemplateInfo>> {
+    let dir = match config::template_dir() {
+        Some(dir) if dir.is_dir() => dir,
+        _ => return Ok(vec![]),
+    };
+    let mut templates = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let (name, extension) =
+            match file_name.strip_suffix(SUFFIX).and_then(|n| n.rsplit_once('.')) {
+                Some((name, extension)) if !name.is_empty() => {
+                    (name.to_string(), extension.to_string())
+                }
+                _ => continue,
+            };
+        if BUILT_IN.iter().any(|(built_in, ..)| *built_in == name) {
+            log::warn!("template {} shadows a built-in one, skipped", path.display());
+            continue;
+        }
+        templates.push(TemplateInfo { name, extension, path: Some(path) });
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(templates)
+}
+
+// Built-in ones first
+pub fn list() -> Result<Vec<TemplateInfo>> {
+    let built_in = BUILT_IN.iter().map(|(name, extension, _)| TemplateInfo {
+        name: name.to_string(),
+        extension: extension.to_string(),
+        path: None,
+    });
+    Ok(built_in.chain(user_templates()?).collect())
+}
+
+fn registry() -> Result<Handlebars<'static>> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.register_helper("round", Box::new(round));
+    handlebars.register_helper("div", Box::new(div));
+    for (name, _, source) in BUILT_IN {
+        handlebars.register_template_string(name, source)?;
+    }
+    // One broken user template doesn't take the others down
+    for template in user_templates()? {
+        if let Some(path) = &template.path {
+            if let Err(err) = handlebars.register_template_file(&template.name, path) {
+                log::warn!("template {} skipped: {}", path.display(), err);
+            }
+        }
+    }
+    Ok(handlebars)
+}
+
+// The report through one of `list()`
+pub fn render(report: &Report, template: &str) -> Result<Rendered> {
+    let info = match list()?.into_iter().find(|info| info.name == template) {
+        Some(info) => info,
+        None => bail!("Unknown report template {}", template),
+    };
+    let handlebars = registry()?;
+    if !handlebars.has_template(template) {
+        let path = info.path.unwrap_or_default();
+        bail!("Invalid template {}, see the log", path.display());
+    }
+    let content = handlebars.render(template, report)?;
+    Ok(Rendered { content, extension: info.extension })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Marker, Recording};
+    use gameperf_core::report;
+
+    #[test]
+    fn test_render_markdown() -> Result<()> {
+        let recording = Recording {
+            package: "com.example.game".into(),
+            duration_ms: 2000,
+            frametimes: vec![1000.0 / 60.0; 120],
+            markers: vec![Marker { elapsed_ms: 0, label: "city".into() }],
+            ..Default::default()
+        };
+        let report = report::generate(&recording, None, "city run");
+        let rendered = render(&report, "markdown")?;
+        assert_eq!(rendered.extension, "md");
+        assert!(rendered.content.starts_with("# city run\n\ncom.example.game, 2 s\n"));
+        assert!(rendered.content.contains("| Average FPS | 60.0 |"));
+        assert!(rendered.content.contains("| city | 60.0 |"));
+        let handheld = render(&report, "handheld")?;
+        assert!(handheld.content.contains("## Battery\n\nNot recorded"));
+        assert!(render(&report, "missing").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+
+use crate::analysis::Stats;
+use crate::capture::Recording;
+
+// Standard stats, keys of `Stats::get`, in sheet order
+const STATS: &[(&str, &str)] = &[
+    ("duration_secs", "Duration (s)"),
+    ("frames", "Frames"),
+    ("avg_fps", "Average FPS"),
+    ("p1_low", "1% low"),
+    ("p01_low", "0.1% low"),
+    ("min_fps", "Min FPS"),
+    ("max_fps", "Max FPS"),
+    ("avg_frametime", "Average frame time (ms)"),
+    ("p99_frametime", "99th percentile frame time (ms)"),
+    ("gpu_bound_pct", "GPU-bound (%)"),
+    ("limiter_fps", "Capped at (FPS)"),
+];
+// Excel's limit
+const MAX_SHEET_NAME: usize = 31;
+
+struct Session {
+    name: String,
+    recording: Recording,
+    stats: Stats,
+}
+
+fn header(sheet: &mut Worksheet, bold: &Format, titles: &[&str]) -> Result<()> {
+    for (col, title) in titles.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *title, bold)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    Ok(())
+}
+
+// One row per session
+fn summary(workbook: &mut Workbook, sessions: &[Session], bold: &Format) -> Result<()> {
+    let sheet = workbook.add_worksheet().set_name("Summary")?;
+    let titles: Vec<&str> = ["Session", "Package", "Started (unix)"]
+        .into_iter()
+        .chain(STATS.iter().map(|s| s.1))
+        .collect();
+    header(sheet, bold, &titles)?;
+    for (row, session) in sessions.iter().enumerate() {
+        let row = row as u32 + 1;
+        sheet.write_string(row, 0, &session.name)?;
+        sheet.write_string(row, 1, &session.recording.package)?;
+        sheet.write_number(row, 2, session.recording.started_at as f64)?;
+        for (col, (key, _)) in STATS.iter().enumerate() {
+            if let Some(value) = session.stats.get(key) {
+                sheet.write_number(row, col as u16 + 3, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Stats down, sessions across, each against the first one
+fn comparison(workbook: &mut Workbook, sessions: &[Session], bold: &Format) -> Result<()> {
+    let sheet = workbook.add_worksheet().set_name("Comparison")?;
+    let mut titles = vec!["Stat".to_string()];
+    for (i, session) in sessions.iter().enumerate() {
+        titles.push(session.name.clone());
+        if i > 0 {
+            titles.push(format!("{} vs. {} (%)", session.name, sessions[0].name));
+        }
+    }
+    header(sheet, bold, &titles.iter().map(String::as_str).collect::<Vec<_>>())?;
+    for (row, (key, title)) in STATS.iter().enumerate() {
+        let row = row as u32 + 1;
+        sheet.write_string(row, 0, *title)?;
+        let baseline = sessions[0].stats.get(key);
+        let mut col = 1;
+        for (i, session) in sessions.iter().enumerate() {
+            let value = session.stats.get(key);
+            if let Some(value) = value {
+                sheet.write_number(row, col, value)?;
+            }
+            col += 1;
+            if i > 0 {
+                if let (Some(value), Some(baseline)) = (value, baseline) {
+                    if baseline != 0.0 {
+                        sheet.write_number(row, col, (value - baseline) / baseline * 100.0)?;
+                    }
+                }
+                col += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Every sample of a session, one column per metric
+fn samples(workbook: &mut Workbook, session: &Session, index: usize, bold: &Format) -> Result<()> {
+    let mut name = format!("Samples {} {}", index + 1, session.name);
+    name = name.chars().filter(|c| !"[]:*?/\\".contains(*c)).take(MAX_SHEET_NAME).collect();
+    let sheet = workbook.add_worksheet().set_name(name.trim_end())?;
+    let metrics: BTreeSet<&str> = session
+        .recording
+        .samples
+        .iter()
+        .flat_map(|sample| sample.metrics.keys().map(String::as_str))
+        .collect();
+    let titles: Vec<&str> =
+        Some("Elapsed (s)").into_iter().chain(metrics.iter().copied()).collect();
+    header(sheet, bold, &titles)?;
+    for (row, sample) in session.recording.samples.iter().enumerate() {
+        let row = row as u32 + 1;
+        sheet.write_number(row, 0, sample.elapsed_ms as f64 / 1000.0)?;
+        for (col, metric) in metrics.iter().enumerate() {
+            if let Some(&value) = sample.metrics.get(*metric) {
+                sheet.write_number(row, col as u16 + 1, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A summary sheet, a comparison sheet with more than one session, then the raw samples of each
+pub fn export(ids: &[String], path: &Path) -> Result<()> {
+    if ids.is_empty() {
+        bail!("No sessions to export");
+    }
+    let infos = super::list()?;
+    let mut sessions = vec![];
+    for id in ids {
+        let recording = super::load(id)?;
+        let name = infos.iter().find(|info| &info.id == id).map(|info| info.name.clone());
+        let stats = Stats::compute(&recording);
+        sessions.push(Session { name: name.unwrap_or_else(|| id.clone()), recording, stats });
+    }
+    workbook(&sessions)?.save(path)?;
+    Ok(())
+}
+
+fn workbook(sessions: &[Session]) -> Result<Workbook> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    summary(&mut workbook, sessions, &bold)?;
+    if sessions.len() > 1 {
+        comparison(&mut workbook, sessions, &bold)?;
+    }
+    for (index, session) in sessions.iter().enumerate() {
+        samples(&mut workbook, session, index, &bold)?;
+    }
+    Ok(workbook)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use anyhow::Context;
+    use calamine::{DataType, Reader, Xlsx};
+
+    use super::*;
+    use crate::capture::Sample;
+
+    fn session(name: &str, fps: f64) -> Session {
+        let recording = Recording {
+            package: "com.example.game".into(),
+            started_at: 1_700_000_000,
+            duration_ms: 2000,
+            frametimes: vec![1000.0 / fps; 2 * fps as usize],
+            samples: vec![Sample {
+                elapsed_ms: 1000,
+                metrics: vec![("fps".to_string(), fps)].into_iter().collect(),
+            }],
+            ..Default::default()
+        };
+        let stats = Stats::compute_with(&recording, Default::default());
+        Session { name: name.into(), recording, stats }
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let sessions = [session("before", 60.0), session("after", 30.0)];
+        let buffer = workbook(&sessions)?.save_to_buffer()?;
+        let mut xlsx: Xlsx<_> = Xlsx::new(Cursor::new(buffer))?;
+        let names = ["Summary", "Comparison", "Samples 1 before", "Samples 2 after"];
+        assert_eq!(xlsx.sheet_names(), names);
+
+        let number = |cell: Option<&DataType>| cell.and_then(DataType::get_float);
+        let summary = xlsx.worksheet_range("Summary").context("No summary")??;
+        assert_eq!(summary.get_value((0, 5)), Some(&DataType::String("Average FPS".into())));
+        assert_eq!(summary.get_value((2, 0)), Some(&DataType::String("after".into())));
+        assert_eq!(summary.get_value((2, 1)), Some(&DataType::String("com.example.game".into())));
+        assert_eq!(number(summary.get_value((1, 2))), Some(1_700_000_000.0));
+        assert!((number(summary.get_value((1, 5))).unwrap_or_default() - 60.0).abs() < 0.01);
+
+        // Average FPS of "after" against "before"
+        let comparison = xlsx.worksheet_range("Comparison").context("No comparison")??;
+        assert_eq!(comparison.get_value((3, 0)), Some(&DataType::String("Average FPS".into())));
+        assert!((number(comparison.get_value((3, 3))).unwrap_or_default() + 50.0).abs() < 0.01);
+
+        let samples = xlsx.worksheet_range("Samples 2 after").context("No samples")??;
+        assert_eq!(samples.get_value((0, 1)), Some(&DataType::String("fps".into())));
+        assert_eq!(number(samples.get_value((1, 0))), Some(1.0));
+        assert_eq!(number(samples.get_value((1, 1))), Some(30.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::base::state;
+use crate::config::CONFIG;
+use crate::http;
+use crate::session::{self, anonymize};
+
+const PAGE: &str = include_str!("index.html");
+const MAX_CONNECTIONS: usize = 8;
+// Samples kept for pages that poll late, about two minutes
+const CAPACITY: usize = 240;
+// Live samples keep being taken while a page polled this recently
+const WATCHING: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref LIVE: Mutex<Live> = Mutex::new(Live::default());
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Sample {
+    seq: u64,
+    at_ms: u64,
+    value: Value,
+}
+
+#[derive(Default)]
+struct Live {
+    samples: VecDeque<Sample>,
+    last_seq: u64,
+    polled: Option<Instant>,
+}
+
+impl Live {
+    fn push(&mut self, value: Value, at_ms: u64) {
+        if self.samples.len() >= CAPACITY {
+            self.samples.pop_front();
+        }
+        self.last_seq += 1;
+        self.samples.push_back(Sample { seq: self.last_seq, at_ms, value });
+    }
+
+    fn since(&self, seq: u64) -> Value {
+        let samples: Vec<&Sample> = self.samples.iter().filter(|s| s.seq > seq).collect();
+        json!({ "last_seq": self.last_seq, "samples": samples })
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+// Metrics of a live tick, see `overlay::sample`
+pub fn sample(metrics: &BTreeMap<String, f64>) {
+    LIVE.lock().push(json!(metrics), now_ms());
+}
+
+pub fn is_watching() -> bool {
+    LIVE.lock().polled.map_or(false, |polled| polled.elapsed() < WATCHING)
+}
+
+// Read-only page for a phone or a second PC on the LAN: capture state, live charts and sessions.
+// The API takes the agent's keys in `Authorization: Bearer`, an observer key is enough.
+pub fn serve(port: u16) -> Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .with_context(|| format!("Failed to listen on port {}", port))?;
+    log::info!("viewer on http://0.0.0.0:{}", port);
+    http::serve(listener, "viewer", MAX_CONNECTIONS, handle);
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream) -> Result<()> {
+    let request = http::read(&stream, 0)?;
+    if request.method != "GET" {
+        return http::respond(&mut stream, "405 Method Not Allowed", "text/plain", "Read-only");
+    }
+    if request.path == "/" {
+        return http::respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE);
+    }
+
+    let key = request.key.unwrap_or_default();
+    if CONFIG.read().agent.authorize(&key).is_none() {
+        return http::respond(&mut stream, "401 Unauthorized", "text/plain", "Invalid key");
+    }
+    let body = match request.path.as_str() {
+        "/api/state" => json!(anonymize::private_state(state::current())),
+        "/api/sessions" => {
+            let sessions = session::list()?.into_iter().map(anonymize::private_session);
+            json!(sessions.collect::<Vec<_>>())
+        }
+        "/api/live" => {
+            let since = http::param(&request.query, "since").and_then(|seq| seq.parse().ok());
+            let mut live = LIVE.lock();
+            live.polled = Some(Instant::now());
+            live.since(since.unwrap_or_default())
+        }
+        _ => return http::respond(&mut stream, "404 Not Found", "text/plain", "Not found"),
+    };
+    http::respond(&mut stream, "200 OK", "application/json", &body.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_live() {
+        let mut live = Live::default();
+        for i in 0..CAPACITY + 5 {
+            live.push(json!(i), i as u64);
+        }
+        assert_eq!(live.since(0)["samples"].as_array().unwrap().len(), CAPACITY);
+        let recent = live.since(live.last_seq - 1);
+        assert_eq!(recent["samples"][0]["value"], json!(CAPACITY + 4));
+    }
+}
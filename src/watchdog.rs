@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde_json::json;
+use wry::application::event_loop::EventLoopProxy;
+
+use crate::base::state::{self, CaptureState};
+use crate::rpc;
+use crate::util;
+
+// The live capture loop itself, then what it polls
+pub const CAPTURE: &str = "capture";
+pub const FRAMES: &str = "frames";
+pub const MEMORY: &str = "memory";
+
+// Far longer than an adb call takes on a healthy device
+const STALL: Duration = Duration::from_secs(5);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref PROVIDERS: Mutex<BTreeMap<&'static str, Provider>> = Mutex::new(BTreeMap::new());
+}
+
+struct Provider {
+    last: Instant,
+    degraded: bool,
+    restarts: u32,
+    restarted: Option<Instant>,
+    // Picked up by the capture loop for `FRAMES`, see `should_restart`
+    restart: bool,
+}
+
+impl Provider {
+    fn new() -> Self {
+        Provider {
+            last: Instant::now(),
+            degraded: false,
+            restarts: 0,
+            restarted: None,
+            restart: false,
+        }
+    }
+}
+
+// `provider` answered, with or without new data
+pub fn beat(provider: &'static str) {
+    PROVIDERS.lock().entry(provider).or_insert_with(Provider::new).last = Instant::now();
+}
+
+// No longer polled (e.g. the HUD was closed), so no longer expected to answer
+pub fn forget(provider: &'static str) {
+    PROVIDERS.lock().remove(provider);
+}
+
+// A new capture
+pub fn reset() {
+    PROVIDERS.lock().clear();
+}
+
+// The capture loop starts the provider over, e.g. looks for the game's surface again
+pub fn should_restart(provider: &'static str) -> bool {
+    PROVIDERS
+        .lock()
+        .get_mut(provider)
+        .map_or(false, |provider| std::mem::take(&mut provider.restart))
+}
+
+// Providers quiet for `STALL` while capturing are restarted and reported with
+// `tse_capture_degraded`, then `tse_capture_recovered` once they answer again. A hung adb call
+// blocks the capture loop, reconnecting makes it fail instead.
+pub fn spawn(proxy: EventLoopProxy<rpc::Event>) {
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        let capturing = matches!(state::current(), CaptureState::Capturing { .. });
+        let mut events = vec![];
+        let mut reconnect = false;
+        {
+            let mut providers = PROVIDERS.lock();
+            for (&name, provider) in providers.iter_mut() {
+                // Paused, nothing is polled
+                if !capturing {
+                    provider.last = Instant::now();
+                    continue;
+                }
+                let stalled = provider.last.elapsed();
+                if stalled < STALL {
+                    if provider.degraded {
+                        provider.degraded = false;
+                        provider.restarted = None;
+                        log::info!("watchdog: {} recovered", name);
+                        let recovered = json!({ "provider": name, "restarts": provider.restarts });
+                        events.push(("tse_capture_recovered", recovered));
+                    }
+                    continue;
+                }
+                let retry = provider.restarted.map_or(true, |at| at.elapsed() >= STALL);
+                if !retry {
+                    continue;
+                }
+                // Frames look for the game's surface again, the others are adb calls which a
+                // reconnect gets going
+                provider.restart = name == FRAMES;
+                provider.restarts += 1;
+                provider.restarted = Some(Instant::now());
+                reconnect |= name != FRAMES;
+                if !provider.degraded {
+                    provider.degraded = true;
+                    log::warn!("watchdog: {} stalled for {:?}", name, stalled);
+                    let degraded = json!({
+                        "provider": name,
+                        "stalled_ms": stalled.as_millis() as u64,
+                    });
+                    events.push(("tse_capture_degraded", degraded));
+                }
+            }
+        }
+        if reconnect {
+            if let Err(err) = util::adb("reconnect".into()) {
+                log::warn!("watchdog: {}", err);
+            }
+        }
+        for (name, payload) in events {
+            let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(name, payload));
+        }
+    });
+}
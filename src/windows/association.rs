@@ -0,0 +1,27 @@
+use std::env;
+
+use anyhow::Result;
+use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+
+use crate::{link, session};
+
+const PROG_ID: &str = "GamePerf.Capture";
+
+// Per-user `.gpcap` and `gameperf://` handlers, rewritten on every start so a moved exe still
+// gets opened. Both arrive as the SAVE positional.
+pub fn register() -> Result<()> {
+    let command = format!("\"{}\" \"%1\"", env::current_exe()?.display());
+    let classes = RegKey::predef(HKEY_CURRENT_USER).create_subkey("Software\\Classes")?.0;
+
+    let (extension, _) = classes.create_subkey(format!(".{}", session::EXTENSION))?;
+    extension.set_value("", &PROG_ID)?;
+    let (prog_id, _) = classes.create_subkey(PROG_ID)?;
+    prog_id.set_value("", &"GamePerf Capture")?;
+    prog_id.create_subkey("shell\\open\\command")?.0.set_value("", &command)?;
+
+    let (scheme, _) = classes.create_subkey(link::SCHEME)?;
+    scheme.set_value("", &"URL:GamePerf")?;
+    scheme.set_value("URL Protocol", &"")?;
+    scheme.create_subkey("shell\\open\\command")?.0.set_value("", &command)?;
+    Ok(())
+}
@@ -12,6 +12,9 @@ use crate::rpc;
 
 const GITHUB_API: &str =
     "https://api.github.com/repos/KarlitosVII/trilogy-save-editor/releases/latest";
+// The release of the running version, reinstalled to repair it
+const GITHUB_TAG_API: &str =
+    "https://api.github.com/repos/KarlitosVII/trilogy-save-editor/releases/tags/v";
 
 #[derive(Deserialize, Debug)]
 struct GithubResponse {
@@ -75,6 +78,31 @@ impl AutoUpdate {
         }
     }
 
+    // Same version, through the same download and install steps as an update
+    pub async fn repair(&self, proxy: EventLoopProxy<rpc::Event>) {
+        let result = async {
+            let url = format!("{}{}", GITHUB_TAG_API, env!("CARGO_PKG_VERSION"));
+            let response: GithubResponse = REQWEST.get(url).send().await?.json().await?;
+            let setup = response
+                .assets
+                .into_iter()
+                .find(|asset| asset.name.ends_with("setup.exe"))
+                .ok_or_else(|| anyhow::anyhow!("No installer for this version"))?;
+            *self.update_available.lock() = Some(setup);
+            Ok::<_, Error>(())
+        };
+
+        match result.await {
+            Ok(()) => self.download_and_install(proxy).await,
+            Err(err) => {
+                let _ = proxy.send_event(rpc::Event::DispatchCustomEvent(
+                    "tse_update_error",
+                    json!({ "error": err.to_string() }),
+                ));
+            }
+        }
+    }
+
     pub async fn download_and_install(&self, proxy: EventLoopProxy<rpc::Event>) {
         let asset = self.update_available.lock().take();
         if let Some(GithubAsset { name, browser_download_url, size }) = asset {
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use rand::Rng;
+
+use crate::capture::compositor;
+use crate::config::CONFIG;
+
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+// WPR, xperf and Game Bar traces hold the same present events PresentMon reads live, so it
+// converts them offline. Its CSV has the frames of every process, only the game's and DWM's
+// (see `compositor`) are kept.
+pub fn to_presentmon_csv(etl: &Path) -> Result<String> {
+    let presentmon =
+        CONFIG.read().presentmon.clone().unwrap_or_else(|| PathBuf::from("PresentMon"));
+    // Imports run in parallel
+    let name = format!("gameperf-etl-{:016x}.csv", rand::thread_rng().gen::<u64>());
+    let output = std::env::temp_dir().join(name);
+    let result = Command::new(&presentmon)
+        .arg("--etl_file")
+        .arg(etl)
+        .arg("--output_file")
+        .arg(&output)
+        .arg("--no_console_stats")
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .with_context(|| {
+            format!("Failed to run {}, set `presentmon` in the config", presentmon.display())
+        })?;
+    let csv = fs::read_to_string(&output);
+    let _ = fs::remove_file(&output);
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        bail!("PresentMon couldn't convert {}: {}", etl.display(), stderr.trim());
+    }
+    busiest_application(&csv.context("No present events in the trace")?)
+}
+
+// The header and the rows of the application with the most frames, DWM's are kept too
+fn busiest_application(csv: &str) -> Result<String> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().context("No present events in the trace")?;
+    let application = header
+        .split(',')
+        .position(|column| column.trim() == "Application")
+        .context("Unexpected PresentMon output, no Application column")?;
+    let rows: Vec<&str> = lines.collect();
+    let app = |row: &&str| row.split(',').nth(application).unwrap_or_default().trim().to_string();
+    let mut frames = BTreeMap::new();
+    for row in &rows {
+        *frames.entry(app(row)).or_insert(0) += 1;
+    }
+    let busiest = frames
+        .into_iter()
+        .filter(|(name, _)| !compositor::is_compositor(name))
+        .max_by_key(|(_, count)| *count)
+        .map(|(name, _)| name)
+        .context("No game frames in the trace")?;
+    let kept =
+        rows.into_iter().filter(|row| app(row) == busiest || compositor::is_compositor(&app(row)));
+    Ok(Some(header).into_iter().chain(kept).collect::<Vec<_>>().join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busiest_application() -> Result<()> {
+        let csv = "Application,ProcessID,MsBetweenPresents\n\
+                   launcher.exe,10,33.3\n\
+                   game.exe,20,16.6\n\
+                   dwm.exe,30,16.6\n\
+                   game.exe,20,16.7\n\
+                   dwm.exe,30,16.7\n\
+                   dwm.exe,30,16.6\n";
+        assert_eq!(
+            busiest_application(csv)?,
+            "Application,ProcessID,MsBetweenPresents\n\
+             game.exe,20,16.6\n\
+             dwm.exe,30,16.6\n\
+             game.exe,20,16.7\n\
+             dwm.exe,30,16.7\n\
+             dwm.exe,30,16.6"
+        );
+        assert!(busiest_application("ProcessID\n10").is_err());
+        assert!(busiest_application("Application\ndwm.exe").is_err());
+        Ok(())
+    }
+}
@@ -3,7 +3,10 @@ use std::env;
 use anyhow::{bail, Result};
 use tokio::{fs, process};
 
+pub mod association;
 pub mod auto_update;
+pub mod etl;
+pub mod overlay;
 
 pub async fn install_webview2() -> Result<()> {
     let should_install = rfd::AsyncMessageDialog::new()
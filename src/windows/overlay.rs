@@ -0,0 +1,50 @@
+use anyhow::{bail, Result};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use winapi::shared::windef::HWND;
+use winapi::um::shellapi::{SHQueryUserNotificationState, QUNS_RUNNING_D3D_FULL_SCREEN};
+use winapi::um::winuser::{
+    GetWindowLongPtrW, SetWindowDisplayAffinity, SetWindowLongPtrW, GWL_EXSTYLE, WS_EX_LAYERED,
+    WS_EX_TRANSPARENT,
+};
+use wry::application::window::Window;
+
+// Not in winapi yet, Windows 10 2004 and later
+const WDA_EXCLUDEFROMCAPTURE: u32 = 0x11;
+const WDA_NONE: u32 = 0x00;
+
+fn hwnd(window: &Window) -> Result<HWND> {
+    match window.raw_window_handle() {
+        RawWindowHandle::Windows(handle) => Ok(handle.hwnd as HWND),
+        _ => bail!("Not a Win32 window"),
+    }
+}
+
+// Mouse input goes to whatever is below the window
+pub fn set_click_through(window: &Window, enabled: bool) -> Result<()> {
+    let hwnd = hwnd(window)?;
+    unsafe {
+        let style = GetWindowLongPtrW(hwnd, GWL_EXSTYLE);
+        let flags = (WS_EX_LAYERED | WS_EX_TRANSPARENT) as isize;
+        let style = if enabled { style | flags } else { style & !flags };
+        SetWindowLongPtrW(hwnd, GWL_EXSTYLE, style);
+    }
+    Ok(())
+}
+
+// Left out of screenshots and recordings (shown as black on older Windows versions)
+pub fn set_capture_excluded(window: &Window, excluded: bool) -> Result<()> {
+    let hwnd = hwnd(window)?;
+    let affinity = if excluded { WDA_EXCLUDEFROMCAPTURE } else { WDA_NONE };
+    if unsafe { SetWindowDisplayAffinity(hwnd, affinity) } == 0 {
+        bail!("SetWindowDisplayAffinity failed: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// An exclusive fullscreen Direct3D app hides every other window, topmost or not
+pub fn exclusive_fullscreen() -> bool {
+    let mut state = 0;
+    unsafe {
+        SHQueryUserNotificationState(&mut state) == 0 && state == QUNS_RUNNING_D3D_FULL_SCREEN
+    }
+}